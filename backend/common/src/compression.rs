@@ -0,0 +1,227 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Compression for the messages sent from shards to the core. Each websocket
+//! message is compressed separately (so that it can be decompressed as soon as
+//! it arrives), but a single deflate stream is used for the whole connection so
+//! that we also benefit from the redundancy between messages.
+
+use flate2::{Compress, Compression, Decompress, FlushCompress, FlushDecompress, Status};
+
+/// The query parameter a shard appends to the core URL to ask for compression.
+pub const QUERY_PARAM: &str = "compression";
+
+/// The most that a single message can decompress to. This is the most that the core
+/// accepts in one uncompressed websocket message, so compression doesn't let a shard
+/// send anything bigger.
+pub const MAX_DECOMPRESSED_SIZE: usize = 256 * 1024 * 1024;
+
+/// The kinds of compression that the shard to core connection supports.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Deflate,
+}
+
+impl Kind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Kind::Deflate => "deflate",
+        }
+    }
+}
+
+impl std::str::FromStr for Kind {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "deflate" => Ok(Kind::Deflate),
+            _ => Err(anyhow::anyhow!("Unsupported compression '{s}'")),
+        }
+    }
+}
+
+/// Find the requested compression, if any, from a URI query string.
+pub fn from_query(query: Option<&str>) -> anyhow::Result<Option<Kind>> {
+    let query = match query {
+        Some(query) => query,
+        None => return Ok(None),
+    };
+    for pair in query.split('&') {
+        if let Some((key, value)) = pair.split_once('=') {
+            if key == QUERY_PARAM {
+                return value.parse().map(Some);
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Compress messages, one at a time.
+pub struct Compressor(Compress);
+
+impl Compressor {
+    pub fn new(kind: Kind) -> Self {
+        match kind {
+            Kind::Deflate => Compressor(Compress::new(Compression::fast(), false)),
+        }
+    }
+
+    /// Compress a single message. The output can only be decompressed by a [`Decompressor`]
+    /// which has been given every message compressed before it, in order.
+    pub fn compress(&mut self, input: &[u8]) -> Vec<u8> {
+        let mut output = Vec::with_capacity(input.len() / 2 + 64);
+        let mut consumed = 0;
+        loop {
+            let before = self.0.total_in();
+            self.0
+                .compress_vec(&input[consumed..], &mut output, FlushCompress::Sync)
+                .expect("deflate compression cannot fail");
+            consumed += (self.0.total_in() - before) as usize;
+
+            // A sync flush is complete once it leaves space in the output buffer.
+            if consumed == input.len() && output.len() < output.capacity() {
+                return output;
+            }
+            output.reserve(output.capacity());
+        }
+    }
+}
+
+/// Decompress messages that were compressed via [`Compressor`].
+pub struct Decompressor {
+    inner: Decompress,
+    max_size: usize,
+}
+
+impl Decompressor {
+    pub fn new(kind: Kind) -> Self {
+        match kind {
+            Kind::Deflate => Decompressor {
+                inner: Decompress::new(false),
+                max_size: MAX_DECOMPRESSED_SIZE,
+            },
+        }
+    }
+
+    /// Decompress a single message. Once this returns an error, the stream can't be
+    /// relied on any more, and nothing else should be decompressed with it.
+    pub fn decompress(&mut self, input: &[u8]) -> anyhow::Result<Vec<u8>> {
+        // The output needs room to spare for us to know that the whole message is there,
+        // so it's allowed to grow to one byte more than the largest message:
+        let max_capacity = self.max_size + 1;
+        let mut output = Vec::with_capacity((input.len() * 4 + 64).min(max_capacity));
+        let mut consumed = 0;
+        loop {
+            let (in_before, out_before) = (self.inner.total_in(), self.inner.total_out());
+            let status = self.inner.decompress_vec(
+                &input[consumed..],
+                &mut output,
+                FlushDecompress::Sync,
+            )?;
+            consumed += (self.inner.total_in() - in_before) as usize;
+
+            // Compressor never finishes the stream, so anything after the end of one
+            // would never be decompressed:
+            if status == Status::StreamEnd {
+                anyhow::bail!("Compressed stream ended unexpectedly");
+            }
+            if consumed == input.len() && output.len() < output.capacity() {
+                return Ok(output);
+            }
+            // There was room for output, so if nothing happened, nothing ever will:
+            if self.inner.total_in() == in_before && self.inner.total_out() == out_before {
+                anyhow::bail!("Compressed message can't be decompressed any further");
+            }
+            if output.len() >= max_capacity {
+                anyhow::bail!(
+                    "Compressed message is bigger than {} bytes uncompressed",
+                    self.max_size
+                );
+            }
+            output.reserve_exact(output.capacity().min(max_capacity - output.len()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn messages_round_trip() {
+        let mut compressor = Compressor::new(Kind::Deflate);
+        let mut decompressor = Decompressor::new(Kind::Deflate);
+
+        let msgs: Vec<Vec<u8>> = vec![
+            b"hello world".to_vec(),
+            Vec::new(),
+            b"hello world hello world hello world".to_vec(),
+            (0..100_000).map(|n| (n % 251) as u8).collect(),
+        ];
+
+        for msg in msgs {
+            let compressed = compressor.compress(&msg);
+            let decompressed = decompressor.decompress(&compressed).unwrap();
+            assert_eq!(decompressed, msg);
+        }
+    }
+
+    #[test]
+    fn trailing_bytes_after_the_end_of_the_stream_are_an_error() {
+        let mut compress = Compress::new(Compression::fast(), false);
+        let mut input = Vec::with_capacity(1024);
+        compress
+            .compress_vec(b"hello world", &mut input, FlushCompress::Finish)
+            .unwrap();
+        input.extend_from_slice(&[0; 8]);
+
+        let mut decompressor = Decompressor::new(Kind::Deflate);
+        assert!(decompressor.decompress(&input).is_err());
+    }
+
+    #[test]
+    fn messages_that_decompress_to_too_much_are_an_error() {
+        let mut compressor = Compressor::new(Kind::Deflate);
+        let mut decompressor = Decompressor::new(Kind::Deflate);
+        decompressor.max_size = 1000;
+
+        let fits = compressor.compress(&[0; 1000]);
+        assert_eq!(decompressor.decompress(&fits).unwrap().len(), 1000);
+        let too_big = compressor.compress(&[0; 100_000]);
+        assert!(decompressor.decompress(&too_big).is_err());
+    }
+
+    #[test]
+    fn repeated_messages_compress_well() {
+        let mut compressor = Compressor::new(Kind::Deflate);
+        let msg = br#"{"msg":"system.interval","peers":12,"txcount":0,"best":"0x1234"}"#;
+
+        let first = compressor.compress(msg);
+        let second = compressor.compress(msg);
+        assert!(second.len() < first.len());
+    }
+
+    #[test]
+    fn compression_found_in_query() {
+        assert_eq!(from_query(None).unwrap(), None);
+        assert_eq!(from_query(Some("foo=bar")).unwrap(), None);
+        assert_eq!(
+            from_query(Some("foo=bar&compression=deflate")).unwrap(),
+            Some(Kind::Deflate)
+        );
+        assert!(from_query(Some("compression=zstd")).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use super::feed_message::FeedMessage;
+use crate::node_types::BlockHash;
+use crate::ws_client::{
+    self, ConnectError, ConnectionStats, RecvError, RecvMessage, SentMessage, StatsSnapshot,
+};
+use futures::{Stream, StreamExt};
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+#[derive(thiserror::Error, Debug)]
+pub enum FeedError {
+    #[error("Failed to connect to the feed: {0}")]
+    Connect(#[from] ConnectError),
+    #[error("Failed to send a command to the feed: {0}")]
+    Send(#[from] futures::channel::mpsc::SendError),
+    #[error("Failed to receive from the feed: {0}")]
+    Recv(#[from] RecvError),
+    #[error("Failed to decode feed messages: {0}")]
+    Decode(anyhow::Error),
+}
+
+/// A connection to a telemetry feed. This is a [`Stream`] of the messages that the
+/// feed sends; messages arrive in batches, but are handed out one at a time.
+pub struct FeedClient {
+    sender: ws_client::Sender,
+    receiver: ws_client::Receiver,
+    pending: VecDeque<FeedMessage>,
+}
+
+impl FeedClient {
+    /// Connect to a feed, eg `ws://localhost:8000/feed`.
+    pub async fn connect(uri: &http::Uri) -> Result<FeedClient, FeedError> {
+        FeedClient::connect_with_stats(uri, ConnectionStats::new()).await
+    }
+
+    /// Connect to a feed, recording traffic in the given stats. Reuse the stats when
+    /// reconnecting to keep track of traffic (and the number of reconnects) overall.
+    pub async fn connect_with_stats(
+        uri: &http::Uri,
+        stats: Arc<ConnectionStats>,
+    ) -> Result<FeedClient, FeedError> {
+        let (sender, receiver) = ws_client::connect_with_stats(uri, stats)
+            .await?
+            .into_channels();
+        Ok(FeedClient {
+            sender,
+            receiver,
+            pending: VecDeque::new(),
+        })
+    }
+
+    /// Connect to a feed and subscribe to the chain with the given genesis hash.
+    pub async fn connect_and_subscribe(
+        uri: &http::Uri,
+        genesis_hash: BlockHash,
+    ) -> Result<FeedClient, FeedError> {
+        let client = FeedClient::connect(uri).await?;
+        client.subscribe(genesis_hash)?;
+        Ok(client)
+    }
+
+    /// Subscribe to updates about the chain with the given genesis hash. The feed will
+    /// unsubscribe us from any chain that we were previously subscribed to.
+    ///
+    /// If the feed doesn't know about the chain (yet), this is ignored, so it's worth
+    /// waiting for a [`FeedMessage::SubscribedTo`] to confirm that it's worked.
+    pub fn subscribe(&self, genesis_hash: BlockHash) -> Result<(), FeedError> {
+        self.send_command("subscribe", &format!("{:?}", genesis_hash))
+    }
+
+    /// Ask the feed to also send us the GRANDPA messages for the chain with the given
+    /// genesis hash (the `Afg*` variants of [`FeedMessage`]). Feeds that don't forward
+    /// these ignore it.
+    pub fn send_finality(&self, genesis_hash: BlockHash) -> Result<(), FeedError> {
+        self.send_command("send-finality", &format!("{:?}", genesis_hash))
+    }
+
+    /// Ask the feed to reply with a [`FeedMessage::Pong`] containing the given value.
+    pub fn ping(&self, value: &str) -> Result<(), FeedError> {
+        self.send_command("ping", value)
+    }
+
+    /// Send a WebSocket ping. Once the pong arrives, the round trip time is
+    /// available from [`FeedClient::stats`].
+    pub fn ping_connection(&self) -> Result<(), FeedError> {
+        self.sender.unbounded_send(SentMessage::Ping)?;
+        Ok(())
+    }
+
+    /// The traffic statistics for this connection so far.
+    pub fn stats(&self) -> StatsSnapshot {
+        self.receiver.stats()
+    }
+
+    /// Close the connection.
+    pub async fn close(mut self) -> Result<(), FeedError> {
+        self.receiver.close().await?;
+        Ok(())
+    }
+
+    fn send_command(&self, command: &str, value: &str) -> Result<(), FeedError> {
+        self.sender
+            .unbounded_send(SentMessage::Text(format!("{}:{}", command, value)))?;
+        Ok(())
+    }
+}
+
+impl Stream for FeedClient {
+    type Item = Result<FeedMessage, FeedError>;
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(msg) = self.pending.pop_front() {
+                return Poll::Ready(Some(Ok(msg)));
+            }
+
+            let bytes = match futures::ready!(self.receiver.poll_next_unpin(cx)) {
+                Some(Ok(RecvMessage::Binary(bytes))) => bytes,
+                Some(Ok(RecvMessage::Text(text))) => text.into_bytes(),
+                Some(Err(e)) => return Poll::Ready(Some(Err(e.into()))),
+                None => return Poll::Ready(None),
+            };
+            match FeedMessage::from_bytes(&bytes) {
+                Ok(msgs) => self.pending.extend(msgs),
+                Err(e) => return Poll::Ready(Some(Err(FeedError::Decode(e)))),
+            }
+        }
+    }
+}
@@ -0,0 +1,27 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! A high level client for the telemetry feed. This handles connecting and subscribing,
+//! and decodes the array based feed protocol into [`FeedMessage`]s, so that consumers
+//! of the feed needn't know the details of how it's encoded.
+
+/// The connection to a feed.
+mod client;
+/// Decoding feed messages into something more usable.
+mod feed_message;
+
+pub use client::{FeedClient, FeedError};
+pub use feed_message::{FeedMessage, NodeDetails};
@@ -14,11 +14,14 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
-use futures::io::{BufReader, BufWriter};
+use futures::io::{AsyncRead, AsyncWrite, AsyncWriteExt, BufReader, BufWriter};
 use hyper::server::conn::AddrStream;
 use hyper::{Body, Request, Response, Server};
 use std::future::Future;
 use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
 use tokio_util::compat::{Compat, TokioAsyncReadCompatExt};
 
 /// A convenience function to start up a Hyper server and handle requests.
@@ -40,15 +43,94 @@ where
     Ok(())
 }
 
-type WsStream = BufReader<BufWriter<Compat<hyper::upgrade::Upgraded>>>;
+type WsInnerStream = BufReader<BufWriter<Compat<hyper::upgrade::Upgraded>>>;
 pub type WsSender = soketto::connection::Sender<WsStream>;
 pub type WsReceiver = soketto::connection::Receiver<WsStream>;
 
+/// The stream that our websocket connections are built on. This is shared, so that
+/// a [`WsCloser`] can write to it once the [`WsSender`] is finished with.
+#[derive(Clone)]
+pub struct WsStream(Arc<Mutex<WsInnerStream>>);
+
+impl AsyncRead for WsStream {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut [u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let mut inner = self.0.lock().unwrap();
+        Pin::new(&mut *inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for WsStream {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<std::io::Result<usize>> {
+        let mut inner = self.0.lock().unwrap();
+        Pin::new(&mut *inner).poll_write(cx, buf)
+    }
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let mut inner = self.0.lock().unwrap();
+        Pin::new(&mut *inner).poll_flush(cx)
+    }
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<std::io::Result<()>> {
+        let mut inner = self.0.lock().unwrap();
+        Pin::new(&mut *inner).poll_close(cx)
+    }
+}
+
+/// Soketto always closes connections with a "normal closure" code. This allows us to
+/// close a connection with some other code (for instance 1001, "going away") instead.
+/// It should only be used once nothing else is being sent via the [`WsSender`].
+pub struct WsCloser(WsStream);
+
+impl WsCloser {
+    /// Send a close frame with the given code and reason, and then close the connection.
+    /// The reason is truncated to fit into a single control frame if necessary.
+    pub async fn close(mut self, code: u16, reason: &str) -> std::io::Result<()> {
+        // Control frame payloads can be at most 125 bytes; 2 of which are the code.
+        let mut reason_len = usize::min(reason.len(), 123);
+        while !reason.is_char_boundary(reason_len) {
+            reason_len -= 1;
+        }
+        let reason = &reason.as_bytes()[..reason_len];
+
+        // We are always the server, so the frame is not masked:
+        let mut frame = Vec::with_capacity(4 + reason.len());
+        frame.push(0x88); // FIN + Close opcode
+        frame.push(2 + reason.len() as u8);
+        frame.extend_from_slice(&code.to_be_bytes());
+        frame.extend_from_slice(reason);
+
+        self.0.write_all(&frame).await?;
+        self.0.flush().await?;
+        self.0.close().await
+    }
+}
+
 /// A convenience function to upgrade a Hyper request into a Soketto Websocket.
 pub fn upgrade_to_websocket<H, F>(req: Request<Body>, on_upgrade: H) -> hyper::Response<Body>
 where
     H: 'static + Send + FnOnce(WsSender, WsReceiver) -> F,
     F: Send + Future<Output = ()>,
+{
+    upgrade_to_websocket_with_closer(req, move |ws_send, ws_recv, _ws_closer| {
+        on_upgrade(ws_send, ws_recv)
+    })
+}
+
+/// Like [`upgrade_to_websocket`], but the handler is also given a [`WsCloser`], which
+/// can be used to close the connection with a specific close code.
+pub fn upgrade_to_websocket_with_closer<H, F>(
+    req: Request<Body>,
+    on_upgrade: H,
+) -> hyper::Response<Body>
+where
+    H: 'static + Send + FnOnce(WsSender, WsReceiver, WsCloser) -> F,
+    F: Send + Future<Output = ()>,
 {
     if !is_upgrade_request(&req) {
         return basic_response(400, "Expecting WebSocket upgrade headers");
@@ -101,14 +183,17 @@ where
         };
 
         // Start a Soketto server with it:
-        let server =
-            soketto::handshake::Server::new(BufReader::new(BufWriter::new(stream.compat())));
+        let stream = WsStream(Arc::new(Mutex::new(BufReader::new(BufWriter::new(
+            stream.compat(),
+        )))));
+        let closer = WsCloser(stream.clone());
+        let server = soketto::handshake::Server::new(stream);
 
         // Get hold of a way to send and receive messages:
         let (sender, receiver) = server.into_builder().finish();
 
         // Pass these to our when-upgraded handler:
-        on_upgrade(sender, receiver).await;
+        on_upgrade(sender, receiver, closer).await;
     });
 
     response
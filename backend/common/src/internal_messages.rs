@@ -19,7 +19,7 @@
 use std::net::IpAddr;
 
 use crate::id_type;
-use crate::node_message::Payload;
+use crate::node_message::{Payload, SystemIntervalDelta};
 use crate::node_types::{BlockHash, NodeDetails};
 use serde::{Deserialize, Serialize};
 
@@ -48,6 +48,14 @@ pub enum FromShardAggregator {
     },
     /// Inform the telemetry core that a node has been removed
     RemoveNode { local_id: ShardNodeId },
+    /// Only what's changed in a node's periodic stats since the last ones sent for it
+    /// (whether in full via [`FromShardAggregator::UpdateNode`], or as a delta). Cores
+    /// that don't know about this will boot the shard, so shards only send it when
+    /// they're told to.
+    UpdateNodeInterval {
+        local_id: ShardNodeId,
+        delta: SystemIntervalDelta,
+    },
 }
 
 /// Message sent form the telemetry core to a telemetry shard
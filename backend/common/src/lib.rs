@@ -15,6 +15,8 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 pub mod byte_size;
+pub mod compression;
+pub mod feed_client;
 pub mod http_utils;
 pub mod id_type;
 pub mod internal_messages;
@@ -41,6 +41,14 @@ impl<K, V> MultiMapUnique<K, V> {
         self.key_to_values.get(key)
     }
 
+    /// Return the key that a value is associated with, if any.
+    pub fn get_key(&self, value: &V) -> Option<&K>
+    where
+        V: Eq + Hash,
+    {
+        self.value_to_key.get(value)
+    }
+
     /// Remove a value from the MultiMap, returning the key it was found
     /// under, if it was found at all.
     ///
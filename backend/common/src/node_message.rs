@@ -61,6 +61,12 @@ pub enum Payload {
     NotifyFinalized(Finalized),
     AfgAuthoritySet(AfgAuthoritySet),
     HwBench(NodeHwBench),
+    // The GRANDPA messages below are new; cores that don't know about them will
+    // boot the shard, so shards only send them when they're told to.
+    AfgFinalized(AfgFinalized),
+    AfgReceivedPrevote(AfgReceived),
+    AfgReceivedPrecommit(AfgReceived),
+    AfgAuthorities(AfgAuthorities),
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -69,7 +75,7 @@ pub struct SystemConnected {
     pub node: NodeDetails,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
 pub struct SystemInterval {
     pub peers: Option<u64>,
     pub txcount: Option<u64>,
@@ -81,6 +87,56 @@ pub struct SystemInterval {
     pub used_state_cache_size: Option<f32>,
 }
 
+/// The fields of a [`SystemInterval`] that have changed since the last one from the
+/// same node. `None` means that a field is the same as it was last time; `Some` holds
+/// its new value (which may itself be `None`).
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct SystemIntervalDelta {
+    pub peers: Option<Option<u64>>,
+    pub txcount: Option<Option<u64>>,
+    pub bandwidth_upload: Option<Option<f64>>,
+    pub bandwidth_download: Option<Option<f64>>,
+    pub finalized_height: Option<Option<BlockNumber>>,
+    pub finalized_hash: Option<Option<BlockHash>>,
+    pub block: Option<Option<Block>>,
+    pub used_state_cache_size: Option<Option<f32>>,
+}
+
+impl SystemIntervalDelta {
+    /// What's changed going from `prev` to `next`.
+    pub fn between(prev: &SystemInterval, next: &SystemInterval) -> Self {
+        fn changed<T: PartialEq + Copy>(prev: Option<T>, next: Option<T>) -> Option<Option<T>> {
+            (prev != next).then_some(next)
+        }
+        SystemIntervalDelta {
+            peers: changed(prev.peers, next.peers),
+            txcount: changed(prev.txcount, next.txcount),
+            bandwidth_upload: changed(prev.bandwidth_upload, next.bandwidth_upload),
+            bandwidth_download: changed(prev.bandwidth_download, next.bandwidth_download),
+            finalized_height: changed(prev.finalized_height, next.finalized_height),
+            finalized_hash: changed(prev.finalized_hash, next.finalized_hash),
+            block: changed(prev.block, next.block),
+            used_state_cache_size: changed(prev.used_state_cache_size, next.used_state_cache_size),
+        }
+    }
+
+    /// Fill in the fields that haven't changed from `prev`, to get back the whole interval.
+    pub fn apply(self, prev: &SystemInterval) -> SystemInterval {
+        SystemInterval {
+            peers: self.peers.unwrap_or(prev.peers),
+            txcount: self.txcount.unwrap_or(prev.txcount),
+            bandwidth_upload: self.bandwidth_upload.unwrap_or(prev.bandwidth_upload),
+            bandwidth_download: self.bandwidth_download.unwrap_or(prev.bandwidth_download),
+            finalized_height: self.finalized_height.unwrap_or(prev.finalized_height),
+            finalized_hash: self.finalized_hash.unwrap_or(prev.finalized_hash),
+            block: self.block.unwrap_or(prev.block),
+            used_state_cache_size: self
+                .used_state_cache_size
+                .unwrap_or(prev.used_state_cache_size),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Finalized {
     pub hash: BlockHash,
@@ -92,6 +148,30 @@ pub struct AfgAuthoritySet {
     pub authority_id: Box<str>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AfgFinalized {
+    pub hash: BlockHash,
+    pub number: Box<str>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AfgReceived {
+    pub target_hash: BlockHash,
+    pub target_number: Box<str>,
+    pub voter: Option<Box<str>>,
+}
+
+/// An [`AfgAuthoritySet`] along with the rest of the authority set that the node is
+/// working with, as of the block it had finalized.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AfgAuthorities {
+    pub authority_id: Box<str>,
+    pub authorities: Box<str>,
+    pub authority_set_id: Box<str>,
+    pub hash: BlockHash,
+    pub number: Box<str>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct NodeHwBench {
     pub cpu_hashrate_score: u64,
@@ -181,6 +261,50 @@ mod tests {
         });
     }
 
+    #[test]
+    fn bincode_can_serialize_and_deserialize_system_interval_delta() {
+        bincode_can_serialize_and_deserialize(SystemIntervalDelta {
+            peers: Some(None),
+            txcount: Some(Some(1)),
+            ..Default::default()
+        });
+    }
+
+    #[test]
+    fn system_interval_delta_round_trips() {
+        let prev = SystemInterval {
+            peers: Some(10),
+            txcount: Some(2),
+            bandwidth_upload: Some(1.5),
+            finalized_height: Some(100),
+            block: Some(Block {
+                hash: BlockHash::from_low_u64_be(1),
+                height: 102,
+            }),
+            ..Default::default()
+        };
+        let next = SystemInterval {
+            peers: None,
+            txcount: Some(3),
+            block: Some(Block {
+                hash: BlockHash::from_low_u64_be(2),
+                height: 103,
+            }),
+            ..prev.clone()
+        };
+
+        let delta = SystemIntervalDelta::between(&prev, &next);
+        assert_eq!(delta.peers, Some(None));
+        assert_eq!(delta.txcount, Some(Some(3)));
+        assert_eq!(delta.bandwidth_upload, None);
+        assert_eq!(delta.finalized_height, None);
+        assert_eq!(delta.apply(&prev), next);
+
+        // Nothing changed; nothing to send:
+        let delta = SystemIntervalDelta::between(&next, &next);
+        assert_eq!(delta, SystemIntervalDelta::default());
+    }
+
     #[test]
     fn bincode_can_serialize_and_deserialize_node_message_block_import() {
         bincode_can_serialize_and_deserialize(NodeMessage::V1 {
@@ -210,6 +334,30 @@ mod tests {
         });
     }
 
+    #[test]
+    fn bincode_can_serialize_and_deserialize_node_message_afg_received_prevote() {
+        bincode_can_serialize_and_deserialize(NodeMessage::V1 {
+            payload: Payload::AfgReceivedPrevote(AfgReceived {
+                target_hash: BlockHash::zero(),
+                target_number: "foo".into(),
+                voter: Some("bar".into()),
+            }),
+        });
+    }
+
+    #[test]
+    fn bincode_can_serialize_and_deserialize_node_message_afg_authorities() {
+        bincode_can_serialize_and_deserialize(NodeMessage::V1 {
+            payload: Payload::AfgAuthorities(AfgAuthorities {
+                authority_id: "foo".into(),
+                authorities: "[]".into(),
+                authority_set_id: "1".into(),
+                hash: BlockHash::zero(),
+                number: "2".into(),
+            }),
+        });
+    }
+
     #[test]
     fn bincode_block_zero() {
         let raw = Block::zero();
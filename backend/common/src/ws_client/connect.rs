@@ -14,11 +14,15 @@
 // You should have received a copy of the GNU General Public License
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 use super::on_close::OnClose;
-use futures::{channel, StreamExt};
+use super::stats::ConnectionStats;
+use futures::{channel, Stream, StreamExt};
+use soketto::connection::Mode;
 use soketto::handshake::{Client, ServerResponse};
 use std::io;
+use std::pin::Pin;
 use std::sync::Arc;
-use tokio::io::{AsyncRead, AsyncWrite};
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio_rustls::rustls::{OwnedTrustAnchor, ServerName};
 use tokio_rustls::{rustls, TlsConnector};
@@ -27,6 +31,7 @@ use tokio_util::compat::TokioAsyncReadCompatExt;
 use super::{
     receiver::{Receiver, RecvMessage},
     sender::{Sender, SentMessage},
+    stream::{self, ChunkReceiver},
 };
 
 pub trait AsyncReadWrite: AsyncRead + AsyncWrite + Unpin + Send {}
@@ -45,9 +50,16 @@ pub type RawReceiver =
 pub struct Connection {
     tx: RawSender,
     rx: RawReceiver,
+    stats: Arc<ConnectionStats>,
 }
 
 impl Connection {
+    /// The statistics for this connection. These are only kept up to date when
+    /// using the channel based interface.
+    pub fn stats(&self) -> Arc<ConnectionStats> {
+        Arc::clone(&self.stats)
+    }
+
     /// Get hold of the raw send/receive interface for this connection.
     /// These are not cancel-safe, but can be more performant than the
     /// cancel-safe channel based interface.
@@ -68,7 +80,8 @@ impl Connection {
     /// This will panic if not called within the context of a tokio runtime.
     ///
     pub fn into_channels(self) -> (Sender, Receiver) {
-        let (mut ws_to_connection, mut ws_from_connection) = (self.tx, self.rx);
+        let (ws_to_connection, mut ws_from_connection) = (self.tx, self.rx);
+        let (recv_stats, send_stats) = (Arc::clone(&self.stats), Arc::clone(&self.stats));
 
         // Shut everything down when we're told to close, which will be either when
         // we hit an error trying to receive data on the socket, or when both the send
@@ -77,7 +90,7 @@ impl Connection {
         // one way communication).
         let (tx_closed1, mut rx_closed1) = tokio::sync::broadcast::channel::<()>(1);
         let tx_closed2 = tx_closed1.clone();
-        let mut rx_closed2 = tx_closed1.subscribe();
+        let rx_closed2 = tx_closed1.subscribe();
 
         // Receive messages from the socket:
         let (tx_to_external, rx_from_ws) = channel::mpsc::unbounded();
@@ -88,7 +101,7 @@ impl Connection {
 
                 // Wait for messages, or bail entirely if asked to close.
                 let message_data = tokio::select! {
-                    msg_data = ws_from_connection.receive_data(&mut data) => { msg_data },
+                    msg_data = receive_data(&mut ws_from_connection, &mut data, &recv_stats) => { msg_data },
                     _ = rx_closed1.recv() => { break }
                 };
 
@@ -105,6 +118,7 @@ impl Connection {
                     }
                     Ok(data) => data,
                 };
+                recv_stats.record_received(data.len(), message_data.is_text());
 
                 // if we hit an error sending, we keep receiving messages and reacting
                 // to recv issues, but we stop trying to send them anywhere.
@@ -131,78 +145,13 @@ impl Connection {
         });
 
         // Send messages to the socket:
-        let (tx_to_ws, mut rx_from_external) = channel::mpsc::unbounded::<SentMessage>();
-        tokio::spawn(async move {
-            loop {
-                // Wait for messages, or bail entirely if asked to close.
-                let msg = tokio::select! {
-                    msg = rx_from_external.next() => { msg },
-                    _ = rx_closed2.recv() => {
-                        // attempt to gracefully end the connection.
-                        let _ = ws_to_connection.close().await;
-                        break
-                    }
-                };
-
-                // No more messages; channel closed. End this loop. Unlike the recv side which
-                // needs to keep receiving data for the WS connection to stay open, there's no
-                // reason to keep this side of the loop open if our channel is closed.
-                let msg = match msg {
-                    Some(msg) => msg,
-                    None => break,
-                };
-
-                // We don't explicitly shut down the channel if we hit send errors. Why? Because the
-                // receive side of the channel will react to socket errors as well, and close things
-                // down from there.
-                match msg {
-                    SentMessage::Text(s) => {
-                        if let Err(e) = ws_to_connection.send_text_owned(s).await {
-                            log::error!(
-                                "Shutting down websocket connection: Failed to send text data: {}",
-                                e
-                            );
-                            break;
-                        }
-                    }
-                    SentMessage::Binary(bytes) => {
-                        if let Err(e) = ws_to_connection.send_binary_mut(bytes).await {
-                            log::error!(
-                                "Shutting down websocket connection: Failed to send binary data: {}",
-                                e
-                            );
-                            break;
-                        }
-                    }
-                    SentMessage::StaticText(s) => {
-                        if let Err(e) = ws_to_connection.send_text(s).await {
-                            log::error!(
-                                "Shutting down websocket connection: Failed to send text data: {}",
-                                e
-                            );
-                            break;
-                        }
-                    }
-                    SentMessage::StaticBinary(bytes) => {
-                        if let Err(e) = ws_to_connection.send_binary(bytes).await {
-                            log::error!(
-                                "Shutting down websocket connection: Failed to send binary data: {}",
-                                e
-                            );
-                            break;
-                        }
-                    }
-                }
-
-                if let Err(e) = ws_to_connection.flush().await {
-                    log::error!(
-                        "Shutting down websocket connection: Failed to flush data: {}",
-                        e
-                    );
-                    break;
-                }
-            }
-        });
+        let (tx_to_ws, rx_from_external) = channel::mpsc::unbounded::<SentMessage>();
+        tokio::spawn(send_loop(
+            ws_to_connection,
+            rx_from_external.map(Outgoing::Message),
+            rx_closed2,
+            send_stats,
+        ));
 
         // Keep track of whether one of sender or received have
         // been dropped. If both have, we close the socket connection.
@@ -212,15 +161,139 @@ impl Connection {
             Sender {
                 inner: tx_to_ws,
                 closer: Arc::clone(&on_close),
+                stats: Arc::clone(&self.stats),
             },
             Receiver {
                 inner: rx_from_ws,
                 closer: on_close,
+                stats: self.stats,
             },
         )
     }
 }
 
+/// Something to write to the socket: a message that we've been handed to send, or
+/// a reply to a ping that we've received.
+pub(super) enum Outgoing {
+    Message(SentMessage),
+    Pong(Vec<u8>),
+}
+
+/// Write whatever comes out of `outgoing` to the socket, until it ends or we're told to close.
+pub(super) async fn send_loop<T, S>(
+    mut ws_to_connection: soketto::connection::Sender<T>,
+    mut outgoing: S,
+    mut rx_closed: tokio::sync::broadcast::Receiver<()>,
+    stats: Arc<ConnectionStats>,
+) where
+    T: futures::AsyncRead + futures::AsyncWrite + Unpin,
+    S: Stream<Item = Outgoing> + Unpin,
+{
+    loop {
+        // Wait for messages, or bail entirely if asked to close.
+        let msg = tokio::select! {
+            msg = outgoing.next() => { msg },
+            _ = rx_closed.recv() => {
+                // attempt to gracefully end the connection.
+                let _ = ws_to_connection.close().await;
+                break
+            }
+        };
+
+        // No more messages; channel closed. End this loop. Unlike the recv side which
+        // needs to keep receiving data for the WS connection to stay open, there's no
+        // reason to keep this side of the loop open if our channel is closed.
+        let msg = match msg {
+            Some(Outgoing::Message(msg)) => msg,
+            Some(Outgoing::Pong(payload)) => {
+                let Ok(payload) = (&payload[..]).try_into() else {
+                    continue;
+                };
+                if let Err(e) = ws_to_connection.send_pong(payload).await {
+                    log::error!(
+                        "Shutting down websocket connection: Failed to send pong: {}",
+                        e
+                    );
+                    break;
+                }
+                if let Err(e) = ws_to_connection.flush().await {
+                    log::error!(
+                        "Shutting down websocket connection: Failed to flush data: {}",
+                        e
+                    );
+                    break;
+                }
+                continue;
+            }
+            None => break,
+        };
+
+        // We don't explicitly shut down the channel if we hit send errors. Why? Because the
+        // receive side of the channel will react to socket errors as well, and close things
+        // down from there.
+        if !matches!(msg, SentMessage::Ping) {
+            stats.record_sent(msg.len(), msg.is_text());
+        }
+        match msg {
+            SentMessage::Ping => {
+                let payload = stats.record_ping();
+                let payload = (&payload[..]).try_into().expect("ping payload fits");
+                if let Err(e) = ws_to_connection.send_ping(payload).await {
+                    log::error!(
+                        "Shutting down websocket connection: Failed to send ping: {}",
+                        e
+                    );
+                    break;
+                }
+            }
+            SentMessage::Text(s) => {
+                if let Err(e) = ws_to_connection.send_text_owned(s).await {
+                    log::error!(
+                        "Shutting down websocket connection: Failed to send text data: {}",
+                        e
+                    );
+                    break;
+                }
+            }
+            SentMessage::Binary(bytes) => {
+                if let Err(e) = ws_to_connection.send_binary_mut(bytes).await {
+                    log::error!(
+                        "Shutting down websocket connection: Failed to send binary data: {}",
+                        e
+                    );
+                    break;
+                }
+            }
+            SentMessage::StaticText(s) => {
+                if let Err(e) = ws_to_connection.send_text(s).await {
+                    log::error!(
+                        "Shutting down websocket connection: Failed to send text data: {}",
+                        e
+                    );
+                    break;
+                }
+            }
+            SentMessage::StaticBinary(bytes) => {
+                if let Err(e) = ws_to_connection.send_binary(bytes).await {
+                    log::error!(
+                        "Shutting down websocket connection: Failed to send binary data: {}",
+                        e
+                    );
+                    break;
+                }
+            }
+        }
+
+        if let Err(e) = ws_to_connection.flush().await {
+            log::error!(
+                "Shutting down websocket connection: Failed to flush data: {}",
+                e
+            );
+            break;
+        }
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 pub enum ConnectError {
     #[error("IO error: {0}")]
@@ -233,15 +306,146 @@ pub enum ConnectError {
     ConnectionFailedRejected { status_code: u16 },
 }
 
+/// Receive the next text or binary message, noting any pongs that arrive in the meantime.
+async fn receive_data(
+    ws_from_connection: &mut RawReceiver,
+    data: &mut Vec<u8>,
+    stats: &ConnectionStats,
+) -> Result<soketto::Data, soketto::connection::Error> {
+    loop {
+        match ws_from_connection.receive(data).await? {
+            soketto::Incoming::Data(d) => return Ok(d),
+            soketto::Incoming::Pong(payload) => stats.record_pong(payload),
+            soketto::Incoming::Closed(_) => {}
+        }
+    }
+}
+
 /// Establish a websocket connection that you can send and receive messages from.
 pub async fn connect(uri: &http::Uri) -> Result<Connection, ConnectError> {
+    connect_with_stats(uri, ConnectionStats::new()).await
+}
+
+/// Establish a websocket connection, recording its traffic in the given stats. Pass
+/// the same stats each time a client reconnects to keep track of its traffic overall.
+pub async fn connect_with_stats(
+    uri: &http::Uri,
+    stats: Arc<ConnectionStats>,
+) -> Result<Connection, ConnectError> {
+    let (ws_to_connection, ws_from_connection) = handshake(uri).await?.into_builder().finish();
+    stats.record_connected();
+    Ok(Connection {
+        tx: ws_to_connection,
+        rx: ws_from_connection,
+        stats,
+    })
+}
+
+/// Establish a websocket connection whose incoming messages are handed out a chunk at a
+/// time as they arrive, rather than once each one has been received in full. Like
+/// [`connect_with_stats`], its traffic is recorded in the given stats.
+///
+/// # Panics
+///
+/// This will panic if not called within the context of a tokio runtime.
+pub async fn connect_streaming(
+    uri: &http::Uri,
+    stats: Arc<ConnectionStats>,
+) -> Result<(Sender, ChunkReceiver), ConnectError> {
+    let mut client = handshake(uri).await?;
+    // Anything that arrived along with the end of the handshake response:
+    let buffered = client.take_buffer();
+    let (socket_reader, socket_writer) = tokio::io::split(client.into_inner().into_inner());
+
+    // Soketto looks after writing frames for us (masking them and so on), while we
+    // read them ourselves, so that it never has to hold a whole message.
+    let (ws_to_connection, _) =
+        soketto::connection::Builder::new(WriteOnly(socket_writer).compat(), Mode::Client).finish();
+    stats.record_connected();
+
+    let (tx_closed1, rx_closed1) = tokio::sync::broadcast::channel::<()>(1);
+    let tx_closed2 = tx_closed1.clone();
+    let rx_closed2 = tx_closed1.subscribe();
+
+    let (tx_pongs, rx_pongs) = channel::mpsc::unbounded();
+    let inner = stream::spawn_reader(
+        std::io::Cursor::new(buffered.to_vec()).chain(socket_reader),
+        tx_pongs,
+        tx_closed1,
+        rx_closed1,
+        Arc::clone(&stats),
+    );
+
+    let (tx_to_ws, rx_from_external) = channel::mpsc::unbounded::<SentMessage>();
+    let outgoing = futures::stream::select(
+        rx_from_external.map(Outgoing::Message),
+        rx_pongs.map(Outgoing::Pong),
+    );
+    tokio::spawn(send_loop(
+        ws_to_connection,
+        outgoing,
+        rx_closed2,
+        Arc::clone(&stats),
+    ));
+
+    let on_close = Arc::new(OnClose(tx_closed2));
+    Ok((
+        Sender {
+            inner: tx_to_ws,
+            closer: Arc::clone(&on_close),
+            stats: Arc::clone(&stats),
+        },
+        ChunkReceiver {
+            inner,
+            closer: on_close,
+            stats,
+        },
+    ))
+}
+
+/// The write half of a socket, for soketto to send frames on. Nothing is ever read
+/// from it.
+struct WriteOnly(tokio::io::WriteHalf<Box<dyn AsyncReadWrite>>);
+
+impl AsyncRead for WriteOnly {
+    fn poll_read(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        _buf: &mut tokio::io::ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Poll::Pending
+    }
+}
+
+impl AsyncWrite for WriteOnly {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.0).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.0).poll_shutdown(cx)
+    }
+}
+
+/// Open a socket to the given URI and perform the websocket handshake over it.
+async fn handshake(
+    uri: &http::Uri,
+) -> Result<Client<'_, tokio_util::compat::Compat<Box<dyn AsyncReadWrite>>>, ConnectError> {
     let host = uri.host().unwrap_or("127.0.0.1");
     let scheme = uri.scheme_str().unwrap_or("ws");
     let mut port = 80;
     if scheme == "https" || scheme == "wss" {
         port = 443
     }
-    let path = uri.path();
+    let path = uri.path_and_query().map(|p| p.as_str()).unwrap_or("/");
     let port = uri.port_u16().unwrap_or(port);
     let socket = TcpStream::connect((host, port)).await?;
     socket.set_nodelay(true).expect("socket set_nodelay failed");
@@ -249,21 +453,16 @@ pub async fn connect(uri: &http::Uri) -> Result<Connection, ConnectError> {
     let socket = may_connect_tls(socket, host, scheme == "https" || scheme == "wss").await?;
 
     // Establish a WS connection:
-    let mut client = Client::new(socket.compat(), host, &path);
-    let (ws_to_connection, ws_from_connection) = match client.handshake().await? {
-        ServerResponse::Accepted { .. } => client.into_builder().finish(),
+    let mut client = Client::new(socket.compat(), host, path);
+    match client.handshake().await? {
+        ServerResponse::Accepted { .. } => Ok(client),
         ServerResponse::Redirect { status_code, .. } => {
-            return Err(ConnectError::ConnectionFailedRedirect { status_code })
+            Err(ConnectError::ConnectionFailedRedirect { status_code })
         }
         ServerResponse::Rejected { status_code } => {
-            return Err(ConnectError::ConnectionFailedRejected { status_code })
+            Err(ConnectError::ConnectionFailedRejected { status_code })
         }
-    };
-
-    Ok(Connection {
-        tx: ws_to_connection,
-        rx: ws_from_connection,
-    })
+    }
 }
 
 async fn may_connect_tls(
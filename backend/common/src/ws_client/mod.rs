@@ -22,7 +22,16 @@ mod on_close;
 mod receiver;
 /// The channel based send interface
 mod sender;
+/// Traffic statistics for a connection
+mod stats;
+/// The streaming receive interface, for messages too large to hold in full
+mod stream;
 
-pub use connect::{connect, ConnectError, Connection, RawReceiver, RawSender};
+pub use connect::{
+    connect, connect_streaming, connect_with_stats, ConnectError, Connection, RawReceiver,
+    RawSender,
+};
 pub use receiver::{Receiver, RecvError, RecvMessage};
 pub use sender::{SendError, Sender, SentMessage};
+pub use stats::{ConnectionStats, StatsSnapshot};
+pub use stream::{ChunkReceiver, RecvChunk, CHUNK_BYTES};
@@ -15,6 +15,7 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use super::on_close::OnClose;
+use super::stats::{ConnectionStats, StatsSnapshot};
 use futures::{channel, Stream, StreamExt};
 use std::sync::Arc;
 
@@ -22,6 +23,7 @@ use std::sync::Arc;
 pub struct Receiver {
     pub(super) inner: channel::mpsc::UnboundedReceiver<Result<RecvMessage, RecvError>>,
     pub(super) closer: Arc<OnClose>,
+    pub(super) stats: Arc<ConnectionStats>,
 }
 
 #[derive(thiserror::Error, Debug)]
@@ -40,6 +42,10 @@ impl Receiver {
         self.closer.0.send(()).map_err(|_| RecvError::CloseError)?;
         Ok(())
     }
+    /// The traffic statistics for this connection so far.
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
 }
 
 impl Stream for Receiver {
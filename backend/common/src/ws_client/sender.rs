@@ -15,6 +15,7 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use super::on_close::OnClose;
+use super::stats::{ConnectionStats, StatsSnapshot};
 use futures::channel;
 use std::sync::Arc;
 
@@ -35,6 +36,25 @@ pub enum SentMessage {
     Text(String),
     /// Send owned bytes into the socket.
     Binary(Vec<u8>),
+    /// Send a ping. The round trip time is recorded in the connection's stats
+    /// once the pong comes back.
+    Ping,
+}
+
+impl SentMessage {
+    /// The number of payload bytes in this message.
+    pub(super) fn len(&self) -> usize {
+        match self {
+            SentMessage::StaticText(s) => s.len(),
+            SentMessage::StaticBinary(b) => b.len(),
+            SentMessage::Text(s) => s.len(),
+            SentMessage::Binary(b) => b.len(),
+            SentMessage::Ping => 0,
+        }
+    }
+    pub(super) fn is_text(&self) -> bool {
+        matches!(self, SentMessage::StaticText(_) | SentMessage::Text(_))
+    }
 }
 
 /// Send messages into the connection
@@ -42,6 +62,7 @@ pub enum SentMessage {
 pub struct Sender {
     pub(super) inner: channel::mpsc::UnboundedSender<SentMessage>,
     pub(super) closer: Arc<OnClose>,
+    pub(super) stats: Arc<ConnectionStats>,
 }
 
 impl Sender {
@@ -62,6 +83,10 @@ impl Sender {
             .map_err(|e| e.into_send_error())?;
         Ok(())
     }
+    /// The traffic statistics for this connection so far.
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
     /// Convert this sender into a Sink
     pub fn into_sink(
         self,
@@ -0,0 +1,199 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Counters describing the traffic over a connection. These can be shared between
+/// successive connections (see [`super::connect_with_stats`]) so that they describe
+/// a client's traffic across reconnects.
+#[derive(Debug)]
+pub struct ConnectionStats {
+    /// Ping payloads are the time since this, so that we can work out round trip times
+    /// from the matching pongs without keeping track of outstanding pings.
+    started: Instant,
+    connections: AtomicU64,
+    bytes_received: AtomicU64,
+    bytes_sent: AtomicU64,
+    text_received: AtomicU64,
+    binary_received: AtomicU64,
+    text_sent: AtomicU64,
+    binary_sent: AtomicU64,
+    pings_sent: AtomicU64,
+    pongs_received: AtomicU64,
+    /// The most recent ping round trip time in microseconds, or `u64::MAX` if none.
+    last_ping_rtt_micros: AtomicU64,
+}
+
+/// A copy of the [`ConnectionStats`] at some point in time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct StatsSnapshot {
+    /// How many times a connection has been established.
+    pub connections: u64,
+    /// How many of those connections replaced an earlier one.
+    pub reconnects: u64,
+    /// Payload bytes received, not counting WebSocket framing.
+    pub bytes_received: u64,
+    /// Payload bytes sent, not counting WebSocket framing.
+    pub bytes_sent: u64,
+    /// Messages received. A message may have been split across several frames.
+    pub messages_received: u64,
+    /// Messages sent.
+    pub messages_sent: u64,
+    pub text_received: u64,
+    pub binary_received: u64,
+    pub text_sent: u64,
+    pub binary_sent: u64,
+    pub pings_sent: u64,
+    pub pongs_received: u64,
+    /// The round trip time of the most recently answered ping.
+    pub ping_rtt: Option<Duration>,
+}
+
+impl ConnectionStats {
+    pub fn new() -> Arc<ConnectionStats> {
+        Arc::new(ConnectionStats {
+            started: Instant::now(),
+            connections: AtomicU64::new(0),
+            bytes_received: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            text_received: AtomicU64::new(0),
+            binary_received: AtomicU64::new(0),
+            text_sent: AtomicU64::new(0),
+            binary_sent: AtomicU64::new(0),
+            pings_sent: AtomicU64::new(0),
+            pongs_received: AtomicU64::new(0),
+            last_ping_rtt_micros: AtomicU64::new(u64::MAX),
+        })
+    }
+
+    /// Take a copy of the current values.
+    pub fn snapshot(&self) -> StatsSnapshot {
+        let get = |n: &AtomicU64| n.load(Ordering::Relaxed);
+        let connections = get(&self.connections);
+        let text_received = get(&self.text_received);
+        let binary_received = get(&self.binary_received);
+        let text_sent = get(&self.text_sent);
+        let binary_sent = get(&self.binary_sent);
+        let ping_rtt = match get(&self.last_ping_rtt_micros) {
+            u64::MAX => None,
+            micros => Some(Duration::from_micros(micros)),
+        };
+        StatsSnapshot {
+            connections,
+            reconnects: connections.saturating_sub(1),
+            bytes_received: get(&self.bytes_received),
+            bytes_sent: get(&self.bytes_sent),
+            messages_received: text_received + binary_received,
+            messages_sent: text_sent + binary_sent,
+            text_received,
+            binary_received,
+            text_sent,
+            binary_sent,
+            pings_sent: get(&self.pings_sent),
+            pongs_received: get(&self.pongs_received),
+            ping_rtt,
+        }
+    }
+
+    pub(super) fn record_connected(&self) {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn record_received(&self, len: usize, is_text: bool) {
+        self.bytes_received.fetch_add(len as u64, Ordering::Relaxed);
+        let count = if is_text {
+            &self.text_received
+        } else {
+            &self.binary_received
+        };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(super) fn record_sent(&self, len: usize, is_text: bool) {
+        self.bytes_sent.fetch_add(len as u64, Ordering::Relaxed);
+        let count = if is_text {
+            &self.text_sent
+        } else {
+            &self.binary_sent
+        };
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a ping is being sent, returning the payload to send with it.
+    pub(super) fn record_ping(&self) -> [u8; 8] {
+        self.pings_sent.fetch_add(1, Ordering::Relaxed);
+        (self.started.elapsed().as_micros() as u64).to_le_bytes()
+    }
+
+    /// Record a pong, working out the round trip time from its payload. Pongs that
+    /// weren't sent in reply to one of our pings are counted but otherwise ignored.
+    pub(super) fn record_pong(&self, payload: &[u8]) {
+        self.pongs_received.fetch_add(1, Ordering::Relaxed);
+        let sent_at = match <[u8; 8]>::try_from(payload) {
+            Ok(bytes) => u64::from_le_bytes(bytes),
+            Err(_) => return,
+        };
+        let now = self.started.elapsed().as_micros() as u64;
+        if let Some(rtt) = now.checked_sub(sent_at) {
+            self.last_ping_rtt_micros.store(rtt, Ordering::Relaxed);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn snapshot_adds_up_counts() {
+        let stats = ConnectionStats::new();
+        stats.record_connected();
+        stats.record_connected();
+        stats.record_received(10, true);
+        stats.record_received(5, false);
+        stats.record_received(1, false);
+        stats.record_sent(3, true);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.connections, 2);
+        assert_eq!(snapshot.reconnects, 1);
+        assert_eq!(snapshot.bytes_received, 16);
+        assert_eq!(snapshot.messages_received, 3);
+        assert_eq!(snapshot.text_received, 1);
+        assert_eq!(snapshot.binary_received, 2);
+        assert_eq!(snapshot.bytes_sent, 3);
+        assert_eq!(snapshot.messages_sent, 1);
+        assert_eq!(snapshot.ping_rtt, None);
+    }
+
+    #[test]
+    fn pong_gives_round_trip_time() {
+        let stats = ConnectionStats::new();
+        let payload = stats.record_ping();
+        std::thread::sleep(Duration::from_millis(5));
+        stats.record_pong(b"not ours");
+        assert_eq!(stats.snapshot().ping_rtt, None);
+        stats.record_pong(&payload);
+
+        let snapshot = stats.snapshot();
+        assert_eq!(snapshot.pings_sent, 1);
+        assert_eq!(snapshot.pongs_received, 2);
+        assert!(snapshot.ping_rtt.unwrap() >= Duration::from_millis(5));
+    }
+}
@@ -0,0 +1,311 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+use super::on_close::OnClose;
+use super::stats::{ConnectionStats, StatsSnapshot};
+use futures::{channel, SinkExt, Stream, StreamExt};
+use soketto::base::{Codec, Header, OpCode};
+use soketto::Parsing;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncReadExt, BufReader};
+use tokio::sync::broadcast;
+
+/// The most of a message that's handed out at once.
+pub const CHUNK_BYTES: usize = 64 * 1024;
+
+/// How many chunks can be waiting to be received before we stop reading from the
+/// socket, so that a consumer that's slow doesn't end up with the whole message
+/// buffered up anyway.
+const QUEUE_CHUNKS: usize = 16;
+
+/// Receive messages out of a connection a chunk at a time, as they arrive. See
+/// [`super::connect_streaming`].
+pub struct ChunkReceiver {
+    pub(super) inner: channel::mpsc::Receiver<RecvChunk>,
+    pub(super) closer: Arc<OnClose>,
+    pub(super) stats: Arc<ConnectionStats>,
+}
+
+impl ChunkReceiver {
+    /// Ask the underlying Websocket connection to close.
+    pub async fn close(&mut self) -> Result<(), super::RecvError> {
+        self.closer
+            .0
+            .send(())
+            .map_err(|_| super::RecvError::CloseError)?;
+        Ok(())
+    }
+    /// The traffic statistics for this connection so far. Messages are counted once
+    /// they've been received in full.
+    pub fn stats(&self) -> StatsSnapshot {
+        self.stats.snapshot()
+    }
+}
+
+impl Stream for ChunkReceiver {
+    type Item = RecvChunk;
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Self::Item>> {
+        self.inner.poll_next_unpin(cx)
+    }
+}
+
+/// A piece of an incoming message, of at most [`CHUNK_BYTES`]. The chunks of each
+/// message arrive in order, one message after another. If the connection goes away
+/// part way through a message, the stream ends without its last chunk.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RecvChunk {
+    /// Text messages are handed out as bytes too, since a chunk can end part way
+    /// through a character. It's up to the consumer to check that they're UTF8.
+    pub data: Vec<u8>,
+    pub is_text: bool,
+    /// Whether this is the first chunk of a message.
+    pub is_first: bool,
+    /// Whether this is the last chunk of a message.
+    pub is_last: bool,
+}
+
+/// Read frames from the socket until it closes, or until we're told to close, handing
+/// out their payloads as chunks, pings to be answered through `pongs`.
+pub(super) fn spawn_reader<R>(
+    reader: R,
+    pongs: channel::mpsc::UnboundedSender<Vec<u8>>,
+    tx_closed: broadcast::Sender<()>,
+    mut rx_closed: broadcast::Receiver<()>,
+    stats: Arc<ConnectionStats>,
+) -> channel::mpsc::Receiver<RecvChunk>
+where
+    R: AsyncRead + Unpin + Send + 'static,
+{
+    let (tx_to_external, rx_from_ws) = channel::mpsc::channel(QUEUE_CHUNKS);
+    tokio::spawn(async move {
+        let reader = BufReader::with_capacity(CHUNK_BYTES, reader);
+        let result = tokio::select! {
+            result = read_frames(reader, tx_to_external, pongs, &stats) => result,
+            _ = rx_closed.recv() => Ok(()),
+        };
+        if let Err(e) = result {
+            log::error!(
+                "Shutting down websocket connection: Failed to receive data: {}",
+                e
+            );
+        }
+        let _ = tx_closed.send(());
+    });
+    rx_from_ws
+}
+
+async fn read_frames<R: AsyncRead + Unpin>(
+    mut reader: R,
+    mut tx_to_external: channel::mpsc::Sender<RecvChunk>,
+    pongs: channel::mpsc::UnboundedSender<Vec<u8>>,
+    stats: &ConnectionStats,
+) -> anyhow::Result<()> {
+    let mut codec = Codec::new();
+    // Messages are never held in full, so frames can be as large as they like.
+    codec.set_max_data_size(usize::MAX);
+
+    let mut send_to_external = true;
+    // Whether the message we're part way through is text, and how long it is so far.
+    let mut message: Option<(bool, usize)> = None;
+    loop {
+        let header = read_header(&mut reader, &codec).await?;
+        let len = header.payload_len();
+
+        if header.opcode().is_control() {
+            let mut payload = vec![0; len];
+            reader.read_exact(&mut payload).await?;
+            unmask(&header, 0, &mut payload);
+            match header.opcode() {
+                OpCode::Ping => {
+                    let _ = pongs.unbounded_send(payload);
+                }
+                OpCode::Pong => stats.record_pong(&payload),
+                _ => return Ok(()),
+            }
+            continue;
+        }
+
+        let (is_text, mut message_len) = match (header.opcode(), message) {
+            (OpCode::Continue, Some(message)) => message,
+            (OpCode::Text, None) => (true, 0),
+            (OpCode::Binary, None) => (false, 0),
+            (opcode, _) => anyhow::bail!("unexpected {} frame", opcode),
+        };
+
+        // Every frame is handed out as at least one chunk, even if it's empty, so
+        // that there's always a chunk to say when a message ends.
+        let mut is_first = message.is_none();
+        let mut offset = 0;
+        loop {
+            let mut data = vec![0; (len - offset).min(CHUNK_BYTES)];
+            reader.read_exact(&mut data).await?;
+            unmask(&header, offset, &mut data);
+            offset += data.len();
+            message_len += data.len();
+
+            let chunk = RecvChunk {
+                data,
+                is_text,
+                is_first,
+                is_last: header.is_fin() && offset == len,
+            };
+            is_first = false;
+            // As with the channel interface, keep reading if nobody's listening, so
+            // that the connection carries on working for sending.
+            if send_to_external && tx_to_external.send(chunk).await.is_err() {
+                log::warn!("Failed to send data out: receiver dropped");
+                send_to_external = false;
+            }
+            if offset == len {
+                break;
+            }
+        }
+
+        message = if header.is_fin() {
+            stats.record_received(message_len, is_text);
+            None
+        } else {
+            Some((is_text, message_len))
+        };
+    }
+}
+
+async fn read_header<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    codec: &Codec,
+) -> anyhow::Result<Header> {
+    let mut bytes = Vec::with_capacity(14);
+    loop {
+        match codec.decode_header(&bytes)? {
+            Parsing::Done { value, .. } => return Ok(value),
+            Parsing::NeedMore(more) => {
+                let start = bytes.len();
+                bytes.resize(start + more, 0);
+                reader.read_exact(&mut bytes[start..]).await?;
+            }
+        }
+    }
+}
+
+/// Unmask part of a frame's payload, starting `offset` bytes in. Servers aren't meant
+/// to mask frames, but nothing stops them.
+fn unmask(header: &Header, offset: usize, data: &mut [u8]) {
+    if !header.is_masked() {
+        return;
+    }
+    let mask = header.mask().to_be_bytes();
+    for (i, byte) in data.iter_mut().enumerate() {
+        *byte ^= mask[(offset + i) % 4];
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use soketto::handshake::{server::Response, Server};
+    use tokio::net::TcpListener;
+    use tokio_util::compat::TokioAsyncReadCompatExt;
+
+    #[tokio::test]
+    async fn large_messages_arrive_in_chunks() {
+        let big: Vec<u8> = (0..CHUNK_BYTES * 5 + 10).map(|n| n as u8).collect();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let server_big = big.clone();
+        let server = tokio::spawn(async move {
+            let (socket, _) = listener.accept().await.unwrap();
+            let mut server = Server::new(socket.compat());
+            let key = server.receive_request().await.unwrap().key();
+            server
+                .send_response(&Response::Accept {
+                    key,
+                    protocol: None,
+                })
+                .await
+                .unwrap();
+            let (mut tx, mut rx) = server.into_builder().finish();
+            tx.send_ping((&b"hello"[..]).try_into().unwrap())
+                .await
+                .unwrap();
+            tx.send_binary(&server_big).await.unwrap();
+            tx.send_text("small").await.unwrap();
+            tx.flush().await.unwrap();
+
+            // We should hear back about the ping, and then get a message:
+            let mut data = Vec::new();
+            match rx.receive(&mut data).await.unwrap() {
+                soketto::Incoming::Pong(payload) => assert_eq!(payload, b"hello"),
+                other => panic!("expected a pong, got {:?}", other),
+            }
+            rx.receive_data(&mut data).await.unwrap();
+            assert_eq!(data, b"thanks");
+        });
+
+        let uri = format!("ws://{}/", addr).parse().unwrap();
+        let (tx, mut rx) = super::super::connect_streaming(&uri, ConnectionStats::new())
+            .await
+            .unwrap();
+
+        let mut chunks = vec![];
+        while chunks.len() < 7 {
+            chunks.push(rx.next().await.unwrap());
+        }
+        let (big_chunks, small) = chunks.split_at(6);
+        assert!(big_chunks
+            .iter()
+            .all(|c| c.data.len() <= CHUNK_BYTES && !c.is_text));
+        assert!(big_chunks[0].is_first && !big_chunks[0].is_last);
+        assert!(big_chunks[5].is_last);
+        let received: Vec<u8> = big_chunks.iter().flat_map(|c| c.data.clone()).collect();
+        assert_eq!(received, big);
+        assert_eq!(
+            small[0],
+            RecvChunk {
+                data: b"small".to_vec(),
+                is_text: true,
+                is_first: true,
+                is_last: true,
+            }
+        );
+
+        tx.unbounded_send(super::super::SentMessage::StaticText("thanks"))
+            .unwrap();
+        server.await.unwrap();
+
+        let stats = rx.stats();
+        assert_eq!(stats.binary_received, 1);
+        assert_eq!(stats.text_received, 1);
+        assert_eq!(stats.bytes_received, big.len() as u64 + 5);
+    }
+
+    #[test]
+    fn unmasking_carries_on_from_an_offset() {
+        let mut header = Header::new(OpCode::Binary);
+        header.set_masked(true);
+        header.set_mask(0x01020304);
+        let mut whole = vec![0u8; 10];
+        unmask(&header, 0, &mut whole);
+        let mut parts = vec![0u8; 10];
+        let (start, end) = parts.split_at_mut(3);
+        unmask(&header, 0, start);
+        unmask(&header, 3, end);
+        assert_eq!(whole, parts);
+    }
+}
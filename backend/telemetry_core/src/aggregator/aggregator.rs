@@ -17,7 +17,7 @@
 use super::inner_loop;
 use crate::find_location::find_location;
 use crate::state::NodeId;
-use common::id_type;
+use common::{id_type, node_types::BlockHash};
 use futures::{future, Sink, SinkExt};
 use std::net::IpAddr;
 use std::sync::atomic::AtomicU64;
@@ -114,6 +114,20 @@ impl Aggregator {
         Ok(metrics)
     }
 
+    /// Ask our aggregator loop for a JSON snapshot of a chain.
+    pub async fn gather_snapshot(
+        &self,
+        genesis_hash: BlockHash,
+    ) -> anyhow::Result<Option<bytes::Bytes>> {
+        let (tx, rx) = flume::unbounded();
+        let msg = inner_loop::ToAggregator::GatherSnapshot(genesis_hash, tx);
+
+        self.0.tx_to_aggregator.send_async(msg).await?;
+
+        let snapshot = rx.recv_async().await?;
+        Ok(snapshot)
+    }
+
     /// Return a sink that a shard can send messages into to be handled by the aggregator.
     pub fn subscribe_shard(
         &self,
@@ -1,6 +1,6 @@
 use super::aggregator::{Aggregator, AggregatorOpts};
 use super::inner_loop;
-use common::EitherSink;
+use common::{node_types::BlockHash, EitherSink};
 use futures::{Sink, SinkExt};
 use inner_loop::{FromShardWebsocket, Metrics};
 use std::sync::atomic::{AtomicUsize, Ordering};
@@ -82,6 +82,13 @@ impl AggregatorSet {
         self.0.metrics.lock().unwrap().clone()
     }
 
+    /// Return a JSON snapshot of the chain with the given genesis hash, or `None` if no such
+    /// chain exists. Every aggregator is sent all shard messages and so holds the entire node
+    /// state; we just ask the first one.
+    pub async fn snapshot(&self, genesis_hash: BlockHash) -> anyhow::Result<Option<bytes::Bytes>> {
+        self.0.aggregators[0].gather_snapshot(genesis_hash).await
+    }
+
     /// Return a sink that a shard can send messages into to be handled by all aggregators.
     pub fn subscribe_shard(
         &self,
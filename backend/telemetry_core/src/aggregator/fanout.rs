@@ -0,0 +1,349 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Sending chain updates out to every feed subscribed to a chain is done by a task per
+//! chain, rather than by the aggregator loop itself, so that a chain with a great many
+//! subscribers doesn't hold up updates for every other chain. Each feed has its own
+//! queue; a feed whose queue fills up is disconnected, rather than being allowed to
+//! hold anyone else up or consume ever more memory.
+
+use super::aggregator::ConnId;
+use super::inner_loop::ToFeedWebsocket;
+use common::node_types::BlockHash;
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+/// The queue of messages waiting to be sent to a single feed. This is shared between
+/// the aggregator loop, which sends the feed replies to its own commands, and the
+/// fan-out task for the chain that the feed is subscribed to.
+pub struct FeedQueue {
+    inner: Mutex<FeedQueueInner>,
+    /// Counts every feed disconnected for falling behind.
+    closed_feeds: Arc<AtomicU64>,
+}
+
+struct FeedQueueInner {
+    /// `None` once the feed has been disconnected for falling behind.
+    channel: Option<flume::Sender<ToFeedWebsocket>>,
+    /// Bumped every time the feed subscribes to a chain, so that messages meant for an
+    /// earlier subscription aren't sent after the reply to a later one.
+    subscription: u64,
+}
+
+impl FeedQueue {
+    pub fn new(channel: flume::Sender<ToFeedWebsocket>, closed_feeds: Arc<AtomicU64>) -> Self {
+        FeedQueue {
+            inner: Mutex::new(FeedQueueInner {
+                channel: Some(channel),
+                subscription: 0,
+            }),
+            closed_feeds,
+        }
+    }
+
+    /// Start a new subscription, returning its number. Nothing sent for an earlier
+    /// subscription will be queued after this returns.
+    pub fn subscribe(&self) -> u64 {
+        let mut inner = self.inner.lock();
+        inner.subscription += 1;
+        inner.subscription
+    }
+
+    /// Queue a message for the feed, whatever it's subscribed to. Returns false if the
+    /// feed has been disconnected.
+    pub fn send(&self, message: ToFeedWebsocket) -> bool {
+        self.try_send(&mut self.inner.lock(), message)
+    }
+
+    /// Queue a message for the feed if it's still on the given subscription. Returns
+    /// false if it isn't, or if the feed has been disconnected.
+    pub fn send_to_subscription(&self, subscription: u64, message: ToFeedWebsocket) -> bool {
+        let mut inner = self.inner.lock();
+        inner.subscription == subscription && self.try_send(&mut inner, message)
+    }
+
+    fn try_send(&self, inner: &mut FeedQueueInner, message: ToFeedWebsocket) -> bool {
+        let channel = match &inner.channel {
+            Some(channel) => channel,
+            None => return false,
+        };
+        match channel.try_send(message) {
+            Ok(()) => true,
+            Err(flume::TrySendError::Full(_)) => {
+                // Dropping our end ends the feed's stream of messages, which closes it:
+                log::debug!("Disconnecting a feed that's too far behind to catch up");
+                self.closed_feeds.fetch_add(1, Ordering::Relaxed);
+                inner.channel = None;
+                false
+            }
+            Err(flume::TrySendError::Disconnected(_)) => {
+                inner.channel = None;
+                false
+            }
+        }
+    }
+
+    /// Whether the feed has been disconnected, so that nothing more can be sent to it.
+    pub fn is_disconnected(&self) -> bool {
+        self.inner.lock().channel.is_none()
+    }
+
+    /// How many messages are waiting to be sent to the feed.
+    pub fn queued_messages(&self) -> usize {
+        self.inner.lock().channel.as_ref().map_or(0, |c| c.len())
+    }
+}
+
+/// What the aggregator loop tells a chain's fan-out task.
+enum FanoutMessage {
+    /// Send future messages to this feed, for as long as it's on this subscription.
+    Add(ConnId, Arc<FeedQueue>, u64),
+    Remove(ConnId),
+    /// Whether to send this feed GRANDPA messages, until it's removed.
+    SetFinality(ConnId, bool),
+    Broadcast(ToFeedWebsocket),
+    /// A message for just the feeds that have asked for GRANDPA messages.
+    BroadcastFinality(ToFeedWebsocket),
+    /// A message for every feed, which is sent whatever the feed is now subscribed to.
+    BroadcastToAll(ToFeedWebsocket),
+}
+
+/// A handle to the task sending messages out to every feed subscribed to one chain.
+/// The task ends when this is dropped.
+pub struct ChainFanout {
+    tx: flume::Sender<FanoutMessage>,
+}
+
+impl ChainFanout {
+    /// Spawn the fan-out task for a chain.
+    pub fn spawn(genesis_hash: BlockHash) -> Self {
+        let (tx, rx) = flume::unbounded();
+        tokio::spawn(async move {
+            let mut feeds: HashMap<ConnId, (Arc<FeedQueue>, u64)> = HashMap::new();
+            let mut finality_feeds: HashSet<ConnId> = HashSet::new();
+            while let Ok(msg) = rx.recv_async().await {
+                match msg {
+                    FanoutMessage::Add(feed_conn_id, queue, subscription) => {
+                        feeds.insert(feed_conn_id, (queue, subscription));
+                        finality_feeds.remove(&feed_conn_id);
+                    }
+                    FanoutMessage::Remove(feed_conn_id) => {
+                        feeds.remove(&feed_conn_id);
+                        finality_feeds.remove(&feed_conn_id);
+                    }
+                    FanoutMessage::SetFinality(feed_conn_id, true) => {
+                        if feeds.contains_key(&feed_conn_id) {
+                            finality_feeds.insert(feed_conn_id);
+                        }
+                    }
+                    FanoutMessage::SetFinality(feed_conn_id, false) => {
+                        finality_feeds.remove(&feed_conn_id);
+                    }
+                    FanoutMessage::Broadcast(message) => {
+                        // Feeds that have gone away are forgotten about. Those that have
+                        // moved on to another chain are kept until they're removed, since
+                        // messages for every feed are still sent to them until then:
+                        feeds.retain(|_, (queue, subscription)| {
+                            queue.send_to_subscription(*subscription, message.clone())
+                                || !queue.is_disconnected()
+                        });
+                    }
+                    FanoutMessage::BroadcastFinality(message) => {
+                        for feed_conn_id in &finality_feeds {
+                            if let Some((queue, subscription)) = feeds.get(feed_conn_id) {
+                                queue.send_to_subscription(*subscription, message.clone());
+                            }
+                        }
+                    }
+                    FanoutMessage::BroadcastToAll(message) => {
+                        feeds.retain(|_, (queue, _)| queue.send(message.clone()));
+                    }
+                }
+            }
+            log::debug!("Stopped sending messages out for chain {genesis_hash:?}");
+        });
+        ChainFanout { tx }
+    }
+
+    pub fn add(&self, feed_conn_id: ConnId, queue: Arc<FeedQueue>, subscription: u64) {
+        let _ = self
+            .tx
+            .send(FanoutMessage::Add(feed_conn_id, queue, subscription));
+    }
+
+    pub fn remove(&self, feed_conn_id: ConnId) {
+        let _ = self.tx.send(FanoutMessage::Remove(feed_conn_id));
+    }
+
+    /// Start (or stop) sending a feed that's been added the messages given to
+    /// [`ChainFanout::broadcast_finality`].
+    pub fn set_finality(&self, feed_conn_id: ConnId, finality: bool) {
+        let _ = self
+            .tx
+            .send(FanoutMessage::SetFinality(feed_conn_id, finality));
+    }
+
+    pub fn broadcast(&self, message: ToFeedWebsocket) {
+        let _ = self.tx.send(FanoutMessage::Broadcast(message));
+    }
+
+    /// Send a GRANDPA message to the feeds subscribed to the chain that have asked for them.
+    pub fn broadcast_finality(&self, message: ToFeedWebsocket) {
+        let _ = self.tx.send(FanoutMessage::BroadcastFinality(message));
+    }
+
+    /// Send every feed subscribed to the chain a message meant for all feeds, in order
+    /// with the messages about the chain.
+    pub fn broadcast_to_all(&self, message: ToFeedWebsocket) {
+        let _ = self.tx.send(FanoutMessage::BroadcastToAll(message));
+    }
+
+    /// How many messages are waiting to be sent out by the task.
+    pub fn queued_messages(&self) -> usize {
+        self.tx.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    fn msg(s: &'static str) -> ToFeedWebsocket {
+        ToFeedWebsocket::Bytes(bytes::Bytes::from_static(s.as_bytes()))
+    }
+
+    fn received(rx: &flume::Receiver<ToFeedWebsocket>) -> Vec<String> {
+        rx.drain()
+            .map(|ToFeedWebsocket::Bytes(b)| String::from_utf8(b.to_vec()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn messages_for_old_subscriptions_are_not_sent() {
+        let (tx, rx) = flume::unbounded();
+        let queue = FeedQueue::new(tx, Arc::default());
+        let first = queue.subscribe();
+        assert!(queue.send_to_subscription(first, msg("a")));
+
+        let second = queue.subscribe();
+        assert!(queue.send(msg("subscribed")));
+        assert!(!queue.send_to_subscription(first, msg("b")));
+        assert!(queue.send_to_subscription(second, msg("c")));
+        assert_eq!(received(&rx), vec!["a", "subscribed", "c"]);
+    }
+
+    #[test]
+    fn feeds_that_fall_behind_are_disconnected() {
+        let closed_feeds = Arc::new(AtomicU64::new(0));
+        let (tx, rx) = flume::bounded(2);
+        let queue = FeedQueue::new(tx, closed_feeds.clone());
+        assert!(queue.send(msg("a")));
+        assert!(queue.send(msg("b")));
+        assert_eq!(queue.queued_messages(), 2);
+        assert!(!queue.send(msg("c")));
+        assert_eq!(closed_feeds.load(Ordering::Relaxed), 1);
+
+        // What was already queued can still be read, after which the feed's stream ends:
+        assert_eq!(received(&rx), vec!["a", "b"]);
+        assert!(matches!(rx.recv(), Err(flume::RecvError::Disconnected)));
+        assert!(!queue.send(msg("d")));
+        assert_eq!(closed_feeds.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn chain_fanout_sends_to_its_subscribers() {
+        let closed_feeds = Arc::new(AtomicU64::new(0));
+        let fanout = ChainFanout::spawn(BlockHash::from_low_u64_be(1));
+
+        let (fast_tx, fast_rx) = flume::unbounded();
+        let fast = Arc::new(FeedQueue::new(fast_tx, closed_feeds.clone()));
+        let (slow_tx, slow_rx) = flume::bounded(1);
+        let slow = Arc::new(FeedQueue::new(slow_tx, closed_feeds.clone()));
+        fanout.add(ConnId::from(1), fast.clone(), fast.subscribe());
+        fanout.add(ConnId::from(2), slow.clone(), slow.subscribe());
+
+        fanout.broadcast(msg("a"));
+        fanout.broadcast(msg("b"));
+        fanout.remove(ConnId::from(1));
+
+        // Once this has heard about "c", the task has finished with everything before it:
+        let (witness_tx, witness_rx) = flume::unbounded();
+        let witness = Arc::new(FeedQueue::new(witness_tx, closed_feeds.clone()));
+        fanout.add(ConnId::from(3), witness.clone(), witness.subscribe());
+        fanout.broadcast(msg("c"));
+        let heard = tokio::time::timeout(Duration::from_secs(5), witness_rx.recv_async()).await;
+        assert!(heard.is_ok());
+
+        assert_eq!(received(&fast_rx), vec!["a", "b"]);
+        assert_eq!(received(&slow_rx), vec!["a"]);
+        assert_eq!(closed_feeds.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn messages_for_all_feeds_stay_in_order() {
+        let fanout = ChainFanout::spawn(BlockHash::from_low_u64_be(1));
+        let (tx, rx) = flume::unbounded();
+        let queue = Arc::new(FeedQueue::new(tx, Arc::default()));
+        fanout.add(ConnId::from(1), queue.clone(), queue.subscribe());
+        let next = || async {
+            let next = tokio::time::timeout(Duration::from_secs(5), rx.recv_async()).await;
+            let ToFeedWebsocket::Bytes(b) = next.unwrap().unwrap();
+            String::from_utf8(b.to_vec()).unwrap()
+        };
+
+        fanout.broadcast(msg("added node"));
+        fanout.broadcast_to_all(msg("added chain"));
+        assert_eq!(next().await, "added node");
+        assert_eq!(next().await, "added chain");
+
+        // A feed that's moved on is still sent messages for everybody, until it's removed:
+        queue.subscribe();
+        fanout.broadcast(msg("old chain"));
+        fanout.broadcast_to_all(msg("removed chain"));
+        assert_eq!(next().await, "removed chain");
+        fanout.remove(ConnId::from(1));
+        fanout.broadcast_to_all(msg("too late"));
+        assert!(received(&rx).is_empty());
+    }
+
+    #[tokio::test]
+    async fn finality_is_only_sent_to_feeds_that_ask() {
+        let fanout = ChainFanout::spawn(BlockHash::from_low_u64_be(1));
+        let (tx, rx) = flume::unbounded();
+        let queue = Arc::new(FeedQueue::new(tx, Arc::default()));
+        fanout.add(ConnId::from(1), queue.clone(), queue.subscribe());
+        let next = || async {
+            let next = tokio::time::timeout(Duration::from_secs(5), rx.recv_async()).await;
+            let ToFeedWebsocket::Bytes(b) = next.unwrap().unwrap();
+            String::from_utf8(b.to_vec()).unwrap()
+        };
+
+        fanout.broadcast_finality(msg("not asked for"));
+        fanout.set_finality(ConnId::from(1), true);
+        fanout.broadcast_finality(msg("prevote"));
+        fanout.broadcast(msg("imported block"));
+        assert_eq!(next().await, "prevote");
+        assert_eq!(next().await, "imported block");
+
+        fanout.set_finality(ConnId::from(1), false);
+        fanout.broadcast_finality(msg("precommit"));
+        fanout.broadcast(msg("finalized block"));
+        assert_eq!(next().await, "finalized block");
+    }
+}
@@ -15,7 +15,9 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use super::aggregator::ConnId;
+use super::fanout::{ChainFanout, FeedQueue};
 use crate::feed_message::{self, FeedMessageSerializer};
+use crate::snapshot::ChainSnapshot;
 use crate::state::{self, NodeId, State};
 use crate::{find_location, AggregatorOpts};
 use bimap::BiMap;
@@ -41,6 +43,10 @@ pub enum ToAggregator {
     /// Hand back some metrics. The provided sender is expected not to block when
     /// a message is sent into it.
     GatherMetrics(flume::Sender<Metrics>),
+    /// Hand back a JSON snapshot of the chain with the given genesis hash, or `None`
+    /// if we don't know about it. The provided sender is expected not to block when
+    /// a message is sent into it.
+    GatherSnapshot(BlockHash, flume::Sender<Option<bytes::Bytes>>),
 }
 
 /// An incoming shard connection can send these messages to the aggregator.
@@ -84,14 +90,18 @@ pub enum ToShardWebsocket {
 pub enum FromFeedWebsocket {
     /// When the socket is opened, it'll send this first
     /// so that we have a way to communicate back to it.
-    /// Unbounded so that slow feeds don't block aggregator
-    /// progress.
+    /// Messages are never waited on to be sent; if the
+    /// channel is bounded and fills up, it's dropped.
     Initialize {
         channel: flume::Sender<ToFeedWebsocket>,
     },
     /// The feed can subscribe to a chain to receive
     /// messages relating to it.
     Subscribe { chain: BlockHash },
+    /// The feed wants GRANDPA messages for the chain it's subscribed to.
+    SendFinality { chain: BlockHash },
+    /// The feed doesn't want GRANDPA messages any more.
+    NoMoreFinality { chain: BlockHash },
     /// An explicit ping message.
     Ping { value: Box<str> },
     /// The feed is disconnected.
@@ -110,6 +120,10 @@ pub struct Metrics {
     /// How many messages are currently queued up in internal channels
     /// waiting to be sent out to feeds.
     pub total_messages_to_feeds: usize,
+    /// How many messages are waiting to be fanned out to the feeds subscribed to each chain.
+    pub total_messages_to_fanouts: usize,
+    /// How many feeds have been disconnected because their queue filled up.
+    pub feeds_closed_for_falling_behind: u64,
     /// How many messages are currently queued waiting to be handled by this aggregator.
     pub current_messages_to_aggregator: usize,
     /// The total number of messages sent to the aggregator.
@@ -139,6 +153,12 @@ impl FromStr for FromFeedWebsocket {
             "subscribe" => Ok(FromFeedWebsocket::Subscribe {
                 chain: value.parse()?,
             }),
+            "send-finality" => Ok(FromFeedWebsocket::SendFinality {
+                chain: value.parse()?,
+            }),
+            "no-more-finality" => Ok(FromFeedWebsocket::NoMoreFinality {
+                chain: value.parse()?,
+            }),
             _ => return Err(anyhow::anyhow!("Command {} not recognised", cmd)),
         }
     }
@@ -160,12 +180,17 @@ pub struct InnerLoop {
     node_ids: BiMap<NodeId, (ConnId, ShardNodeId)>,
 
     /// Keep track of how to send messages out to feeds.
-    feed_channels: HashMap<ConnId, flume::Sender<ToFeedWebsocket>>,
+    feed_channels: HashMap<ConnId, Arc<FeedQueue>>,
     /// Keep track of how to send messages out to shards.
     shard_channels: HashMap<ConnId, flume::Sender<ToShardWebsocket>>,
 
     /// Which feeds are subscribed to a given chain?
     chain_to_feed_conn_ids: MultiMapUnique<BlockHash, ConnId>,
+    /// The tasks sending messages out to the feeds subscribed to each chain. There's one
+    /// for each chain that has any subscribers.
+    chain_fanouts: HashMap<BlockHash, ChainFanout>,
+    /// How many feeds have been disconnected for falling behind.
+    closed_feeds: Arc<AtomicU64>,
 
     /// Send messages here to make geographical location requests.
     tx_to_locator: flume::Sender<(NodeId, IpAddr)>,
@@ -188,6 +213,8 @@ impl InnerLoop {
             feed_channels: HashMap::new(),
             shard_channels: HashMap::new(),
             chain_to_feed_conn_ids: MultiMapUnique::new(),
+            chain_fanouts: HashMap::new(),
+            closed_feeds: Arc::new(AtomicU64::new(0)),
             tx_to_locator,
             max_queue_len: opts.max_queue_len,
             expose_node_details: opts.expose_node_details,
@@ -226,6 +253,9 @@ impl InnerLoop {
                         dropped_messages2.load(Ordering::Relaxed),
                         total_messages2.load(Ordering::Relaxed),
                     ),
+                    ToAggregator::GatherSnapshot(genesis_hash, tx) => {
+                        self.handle_gather_snapshot(genesis_hash, tx)
+                    }
                 }
             }
         });
@@ -269,7 +299,17 @@ impl InnerLoop {
         let chains_subscribed_to = self.chain_to_feed_conn_ids.num_keys();
         let connected_shards = self.shard_channels.len();
         let connected_feeds = self.feed_channels.len();
-        let total_messages_to_feeds: usize = self.feed_channels.values().map(|c| c.len()).sum();
+        let total_messages_to_feeds: usize = self
+            .feed_channels
+            .values()
+            .map(|q| q.queued_messages())
+            .sum();
+        let total_messages_to_fanouts: usize = self
+            .chain_fanouts
+            .values()
+            .map(|f| f.queued_messages())
+            .sum();
+        let feeds_closed_for_falling_behind = self.closed_feeds.load(Ordering::Relaxed);
 
         // Ignore error sending; assume the receiver stopped caring and dropped the channel:
         let _ = rx.send(Metrics {
@@ -277,6 +317,8 @@ impl InnerLoop {
             chains_subscribed_to,
             subscribed_feeds,
             total_messages_to_feeds,
+            total_messages_to_fanouts,
+            feeds_closed_for_falling_behind,
             current_messages_to_aggregator,
             total_messages_to_aggregator,
             dropped_messages_to_aggregator,
@@ -286,6 +328,26 @@ impl InnerLoop {
         });
     }
 
+    /// Serialize and return a snapshot of a single chain.
+    fn handle_gather_snapshot(
+        &mut self,
+        genesis_hash: BlockHash,
+        tx: flume::Sender<Option<bytes::Bytes>>,
+    ) {
+        let snapshot = self
+            .node_state
+            .get_chain_by_genesis_hash(&genesis_hash)
+            .map(|chain| {
+                let snapshot = ChainSnapshot::new(&chain, time::now(), self.expose_node_details);
+                serde_json::to_vec(&snapshot)
+                    .expect("chain snapshot should serialize")
+                    .into()
+            });
+
+        // Ignore error sending; assume the receiver stopped caring and dropped the channel:
+        let _ = tx.send(snapshot);
+    }
+
     /// Handle messages that come from the node geographical locator.
     fn handle_from_find_location(&mut self, node_id: NodeId, location: find_location::Location) {
         self.node_state
@@ -411,10 +473,12 @@ impl InnerLoop {
                 };
 
                 let mut feed_message_serializer = FeedMessageSerializer::new();
+                let mut finality_serializer = FeedMessageSerializer::new();
                 self.node_state.update_node(
                     node_id,
                     payload,
                     &mut feed_message_serializer,
+                    &mut finality_serializer,
                     self.expose_node_details,
                 );
 
@@ -424,6 +488,10 @@ impl InnerLoop {
                         &genesis_hash,
                         feed_message_serializer,
                     );
+                    self.finalize_and_broadcast_to_chain_finality_feeds(
+                        &genesis_hash,
+                        finality_serializer,
+                    );
                 }
             }
             FromShardWebsocket::Disconnected => {
@@ -447,7 +515,8 @@ impl InnerLoop {
     fn handle_from_feed(&mut self, feed_conn_id: ConnId, msg: FromFeedWebsocket) {
         match msg {
             FromFeedWebsocket::Initialize { channel } => {
-                self.feed_channels.insert(feed_conn_id, channel.clone());
+                let feed_queue = Arc::new(FeedQueue::new(channel, self.closed_feeds.clone()));
+                self.feed_channels.insert(feed_conn_id, feed_queue.clone());
 
                 // Tell the new feed subscription some basic things to get it going:
                 let mut feed_serializer = FeedMessageSerializer::new();
@@ -462,12 +531,12 @@ impl InnerLoop {
 
                 // Send this to the channel that subscribed:
                 if let Some(bytes) = feed_serializer.into_finalized() {
-                    let _ = channel.send(ToFeedWebsocket::Bytes(bytes));
+                    feed_queue.send(ToFeedWebsocket::Bytes(bytes));
                 }
             }
             FromFeedWebsocket::Ping { value } => {
-                let feed_channel = match self.feed_channels.get_mut(&feed_conn_id) {
-                    Some(chan) => chan,
+                let feed_queue = match self.feed_channels.get(&feed_conn_id) {
+                    Some(queue) => queue,
                     None => return,
                 };
 
@@ -475,17 +544,17 @@ impl InnerLoop {
                 let mut feed_serializer = FeedMessageSerializer::new();
                 feed_serializer.push(feed_message::Pong(&value));
                 if let Some(bytes) = feed_serializer.into_finalized() {
-                    let _ = feed_channel.send(ToFeedWebsocket::Bytes(bytes));
+                    feed_queue.send(ToFeedWebsocket::Bytes(bytes));
                 }
             }
             FromFeedWebsocket::Subscribe { chain } => {
-                let feed_channel = match self.feed_channels.get_mut(&feed_conn_id) {
-                    Some(chan) => chan,
+                let feed_queue = match self.feed_channels.get(&feed_conn_id) {
+                    Some(queue) => queue.clone(),
                     None => return,
                 };
 
                 // Unsubscribe from previous chain if subscribed to one:
-                let old_genesis_hash = self.chain_to_feed_conn_ids.remove_value(&feed_conn_id);
+                let old_genesis_hash = self.unsubscribe_feed(feed_conn_id);
 
                 // Get old chain if there was one:
                 let node_state = &self.node_state;
@@ -498,6 +567,10 @@ impl InnerLoop {
                     None => return,
                 };
 
+                // Nothing more from the old chain's fan-out can reach the feed after this,
+                // so it'll see the messages below before anything else about the new chain:
+                let subscription = feed_queue.subscribe();
+
                 // Send messages to the feed about this subscription:
                 let mut feed_serializer = FeedMessageSerializer::new();
                 if let Some(old_chain) = old_chain {
@@ -516,7 +589,7 @@ impl InnerLoop {
                 ));
                 feed_serializer.push(feed_message::ChainStatsUpdate(new_chain.stats()));
                 if let Some(bytes) = feed_serializer.into_finalized() {
-                    let _ = feed_channel.send(ToFeedWebsocket::Bytes(bytes));
+                    feed_queue.send(ToFeedWebsocket::Bytes(bytes));
                 }
 
                 // If many (eg 10k) nodes are connected, serializing all of their info takes time.
@@ -555,22 +628,59 @@ impl InnerLoop {
                     })
                     .collect();
                 for bytes in all_feed_messages {
-                    let _ = feed_channel.send(ToFeedWebsocket::Bytes(bytes));
+                    feed_queue.send(ToFeedWebsocket::Bytes(bytes));
                 }
 
-                // Actually make a note of the new chain subscription:
+                // Actually make a note of the new chain subscription, and have its fan-out
+                // send the feed everything from here on:
                 let new_genesis_hash = new_chain.genesis_hash();
                 self.chain_to_feed_conn_ids
                     .insert(new_genesis_hash, feed_conn_id);
+                self.chain_fanouts
+                    .entry(new_genesis_hash)
+                    .or_insert_with(|| ChainFanout::spawn(new_genesis_hash))
+                    .add(feed_conn_id, feed_queue, subscription);
+            }
+            FromFeedWebsocket::SendFinality { chain } => {
+                // Only feeds subscribed to the chain are sent anything about it:
+                if self.chain_to_feed_conn_ids.get_key(&feed_conn_id) != Some(&chain) {
+                    return;
+                }
+                if let Some(fanout) = self.chain_fanouts.get(&chain) {
+                    fanout.set_finality(feed_conn_id, true);
+                }
+            }
+            FromFeedWebsocket::NoMoreFinality { chain } => {
+                if let Some(fanout) = self.chain_fanouts.get(&chain) {
+                    fanout.set_finality(feed_conn_id, false);
+                }
             }
             FromFeedWebsocket::Disconnected => {
                 // The feed has disconnected; clean up references to it:
-                self.chain_to_feed_conn_ids.remove_value(&feed_conn_id);
+                self.unsubscribe_feed(feed_conn_id);
                 self.feed_channels.remove(&feed_conn_id);
             }
         }
     }
 
+    /// Stop sending a feed messages about the chain it's subscribed to, if any, returning
+    /// that chain. The chain's fan-out task is stopped if nobody else is subscribed to it.
+    fn unsubscribe_feed(&mut self, feed_conn_id: ConnId) -> Option<BlockHash> {
+        let genesis_hash = self.chain_to_feed_conn_ids.remove_value(&feed_conn_id)?;
+        if self
+            .chain_to_feed_conn_ids
+            .get_values(&genesis_hash)
+            .is_some()
+        {
+            if let Some(fanout) = self.chain_fanouts.get(&genesis_hash) {
+                fanout.remove(feed_conn_id);
+            }
+        } else {
+            self.chain_fanouts.remove(&genesis_hash);
+        }
+        Some(genesis_hash)
+    }
+
     /// Remove all of the node IDs provided and broadcast messages to feeds as needed.
     fn remove_nodes_and_broadcast_result(&mut self, node_ids: impl IntoIterator<Item = NodeId>) {
         // Group by chain to simplify the handling of feed messages:
@@ -655,13 +765,23 @@ impl InnerLoop {
         }
     }
 
-    /// Send a message to all chain feeds.
+    /// Send a message to all chain feeds, by way of the chain's fan-out task.
     fn broadcast_to_chain_feeds(&mut self, genesis_hash: &BlockHash, message: ToFeedWebsocket) {
-        if let Some(feeds) = self.chain_to_feed_conn_ids.get_values(genesis_hash) {
-            for &feed_id in feeds {
-                if let Some(chan) = self.feed_channels.get_mut(&feed_id) {
-                    let _ = chan.send(message.clone());
-                }
+        if let Some(fanout) = self.chain_fanouts.get(genesis_hash) {
+            fanout.broadcast(message);
+        }
+    }
+
+    /// Finalize a [`FeedMessageSerializer`] and broadcast the result to the feeds for the
+    /// chain that have asked for GRANDPA messages.
+    fn finalize_and_broadcast_to_chain_finality_feeds(
+        &mut self,
+        genesis_hash: &BlockHash,
+        serializer: FeedMessageSerializer,
+    ) {
+        if let Some(bytes) = serializer.into_finalized() {
+            if let Some(fanout) = self.chain_fanouts.get(genesis_hash) {
+                fanout.broadcast_finality(ToFeedWebsocket::Bytes(bytes));
             }
         }
     }
@@ -673,10 +793,17 @@ impl InnerLoop {
         }
     }
 
-    /// Send a message to everybody.
+    /// Send a message to everybody. Feeds that are subscribed to a chain are sent it by
+    /// way of the chain's fan-out, so that it doesn't overtake messages about the chain
+    /// that were sent before it.
     fn broadcast_to_all_feeds(&mut self, message: ToFeedWebsocket) {
-        for chan in self.feed_channels.values_mut() {
-            let _ = chan.send(message.clone());
+        for fanout in self.chain_fanouts.values() {
+            fanout.broadcast_to_all(message.clone());
+        }
+        for (feed_conn_id, queue) in &self.feed_channels {
+            if self.chain_to_feed_conn_ids.get_key(feed_conn_id).is_none() {
+                queue.send(message.clone());
+            }
         }
     }
 }
@@ -16,6 +16,7 @@
 
 mod aggregator;
 mod aggregator_set;
+mod fanout;
 mod inner_loop;
 
 // Expose the various message types that can be worked with externally:
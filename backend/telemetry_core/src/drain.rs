@@ -0,0 +1,149 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! When the core is being restarted, we'd rather that consumers see their
+//! connection go away cleanly than see every node on the network disconnect.
+//! A [`Drain`] is handed to each connection so that it can be told to wrap up,
+//! and so that we can wait for those connections to finish before exiting.
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::watch;
+use tokio::time::{Duration, Instant};
+
+/// The websocket close code we send when draining ("going away").
+pub const GOING_AWAY: u16 = 1001;
+
+#[derive(Clone)]
+pub struct Drain {
+    sender: Arc<watch::Sender<bool>>,
+    draining: watch::Receiver<bool>,
+    connections: Arc<AtomicUsize>,
+}
+
+impl Drain {
+    pub fn new() -> Self {
+        let (sender, draining) = watch::channel(false);
+        Drain {
+            sender: Arc::new(sender),
+            draining,
+            connections: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Tell every connection tracked by this to wrap up.
+    pub fn start(&self) {
+        self.sender.send_replace(true);
+    }
+
+    /// Have we been told to drain?
+    pub fn is_draining(&self) -> bool {
+        *self.draining.borrow()
+    }
+
+    /// Resolves once we've been told to drain. This is cancel safe.
+    pub async fn draining(&self) {
+        let mut draining = self.draining.clone();
+        // Errors only if the sender is dropped, which can't happen while we hold it.
+        let _ = draining.wait_for(|is_draining| *is_draining).await;
+    }
+
+    /// Keep the returned guard alive for as long as the connection is open.
+    pub fn track_connection(&self) -> ConnectionGuard {
+        self.connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self.connections.clone())
+    }
+
+    /// Wait until every tracked connection has closed, or the deadline is reached.
+    /// Returns the number of connections still open.
+    pub async fn wait_for_connections(&self, deadline: Instant) -> usize {
+        loop {
+            let open = self.connections.load(Ordering::Relaxed);
+            if open == 0 || Instant::now() >= deadline {
+                return open;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    }
+}
+
+impl Default for Drain {
+    fn default() -> Self {
+        Drain::new()
+    }
+}
+
+/// Decrements the count of open connections when dropped.
+pub struct ConnectionGuard(Arc<AtomicUsize>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Resolves when we receive SIGTERM or SIGINT.
+pub async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut sigterm = signal(SignalKind::terminate()).expect("can listen for SIGTERM");
+        tokio::select! {
+            _ = sigterm.recv() => {},
+            _ = tokio::signal::ctrl_c() => {},
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = tokio::signal::ctrl_c().await;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn waits_for_tracked_connections_to_close() {
+        let drain = Drain::new();
+        let guard = drain.track_connection();
+        assert!(!drain.is_draining());
+
+        let waiter = tokio::spawn({
+            let drain = drain.clone();
+            async move {
+                drain.draining().await;
+                drop(guard);
+            }
+        });
+
+        drain.start();
+        assert!(drain.is_draining());
+        let deadline = Instant::now() + Duration::from_secs(5);
+        assert_eq!(drain.wait_for_connections(deadline).await, 0);
+        waiter.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn gives_up_waiting_at_the_deadline() {
+        let drain = Drain::new();
+        let _guard = drain.track_connection();
+
+        drain.start();
+        let deadline = Instant::now() + Duration::from_millis(100);
+        assert_eq!(drain.wait_for_connections(deadline).await, 1);
+    }
+}
@@ -118,8 +118,11 @@ actions! {
     13: SubscribedTo,
     14: UnsubscribedFrom,
     15: Pong<'_>,
-    // Note; some now-unused messages were removed between IDs 15 and 20.
-    // We maintain existing IDs for backward compatibility.
+    // These are only sent to feeds that ask for them, with "send-finality":
+    16: AfgFinalized<'_>,
+    17: AfgReceivedPrevote<'_>,
+    18: AfgReceivedPrecommit<'_>,
+    19: AfgAuthoritySet<'_>,
     20: StaleNode,
     21: NodeIOUpdate<'_>,
     22: ChainStatsUpdate<'_>,
@@ -175,6 +178,35 @@ pub struct UnsubscribedFrom(pub BlockHash);
 #[derive(Serialize)]
 pub struct Pong<'a>(pub &'a str);
 
+#[derive(Serialize)]
+pub struct AfgFinalized<'a>(pub &'a str, pub BlockNumber, pub BlockHash);
+
+#[derive(Serialize)]
+pub struct AfgReceivedPrevote<'a>(
+    pub &'a str,
+    pub BlockNumber,
+    pub BlockHash,
+    pub Option<&'a str>,
+);
+
+#[derive(Serialize)]
+pub struct AfgReceivedPrecommit<'a>(
+    pub &'a str,
+    pub BlockNumber,
+    pub BlockHash,
+    pub Option<&'a str>,
+);
+
+/// The reporting node's authority ID, the authorities as a JSON list, and the ID of the set.
+#[derive(Serialize)]
+pub struct AfgAuthoritySet<'a>(
+    pub &'a str,
+    pub &'a str,
+    pub &'a str,
+    pub BlockNumber,
+    pub BlockHash,
+);
+
 #[derive(Serialize)]
 pub struct StaleNode(pub FeedNodeId);
 
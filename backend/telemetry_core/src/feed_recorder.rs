@@ -0,0 +1,221 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Record the feed for a chain to disk, exactly as a feed subscribed to that
+//! chain would see it. The recorder subscribes to the aggregator just like any
+//! other feed does, and writes each batch of messages it is sent out on its own
+//! line, prefixed with the time (unix MS) that it was received and a tab.
+//!
+//! Recordings are split across files. Each time we move to a new file, we
+//! resubscribe to the chain so that the file starts with the full chain state
+//! and can be replayed without needing any of the files before it.
+
+use std::path::PathBuf;
+
+use common::node_types::BlockHash;
+use common::time;
+use futures::{Sink, SinkExt};
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::time::{Duration, Instant};
+
+use crate::aggregator::{AggregatorSet, FromFeedWebsocket, ToFeedWebsocket};
+use crate::drain::Drain;
+
+/// If the chain we want to record isn't known about yet (or goes away when we
+/// resubscribe), try subscribing again this often.
+const RESUBSCRIBE_INTERVAL: Duration = Duration::from_secs(10);
+
+#[derive(Clone, Debug)]
+pub struct FeedRecorderOpts {
+    /// Recordings are written to a subdirectory of this, named by genesis hash.
+    pub dir: PathBuf,
+    /// Move to a new file once the current one has been open for this long.
+    pub rotate_after: Duration,
+    /// Move to a new file once the current one is at least this many bytes.
+    pub rotate_after_bytes: Option<usize>,
+}
+
+/// Start recording the feed for the chain with the given genesis hash.
+pub fn spawn(
+    aggregator: &AggregatorSet,
+    genesis_hash: BlockHash,
+    opts: FeedRecorderOpts,
+    drain: Drain,
+) {
+    let (_feed_id, tx_to_aggregator) = aggregator.subscribe_feed();
+    tokio::spawn(async move {
+        let _guard = drain.track_connection();
+        log::info!(
+            "Recording feed for {genesis_hash:?} to {}",
+            opts.dir.display()
+        );
+        if let Err(e) = record(tx_to_aggregator, genesis_hash, opts, &drain).await {
+            log::error!("Stopped recording feed for {genesis_hash:?}: {e}");
+        }
+    });
+}
+
+async fn record<S>(
+    mut tx_to_aggregator: S,
+    genesis_hash: BlockHash,
+    opts: FeedRecorderOpts,
+    drain: &Drain,
+) -> anyhow::Result<()>
+where
+    S: Sink<FromFeedWebsocket, Error = anyhow::Error> + Unpin,
+{
+    let dir = opts.dir.join(format!("{genesis_hash:?}"));
+    tokio::fs::create_dir_all(&dir).await?;
+
+    // Unbounded, so that a slow disk never gets the recording disconnected:
+    let (tx_to_recorder, rx_from_aggregator) = flume::unbounded();
+    tx_to_aggregator
+        .send(FromFeedWebsocket::Initialize {
+            channel: tx_to_recorder,
+        })
+        .await?;
+    tx_to_aggregator
+        .send(FromFeedWebsocket::Subscribe {
+            chain: genesis_hash,
+        })
+        .await?;
+
+    // Feeds are told about every chain (and its node count) as it changes, so this lets
+    // us spot if the chain we want to record has turned up while we're waiting for it:
+    let genesis_hash_str = format!("{genesis_hash:?}");
+
+    let mut file = RecordingFile::create(&dir).await?;
+    let mut subscribed = false;
+    let mut last_subscribe = Instant::now();
+    let mut rotating = false;
+    let mut flush_interval = tokio::time::interval(Duration::from_secs(1));
+
+    loop {
+        tokio::select! {
+            msg = rx_from_aggregator.recv_async() => {
+                let bytes = match msg {
+                    Ok(ToFeedWebsocket::Bytes(bytes)) => bytes,
+                    Err(flume::RecvError::Disconnected) => break,
+                };
+
+                // The response to subscribing begins with an "unsubscribed from" message if we were
+                // already subscribed, or a "subscribed to" message if not. Either way, this is where
+                // the next file should begin.
+                let is_subscription = bytes.starts_with(b"[13,") || bytes.starts_with(b"[14,");
+                if is_subscription {
+                    subscribed = true;
+                    if rotating {
+                        rotating = false;
+                        file.finish().await?;
+                        file = RecordingFile::create(&dir).await?;
+                    }
+                }
+                file.write_line(&bytes).await?;
+
+                if !subscribed && contains(&bytes, genesis_hash_str.as_bytes()) {
+                    tx_to_aggregator.send(FromFeedWebsocket::Subscribe { chain: genesis_hash }).await?;
+                    last_subscribe = Instant::now();
+                }
+            }
+            _ = flush_interval.tick() => {
+                file.flush().await?;
+
+                // If our subscription did not take, the chain may not exist (yet). We may already be
+                // unsubscribed from the chain if this happened while rotating, so rotate anyway:
+                if (!subscribed || rotating) && last_subscribe.elapsed() >= RESUBSCRIBE_INTERVAL {
+                    if rotating {
+                        rotating = false;
+                        subscribed = false;
+                        file.finish().await?;
+                        file = RecordingFile::create(&dir).await?;
+                    }
+                    tx_to_aggregator.send(FromFeedWebsocket::Subscribe { chain: genesis_hash }).await?;
+                    last_subscribe = Instant::now();
+                }
+
+                // Time for a new file? Resubscribe, and start the new file once we hear back.
+                if subscribed && !rotating && file.should_rotate(&opts) {
+                    rotating = true;
+                    tx_to_aggregator.send(FromFeedWebsocket::Subscribe { chain: genesis_hash }).await?;
+                    last_subscribe = Instant::now();
+                }
+            }
+            _ = drain.draining() => break,
+        }
+    }
+
+    file.finish().await?;
+    let _ = tx_to_aggregator.send(FromFeedWebsocket::Disconnected).await;
+    Ok(())
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// A single file that part of a feed is being recorded to.
+struct RecordingFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+    opened: Instant,
+    bytes_written: usize,
+}
+
+impl RecordingFile {
+    async fn create(dir: &std::path::Path) -> anyhow::Result<Self> {
+        let path = dir.join(format!("{}.feed", time::now()));
+        let file = File::create(&path).await?;
+        log::debug!("Recording feed to {}", path.display());
+        Ok(RecordingFile {
+            path,
+            writer: BufWriter::new(file),
+            opened: Instant::now(),
+            bytes_written: 0,
+        })
+    }
+
+    async fn write_line(&mut self, bytes: &[u8]) -> anyhow::Result<()> {
+        let prefix = format!("{}\t", time::now());
+        self.writer.write_all(prefix.as_bytes()).await?;
+        self.writer.write_all(bytes).await?;
+        self.writer.write_all(b"\n").await?;
+        self.bytes_written += prefix.len() + bytes.len() + 1;
+        Ok(())
+    }
+
+    async fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush().await?;
+        Ok(())
+    }
+
+    async fn finish(mut self) -> anyhow::Result<()> {
+        self.flush().await?;
+        log::debug!(
+            "Finished recording {} bytes of feed to {}",
+            self.bytes_written,
+            self.path.display()
+        );
+        Ok(())
+    }
+
+    fn should_rotate(&self, opts: &FeedRecorderOpts) -> bool {
+        self.opened.elapsed() >= opts.rotate_after
+            || opts
+                .rotate_after_bytes
+                .is_some_and(|max| self.bytes_written >= max)
+    }
+}
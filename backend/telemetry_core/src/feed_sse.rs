@@ -0,0 +1,197 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Some networks (corporate proxies, mostly) break WebSockets, so the feed is also
+//! offered as a stream of Server-Sent Events. Events only go one way, so the commands
+//! that a WebSocket feed would send are given up front in the query string instead,
+//! as `CMD=VALUE` pairs (eg `?subscribe=0x...`). Each event holds exactly what a
+//! WebSocket feed would be sent in a single message.
+
+use std::str::FromStr;
+
+use bytes::Bytes;
+use common::ready_chunks_all::ReadyChunksAll;
+use futures::{FutureExt, SinkExt, StreamExt};
+use hyper::{Body, Response};
+use tokio::time::{Duration, Instant};
+
+use crate::aggregator::{FromFeedWebsocket, ToFeedWebsocket};
+use crate::FeedOpts;
+
+/// How long to go without sending anything before sending a comment, so that proxies
+/// don't decide that the connection has gone idle.
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// Parse the commands given in the query string of an SSE feed request, in order.
+pub fn commands_from_query(query: Option<&str>) -> anyhow::Result<Vec<FromFeedWebsocket>> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (cmd, value) = pair.split_once('=').unwrap_or((pair, ""));
+            FromFeedWebsocket::from_str(&format!("{cmd}:{value}"))
+        })
+        .collect()
+}
+
+/// An SSE feed's response, and the sender that feed messages are streamed into it with.
+pub fn response() -> (Response<Body>, hyper::body::Sender) {
+    let (body_tx, body) = Body::channel();
+    let response = Response::builder()
+        .header(http::header::CONTENT_TYPE, "text/event-stream")
+        .header(http::header::CACHE_CONTROL, "no-cache")
+        // Otherwise nginx (and so some proxies) hold events back to send in bulk:
+        .header("X-Accel-Buffering", "no")
+        .body(body)
+        .unwrap();
+    (response, body_tx)
+}
+
+/// Send feed messages to an SSE feed until it goes away, is too slow to keep up, or we
+/// start draining. Hands back the sink to the aggregator, so it can be told that the
+/// feed has gone.
+pub async fn handle_feed_sse_connection<S>(
+    mut body_tx: hyper::body::Sender,
+    commands: Vec<FromFeedWebsocket>,
+    mut tx_to_aggregator: S,
+    opts: FeedOpts,
+) -> S
+where
+    S: futures::Sink<FromFeedWebsocket, Error = anyhow::Error> + Unpin + Send + 'static,
+{
+    let FeedOpts {
+        feed_timeout,
+        feed_queue_len,
+        drain,
+        subscriber,
+    } = opts;
+
+    // The aggregator never waits on this; if the feed lets it fill up, it's disconnected:
+    let (tx_to_feed_conn, rx_from_aggregator) = flume::bounded(feed_queue_len);
+    subscriber.set_queue(rx_from_aggregator.clone());
+    let mut rx_from_aggregator_chunks = ReadyChunksAll::new(rx_from_aggregator.into_stream());
+
+    // Tell the aggregator about this new connection, and then pass on the commands,
+    // just as a WebSocket feed would send them:
+    let init_msg = FromFeedWebsocket::Initialize {
+        channel: tx_to_feed_conn,
+    };
+    if let Err(e) = tx_to_aggregator.send(init_msg).await {
+        log::error!("Error sending message to aggregator: {e}");
+        return tx_to_aggregator;
+    }
+    for cmd in commands {
+        subscriber.received_command();
+        if let FromFeedWebsocket::Subscribe { chain } = &cmd {
+            subscriber.subscribed(*chain);
+        }
+        if let Err(e) = tx_to_aggregator.send(cmd).await {
+            log::error!("Failed to send message to aggregator; closing feed: {e}");
+            return tx_to_aggregator;
+        }
+    }
+
+    let mut keepalive =
+        tokio::time::interval_at(Instant::now() + KEEPALIVE_INTERVAL, KEEPALIVE_INTERVAL);
+    loop {
+        let debounce = tokio::time::sleep_until(Instant::now() + Duration::from_millis(75));
+
+        let (msgs, is_last) = tokio::select! {
+            msgs = rx_from_aggregator_chunks.next() => (msgs, false),
+            // When draining, send whatever is already queued up and then stop:
+            _ = drain.draining() => (rx_from_aggregator_chunks.next().now_or_never().flatten(), true),
+            _ = keepalive.tick() => {
+                // This is also how we find out that a quiet feed has gone away.
+                let deadline = Instant::now() + Duration::from_secs(feed_timeout);
+                if !send(&mut body_tx, Bytes::from_static(b":\n\n"), deadline).await {
+                    break;
+                }
+                continue;
+            }
+        };
+
+        // End the loop when connection from aggregator ends:
+        let msgs = match msgs {
+            Some(msgs) => msgs,
+            None => break,
+        };
+
+        // If the feed is too slow to receive the current batch of messages, we'll drop it.
+        let batch_started = Instant::now();
+        let message_send_deadline = batch_started + Duration::from_secs(feed_timeout);
+        for ToFeedWebsocket::Bytes(bytes) in msgs {
+            // Feed messages are JSON, and so never hold a raw newline that would end
+            // the event early.
+            let mut event = Vec::with_capacity(bytes.len() + 8);
+            event.extend_from_slice(b"data: ");
+            event.extend_from_slice(&bytes);
+            event.extend_from_slice(b"\n\n");
+            if !send(&mut body_tx, event.into(), message_send_deadline).await {
+                return tx_to_aggregator;
+            }
+            subscriber.sent(bytes.len());
+        }
+        subscriber.sent_batch(batch_started.elapsed());
+        keepalive.reset();
+
+        if is_last {
+            break;
+        }
+
+        debounce.await;
+    }
+    tx_to_aggregator
+}
+
+/// Send some bytes to the feed, returning false if it's gone away or couldn't take
+/// them before the deadline.
+async fn send(body_tx: &mut hyper::body::Sender, bytes: Bytes, deadline: Instant) -> bool {
+    match tokio::time::timeout_at(deadline, body_tx.send_data(bytes)).await {
+        Err(_) => {
+            log::debug!("Closing SSE feed that was too slow to keep up");
+            false
+        }
+        Ok(Err(e)) => {
+            log::debug!("Closing SSE feed due to error sending data: {e}");
+            false
+        }
+        Ok(Ok(())) => true,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn commands_are_read_from_the_query_string() {
+        assert!(commands_from_query(None).unwrap().is_empty());
+
+        let hash = format!("{:?}", common::node_types::BlockHash::from_low_u64_be(1));
+        let commands = commands_from_query(Some(&format!("subscribe={hash}&ping=hi"))).unwrap();
+        assert!(matches!(
+            &commands[..],
+            [
+                FromFeedWebsocket::Subscribe { chain },
+                FromFeedWebsocket::Ping { value },
+            ] if format!("{chain:?}") == hash && &**value == "hi"
+        ));
+
+        assert!(commands_from_query(Some("subscribe=nonsense")).is_err());
+        assert!(commands_from_query(Some("unsubscribe=0x01")).is_err());
+    }
+}
@@ -15,10 +15,17 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 mod aggregator;
+mod drain;
 mod feed_message;
+mod feed_recorder;
+mod feed_sse;
 mod find_location;
+mod snapshot;
 mod state;
+mod subscribers;
+use std::collections::HashMap;
 use std::str::FromStr;
+use std::sync::Arc;
 use tokio::time::{Duration, Instant};
 
 use aggregator::{
@@ -26,13 +33,20 @@ use aggregator::{
     ToShardWebsocket,
 };
 use bincode::Options;
+use common::byte_size::ByteSize;
+use common::compression::{self, Decompressor};
 use common::http_utils;
-use common::internal_messages;
+use common::internal_messages::{self, ShardNodeId};
+use common::node_message::{Payload, SystemInterval};
+use common::node_types::BlockHash;
 use common::ready_chunks_all::ReadyChunksAll;
-use futures::{SinkExt, StreamExt};
+use drain::Drain;
+use feed_recorder::FeedRecorderOpts;
+use futures::{FutureExt, SinkExt, StreamExt};
 use hyper::{Method, Response};
 use simple_logger::SimpleLogger;
 use structopt::StructOpt;
+use subscribers::{Subscriber, Subscribers};
 
 #[cfg(not(target_env = "msvc"))]
 use jemallocator::Jemalloc;
@@ -67,6 +81,10 @@ struct Opts {
     /// to a feed, the feed connection will be closed.
     #[structopt(long, default_value = "10")]
     feed_timeout: u64,
+    /// How many messages can be waiting to be sent to a feed before it's disconnected for
+    /// falling too far behind. Each message may carry many updates.
+    #[structopt(long, default_value = "10000")]
+    feed_queue_len: usize,
     /// Number of worker threads to spawn. If "0" is given, use the number of CPUs available
     /// on the machine. If no value is given, use an internal default that we have deemed sane.
     #[structopt(long)]
@@ -86,6 +104,30 @@ struct Opts {
     /// nodes to the feed subscribers.
     #[structopt(long)]
     pub expose_node_details: bool,
+    /// On SIGTERM or SIGINT, we stop accepting new connections and close existing feed and
+    /// then shard connections with a "going away" code. This is how many seconds we'll wait
+    /// for those connections to close before exiting anyway.
+    #[structopt(long, default_value = "30")]
+    drain_timeout: u64,
+    /// Record the feed for the chain with this genesis hash to disk. This can be
+    /// given multiple times to record the feeds for several chains.
+    #[structopt(long)]
+    record_feed: Vec<BlockHash>,
+    /// The directory that feed recordings are written into.
+    #[structopt(long, default_value = "feed_recordings")]
+    record_dir: std::path::PathBuf,
+    /// Start a new feed recording file once the current one has been written to for
+    /// this many seconds.
+    #[structopt(long, default_value = "3600")]
+    record_rotate_secs: u64,
+    /// Start a new feed recording file once the current one reaches this size (eg "500M").
+    #[structopt(long)]
+    record_rotate_size: Option<ByteSize>,
+    /// Serve the admin endpoints (such as /admin/subscribers) to requests that carry
+    /// this in an "Authorization: Bearer <TOKEN>" header. The admin endpoints are
+    /// disabled if this isn't given.
+    #[structopt(long, env = "TELEMETRY_ADMIN_TOKEN", hide_env_values = true)]
+    admin_token: Option<String>,
 }
 
 fn main() {
@@ -142,79 +184,238 @@ async fn start_server(num_aggregators: usize, opts: Opts) -> anyhow::Result<()>
     .await?;
     let socket_addr = opts.socket;
     let feed_timeout = opts.feed_timeout;
+    let feed_queue_len = opts.feed_queue_len;
+    let drain_timeout = Duration::from_secs(opts.drain_timeout);
+
+    // Feeds are drained before shards, so that they are told that we're going
+    // away rather than seeing every node on every chain be removed.
+    let feed_drain = Drain::new();
+    let shard_drain = Drain::new();
+
+    // Recorders are drained along with feeds, so that they flush what they've been sent.
+    let recorder_opts = FeedRecorderOpts {
+        dir: opts.record_dir,
+        rotate_after: Duration::from_secs(opts.record_rotate_secs),
+        rotate_after_bytes: opts.record_rotate_size.map(|size| size.num_bytes()),
+    };
+    for genesis_hash in opts.record_feed {
+        feed_recorder::spawn(
+            &aggregator,
+            genesis_hash,
+            recorder_opts.clone(),
+            feed_drain.clone(),
+        );
+    }
+
+    let subscribers = Subscribers::default();
+    let admin_token: Option<Arc<str>> = opts.admin_token.map(Into::into);
+
+    let server = http_utils::start_server(socket_addr, {
+        let feed_drain = feed_drain.clone();
+        let shard_drain = shard_drain.clone();
+        move |addr, req| {
+            let aggregator = aggregator.clone();
+            let feed_drain = feed_drain.clone();
+            let shard_drain = shard_drain.clone();
+            let subscribers = subscribers.clone();
+            let admin_token = admin_token.clone();
+            async move {
+                let path = req.uri().path().trim_end_matches('/');
+
+                // Once we start draining, don't accept anything new, and tell load
+                // balancers (via the health check) to send traffic elsewhere:
+                if feed_drain.is_draining()
+                    && matches!(path, "/health" | "/feed" | "/feed/sse" | "/shard_submit")
+                {
+                    return Ok(Response::builder()
+                        .status(503)
+                        .body("Shutting down".into())
+                        .unwrap());
+                }
 
-    let server = http_utils::start_server(socket_addr, move |addr, req| {
-        let aggregator = aggregator.clone();
-        async move {
-            match (req.method(), req.uri().path().trim_end_matches('/')) {
-                // Check that the server is up and running:
-                (&Method::GET, "/health") => Ok(Response::new("OK".into())),
-                // Subscribe to feed messages:
-                (&Method::GET, "/feed") => {
-                    log::info!("Opening /feed connection from {:?}", addr);
-                    Ok(http_utils::upgrade_to_websocket(
-                        req,
-                        move |ws_send, ws_recv| async move {
-                            let (feed_id, tx_to_aggregator) = aggregator.subscribe_feed();
-                            let (mut tx_to_aggregator, mut ws_send) =
-                                handle_feed_websocket_connection(
-                                    ws_send,
-                                    ws_recv,
-                                    tx_to_aggregator,
+                match (req.method(), path) {
+                    // Check that the server is up and running:
+                    (&Method::GET, "/health") => Ok(Response::new("OK".into())),
+                    // Subscribe to feed messages:
+                    (&Method::GET, "/feed") => {
+                        log::info!("Opening /feed connection from {:?}", addr);
+                        Ok(http_utils::upgrade_to_websocket_with_closer(
+                            req,
+                            move |ws_send, ws_recv, ws_closer| async move {
+                                let _guard = feed_drain.track_connection();
+                                let subscriber = subscribers.add(addr);
+                                let (feed_id, tx_to_aggregator) = aggregator.subscribe_feed();
+                                let (mut tx_to_aggregator, ws_send) =
+                                    handle_feed_websocket_connection(
+                                        ws_send,
+                                        ws_recv,
+                                        tx_to_aggregator,
+                                        feed_id,
+                                        FeedOpts {
+                                            feed_timeout,
+                                            feed_queue_len,
+                                            drain: feed_drain.clone(),
+                                            subscriber: subscriber.subscriber(),
+                                        },
+                                    )
+                                    .await;
+                                log::info!("Closing /feed connection from {:?}", addr);
+                                // Tell the aggregator that this connection has closed, so it can tidy up.
+                                let _ =
+                                    tx_to_aggregator.send(FromFeedWebsocket::Disconnected).await;
+                                close_websocket(ws_send, ws_closer, &feed_drain).await;
+                            },
+                        ))
+                    }
+                    // The same feed, as Server-Sent Events, for those who can't use WebSockets:
+                    (&Method::GET, "/feed/sse") => {
+                        let commands = match feed_sse::commands_from_query(req.uri().query()) {
+                            Ok(commands) => commands,
+                            Err(e) => {
+                                return Ok(Response::builder()
+                                    .status(400)
+                                    .body(e.to_string().into())
+                                    .unwrap())
+                            }
+                        };
+                        log::info!("Opening /feed/sse connection from {:?}", addr);
+                        let (response, body_tx) = feed_sse::response();
+                        tokio::spawn(async move {
+                            let _guard = feed_drain.track_connection();
+                            let subscriber = subscribers.add(addr);
+                            let (_feed_id, tx_to_aggregator) = aggregator.subscribe_feed();
+                            let mut tx_to_aggregator = feed_sse::handle_feed_sse_connection(
+                                body_tx,
+                                commands,
+                                tx_to_aggregator,
+                                FeedOpts {
                                     feed_timeout,
-                                    feed_id,
-                                )
-                                .await;
-                            log::info!("Closing /feed connection from {:?}", addr);
+                                    feed_queue_len,
+                                    drain: feed_drain.clone(),
+                                    subscriber: subscriber.subscriber(),
+                                },
+                            )
+                            .await;
+                            log::info!("Closing /feed/sse connection from {:?}", addr);
                             // Tell the aggregator that this connection has closed, so it can tidy up.
                             let _ = tx_to_aggregator.send(FromFeedWebsocket::Disconnected).await;
-                            let _ = ws_send.close().await;
-                        },
-                    ))
-                }
-                // Subscribe to shard messages:
-                (&Method::GET, "/shard_submit") => {
-                    Ok(http_utils::upgrade_to_websocket(
-                        req,
-                        move |ws_send, ws_recv| async move {
-                            log::info!("Opening /shard_submit connection from {:?}", addr);
-                            let tx_to_aggregator = aggregator.subscribe_shard();
-                            let (mut tx_to_aggregator, mut ws_send) =
-                                handle_shard_websocket_connection(
-                                    ws_send,
-                                    ws_recv,
-                                    tx_to_aggregator,
-                                )
-                                .await;
-                            log::info!("Closing /shard_submit connection from {:?}", addr);
-                            // Tell the aggregator that this connection has closed, so it can tidy up.
-                            let _ = tx_to_aggregator
-                                .send(FromShardWebsocket::Disconnected)
-                                .await;
-                            let _ = ws_send.close().await;
-                        },
-                    ))
+                        });
+                        Ok(response)
+                    }
+                    // Subscribe to shard messages:
+                    (&Method::GET, "/shard_submit") => {
+                        // Shards can ask for the messages they send to be compressed:
+                        let compression = match compression::from_query(req.uri().query()) {
+                            Ok(compression) => compression,
+                            Err(e) => {
+                                return Ok(Response::builder()
+                                    .status(400)
+                                    .body(e.to_string().into())
+                                    .unwrap())
+                            }
+                        };
+                        Ok(http_utils::upgrade_to_websocket_with_closer(
+                            req,
+                            move |ws_send, ws_recv, ws_closer| async move {
+                                let _guard = shard_drain.track_connection();
+                                log::info!("Opening /shard_submit connection from {:?}", addr);
+                                let tx_to_aggregator = aggregator.subscribe_shard();
+                                let (mut tx_to_aggregator, ws_send) =
+                                    handle_shard_websocket_connection(
+                                        ws_send,
+                                        ws_recv,
+                                        tx_to_aggregator,
+                                        compression,
+                                        shard_drain.clone(),
+                                    )
+                                    .await;
+                                log::info!("Closing /shard_submit connection from {:?}", addr);
+                                // Tell the aggregator that this connection has closed, so it can tidy up.
+                                let _ = tx_to_aggregator
+                                    .send(FromShardWebsocket::Disconnected)
+                                    .await;
+                                close_websocket(ws_send, ws_closer, &shard_drain).await;
+                            },
+                        ))
+                    }
+                    // Return metrics in a prometheus-friendly text based format:
+                    (&Method::GET, "/metrics") => Ok(return_prometheus_metrics(aggregator).await),
+                    // Return the current state of a single chain as one JSON document:
+                    (&Method::GET, path) if path.starts_with("/snapshot/") => {
+                        let genesis_hash = &path["/snapshot/".len()..];
+                        Ok(return_chain_snapshot(aggregator, genesis_hash).await)
+                    }
+                    // List the feeds that are connected, for operators:
+                    (&Method::GET, "/admin/subscribers" | "/admin/subscribers/metrics") => {
+                        if let Some(res) = refuse_admin_request(&req, admin_token.as_deref()) {
+                            return Ok(res);
+                        }
+                        let list = subscribers.list();
+                        if path.ends_with("/metrics") {
+                            Ok(Response::new(subscribers::prometheus_metrics(&list).into()))
+                        } else {
+                            Ok(Response::builder()
+                                .header(http::header::CONTENT_TYPE, "application/json")
+                                .body(serde_json::to_vec(&list)?.into())
+                                .unwrap())
+                        }
+                    }
+                    // 404 for anything else:
+                    _ => Ok(Response::builder()
+                        .status(404)
+                        .body("Not found".into())
+                        .unwrap()),
                 }
-                // Return metrics in a prometheus-friendly text based format:
-                (&Method::GET, "/metrics") => Ok(return_prometheus_metrics(aggregator).await),
-                // 404 for anything else:
-                _ => Ok(Response::builder()
-                    .status(404)
-                    .body("Not found".into())
-                    .unwrap()),
             }
         }
     });
 
-    server.await?;
+    tokio::select! {
+        res = server => res?,
+        _ = drain::shutdown_signal() => {
+            log::info!("Shutdown requested; draining connections");
+            let deadline = Instant::now() + drain_timeout;
+
+            feed_drain.start();
+            let open_feeds = feed_drain.wait_for_connections(deadline).await;
+            shard_drain.start();
+            let open_shards = shard_drain.wait_for_connections(deadline).await;
+
+            if open_feeds > 0 || open_shards > 0 {
+                log::warn!(
+                    "Drain timed out; exiting with {open_feeds} feed and {open_shards} shard connections still open"
+                );
+            } else {
+                log::info!("All connections drained; exiting");
+            }
+        }
+    }
     Ok(())
 }
 
+/// Close a websocket connection. If we're draining, the other end is told that we're
+/// going away so that it knows to reconnect (to us or another instance) shortly.
+async fn close_websocket(
+    mut ws_send: http_utils::WsSender,
+    ws_closer: http_utils::WsCloser,
+    drain: &Drain,
+) {
+    if drain.is_draining() {
+        let _ = ws_closer
+            .close(drain::GOING_AWAY, "Telemetry core is shutting down")
+            .await;
+    } else {
+        let _ = ws_send.close().await;
+    }
+}
+
 /// This handles messages coming to/from a shard connection
 async fn handle_shard_websocket_connection<S>(
     mut ws_send: http_utils::WsSender,
     mut ws_recv: http_utils::WsReceiver,
     mut tx_to_aggregator: S,
+    compression: Option<compression::Kind>,
+    drain: Drain,
 ) -> (S, http_utils::WsSender)
 where
     S: futures::Sink<FromShardWebsocket, Error = anyhow::Error> + Unpin + Send + 'static,
@@ -236,6 +437,10 @@ where
 
     // Receive messages from a shard:
     let recv_handle = tokio::spawn(async move {
+        let mut decompressor = compression.map(Decompressor::new);
+        // The last periodic stats we've had for each node, so that deltas of them can be
+        // turned back into the whole thing before the aggregator sees them:
+        let mut last_intervals: HashMap<ShardNodeId, SystemInterval> = HashMap::new();
         loop {
             let mut bytes = Vec::new();
 
@@ -256,6 +461,16 @@ where
                 break;
             }
 
+            if let Some(decompressor) = &mut decompressor {
+                bytes = match decompressor.decompress(&bytes) {
+                    Ok(bytes) => bytes,
+                    Err(e) => {
+                        log::error!("Failed to decompress message from shard; booting it: {e}");
+                        break;
+                    }
+                };
+            }
+
             let msg: internal_messages::FromShardAggregator =
                 match bincode::options().deserialize(&bytes) {
                     Ok(msg) => msg,
@@ -279,9 +494,21 @@ where
                     local_id,
                 },
                 internal_messages::FromShardAggregator::UpdateNode { payload, local_id } => {
+                    if let Payload::SystemInterval(interval) = &payload {
+                        last_intervals.insert(local_id, interval.clone());
+                    }
                     FromShardWebsocket::Update { local_id, payload }
                 }
+                internal_messages::FromShardAggregator::UpdateNodeInterval { local_id, delta } => {
+                    let last = last_intervals.entry(local_id).or_default();
+                    *last = delta.apply(last);
+                    FromShardWebsocket::Update {
+                        local_id,
+                        payload: Payload::SystemInterval(last.clone()),
+                    }
+                }
                 internal_messages::FromShardAggregator::RemoveNode { local_id } => {
+                    last_intervals.remove(&local_id);
                     FromShardWebsocket::Remove { local_id }
                 }
             };
@@ -301,6 +528,7 @@ where
         loop {
             let msg = tokio::select! {
                 msg = rx_from_aggregator.recv_async() => msg,
+                _ = drain.draining() => { break }
                 _ = &mut send_closer_rx => { break }
             };
 
@@ -340,19 +568,37 @@ where
     (tx_to_aggregator, ws_send)
 }
 
+/// What a feed connection needs to know, whether it's a websocket or SSE one.
+struct FeedOpts {
+    /// How many seconds a feed has to accept each batch of messages before we give up on it.
+    feed_timeout: u64,
+    /// How many messages can be waiting to be sent to a feed before it's disconnected.
+    feed_queue_len: usize,
+    drain: Drain,
+    subscriber: Arc<Subscriber>,
+}
+
 /// This handles messages coming from a feed connection
 async fn handle_feed_websocket_connection<S>(
     mut ws_send: http_utils::WsSender,
     mut ws_recv: http_utils::WsReceiver,
     mut tx_to_aggregator: S,
-    feed_timeout: u64,
     _feed_id: u64, // <- can be useful for debugging purposes.
+    opts: FeedOpts,
 ) -> (S, http_utils::WsSender)
 where
     S: futures::Sink<FromFeedWebsocket, Error = anyhow::Error> + Unpin + Send + 'static,
 {
-    // unbounded channel so that slow feeds don't block aggregator progress:
-    let (tx_to_feed_conn, rx_from_aggregator) = flume::unbounded();
+    let FeedOpts {
+        feed_timeout,
+        feed_queue_len,
+        drain,
+        subscriber,
+    } = opts;
+
+    // The aggregator never waits on this; if the feed lets it fill up, it's disconnected:
+    let (tx_to_feed_conn, rx_from_aggregator) = flume::bounded(feed_queue_len);
+    subscriber.set_queue(rx_from_aggregator.clone());
 
     // `Receiver::into_stream()` is currently problematic at the time of writing
     // (see https://github.com/zesterer/flume/issues/88). If this stream is polled lots
@@ -375,6 +621,7 @@ where
     let (send_closer_tx, mut send_closer_rx) = tokio::sync::oneshot::channel::<()>();
 
     // Receive messages from the feed:
+    let recv_subscriber = subscriber.clone();
     let recv_handle = tokio::spawn(async move {
         loop {
             let mut bytes = Vec::new();
@@ -409,6 +656,10 @@ where
                     continue;
                 }
             };
+            recv_subscriber.received_command();
+            if let FromFeedWebsocket::Subscribe { chain } = &cmd {
+                recv_subscriber.subscribed(*chain);
+            }
             if let Err(e) = tx_to_aggregator.send(cmd).await {
                 log::error!("Failed to send message to aggregator; closing feed: {e}");
                 break;
@@ -424,8 +675,10 @@ where
         'outer: loop {
             let debounce = tokio::time::sleep_until(Instant::now() + Duration::from_millis(75));
 
-            let msgs = tokio::select! {
-                msgs = rx_from_aggregator_chunks.next() => msgs,
+            let (msgs, is_last) = tokio::select! {
+                msgs = rx_from_aggregator_chunks.next() => (msgs, false),
+                // When draining, send whatever is already queued up and then stop:
+                _ = drain.draining() => (rx_from_aggregator_chunks.next().now_or_never().flatten(), true),
                 _ = &mut send_closer_rx => { break }
             };
 
@@ -442,7 +695,8 @@ where
             });
 
             // If the feed is too slow to receive the current batch of messages, we'll drop it.
-            let message_send_deadline = Instant::now() + Duration::from_secs(feed_timeout);
+            let batch_started = Instant::now();
+            let message_send_deadline = batch_started + Duration::from_secs(feed_timeout);
 
             for bytes in all_msg_bytes {
                 match tokio::time::timeout_at(message_send_deadline, ws_send.send_binary(&bytes))
@@ -459,7 +713,7 @@ where
                         log::debug!("Closing feed websocket due to error sending data: {}", e);
                         break 'outer;
                     }
-                    Ok(_) => {}
+                    Ok(_) => subscriber.sent(bytes.len()),
                 }
             }
 
@@ -475,7 +729,11 @@ where
                     log::debug!("Closing feed websocket due to error flushing data: {}", e);
                     break;
                 }
-                Ok(_) => {}
+                Ok(_) => subscriber.sent_batch(batch_started.elapsed()),
+            }
+
+            if is_last {
+                break;
             }
 
             debounce.await;
@@ -494,6 +752,74 @@ where
     (tx_to_aggregator, ws_send)
 }
 
+/// Admin endpoints are only served if an admin token was configured, and then only
+/// to requests that present it. Returns the response to send back if the request
+/// isn't allowed.
+fn refuse_admin_request(
+    req: &hyper::Request<hyper::Body>,
+    admin_token: Option<&str>,
+) -> Option<Response<hyper::Body>> {
+    let admin_token = match admin_token {
+        Some(token) => token,
+        None => {
+            return Some(
+                Response::builder()
+                    .status(404)
+                    .body("Not found".into())
+                    .unwrap(),
+            )
+        }
+    };
+    let presented = req
+        .headers()
+        .get(http::header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match presented {
+        Some(presented) if subscribers::token_matches(presented, admin_token) => None,
+        _ => Some(
+            Response::builder()
+                .status(401)
+                .header(http::header::WWW_AUTHENTICATE, "Bearer")
+                .body("Unauthorized".into())
+                .unwrap(),
+        ),
+    }
+}
+
+async fn return_chain_snapshot(
+    aggregator: AggregatorSet,
+    genesis_hash: &str,
+) -> Response<hyper::Body> {
+    let genesis_hash = match genesis_hash.parse() {
+        Ok(hash) => hash,
+        Err(_) => {
+            return Response::builder()
+                .status(400)
+                .body("Invalid genesis hash".into())
+                .unwrap()
+        }
+    };
+
+    match aggregator.snapshot(genesis_hash).await {
+        Ok(Some(bytes)) => Response::builder()
+            .header(http::header::CONTENT_TYPE, "application/json")
+            .body(bytes.into())
+            .unwrap(),
+        Ok(None) => Response::builder()
+            .status(404)
+            .body("Chain not found".into())
+            .unwrap(),
+        Err(e) => {
+            log::error!("Error obtaining chain snapshot: {e}");
+            Response::builder()
+                .status(500)
+                .body("Error obtaining chain snapshot".into())
+                .unwrap()
+        }
+    }
+}
+
 async fn return_prometheus_metrics(aggregator: AggregatorSet) -> Response<hyper::Body> {
     let metrics = aggregator.latest_metrics();
 
@@ -540,6 +866,16 @@ async fn return_prometheus_metrics(aggregator: AggregatorSet) -> Response<hyper:
             "telemetry_core_total_messages_to_feeds{{aggregator=\"{}\"}} {} {}\n",
             idx, m.total_messages_to_feeds, m.timestamp_unix_ms
         );
+        let _ = writeln!(
+            &mut s,
+            "telemetry_core_total_messages_to_fanouts{{aggregator=\"{}\"}} {} {}",
+            idx, m.total_messages_to_fanouts, m.timestamp_unix_ms
+        );
+        let _ = writeln!(
+            &mut s,
+            "telemetry_core_feeds_closed_for_falling_behind{{aggregator=\"{}\"}} {} {}",
+            idx, m.feeds_closed_for_falling_behind, m.timestamp_unix_ms
+        );
         let _ = write!(
             &mut s,
             "telemetry_core_current_messages_to_aggregator{{aggregator=\"{}\"}} {} {}\n\n",
@@ -0,0 +1,158 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! This module provides a JSON representation of the entire current state of
+//! a chain. Unlike feed messages, which are compact and incremental, a snapshot
+//! is a single self describing document that consumers can use to warm-start
+//! instead of reconstructing state from the feed.
+
+use serde::Serialize;
+
+use crate::feed_message::ChainStats;
+use crate::state::{Node, StateChain};
+use common::node_types::{Block, BlockHash, BlockNumber, NodeHwBench, NodeSysInfo, Timestamp};
+
+#[derive(Serialize)]
+pub struct ChainSnapshot<'a> {
+    /// When (in unix MS from epoch) this snapshot was taken.
+    pub timestamp: Timestamp,
+    pub genesis_hash: BlockHash,
+    pub label: &'a str,
+    pub node_count: usize,
+    pub best_block: BestBlockSnapshot,
+    pub finalized_block: &'a Block,
+    pub stats: &'a ChainStats,
+    pub nodes: Vec<NodeSnapshot<'a>>,
+}
+
+#[derive(Serialize)]
+pub struct BestBlockSnapshot {
+    pub height: BlockNumber,
+    pub hash: BlockHash,
+    /// When the best block first arrived.
+    pub timestamp: Timestamp,
+    pub average_block_time: Option<u64>,
+}
+
+#[derive(Serialize)]
+pub struct NodeSnapshot<'a> {
+    /// The same ID that is used to refer to this node in feed messages.
+    pub id: usize,
+    pub name: &'a str,
+    pub implementation: &'a str,
+    pub version: &'a str,
+    pub validator: Option<&'a str>,
+    pub network_id: &'a str,
+    pub target_os: Option<&'a str>,
+    pub target_arch: Option<&'a str>,
+    pub target_env: Option<&'a str>,
+    pub ip: Option<&'a str>,
+    pub sysinfo: Option<&'a NodeSysInfo>,
+    pub hwbench: Option<&'a NodeHwBench>,
+    pub startup_time: Option<Timestamp>,
+    pub peers: u64,
+    pub txcount: u64,
+    pub best_block: NodeBlockSnapshot,
+    pub finalized_block: &'a Block,
+    pub location: Option<LocationSnapshot<'a>>,
+    pub stale: bool,
+}
+
+#[derive(Serialize)]
+pub struct LocationSnapshot<'a> {
+    pub latitude: f32,
+    pub longitude: f32,
+    pub city: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct NodeBlockSnapshot {
+    pub height: BlockNumber,
+    pub hash: BlockHash,
+    pub block_time: u64,
+    pub block_timestamp: Timestamp,
+    pub propagation_time: Option<u64>,
+}
+
+impl<'a> ChainSnapshot<'a> {
+    /// Take a snapshot of the given chain. The node's IP address and hardware benchmarks
+    /// are only included if `expose_node_details` is true, mirroring what feeds are sent.
+    pub fn new(chain: &StateChain<'a>, timestamp: Timestamp, expose_node_details: bool) -> Self {
+        let best = chain.best_block();
+        let nodes = chain
+            .nodes_slice()
+            .iter()
+            .enumerate()
+            .filter_map(|(id, node)| node.as_ref().map(|n| (id, n)))
+            .map(|(id, node)| NodeSnapshot::new(id, node, expose_node_details))
+            .collect();
+
+        ChainSnapshot {
+            timestamp,
+            genesis_hash: chain.genesis_hash(),
+            label: chain.label(),
+            node_count: chain.node_count(),
+            best_block: BestBlockSnapshot {
+                height: best.height,
+                hash: best.hash,
+                timestamp: chain.timestamp(),
+                average_block_time: chain.average_block_time(),
+            },
+            finalized_block: chain.finalized_block(),
+            stats: chain.stats(),
+            nodes,
+        }
+    }
+}
+
+impl<'a> NodeSnapshot<'a> {
+    fn new(id: usize, node: &'a Node, expose_node_details: bool) -> Self {
+        let details = node.details();
+        let best = node.block_details();
+
+        NodeSnapshot {
+            id,
+            name: &details.name,
+            implementation: &details.implementation,
+            version: &details.version,
+            validator: details.validator.as_deref(),
+            network_id: &details.network_id,
+            target_os: details.target_os.as_deref(),
+            target_arch: details.target_arch.as_deref(),
+            target_env: details.target_env.as_deref(),
+            ip: details.ip.as_deref().filter(|_| expose_node_details),
+            sysinfo: details.sysinfo.as_ref(),
+            hwbench: node.hwbench().filter(|_| expose_node_details),
+            startup_time: node.startup_time(),
+            peers: node.stats().peers,
+            txcount: node.stats().txcount,
+            best_block: NodeBlockSnapshot {
+                height: best.block.height,
+                hash: best.block.hash,
+                block_time: best.block_time,
+                block_timestamp: best.block_timestamp,
+                propagation_time: best.propagation_time,
+            },
+            finalized_block: node.finalized(),
+            location: node.location().map(|loc| LocationSnapshot {
+                latitude: loc.latitude,
+                longitude: loc.longitude,
+                city: &loc.city,
+            }),
+            stale: node.stale(),
+        }
+    }
+}
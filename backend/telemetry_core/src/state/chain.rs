@@ -16,7 +16,7 @@
 
 use common::node_message::Payload;
 use common::node_types::BlockHash;
-use common::node_types::{Block, Timestamp};
+use common::node_types::{Block, BlockNumber, Timestamp};
 use common::{id_type, time, DenseMap, MostSeen, NumStats};
 use once_cell::sync::Lazy;
 use std::collections::HashSet;
@@ -169,12 +169,14 @@ impl Chain {
         }
     }
 
-    /// Attempt to update the best block seen in this chain.
+    /// Attempt to update the best block seen in this chain. GRANDPA messages go into
+    /// `finality_feed`, for just the feeds that have asked for them.
     pub fn update_node(
         &mut self,
         nid: ChainNodeId,
         payload: Payload,
         feed: &mut FeedMessageSerializer,
+        finality_feed: &mut FeedMessageSerializer,
         expose_node_details: bool,
     ) {
         if let Some(block) = payload.best_block() {
@@ -207,6 +209,68 @@ impl Chain {
                     }
                     return;
                 }
+                Payload::AfgAuthorities(authorities) => {
+                    if node.set_validator_address(authorities.authority_id.clone()) {
+                        feed.push(feed_message::AddedNode(
+                            nid.into(),
+                            node,
+                            expose_node_details,
+                        ));
+                    }
+                    if let Ok(number) = authorities.number.parse::<BlockNumber>() {
+                        finality_feed.push(feed_message::AfgAuthoritySet(
+                            &authorities.authority_id,
+                            &authorities.authorities,
+                            &authorities.authority_set_id,
+                            number,
+                            authorities.hash,
+                        ));
+                    }
+                    return;
+                }
+                // We say which validator each of these came through, so we only pass them
+                // on once we know that:
+                Payload::AfgFinalized(finalized) => {
+                    if let (Some(addr), Ok(number)) = (
+                        node.details().validator.as_deref(),
+                        finalized.number.parse::<BlockNumber>(),
+                    ) {
+                        finality_feed.push(feed_message::AfgFinalized(
+                            addr,
+                            number,
+                            finalized.hash,
+                        ));
+                    }
+                    return;
+                }
+                Payload::AfgReceivedPrevote(vote) => {
+                    if let (Some(addr), Ok(number)) = (
+                        node.details().validator.as_deref(),
+                        vote.target_number.parse::<BlockNumber>(),
+                    ) {
+                        finality_feed.push(feed_message::AfgReceivedPrevote(
+                            addr,
+                            number,
+                            vote.target_hash,
+                            vote.voter.as_deref(),
+                        ));
+                    }
+                    return;
+                }
+                Payload::AfgReceivedPrecommit(vote) => {
+                    if let (Some(addr), Ok(number)) = (
+                        node.details().validator.as_deref(),
+                        vote.target_number.parse::<BlockNumber>(),
+                    ) {
+                        finality_feed.push(feed_message::AfgReceivedPrecommit(
+                            addr,
+                            number,
+                            vote.target_hash,
+                            vote.voter.as_deref(),
+                        ));
+                    }
+                    return;
+                }
                 Payload::HwBench(ref hwbench) => {
                     let new_hwbench = common::node_types::NodeHwBench {
                         cpu_hashrate_score: hwbench.cpu_hashrate_score,
@@ -218,6 +218,7 @@ impl State {
         NodeId(chain_id, chain_node_id): NodeId,
         payload: Payload,
         feed: &mut FeedMessageSerializer,
+        finality_feed: &mut FeedMessageSerializer,
         expose_node_details: bool,
     ) {
         let chain = match self.chains.get_mut(chain_id) {
@@ -228,7 +229,13 @@ impl State {
             }
         };
 
-        chain.update_node(chain_node_id, payload, feed, expose_node_details)
+        chain.update_node(
+            chain_node_id,
+            payload,
+            feed,
+            finality_feed,
+            expose_node_details,
+        )
     }
 
     /// Update the location for a node. Return `false` if the node was not found.
@@ -275,10 +282,10 @@ impl<'a> StateChain<'a> {
     pub fn finalized_block(&self) -> &'a Block {
         self.chain.finalized_block()
     }
-    pub fn nodes_slice(&self) -> &[Option<Node>] {
+    pub fn nodes_slice(&self) -> &'a [Option<Node>] {
         self.chain.nodes_slice()
     }
-    pub fn stats(&self) -> &ChainStats {
+    pub fn stats(&self) -> &'a ChainStats {
         self.chain.stats()
     }
 }
@@ -0,0 +1,276 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Operators want to know who is consuming the feed, and who is falling behind.
+//! Each feed connection registers itself with [`Subscribers`] and keeps counters
+//! up to date as it goes, so that the admin endpoints can list them.
+
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use common::node_types::BlockHash;
+use parking_lot::Mutex;
+use serde::Serialize;
+
+use crate::aggregator::ToFeedWebsocket;
+
+/// Every feed connection that's currently open.
+#[derive(Clone, Default)]
+pub struct Subscribers {
+    next_id: Arc<AtomicU64>,
+    subscribers: Arc<Mutex<BTreeMap<u64, Arc<Subscriber>>>>,
+}
+
+impl Subscribers {
+    /// Register a new feed connection. It's forgotten about when the returned
+    /// guard is dropped.
+    pub fn add(&self, addr: SocketAddr) -> SubscriberGuard {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let subscriber = Arc::new(Subscriber {
+            id,
+            addr,
+            connected_at: unix_ms(),
+            chain: Mutex::new(None),
+            queue: Mutex::new(None),
+            messages_sent: AtomicU64::new(0),
+            bytes_sent: AtomicU64::new(0),
+            commands_received: AtomicU64::new(0),
+            last_batch_ms: AtomicU64::new(0),
+            slowest_batch_ms: AtomicU64::new(0),
+        });
+        self.subscribers.lock().insert(id, subscriber.clone());
+        SubscriberGuard {
+            subscriber,
+            subscribers: self.subscribers.clone(),
+        }
+    }
+
+    /// A summary of every open feed connection, oldest first.
+    pub fn list(&self) -> Vec<SubscriberSummary> {
+        let now = unix_ms();
+        self.subscribers
+            .lock()
+            .values()
+            .map(|s| s.summary(now))
+            .collect()
+    }
+}
+
+/// Removes a subscriber from the list when dropped.
+pub struct SubscriberGuard {
+    subscriber: Arc<Subscriber>,
+    subscribers: Arc<Mutex<BTreeMap<u64, Arc<Subscriber>>>>,
+}
+
+impl SubscriberGuard {
+    pub fn subscriber(&self) -> Arc<Subscriber> {
+        self.subscriber.clone()
+    }
+}
+
+impl Drop for SubscriberGuard {
+    fn drop(&mut self) {
+        self.subscribers.lock().remove(&self.subscriber.id);
+    }
+}
+
+/// What we know about a single feed connection.
+pub struct Subscriber {
+    id: u64,
+    addr: SocketAddr,
+    connected_at: u64,
+    chain: Mutex<Option<BlockHash>>,
+    /// A handle on the messages waiting to be sent to this feed, so that we can
+    /// see how far behind it is. We never receive from this.
+    queue: Mutex<Option<flume::Receiver<ToFeedWebsocket>>>,
+    messages_sent: AtomicU64,
+    bytes_sent: AtomicU64,
+    commands_received: AtomicU64,
+    last_batch_ms: AtomicU64,
+    slowest_batch_ms: AtomicU64,
+}
+
+impl Subscriber {
+    pub fn set_queue(&self, queue: flume::Receiver<ToFeedWebsocket>) {
+        *self.queue.lock() = Some(queue);
+    }
+
+    pub fn received_command(&self) {
+        self.commands_received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// The feed can only be subscribed to one chain at a time, so this replaces
+    /// any chain it subscribed to before.
+    pub fn subscribed(&self, chain: BlockHash) {
+        *self.chain.lock() = Some(chain);
+    }
+
+    pub fn sent(&self, bytes: usize) {
+        self.messages_sent.fetch_add(1, Ordering::Relaxed);
+        self.bytes_sent.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// How long it took to send (and flush) the last batch of messages.
+    pub fn sent_batch(&self, took: Duration) {
+        let ms = took.as_millis() as u64;
+        self.last_batch_ms.store(ms, Ordering::Relaxed);
+        self.slowest_batch_ms.fetch_max(ms, Ordering::Relaxed);
+    }
+
+    fn summary(&self, now: u64) -> SubscriberSummary {
+        SubscriberSummary {
+            id: self.id,
+            addr: self.addr.to_string(),
+            connected_at: self.connected_at,
+            connected_secs: now.saturating_sub(self.connected_at) / 1000,
+            chain: *self.chain.lock(),
+            messages_sent: self.messages_sent.load(Ordering::Relaxed),
+            bytes_sent: self.bytes_sent.load(Ordering::Relaxed),
+            commands_received: self.commands_received.load(Ordering::Relaxed),
+            queued_messages: self.queue.lock().as_ref().map_or(0, |q| q.len()),
+            last_batch_ms: self.last_batch_ms.load(Ordering::Relaxed),
+            slowest_batch_ms: self.slowest_batch_ms.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct SubscriberSummary {
+    pub id: u64,
+    /// The remote address of the connection.
+    pub addr: String,
+    /// When (in unix MS from epoch) the feed connected.
+    pub connected_at: u64,
+    pub connected_secs: u64,
+    /// The chain the feed last asked to subscribe to, if any.
+    pub chain: Option<BlockHash>,
+    pub messages_sent: u64,
+    pub bytes_sent: u64,
+    pub commands_received: u64,
+    /// Messages waiting to be sent to the feed; a feed that can't keep up will see
+    /// this grow until it's disconnected after `--feed-timeout`.
+    pub queued_messages: usize,
+    /// How long the last batch of messages took to send, in milliseconds.
+    pub last_batch_ms: u64,
+    pub slowest_batch_ms: u64,
+}
+
+/// Per-subscriber metrics in the prometheus text format.
+pub fn prometheus_metrics(subscribers: &[SubscriberSummary]) -> String {
+    use std::fmt::Write;
+    let mut s = String::new();
+    for sub in subscribers {
+        let chain = sub.chain.map(|c| format!("{c:?}")).unwrap_or_default();
+        let labels = format!(
+            "subscriber=\"{}\",addr=\"{}\",chain=\"{}\"",
+            sub.id, sub.addr, chain
+        );
+        let _ = writeln!(
+            s,
+            "telemetry_core_subscriber_connected_secs{{{labels}}} {}",
+            sub.connected_secs
+        );
+        let _ = writeln!(
+            s,
+            "telemetry_core_subscriber_messages_sent{{{labels}}} {}",
+            sub.messages_sent
+        );
+        let _ = writeln!(
+            s,
+            "telemetry_core_subscriber_bytes_sent{{{labels}}} {}",
+            sub.bytes_sent
+        );
+        let _ = writeln!(
+            s,
+            "telemetry_core_subscriber_queued_messages{{{labels}}} {}",
+            sub.queued_messages
+        );
+        let _ = writeln!(
+            s,
+            "telemetry_core_subscriber_last_batch_ms{{{labels}}} {}",
+            sub.last_batch_ms
+        );
+    }
+    s
+}
+
+/// Compare a presented token with the one we expect, without bailing out at the
+/// first difference so that how long it takes doesn't give the token away.
+pub fn token_matches(presented: &str, expected: &str) -> bool {
+    let (presented, expected) = (presented.as_bytes(), expected.as_bytes());
+    presented.len() == expected.len()
+        && presented
+            .iter()
+            .zip(expected)
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+}
+
+fn unix_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn subscribers_are_listed_until_they_go() {
+        let subscribers = Subscribers::default();
+        let alice = subscribers.add("127.0.0.1:1000".parse().unwrap());
+        let bob = subscribers.add("127.0.0.2:2000".parse().unwrap());
+
+        let (tx, rx) = flume::unbounded();
+        alice.subscriber().set_queue(rx);
+        tx.send(ToFeedWebsocket::Bytes(bytes::Bytes::from_static(b"[]")))
+            .unwrap();
+        alice.subscriber().subscribed(BlockHash::from_low_u64_be(1));
+        alice.subscriber().sent(100);
+        alice.subscriber().sent(50);
+        alice.subscriber().sent_batch(Duration::from_millis(20));
+        alice.subscriber().sent_batch(Duration::from_millis(5));
+
+        let list = subscribers.list();
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].addr, "127.0.0.1:1000");
+        assert_eq!(list[0].chain, Some(BlockHash::from_low_u64_be(1)));
+        assert_eq!(list[0].messages_sent, 2);
+        assert_eq!(list[0].bytes_sent, 150);
+        assert_eq!(list[0].queued_messages, 1);
+        assert_eq!(list[0].last_batch_ms, 5);
+        assert_eq!(list[0].slowest_batch_ms, 20);
+        assert_eq!(list[1].chain, None);
+
+        drop(bob);
+        let list = subscribers.list();
+        assert_eq!(list.len(), 1);
+        assert_eq!(list[0].addr, "127.0.0.1:1000");
+    }
+
+    #[test]
+    fn tokens_must_match_exactly() {
+        assert!(token_matches("secret", "secret"));
+        assert!(!token_matches("secreT", "secret"));
+        assert!(!token_matches("secret2", "secret"));
+        assert!(!token_matches("", "secret"));
+    }
+}
@@ -260,6 +260,758 @@ async fn e2e_feed_add_and_remove_node() {
     server.shutdown().await;
 }
 
+/// Shards can compress the messages that they send to the core; this should
+/// make no difference to what the feeds see.
+#[tokio::test]
+async fn e2e_shard_compression_is_transparent_to_feeds() {
+    let mut server = start_server(
+        ServerOpts::default(),
+        CoreOpts::default(),
+        ShardOpts {
+            core_compression: Some("deflate".to_owned()),
+            ..Default::default()
+        },
+    )
+    .await;
+    let shard_id = server.add_shard().await.unwrap();
+
+    // Connect a couple of nodes to the shard:
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_multiple_nodes(2)
+        .await
+        .expect("can connect to shard")
+        .into_iter()
+        .unzip::<_, _, Vec<_>, Vec<_>>();
+
+    for (idx, node_tx) in node_tx.iter_mut().enumerate() {
+        node_tx
+            .send_json_text(json!(
+                {
+                    "id":1,
+                    "ts":"2021-07-12T10:37:47.714666+01:00",
+                    "payload": {
+                        "authority":true,
+                        "chain":"Local Testnet",
+                        "config":"",
+                        "genesis_hash": ghash(1),
+                        "implementation":"Substrate Node",
+                        "msg":"system.connected",
+                        "name": format!("Node {idx}"),
+                        "network_id": format!("Node {idx}"),
+                        "startup_time":"1625565542717",
+                        "version":"2.0.0-07a1af348-aarch64-macos"
+                    },
+                }
+            ))
+            .unwrap();
+    }
+
+    // Wait a little for these messages to propagate to the core.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // Connect a feed.
+    let (_feed_tx, mut feed_rx) = server.get_core().connect_feed().await.unwrap();
+
+    let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+    assert!(feed_messages.contains(&FeedMessage::AddedChain {
+        name: "Local Testnet".to_owned(),
+        genesis_hash: ghash(1),
+        node_count: 2,
+    }));
+
+    // Tidy up:
+    server.shutdown().await;
+}
+
+/// Shards can send just what's changed in each node's periodic stats; feeds should
+/// see the same updates as they would if they were sent in full.
+#[tokio::test]
+async fn e2e_shard_stats_deltas_are_transparent_to_feeds() {
+    use FeedMessage::*;
+
+    let mut server = start_server(
+        ServerOpts::default(),
+        CoreOpts::default(),
+        ShardOpts {
+            core_stats_deltas: Some(3),
+            ..Default::default()
+        },
+    )
+    .await;
+    let shard_id = server.add_shard().await.unwrap();
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .unwrap();
+
+    node_tx
+        .send_json_text(json!(
+            {
+                "id":1,
+                "ts":"2021-07-12T10:37:47.714666+01:00",
+                "payload": {
+                    "authority":true,
+                    "chain":"Local Testnet",
+                    "config":"",
+                    "genesis_hash": ghash(1),
+                    "implementation":"Substrate Node",
+                    "msg":"system.connected",
+                    "name":"Alice",
+                    "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
+                    "startup_time":"1625565542717",
+                    "version":"2.0.0-07a1af348-aarch64-macos"
+                },
+            }
+        ))
+        .unwrap();
+
+    // Connect a feed and subscribe it to the chain:
+    let (feed_tx, mut feed_rx) = server.get_core().connect_feed().await.unwrap();
+    feed_rx.recv_feed_messages().await.unwrap();
+    feed_tx
+        .send_command("subscribe", &format!("{:?}", ghash(1)))
+        .unwrap();
+    let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+    assert_contains_matches!(feed_messages, SubscribedTo { genesis_hash } if genesis_hash == ghash(1));
+
+    // Some of these are sent in full and some as deltas; only changes reach the feed
+    // either way, and every field should be as the node sent it:
+    let intervals = [(1, 0, 10), (1, 5, 11), (2, 5, 11), (2, 5, 12), (3, 6, 12)];
+    for (peers, txcount, height) in intervals {
+        node_tx
+            .send_json_text(json!(
+                {
+                    "id":1,
+                    "ts":"2021-07-12T10:37:48.330433+01:00",
+                    "payload": {
+                        "msg":"system.interval",
+                        "peers":peers,
+                        "txcount":txcount,
+                        "best":ghash(height),
+                        "height":height,
+                    },
+                }
+            ))
+            .unwrap();
+        // Give each one time to arrive, so that they don't end up in the same batch:
+        tokio::time::sleep(Duration::from_millis(250)).await;
+    }
+
+    let mut stats = vec![];
+    let mut best = 0;
+    while stats.len() < 4 || best < 12 {
+        let feed_messages =
+            tokio::time::timeout(Duration::from_secs(5), feed_rx.recv_feed_messages())
+                .await
+                .expect("feed messages arrive in time")
+                .unwrap();
+        for msg in feed_messages {
+            match msg {
+                NodeStatsUpdate { stats: s, .. } => stats.push((s.peers, s.txcount)),
+                BestBlock { block_number, .. } => best = block_number,
+                _ => {}
+            }
+        }
+    }
+    assert_eq!(stats, vec![(1, 0), (1, 5), (2, 5), (3, 6)]);
+
+    // Tidy up:
+    server.shutdown().await;
+}
+
+/// Feeds that ask for them are sent the GRANDPA messages that nodes report, as decoded
+/// by the same feed client that the observer uses.
+#[tokio::test]
+async fn e2e_feed_sent_finality_when_asked_for() {
+    use FeedMessage::*;
+
+    const ALICE: &str = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY";
+    const BOB: &str = "5FHneW46xGXgs5mUiveU4sbTyGBzmstUspZC92UhjJM694ty";
+
+    let mut server = start_server(
+        ServerOpts::default(),
+        CoreOpts::default(),
+        ShardOpts {
+            core_finality: true,
+            ..Default::default()
+        },
+    )
+    .await;
+    let shard_id = server.add_shard().await.unwrap();
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .unwrap();
+
+    let mut send = |payload: serde_json::Value| {
+        node_tx
+            .send_json_text(json!({
+                "id":1,
+                "ts":"2021-07-12T10:37:47.714666+01:00",
+                "payload": payload,
+            }))
+            .unwrap();
+    };
+    send(json!({
+        "authority":true,
+        "chain":"Local Testnet",
+        "config":"",
+        "genesis_hash": ghash(1),
+        "implementation":"Substrate Node",
+        "msg":"system.connected",
+        "name":"Alice",
+        "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
+        "startup_time":"1625565542717",
+        "version":"2.0.0-07a1af348-aarch64-macos"
+    }));
+    let authority_set = json!({
+        "msg":"afg.authority_set",
+        "authority_id":ALICE,
+        "authorities":format!(r#"["{ALICE}","{BOB}"]"#),
+        "authority_set_id":"3",
+        "hash":ghash(10),
+        "number":"10"
+    });
+    send(authority_set.clone());
+
+    // Connect a feed and subscribe it to the chain:
+    let (feed_tx, mut feed_rx) = server.get_core().connect_feed().await.unwrap();
+    feed_rx.recv_feed_messages().await.unwrap();
+    feed_tx
+        .send_command("subscribe", &format!("{:?}", ghash(1)))
+        .unwrap();
+    let feed_messages = feed_rx.recv_feed_messages().await.unwrap();
+    assert_contains_matches!(
+        feed_messages,
+        SubscribedTo { genesis_hash } if genesis_hash == ghash(1),
+        AddedNode { node: NodeDetails { validator, .. }, .. } if validator.as_deref() == Some(ALICE),
+    );
+
+    // Until it asks, the feed isn't sent any; once it's seen the stats sent after this
+    // vote, it knows that the vote was handled:
+    send(json!({
+        "msg":"afg.received_prevote",
+        "target_hash":ghash(10),
+        "target_number":"10",
+        "voter":format!("{BOB} (8eaf0...)")
+    }));
+    send(json!({ "msg":"system.interval", "peers":1 }));
+    let mut seen_stats = false;
+    while !seen_stats {
+        let feed_messages =
+            tokio::time::timeout(Duration::from_secs(5), feed_rx.recv_feed_messages())
+                .await
+                .expect("feed messages arrive in time")
+                .unwrap();
+        for msg in feed_messages {
+            match msg {
+                NodeStatsUpdate { .. } => seen_stats = true,
+                AfgReceivedPrevote { .. } => panic!("finality sent without asking for it"),
+                _ => {}
+            }
+        }
+    }
+
+    feed_tx
+        .send_command("send-finality", &format!("{:?}", ghash(1)))
+        .unwrap();
+    // Give the command time to arrive, since it doesn't have a reply:
+    tokio::time::sleep(Duration::from_millis(500)).await;
+    send(authority_set);
+    send(json!({
+        "msg":"afg.received_prevote",
+        "target_hash":ghash(11),
+        "target_number":"11",
+        "voter":format!("{BOB} (8eaf0...)")
+    }));
+    send(json!({
+        "msg":"afg.received_precommit",
+        "target_hash":ghash(11),
+        "target_number":"11",
+        "voter":format!("{BOB} (8eaf0...)")
+    }));
+    send(json!({
+        "msg":"afg.finalized_blocks_up_to",
+        "hash":ghash(11),
+        "number":"11"
+    }));
+
+    let mut finality = vec![];
+    while !matches!(finality.last(), Some(AfgFinalized { .. })) {
+        let feed_messages =
+            tokio::time::timeout(Duration::from_secs(5), feed_rx.recv_feed_messages())
+                .await
+                .expect("feed messages arrive in time")
+                .unwrap();
+        finality.extend(feed_messages.into_iter().filter(|msg| {
+            matches!(
+                msg,
+                AfgAuthoritySet { .. }
+                    | AfgReceivedPrevote { .. }
+                    | AfgReceivedPrecommit { .. }
+                    | AfgFinalized { .. }
+            )
+        }));
+    }
+    assert_eq!(finality.len(), 4);
+    assert_contains_matches!(
+        finality,
+        AfgAuthoritySet { authority_id, authorities, authority_set_id, block_number: 10, .. }
+            if authority_id == ALICE
+                && authorities == format!(r#"["{ALICE}","{BOB}"]"#)
+                && authority_set_id == "3",
+        AfgReceivedPrevote { address, block_number: 11, voter: Some(voter), .. }
+            if address == ALICE && voter.starts_with(BOB),
+        AfgReceivedPrecommit { block_number: 11, voter: Some(voter), .. }
+            if voter.starts_with(BOB),
+        AfgFinalized { address, block_number: 11, block_hash }
+            if address == ALICE && block_hash == ghash(11),
+    );
+
+    // Tidy up:
+    server.shutdown().await;
+}
+
+/// The core can record the feed for a chain to disk. We ask it to record a chain
+/// that doesn't exist yet, to check that the recording starts once it does.
+#[tokio::test]
+async fn e2e_feed_recorded_to_disk() {
+    let record_dir =
+        std::env::temp_dir().join(format!("telemetry_feed_recording_{}", std::process::id()));
+    let mut server = start_server(
+        ServerOpts::default(),
+        CoreOpts {
+            record_feed: vec![format!("{:?}", ghash(1))],
+            record_dir: Some(record_dir.to_string_lossy().into_owned()),
+            ..Default::default()
+        },
+        ShardOpts::default(),
+    )
+    .await;
+    let shard_id = server.add_shard().await.unwrap();
+
+    // Connect a node to the shard:
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .expect("can connect to shard");
+
+    // Send a "system connected" message:
+    node_tx
+        .send_json_text(json!(
+            {
+                "id":1,
+                "ts":"2021-07-12T10:37:47.714666+01:00",
+                "payload": {
+                    "authority":true,
+                    "chain":"Local Testnet",
+                    "config":"",
+                    "genesis_hash": ghash(1),
+                    "implementation":"Substrate Node",
+                    "msg":"system.connected",
+                    "name":"Alice",
+                    "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
+                    "startup_time":"1625565542717",
+                    "version":"2.0.0-07a1af348-aarch64-macos"
+                },
+            }
+        ))
+        .unwrap();
+
+    // Recordings are flushed to disk every second:
+    tokio::time::sleep(Duration::from_millis(2000)).await;
+
+    let chain_dir = record_dir.join(format!("{:?}", ghash(1)));
+    let mut recording = String::new();
+    for entry in std::fs::read_dir(&chain_dir).unwrap() {
+        recording.push_str(&std::fs::read_to_string(entry.unwrap().path()).unwrap());
+    }
+
+    // Each line is a timestamp followed by a batch of feed messages:
+    let feed_messages: Vec<FeedMessage> = recording
+        .lines()
+        .flat_map(|line| {
+            let (timestamp, msgs) = line.split_once('\t').expect("tab separated");
+            assert!(timestamp.parse::<u64>().is_ok());
+            FeedMessage::from_bytes(msgs.as_bytes()).unwrap()
+        })
+        .collect();
+    assert!(feed_messages.contains(&FeedMessage::SubscribedTo {
+        genesis_hash: ghash(1)
+    }));
+    assert_contains_matches!(
+        feed_messages,
+        FeedMessage::AddedNode {
+            node: NodeDetails { name, .. },
+            ..
+        } if name == "Alice"
+    );
+
+    // Tidy up:
+    server.shutdown().await;
+    let _ = std::fs::remove_dir_all(&record_dir);
+}
+
+/// A snapshot of a chain's current state can be requested over HTTP, and
+/// unknown chains lead to a 404.
+#[tokio::test]
+async fn e2e_snapshot_returns_chain_state() {
+    // Connect server and add shard
+    let mut server = start_server_debug().await;
+    let shard_id = server.add_shard().await.unwrap();
+
+    // Connect a node to the shard:
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .expect("can connect to shard");
+
+    // Send a "system connected" message:
+    node_tx
+        .send_json_text(json!(
+            {
+                "id":1,
+                "ts":"2021-07-12T10:37:47.714666+01:00",
+                "payload": {
+                    "authority":true,
+                    "chain":"Local Testnet",
+                    "config":"",
+                    "genesis_hash": ghash(1),
+                    "implementation":"Substrate Node",
+                    "msg":"system.connected",
+                    "name":"Alice",
+                    "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
+                    "startup_time":"1625565542717",
+                    "version":"2.0.0-07a1af348-aarch64-macos"
+                },
+            }
+        ))
+        .unwrap();
+
+    // Wait a little for this message to propagate to the core.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let core_host = server.get_core().host();
+    let res = reqwest::get(format!("http://{core_host}/snapshot/{:?}", ghash(1)))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+
+    let snapshot: serde_json::Value = res.json().await.unwrap();
+    assert_eq!(snapshot["label"], "Local Testnet");
+    assert_eq!(snapshot["node_count"], 1);
+    assert_eq!(snapshot["nodes"][0]["name"], "Alice");
+    assert_eq!(snapshot["nodes"][0]["implementation"], "Substrate Node");
+
+    // A chain that we don't know about:
+    let res = reqwest::get(format!("http://{core_host}/snapshot/{:?}", ghash(2)))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+
+    // Tidy up:
+    server.shutdown().await;
+}
+
+/// The feed can also be streamed as Server-Sent Events, subscribing to a chain by
+/// way of the query string.
+#[tokio::test]
+async fn e2e_feed_streamed_as_server_sent_events() {
+    // Connect server and add shard
+    let mut server = start_server_debug().await;
+    let shard_id = server.add_shard().await.unwrap();
+
+    // Connect a node to the shard:
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .expect("can connect to shard");
+
+    // Send a "system connected" message:
+    node_tx
+        .send_json_text(json!(
+            {
+                "id":1,
+                "ts":"2021-07-12T10:37:47.714666+01:00",
+                "payload": {
+                    "authority":true,
+                    "chain":"Local Testnet",
+                    "config":"",
+                    "genesis_hash": ghash(1),
+                    "implementation":"Substrate Node",
+                    "msg":"system.connected",
+                    "name":"Alice",
+                    "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
+                    "startup_time":"1625565542717",
+                    "version":"2.0.0-07a1af348-aarch64-macos"
+                },
+            }
+        ))
+        .unwrap();
+
+    // Wait a little for this message to propagate to the core.
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let core_host = server.get_core().host();
+    let mut res = reqwest::get(format!(
+        "http://{core_host}/feed/sse?subscribe={:?}",
+        ghash(1)
+    ))
+    .await
+    .unwrap();
+    assert_eq!(res.status(), 200);
+    assert_eq!(res.headers()["content-type"], "text/event-stream");
+
+    // Read events until we've been told about the node on the chain we subscribed to:
+    let mut body = String::new();
+    let mut feed_messages = vec![];
+    while !feed_messages
+        .iter()
+        .any(|m| matches!(m, FeedMessage::AddedNode { node, .. } if node.name == "Alice"))
+    {
+        let chunk = tokio::time::timeout(Duration::from_secs(10), res.chunk())
+            .await
+            .expect("events arrive in time")
+            .unwrap()
+            .expect("stream is still open");
+        body.push_str(std::str::from_utf8(&chunk).unwrap());
+        while let Some(end) = body.find("\n\n") {
+            let event: String = body.drain(..end + 2).collect();
+            // Anything else is a keepalive comment:
+            if let Some(data) = event.trim_end().strip_prefix("data: ") {
+                feed_messages.extend(FeedMessage::from_bytes(data.as_bytes()).unwrap());
+            }
+        }
+    }
+    assert!(feed_messages.contains(&FeedMessage::SubscribedTo {
+        genesis_hash: ghash(1)
+    }));
+
+    // Unknown commands are turned away before the stream starts:
+    let res = reqwest::get(format!("http://{core_host}/feed/sse?nonsense=1"))
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 400);
+
+    // Tidy up:
+    server.shutdown().await;
+}
+
+/// Operators can list the feeds that are connected, given the admin token.
+#[tokio::test]
+async fn e2e_admin_lists_feed_subscribers() {
+    let mut server = start_server(
+        ServerOpts::default(),
+        CoreOpts {
+            admin_token: Some("let-me-in".to_owned()),
+            ..Default::default()
+        },
+        ShardOpts::default(),
+    )
+    .await;
+    let shard_id = server.add_shard().await.unwrap();
+
+    // Connect a node to the shard:
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .expect("can connect to shard");
+    node_tx
+        .send_json_text(json!(
+            {
+                "id":1,
+                "ts":"2021-07-12T10:37:47.714666+01:00",
+                "payload": {
+                    "authority":true,
+                    "chain":"Local Testnet",
+                    "config":"",
+                    "genesis_hash": ghash(1),
+                    "implementation":"Substrate Node",
+                    "msg":"system.connected",
+                    "name":"Alice",
+                    "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
+                    "startup_time":"1625565542717",
+                    "version":"2.0.0-07a1af348-aarch64-macos"
+                },
+            }
+        ))
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    // Connect a couple of feeds, one of which subscribes to the chain:
+    let (feed_tx, mut feed_rx) = server.get_core().connect_feed().await.unwrap();
+    feed_tx
+        .send_command("subscribe", &format!("{:?}", ghash(1)))
+        .unwrap();
+    feed_rx.recv_feed_messages().await.unwrap();
+    let (_idle_tx, _idle_rx) = server.get_core().connect_feed().await.unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let url = format!("http://{}/admin/subscribers", server.get_core().host());
+    let client = reqwest::Client::new();
+
+    // Without the token (or with the wrong one), we're turned away:
+    let res = client.get(&url).send().await.unwrap();
+    assert_eq!(res.status(), 401);
+    let res = client.get(&url).bearer_auth("guess").send().await.unwrap();
+    assert_eq!(res.status(), 401);
+
+    let res = client
+        .get(&url)
+        .bearer_auth("let-me-in")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 200);
+    let subscribers: Vec<serde_json::Value> = res.json().await.unwrap();
+    assert_eq!(subscribers.len(), 2);
+    let subscribed = &subscribers[0];
+    assert_eq!(subscribed["chain"], format!("{:?}", ghash(1)));
+    assert!(subscribed["messages_sent"].as_u64().unwrap() > 0);
+    assert!(subscribed["bytes_sent"].as_u64().unwrap() > 0);
+    assert_eq!(subscribed["commands_received"], 1);
+    assert_eq!(subscribers[1]["chain"], serde_json::Value::Null);
+
+    let res = client
+        .get(format!("{url}/metrics"))
+        .bearer_auth("let-me-in")
+        .send()
+        .await
+        .unwrap();
+    let metrics = res.text().await.unwrap();
+    assert!(metrics.contains("telemetry_core_subscriber_queued_messages{subscriber="));
+
+    // Tidy up:
+    server.shutdown().await;
+}
+
+/// The admin endpoints don't exist unless an admin token is given.
+#[tokio::test]
+async fn e2e_admin_disabled_without_token() {
+    let server = start_server_debug().await;
+    let url = format!("http://{}/admin/subscribers", server.get_core().host());
+    let res = reqwest::Client::new()
+        .get(&url)
+        .bearer_auth("anything")
+        .send()
+        .await
+        .unwrap();
+    assert_eq!(res.status(), 404);
+    server.shutdown().await;
+}
+
+/// Node operators can see what a shard has received from their address.
+#[tokio::test]
+async fn e2e_shard_lists_ingest_stats() {
+    let mut server = start_server_debug().await;
+    let shard_id = server.add_shard().await.unwrap();
+
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .expect("can connect to shard");
+    node_tx
+        .send_json_text(json!(
+            {
+                "id":1,
+                "ts":"2021-07-12T10:37:47.714666+01:00",
+                "payload": {
+                    "authority":true,
+                    "chain":"Local Testnet",
+                    "config":"",
+                    "genesis_hash": ghash(1),
+                    "implementation":"Substrate Node",
+                    "msg":"system.connected",
+                    "name":"Alice",
+                    "network_id":"12D3KooWEyoppNCUx8Yx66oV9fJnriXwCcXwDDUA2kj6vnc6iDEp",
+                    "startup_time":"1625565542717",
+                    "version":"2.0.0-07a1af348-aarch64-macos"
+                },
+            }
+        ))
+        .unwrap();
+    node_tx
+        .send_json_text(json!(
+            {
+                "id":1,
+                "ts":"2021-07-12T10:37:48.714666+01:00",
+                "payload": {
+                    "msg":"block.import",
+                    "best": BlockHash::from_low_u64_be(1),
+                    "height": 1,
+                    "origin":"Own"
+                },
+            }
+        ))
+        .unwrap();
+    // Not a node message at all:
+    node_tx.send_json_text(json!({ "hello": "world" })).unwrap();
+    // About a node that was never added:
+    node_tx
+        .send_json_text(json!(
+            {
+                "id":2,
+                "ts":"2021-07-12T10:37:48.714666+01:00",
+                "payload": {
+                    "msg":"block.import",
+                    "best": BlockHash::from_low_u64_be(1),
+                    "height": 1,
+                    "origin":"Own"
+                },
+            }
+        ))
+        .unwrap();
+    tokio::time::sleep(Duration::from_millis(500)).await;
+
+    let url = format!(
+        "http://{}/ingest_stats",
+        server.get_shard(shard_id).unwrap().host()
+    );
+    let res = reqwest::Client::new().get(&url).send().await.unwrap();
+    assert_eq!(res.status(), 200);
+    let connections: Vec<serde_json::Value> = res.json().await.unwrap();
+    assert_eq!(connections.len(), 1);
+    let connection = &connections[0];
+    assert_eq!(connection["addr"], "127.0.0.1");
+    assert_eq!(connection["messages_received"], 4);
+    assert_eq!(connection["payload_errors"], 1);
+    assert_eq!(connection["ignored_messages"], 1);
+    let nodes = connection["nodes"].as_array().unwrap();
+    assert_eq!(nodes.len(), 1);
+    assert_eq!(nodes[0]["name"], "Alice");
+    assert_eq!(nodes[0]["genesis_hash"], format!("{:?}", ghash(1)));
+    assert_eq!(nodes[0]["messages_received"], 2);
+
+    // Claiming to be someone else doesn't work, since the shard doesn't trust any proxies:
+    let res = reqwest::Client::new()
+        .get(&url)
+        .header("X-Forwarded-For", "10.0.0.1")
+        .send()
+        .await
+        .unwrap();
+    let connections: Vec<serde_json::Value> = res.json().await.unwrap();
+    assert_eq!(connections.len(), 1);
+    assert_eq!(connections[0]["addr"], "127.0.0.1");
+
+    server.shutdown().await;
+}
+
 /// If a node is added, a connecting feed should be told about the new chain.
 /// However, sending a duplicate "system.connected" message from the same node
 /// should not count as a new node but rather the second message should be ignored.
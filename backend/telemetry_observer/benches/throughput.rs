@@ -0,0 +1,166 @@
+use common::node_types::BlockHash;
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use serde_json::json;
+use std::process::Stdio;
+use std::time::{Duration, Instant};
+use test_utils::workspace::{start_server, CoreOpts, ServerOpts, ShardOpts};
+use tokio::process::Command;
+use tokio::runtime::Runtime;
+
+/// Any more than 1000 nodes on a chain that isn't first party are turned away by the core.
+const NUMBER_OF_NODES: usize = 500;
+const NUMBER_OF_BLOCKS: u64 = 20;
+
+/// The core only hands out propagation times for imports of its current best block,
+/// and they're in whole milliseconds, so we wait a little after the first import of
+/// each block before sending the rest.
+const FIRST_IMPORT_GAP: Duration = Duration::from_millis(5);
+
+/// The core stops passing on a node's imports for a while if they come in less than
+/// 100ms apart. Along with the above, this puts a floor of around
+/// `NUMBER_OF_BLOCKS * (FIRST_IMPORT_GAP + BLOCK_GAP)` on each iteration, which is why
+/// there are plenty of nodes importing each block.
+const BLOCK_GAP: Duration = Duration::from_millis(150);
+
+/// This benchmark times how long the observer takes to attribute a fixed number of
+/// blocks, from the first import being sent to the shard until it exits, with
+/// different numbers of workers. Everything in between (the shard, the core and the
+/// feed) is included, so compare the timings with each other rather than reading too
+/// much into any one of them.
+pub fn benchmark_observer_throughput(c: &mut Criterion) {
+    let rt = Runtime::new().expect("tokio runtime should start");
+    let mut group = c.benchmark_group("observer throughput: time till blocks attributed");
+
+    for workers in [1, 4] {
+        group.bench_with_input(
+            BenchmarkId::from_parameter(workers),
+            &workers,
+            |b, &workers| {
+                b.to_async(&rt).iter_custom(|iters| async move {
+                    let mut total_time = Duration::ZERO;
+                    for n in 0..iters {
+                        total_time += run_observer(workers, n).await;
+                    }
+                    total_time
+                })
+            },
+        );
+    }
+    group.finish();
+}
+
+async fn run_observer(workers: usize, iteration: u64) -> Duration {
+    let genesis_hash = BlockHash::from_low_u64_ne(1);
+
+    // Start a server:
+    let mut server = start_server(
+        ServerOpts {
+            release_mode: true,
+            log_output: false,
+        },
+        CoreOpts::default(),
+        ShardOpts {
+            max_nodes_per_connection: Some(usize::MAX),
+            max_node_data_per_second: Some(usize::MAX),
+            ..Default::default()
+        },
+    )
+    .await;
+    let shard_id = server.add_shard().await.unwrap();
+
+    // Connect a bunch of nodes on the same chain:
+    let (mut node_tx, _node_rx) = server
+        .get_shard(shard_id)
+        .unwrap()
+        .connect_node()
+        .await
+        .expect("node can connect");
+    for n in 0..NUMBER_OF_NODES {
+        node_tx
+            .send_json_text(json!({
+                "id":n,
+                "ts":"2021-07-12T10:37:47.714666+01:00",
+                "payload": {
+                    "authority":true,
+                    "chain":"Local Testnet",
+                    "config":"",
+                    "genesis_hash": genesis_hash,
+                    "implementation":"Substrate Node",
+                    "msg":"system.connected",
+                    "name": format!("Node {}", n),
+                    "network_id": format!("12D3KooWNode{}", n),
+                    "startup_time":"1625565542717",
+                    "version":"2.0.0-07a1af348-aarch64-macos"
+                }
+            }))
+            .unwrap();
+    }
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // Start the observer, in a directory of its own, once the chain exists to subscribe to:
+    let dir = std::env::temp_dir().join(format!(
+        "observer-bench-{}-{}-{}",
+        std::process::id(),
+        workers,
+        iteration
+    ));
+    std::fs::create_dir_all(dir.join("data")).unwrap();
+    let mut observer = Command::new(env!("CARGO_BIN_EXE_telemetry-observer"))
+        .current_dir(&dir)
+        .arg("--genesis-hash")
+        .arg(format!("{:?}", genesis_hash))
+        .arg("--telemetry-url")
+        .arg(format!("ws://{}/feed", server.get_core().host()))
+        .arg("--workers")
+        .arg(workers.to_string())
+        .arg("--max-blocks")
+        .arg(NUMBER_OF_BLOCKS.to_string())
+        .env("RUST_LOG", "error")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .kill_on_drop(true)
+        .spawn()
+        .expect("observer should start");
+    tokio::time::sleep(Duration::from_secs(1)).await;
+
+    // Every node imports every block, plus one more so that the last is decided:
+    let start = Instant::now();
+    for height in 1..=NUMBER_OF_BLOCKS + 1 {
+        let import = |n: usize| {
+            json!({
+                "id":n,
+                "ts":"2021-07-12T10:37:47.714666+01:00",
+                "payload": {
+                    "msg":"block.import",
+                    "best": BlockHash::from_low_u64_be(height),
+                    "height": height,
+                    "origin":"Own"
+                }
+            })
+        };
+        node_tx.send_json_text(import(0)).unwrap();
+        tokio::time::sleep(FIRST_IMPORT_GAP).await;
+        for n in 1..NUMBER_OF_NODES {
+            node_tx.send_json_text(import(n)).unwrap();
+        }
+        tokio::time::sleep(BLOCK_GAP).await;
+    }
+
+    let status = tokio::time::timeout(Duration::from_secs(60), observer.wait())
+        .await
+        .expect("observer should stop once the blocks are attributed")
+        .expect("observer should run");
+    let elapsed = start.elapsed();
+    assert!(status.success(), "observer failed: {}", status);
+
+    server.shutdown().await;
+    let _ = std::fs::remove_dir_all(&dir);
+    elapsed
+}
+
+criterion_group! {
+    name = benches;
+    config = Criterion::default().sample_size(10);
+    targets = benchmark_observer_throughput
+}
+criterion_main!(benches);
@@ -0,0 +1,301 @@
+use anyhow::{anyhow, Result};
+use csv::{Reader, StringRecord, Writer};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+
+/// The columns written to each daily rollup file.
+const ROLLUP_HEADER: &[&str] = &[
+    "date",
+    "chain",
+    "genesis_hash",
+    "node_name",
+    "node_id",
+    "observed_blocks",
+    "attributed_blocks",
+    "share",
+    "mean_propagation_ms",
+    "coverage",
+];
+
+const SECS_PER_DAY: u64 = 86400;
+
+/// Where the columns we need are in an output file. Older files may not have all of them.
+struct Columns {
+    timestamp: usize,
+    chain: Option<usize>,
+    genesis_hash: Option<usize>,
+    node_name: usize,
+    node_id: Option<usize>,
+    block_number: usize,
+    block_hash: usize,
+    propagation_time: usize,
+}
+
+impl Columns {
+    fn find(headers: &StringRecord) -> Result<Self> {
+        let position = |name: &str| headers.iter().position(|h| h == name);
+        let required = |name: &str| position(name).ok_or_else(|| anyhow!("no {} column", name));
+        Ok(Self {
+            timestamp: required("timestamp")?,
+            chain: position("chain"),
+            genesis_hash: position("genesis_hash"),
+            node_name: required("node_name")?,
+            node_id: position("node_id"),
+            block_number: required("block_number")?,
+            block_hash: required("block_hash")?,
+            propagation_time: required("propagation_time")?,
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+struct BlockSummary {
+    block_number: u64,
+    /// When the block was first attributed; the block counts towards this day.
+    timestamp: u64,
+    chain: String,
+    /// The node(s) the block was attributed to, by ID, with their names.
+    authors: BTreeMap<String, String>,
+    propagation_time: u64,
+}
+
+#[derive(Debug, Default)]
+struct AuthorTally {
+    node_name: String,
+    /// As in the author report, tied blocks are shared out between the nodes that tied.
+    attributed: f64,
+    blocks: u64,
+    total_propagation_ms: u64,
+}
+
+/// Per-day, per-author rollups of the rows in one or more output CSV files. Rows for
+/// the same block are only counted once, so overlapping files can be given.
+#[derive(Debug, Default)]
+pub struct DailyRollup {
+    /// Keyed by genesis hash and then block hash.
+    blocks: BTreeMap<(String, String), BlockSummary>,
+}
+
+/// How many rows of a file were read, and how many couldn't be made sense of.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FileSummary {
+    pub rows: u64,
+    pub skipped: u64,
+}
+
+impl DailyRollup {
+    /// Read the rows of an output CSV file. Rows that can't be read (a truncated last
+    /// line, say) are skipped rather than failing the whole file.
+    pub fn add_file(&mut self, path: &Path) -> Result<FileSummary> {
+        let mut reader = Reader::from_path(path)?;
+        let columns = Columns::find(reader.headers()?)
+            .map_err(|e| anyhow!("{} doesn't look like an output file: {}", path.display(), e))?;
+        let mut summary = FileSummary::default();
+        for record in reader.records() {
+            let added = record
+                .map_err(anyhow::Error::from)
+                .and_then(|r| self.add_row(&columns, &r));
+            match added {
+                Ok(()) => summary.rows += 1,
+                Err(_) => summary.skipped += 1,
+            }
+        }
+        Ok(summary)
+    }
+
+    fn add_row(&mut self, columns: &Columns, record: &StringRecord) -> Result<()> {
+        let field = |i: usize| record.get(i).ok_or_else(|| anyhow!("row is too short"));
+        let optional = |i: Option<usize>| i.and_then(|i| record.get(i)).unwrap_or_default();
+
+        let timestamp: u64 = field(columns.timestamp)?.parse()?;
+        let block_number: u64 = field(columns.block_number)?.parse()?;
+        let propagation_time: u64 = field(columns.propagation_time)?.parse()?;
+        let node_name = field(columns.node_name)?;
+        let node_id = match optional(columns.node_id) {
+            "" => node_name,
+            node_id => node_id,
+        };
+
+        let key = (
+            optional(columns.genesis_hash).to_string(),
+            field(columns.block_hash)?.to_string(),
+        );
+        let block = self.blocks.entry(key).or_insert_with(|| BlockSummary {
+            block_number,
+            timestamp,
+            chain: optional(columns.chain).to_string(),
+            propagation_time,
+            ..Default::default()
+        });
+        block.timestamp = block.timestamp.min(timestamp);
+        block
+            .authors
+            .insert(node_id.to_string(), node_name.to_string());
+        Ok(())
+    }
+
+    /// Write a rollup file for each day into `out_dir`, replacing any that are already
+    /// there, and return the paths written to.
+    pub fn write(&self, out_dir: &Path) -> Result<Vec<PathBuf>> {
+        std::fs::create_dir_all(out_dir)?;
+
+        // Group the blocks by day, and within that by chain:
+        let mut days: BTreeMap<u64, BTreeMap<&str, Vec<&BlockSummary>>> = BTreeMap::new();
+        for ((genesis_hash, _), block) in &self.blocks {
+            days.entry(block.timestamp / SECS_PER_DAY)
+                .or_default()
+                .entry(genesis_hash.as_str())
+                .or_default()
+                .push(block);
+        }
+
+        let mut written = vec![];
+        for (day, chains) in days {
+            let date = format_date(day);
+            let path = out_dir.join(format!("authors-{}.csv", date));
+            let mut writer = Writer::from_path(&path)?;
+            writer.write_record(ROLLUP_HEADER)?;
+            for (genesis_hash, blocks) in chains {
+                write_chain_day(&mut writer, &date, genesis_hash, &blocks)?;
+            }
+            writer.flush()?;
+            written.push(path);
+        }
+        Ok(written)
+    }
+}
+
+fn write_chain_day<W: std::io::Write>(
+    writer: &mut Writer<W>,
+    date: &str,
+    genesis_hash: &str,
+    blocks: &[&BlockSummary],
+) -> Result<()> {
+    let observed_blocks = blocks.len();
+    // What fraction of the blocks produced that day did we see? Competing blocks at the
+    // same height only count once towards this.
+    let heights: BTreeSet<_> = blocks.iter().map(|b| b.block_number).collect();
+    let span = match (heights.first(), heights.last()) {
+        (Some(low), Some(high)) => high - low + 1,
+        _ => 0,
+    };
+    let coverage = if span == 0 {
+        0.0
+    } else {
+        heights.len() as f64 / span as f64
+    };
+    // The label may only have been known partway through the day:
+    let chain = blocks
+        .iter()
+        .map(|b| b.chain.as_str())
+        .find(|c| !c.is_empty())
+        .unwrap_or_default();
+
+    let mut tallies: HashMap<&str, AuthorTally> = HashMap::new();
+    for block in blocks {
+        let weight = 1.0 / block.authors.len() as f64;
+        for (node_id, node_name) in &block.authors {
+            let tally = tallies.entry(node_id).or_default();
+            tally.node_name = node_name.clone();
+            tally.attributed += weight;
+            tally.blocks += 1;
+            tally.total_propagation_ms += block.propagation_time;
+        }
+    }
+    let mut tallies: Vec<_> = tallies.into_iter().collect();
+    tallies.sort_by(|a, b| b.1.attributed.total_cmp(&a.1.attributed).then(a.0.cmp(b.0)));
+
+    for (node_id, tally) in tallies {
+        writer.write_record(&[
+            date.to_string(),
+            chain.to_string(),
+            genesis_hash.to_string(),
+            tally.node_name,
+            node_id.to_string(),
+            observed_blocks.to_string(),
+            format!("{:.2}", tally.attributed),
+            format!("{:.6}", tally.attributed / observed_blocks as f64),
+            format!(
+                "{:.1}",
+                tally.total_propagation_ms as f64 / tally.blocks as f64
+            ),
+            format!("{:.4}", coverage),
+        ])?;
+    }
+    Ok(())
+}
+
+/// The UTC date, as `YYYY-MM-DD`, of the given number of days since the Unix epoch.
+fn format_date(days: u64) -> String {
+    // See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    format!("{:04}-{:02}-{:02}", year, month, day)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats_dates() {
+        assert_eq!(format_date(0), "1970-01-01");
+        assert_eq!(format_date(19782), "2024-02-29");
+        assert_eq!(format_date(1_700_000_000 / SECS_PER_DAY), "2023-11-14");
+    }
+
+    #[test]
+    fn rolls_up_blocks_by_day_and_author() {
+        let dir = std::env::temp_dir().join(format!("observer-aggregate-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let input = dir.join("out.csv");
+        std::fs::write(
+            &input,
+            "timestamp,chain,genesis_hash,node_name,node_id,block_number,block_hash,propagation_time\n\
+             86399,Test,0x01,alice,a,1,0xb1,100\n\
+             86400,Test,0x01,bob,b,2,0xb2,200\n\
+             86401,Test,0x01,alice,a,2,0xb2,200\n\
+             86402,Test,0x01,alice,a,4,0xb4,300\n\
+             not,a,valid,row\n",
+        )
+        .unwrap();
+
+        let mut rollup = DailyRollup::default();
+        let summary = rollup.add_file(&input).unwrap();
+        assert_eq!(
+            summary,
+            FileSummary {
+                rows: 4,
+                skipped: 1
+            }
+        );
+        // Overlapping files don't count blocks twice:
+        rollup.add_file(&input).unwrap();
+        let written = rollup.write(&dir).unwrap();
+        assert_eq!(
+            written,
+            vec![
+                dir.join("authors-1970-01-01.csv"),
+                dir.join("authors-1970-01-02.csv")
+            ]
+        );
+
+        let day2 = std::fs::read_to_string(&written[1]).unwrap();
+        let rows: Vec<_> = day2.lines().skip(1).collect();
+        assert_eq!(
+            rows,
+            vec![
+                "1970-01-02,Test,0x01,alice,a,2,1.50,0.750000,250.0,0.6667",
+                "1970-01-02,Test,0x01,bob,b,2,0.50,0.250000,200.0,0.6667",
+            ]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,42 @@
+use anyhow::Result;
+use log::warn;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+
+/// Something noteworthy that an operator should probably look at.
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub timestamp: u64,
+    pub kind: &'static str,
+    pub node_name: Option<String>,
+    pub node_id: Option<String>,
+    pub message: String,
+    /// Anything else worth knowing that's specific to the kind of alert.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<serde_json::Value>,
+}
+
+/// Alerts are logged, and also appended to a file (one JSON object per line) so
+/// that there is a record of them after the fact.
+#[derive(Debug)]
+pub struct AlertLog {
+    file: File,
+}
+
+impl AlertLog {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file })
+    }
+
+    pub fn raise(&mut self, alert: &Alert) -> Result<()> {
+        warn!("ALERT [{}] {}", alert.kind, alert.message);
+        let mut line = serde_json::to_vec(alert)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.flush()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,107 @@
+use crate::csv_file;
+use anyhow::Result;
+use csv::Writer;
+use log::info;
+use ring::hmac;
+use std::collections::HashSet;
+use std::fs::File;
+use std::path::Path;
+
+/// The columns written to the mapping CSV file.
+const MAPPING_HEADER: &[&str] = &["pseudonym", "kind", "value"];
+
+/// How many bytes of the HMAC to keep. 8 bytes makes collisions vanishingly unlikely
+/// for the number of nodes on a network, while keeping pseudonyms short.
+const PSEUDONYM_BYTES: usize = 8;
+
+/// Replaces node names and network IDs with stable, salted hashes, so that datasets
+/// can be published without exposing operator identities. The same salt always gives
+/// the same pseudonym for a given value. Each pseudonym handed out is recorded next to
+/// the real value in a local mapping file for the operator's own use.
+pub struct Anonymizer {
+    key: hmac::Key,
+    mapping: Writer<File>,
+    recorded: HashSet<String>,
+}
+
+impl std::fmt::Debug for Anonymizer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Anonymizer")
+            .field("recorded", &self.recorded.len())
+            .finish()
+    }
+}
+
+impl Anonymizer {
+    pub fn open(salt: &str, mapping_file: &Path) -> Result<Self> {
+        // Don't record the same pseudonyms again each time we restart:
+        let mut recorded = HashSet::new();
+        if let Ok(mut reader) = csv::Reader::from_path(mapping_file) {
+            for record in reader.records().filter_map(|r| r.ok()) {
+                if let Some(pseudonym) = record.get(0) {
+                    recorded.insert(pseudonym.to_string());
+                }
+            }
+        }
+        info!(
+            "Anonymizing node identities; {} known pseudonyms in {:?}",
+            recorded.len(),
+            mapping_file
+        );
+
+        Ok(Self {
+            key: hmac::Key::new(hmac::HMAC_SHA256, salt.as_bytes()),
+            mapping: csv_file::open_with_header(mapping_file, MAPPING_HEADER)?,
+            recorded,
+        })
+    }
+
+    pub fn node_name(&mut self, name: &str) -> Result<String> {
+        self.anonymize("name", name)
+    }
+
+    pub fn node_id(&mut self, node_id: &str) -> Result<String> {
+        self.anonymize("id", node_id)
+    }
+
+    fn anonymize(&mut self, kind: &str, value: &str) -> Result<String> {
+        let pseudonym = pseudonym(&self.key, kind, value);
+        if self.recorded.insert(pseudonym.clone()) {
+            self.mapping
+                .write_record([pseudonym.as_str(), kind, value])?;
+            self.mapping.flush()?;
+        }
+        Ok(pseudonym)
+    }
+}
+
+/// Eg `name-1a2b3c4d5e6f7a8b`. The kind is hashed too, so that a node
+/// whose name happens to equal another's ID doesn't get a matching pseudonym.
+fn pseudonym(key: &hmac::Key, kind: &str, value: &str) -> String {
+    let mut ctx = hmac::Context::with_key(key);
+    ctx.update(kind.as_bytes());
+    ctx.update(&[0]);
+    ctx.update(value.as_bytes());
+    let tag = ctx.sign();
+    format!("{}-{}", kind, hex::encode(&tag.as_ref()[..PSEUDONYM_BYTES]))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn pseudonyms_are_stable_and_salted() {
+        let key = hmac::Key::new(hmac::HMAC_SHA256, b"salt");
+        let other_key = hmac::Key::new(hmac::HMAC_SHA256, b"pepper");
+
+        let a = pseudonym(&key, "name", "Alice");
+        assert_eq!(a, pseudonym(&key, "name", "Alice"));
+        assert_eq!(a.len(), "name-".len() + PSEUDONYM_BYTES * 2);
+        assert!(a.starts_with("name-"));
+
+        assert_ne!(a, pseudonym(&key, "name", "Bob"));
+        assert_ne!(a, pseudonym(&other_key, "name", "Alice"));
+        assert_ne!(a[5..], pseudonym(&key, "id", "Alice")[3..]);
+    }
+}
@@ -0,0 +1,290 @@
+use crate::calibration::{Calibration, TrueAuthor};
+use crate::chain::ChainIdentity;
+use crate::csv_file;
+use crate::rpc::RpcClient;
+use crate::state::Nodes;
+use anyhow::{anyhow, Result};
+use csv::Writer;
+use log::{debug, warn};
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::{mpsc, Mutex};
+
+/// The columns written to the block authors CSV file.
+const AUTHORS_HEADER: &[&str] = &[
+    "block_number",
+    "block_hash",
+    "chain",
+    "genesis_hash",
+    "author_account",
+    "author_node_name",
+    "author_node_id",
+    "attributed_node_ids",
+];
+
+/// How many decided blocks can be waiting to have their authors looked up. Lookups take
+/// a couple of RPC requests each, so if the node falls this far behind, blocks are
+/// skipped rather than held up.
+const QUEUE_BLOCKS: usize = 256;
+
+/// `DigestItem::PreRuntime`, which is where block production engines say who's authoring.
+const PRE_RUNTIME_DIGEST: u8 = 6;
+const BABE_ENGINE_ID: &[u8; 4] = b"BABE";
+const AURA_ENGINE_ID: &[u8; 4] = b"aura";
+
+/// What a block's pre-runtime digest says about who authored it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthorClaim {
+    /// BABE gives the index of the author in the epoch's authorities.
+    Babe { authority_index: u32 },
+    /// Aura only gives the slot; authorities take turns, one slot each.
+    Aura { slot: u64 },
+}
+
+impl AuthorClaim {
+    /// Which of `authorities` authored the block.
+    pub fn author_index(self, authorities: usize) -> Option<usize> {
+        let index = match self {
+            AuthorClaim::Babe { authority_index } => authority_index as usize,
+            AuthorClaim::Aura { slot } => (slot % authorities.max(1) as u64) as usize,
+        };
+        (index < authorities).then_some(index)
+    }
+}
+
+/// Find the author claim among a block's digest logs (each as hex), if there is one.
+pub fn author_claim(logs: &[String]) -> Option<AuthorClaim> {
+    logs.iter().find_map(|log| {
+        let bytes = hex::decode(log.trim_start_matches("0x")).ok()?;
+        let (&kind, rest) = bytes.split_first()?;
+        if kind != PRE_RUNTIME_DIGEST || rest.len() < 4 {
+            return None;
+        }
+        let (engine, rest) = rest.split_at(4);
+        let (len, offset) = crate::rpc::decode_compact(rest)?;
+        let data = rest.get(offset..offset + len as usize)?;
+        if engine == BABE_ENGINE_ID {
+            // The variant of `PreDigest`, followed by the authority index for all of them:
+            let index = data.get(1..5)?;
+            Some(AuthorClaim::Babe {
+                authority_index: u32::from_le_bytes(index.try_into().ok()?),
+            })
+        } else if engine == AURA_ENGINE_ID {
+            Some(AuthorClaim::Aura {
+                slot: u64::from_le_bytes(data.get(..8)?.try_into().ok()?),
+            })
+        } else {
+            None
+        }
+    })
+}
+
+/// The account that a validator address reported to telemetry refers to. These are
+/// normally SS58 addresses, but hex is accepted too. The SS58 checksum isn't checked;
+/// an address that's been mangled just won't match anything.
+pub fn validator_account(address: &str) -> Option<[u8; 32]> {
+    let bytes = match address.strip_prefix("0x") {
+        Some(hex_str) => hex::decode(hex_str).ok()?,
+        None => {
+            let bytes = base58_decode(address)?;
+            // A one or two byte network prefix, the account, and a two byte checksum:
+            match bytes.len() {
+                35 => bytes[1..33].to_vec(),
+                36 => bytes[2..34].to_vec(),
+                _ => return None,
+            }
+        }
+    };
+    bytes.try_into().ok()
+}
+
+fn base58_decode(s: &str) -> Option<Vec<u8>> {
+    const ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+    // Little-endian, for as long as the number needs:
+    let mut number: Vec<u8> = vec![];
+    for c in s.bytes() {
+        let mut carry = ALPHABET.iter().position(|&a| a == c)? as u32;
+        for byte in number.iter_mut() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            number.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+    // Each leading '1' is a leading zero byte:
+    let zeros = s.bytes().take_while(|&c| c == b'1').count();
+    let mut bytes = vec![0u8; zeros];
+    bytes.extend(number.iter().rev());
+    Some(bytes)
+}
+
+/// A block that's been decided, and the node(s) it was attributed to by name and ID.
+#[derive(Debug, Clone)]
+pub struct DecidedBlock {
+    pub block_number: u64,
+    pub block_hash: String,
+    pub attributed: Vec<(String, String)>,
+}
+
+/// Look up who really authored each block sent to the returned sender, using the
+/// given RPC node, recording it in the block authors file and counting it towards
+/// `calibration`. Authors' accounts are left out when `hide_accounts` is set.
+pub fn spawn_checker(
+    rpc_url: &str,
+    nodes: Arc<Mutex<Nodes>>,
+    chain: Arc<Mutex<ChainIdentity>>,
+    authors_file: &Path,
+    calibration: Arc<Mutex<Calibration>>,
+    hide_accounts: bool,
+) -> Result<mpsc::Sender<DecidedBlock>> {
+    let mut client = RpcClient::new(rpc_url)?;
+    let mut writer = csv_file::open_with_header(authors_file, AUTHORS_HEADER)?;
+    let (tx, mut rx) = mpsc::channel::<DecidedBlock>(QUEUE_BLOCKS);
+
+    tokio::spawn(async move {
+        while let Some(block) = rx.recv().await {
+            let author = match true_author(&mut client, &nodes, &block.block_hash).await {
+                Ok(author) => author,
+                Err(e) => {
+                    warn!(
+                        "Failed to look up the author of block #{} ({}): {}",
+                        block.block_number, block.block_hash, e
+                    );
+                    continue;
+                }
+            };
+            let author = TrueAuthor {
+                account: if hide_accounts {
+                    String::new()
+                } else {
+                    author.account
+                },
+                ..author
+            };
+            let chain = chain.lock().await.clone();
+            if let Err(e) = write_author(&mut writer, &block, &author, &chain) {
+                warn!("Failed to write to the block authors file: {}", e);
+            }
+
+            let attributed: Vec<_> = block
+                .attributed
+                .iter()
+                .map(|(name, id)| (name.as_str(), id.as_str()))
+                .collect();
+            let now = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            let mut calibration = calibration.lock().await;
+            calibration.record(&attributed, &author);
+            if let Err(e) = calibration.maybe_write(now, &chain) {
+                warn!("Failed to write the calibration report: {}", e);
+            }
+        }
+    });
+    Ok(tx)
+}
+
+/// Work out who authored a block from its header and the validators at the time, and
+/// which node (if any) reports to telemetry as that validator.
+async fn true_author(
+    client: &mut RpcClient,
+    nodes: &Mutex<Nodes>,
+    block_hash: &str,
+) -> Result<TrueAuthor> {
+    let logs = client.digest_logs(block_hash).await?;
+    let claim = author_claim(&logs).ok_or_else(|| anyhow!("no BABE or Aura pre-runtime digest"))?;
+    let validators = client.session_validators(Some(block_hash)).await?;
+    let index = claim
+        .author_index(validators.len())
+        .ok_or_else(|| anyhow!("{:?} is out of range of the validators", claim))?;
+    let account = validators[index];
+    debug!(
+        "Block {} was authored by 0x{}",
+        block_hash,
+        hex::encode(account)
+    );
+
+    let nodes = nodes.lock().await;
+    let node = nodes.records().values().find(|node| {
+        node.validator
+            .as_deref()
+            .and_then(validator_account)
+            .is_some_and(|validator| validator == account)
+    });
+    Ok(TrueAuthor {
+        account: format!("0x{}", hex::encode(account)),
+        node_name: node.map(|n| n.name.clone()).unwrap_or_default(),
+        node_id: node.map(|n| n.node_id.clone()).unwrap_or_default(),
+    })
+}
+
+fn write_author(
+    writer: &mut Writer<File>,
+    block: &DecidedBlock,
+    author: &TrueAuthor,
+    chain: &ChainIdentity,
+) -> Result<()> {
+    let attributed: Vec<_> = block.attributed.iter().map(|(_, id)| id.as_str()).collect();
+    writer.write_record(&[
+        block.block_number.to_string(),
+        block.block_hash.clone(),
+        chain.label.clone(),
+        chain.genesis_hash.clone(),
+        author.account.clone(),
+        author.node_name.clone(),
+        author.node_id.clone(),
+        attributed.join(";"),
+    ])?;
+    writer.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_babe_and_aura_authors_in_digests() {
+        // A seal, then a BABE secondary plain pre-digest for authority 5 at slot 0x10:
+        let babe = vec![
+            "0x0542414245080102".to_string(),
+            "0x06424142453402050000001000000000000000".to_string(),
+        ];
+        let claim = author_claim(&babe).unwrap();
+        assert_eq!(claim, AuthorClaim::Babe { authority_index: 5 });
+        assert_eq!(claim.author_index(10), Some(5));
+        assert_eq!(claim.author_index(5), None);
+
+        let aura = vec!["0x0661757261200b00000000000000".to_string()];
+        let claim = author_claim(&aura).unwrap();
+        assert_eq!(claim, AuthorClaim::Aura { slot: 11 });
+        assert_eq!(claim.author_index(4), Some(3));
+
+        assert_eq!(author_claim(&["0x0442414245".to_string()]), None);
+    }
+
+    #[test]
+    fn decodes_validator_addresses() {
+        // Alice's well-known development account, on the generic substrate network:
+        let alice = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY";
+        let account =
+            hex::decode("d43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d")
+                .unwrap();
+        assert_eq!(
+            validator_account(alice).map(|a| a.to_vec()),
+            Some(account.clone())
+        );
+        assert_eq!(
+            validator_account(&format!("0x{}", hex::encode(&account))).map(|a| a.to_vec()),
+            Some(account)
+        );
+        assert_eq!(validator_account("not-an-address!"), None);
+        assert_eq!(validator_account("5Grwva"), None);
+    }
+}
@@ -0,0 +1,207 @@
+use crate::alerts::Alert;
+use log::info;
+use serde_json::json;
+use std::collections::VecDeque;
+
+/// How far back the block rate looks (in seconds). Over much less than this, the odd
+/// missed slot moves the rate around too much to say anything about the chain.
+pub const WINDOW_SECS: u64 = 300;
+
+/// When the chain counts as having slowed down: when the block rate has been under
+/// `fraction` of the expected rate for at least `sustained_secs`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SlowdownRule {
+    pub fraction: f64,
+    pub sustained_secs: u64,
+}
+
+impl Default for SlowdownRule {
+    fn default() -> Self {
+        Self {
+            fraction: 0.5,
+            sustained_secs: 600,
+        }
+    }
+}
+
+/// Keeps a rolling count of how many new best blocks the chain produces a minute,
+/// and spots it producing them much more slowly than its slot duration says it should.
+#[derive(Debug)]
+pub struct BlockRate {
+    /// `None` if we don't know the chain's slot duration, in which case the rate is
+    /// still kept, but never alerted about.
+    expected_per_minute: Option<f64>,
+    rule: SlowdownRule,
+    /// When we first heard of a best block. Until there's been a whole window since
+    /// then, there's no rate to give.
+    first_seen: Option<u64>,
+    /// Each new best block and when it was seen, going back to the last one seen
+    /// before the start of the window.
+    bests: VecDeque<(u64, u64)>,
+    slow_since: Option<u64>,
+    alerted: bool,
+}
+
+impl BlockRate {
+    pub fn new(slot_duration_ms: Option<u64>, rule: SlowdownRule) -> Self {
+        Self {
+            expected_per_minute: slot_duration_ms
+                .filter(|&ms| ms > 0)
+                .map(|ms| 60_000.0 / ms as f64),
+            rule,
+            first_seen: None,
+            bests: VecDeque::new(),
+            slow_since: None,
+            alerted: false,
+        }
+    }
+
+    /// Note the chain's best block. Only new best blocks count; the feed tells us
+    /// about the same one again whenever we reconnect.
+    pub fn saw_best(&mut self, block_number: u64, now: u64) {
+        self.first_seen.get_or_insert(now);
+        if self.bests.back().is_none_or(|&(_, n)| block_number > n) {
+            self.bests.push_back((now, block_number));
+        }
+        self.forget_before(now);
+    }
+
+    fn forget_before(&mut self, now: u64) {
+        let window_start = now.saturating_sub(WINDOW_SECS);
+        while self.bests.len() > 1 && self.bests[1].0 <= window_start {
+            self.bests.pop_front();
+        }
+    }
+
+    /// Blocks a minute, averaged over the window up to `now`.
+    pub fn per_minute(&self, now: u64) -> Option<f64> {
+        let first_seen = self.first_seen?;
+        if now.saturating_sub(first_seen) < WINDOW_SECS {
+            return None;
+        }
+        let window_start = now - WINDOW_SECS;
+        let (_, latest) = *self.bests.back()?;
+        let (_, before) = *self.bests.iter().rev().find(|&&(t, _)| t <= window_start)?;
+        Some((latest - before) as f64 * 60.0 / WINDOW_SECS as f64)
+    }
+
+    /// Return an alert if the chain has newly slowed down.
+    pub fn check(&mut self, now: u64) -> Option<Alert> {
+        self.forget_before(now);
+        let expected = self.expected_per_minute?;
+        let rate = self.per_minute(now)?;
+
+        if rate >= expected * self.rule.fraction {
+            if self.alerted {
+                info!(
+                    "The chain is producing {:.1} blocks a minute again (expected {:.1})",
+                    rate, expected
+                );
+            }
+            self.slow_since = None;
+            self.alerted = false;
+            return None;
+        }
+
+        let slow_since = *self.slow_since.get_or_insert(now);
+        if self.alerted || now - slow_since < self.rule.sustained_secs {
+            return None;
+        }
+        self.alerted = true;
+        Some(Alert {
+            timestamp: now,
+            kind: "chain_slowdown",
+            node_name: None,
+            node_id: None,
+            message: format!(
+                "The chain has been producing {:.1} blocks a minute for over {:.1} minutes, under {:.0}% of the {:.1} expected",
+                rate,
+                self.rule.sustained_secs as f64 / 60.0,
+                self.rule.fraction * 100.0,
+                expected
+            ),
+            details: Some(json!({
+                "blocks_per_minute": rate,
+                "expected_blocks_per_minute": expected,
+            })),
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// A 6 second chain, which should produce 10 blocks a minute.
+    fn six_second_chain() -> BlockRate {
+        BlockRate::new(
+            Some(6000),
+            SlowdownRule {
+                fraction: 0.5,
+                sustained_secs: 600,
+            },
+        )
+    }
+
+    #[test]
+    fn rate_is_averaged_over_the_window() {
+        let mut rate = six_second_chain();
+        for t in (0..=WINDOW_SECS).step_by(6) {
+            rate.saw_best(t / 6, t);
+            // Seeing the same best block again doesn't count:
+            rate.saw_best(t / 6, t + 1);
+        }
+        assert_eq!(rate.per_minute(WINDOW_SECS - 1), None);
+        assert_eq!(rate.per_minute(WINDOW_SECS), Some(10.0));
+        assert_eq!(rate.per_minute(2 * WINDOW_SECS), Some(0.0));
+    }
+
+    #[test]
+    fn alerts_once_when_slow_for_long_enough() {
+        let mut rate = six_second_chain();
+        let mut block = 0;
+        let mut alerted_at = vec![];
+        let mut produce = |rate: &mut BlockRate, from: u64, to: u64, every: u64| {
+            for t in (from..to).step_by(every as usize) {
+                block += 1;
+                rate.saw_best(block, t);
+                if rate.check(t).is_some() {
+                    alerted_at.push(t);
+                }
+            }
+        };
+
+        // Healthy for a while, then a block every 24 seconds (2.5 a minute) for an hour:
+        produce(&mut rate, 0, 1200, 6);
+        produce(&mut rate, 1200, 4800, 24);
+        // Healthy again, and then a block a minute:
+        produce(&mut rate, 4800, 6000, 6);
+        produce(&mut rate, 6000, 9600, 60);
+
+        assert_eq!(alerted_at.len(), 2, "alerted at {:?}", alerted_at);
+        // Once the average is under half the expected rate, it has to stay there for
+        // the whole sustained period:
+        assert!(alerted_at[0] >= 1200 + 600 && alerted_at[0] <= 1200 + WINDOW_SECS + 600);
+        assert!(alerted_at[1] >= 6000 + 600 && alerted_at[1] <= 6000 + WINDOW_SECS + 600);
+    }
+
+    #[test]
+    fn slowdown_alert_has_the_rates() {
+        let mut rate = six_second_chain();
+        rate.saw_best(1, 0);
+        assert!(rate.check(WINDOW_SECS).is_none());
+        let alert = rate.check(WINDOW_SECS + 600).unwrap();
+        assert_eq!(alert.kind, "chain_slowdown");
+        let details = alert.details.unwrap();
+        assert_eq!(details["blocks_per_minute"], 0.0);
+        assert_eq!(details["expected_blocks_per_minute"], 10.0);
+    }
+
+    #[test]
+    fn no_alerts_without_a_slot_duration() {
+        let mut rate = BlockRate::new(None, SlowdownRule::default());
+        rate.saw_best(1, 0);
+        assert_eq!(rate.per_minute(3 * WINDOW_SECS), Some(0.0));
+        assert!(rate.check(3 * WINDOW_SECS).is_none());
+    }
+}
@@ -0,0 +1,428 @@
+use crate::chain::ChainIdentity;
+use crate::csv_file;
+use anyhow::{anyhow, Result};
+use csv::{Reader, Writer};
+use log::info;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+/// The columns written to the calibration report CSV file.
+const CALIBRATION_HEADER: &[&str] = &[
+    "period_start",
+    "period_end",
+    "chain",
+    "genesis_hash",
+    "node_name",
+    "node_id",
+    "attributed_blocks",
+    "correct_blocks",
+    "precision",
+    "authored_blocks",
+    "recalled_blocks",
+    "recall",
+    "confused_with",
+    "confused_blocks",
+];
+
+/// Who really authored a block, according to its header.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrueAuthor {
+    /// The author's account, as hex. Empty if it's being kept out of the outputs.
+    pub account: String,
+    /// The node reporting to telemetry as the author's validator, if there is one.
+    pub node_name: String,
+    pub node_id: String,
+}
+
+impl TrueAuthor {
+    fn is_known_node(&self) -> bool {
+        !self.node_id.is_empty()
+    }
+
+    /// How to refer to the author when something else was attributed its block.
+    fn label(&self) -> &str {
+        match (self.is_known_node(), self.account.is_empty()) {
+            (true, _) => &self.node_name,
+            (false, false) => &self.account,
+            (false, true) => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct NodeTally {
+    node_name: String,
+    /// Blocks attributed to the node, and how many of those it really authored.
+    attributed: u64,
+    correct: u64,
+    /// Blocks the node really authored, and how many of those it was attributed.
+    authored: u64,
+    recalled: u64,
+    /// For blocks wrongly attributed to the node, who really authored them.
+    confused_with: HashMap<String, u64>,
+}
+
+/// How well the blocks attributed to nodes match up with who really authored them.
+#[derive(Debug, Default)]
+pub struct Tally {
+    blocks: u64,
+    attributions: u64,
+    correct_attributions: u64,
+    /// Blocks whose author reports to telemetry, which are the only ones we could get right.
+    known_author_blocks: u64,
+    recalled_blocks: u64,
+    nodes: HashMap<String, NodeTally>,
+}
+
+impl Tally {
+    /// Record the node(s) (by name and ID) that a block was attributed to, along with
+    /// who really authored it.
+    pub fn record(&mut self, attributed: &[(&str, &str)], author: &TrueAuthor) {
+        self.blocks += 1;
+        let mut recalled = false;
+        for (node_name, node_id) in attributed {
+            let correct = author.is_known_node() && *node_id == author.node_id;
+            recalled |= correct;
+            self.attributions += 1;
+            if correct {
+                self.correct_attributions += 1;
+            }
+            let tally = self.node(node_name, node_id);
+            tally.attributed += 1;
+            if correct {
+                tally.correct += 1;
+            } else {
+                *tally
+                    .confused_with
+                    .entry(author.label().to_string())
+                    .or_default() += 1;
+            }
+        }
+        if author.is_known_node() {
+            self.known_author_blocks += 1;
+            let tally = self.node(&author.node_name, &author.node_id);
+            tally.authored += 1;
+            if recalled {
+                tally.recalled += 1;
+                self.recalled_blocks += 1;
+            }
+        }
+    }
+
+    fn node(&mut self, node_name: &str, node_id: &str) -> &mut NodeTally {
+        let tally = self.nodes.entry(node_id.to_string()).or_default();
+        tally.node_name = node_name.to_string();
+        tally
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks == 0
+    }
+
+    /// The fraction of attributions that were to the node that really authored the block.
+    pub fn precision(&self) -> Option<f64> {
+        ratio(self.correct_attributions, self.attributions)
+    }
+
+    /// The fraction of blocks authored by nodes reporting to telemetry that were
+    /// attributed to them.
+    pub fn recall(&self) -> Option<f64> {
+        ratio(self.recalled_blocks, self.known_author_blocks)
+    }
+
+    /// A line or two summing up the tally, for logs and the terminal.
+    pub fn summary(&self) -> String {
+        format!(
+            "{} blocks checked, {} by nodes reporting to telemetry; precision {} ({} of {} attributions), recall {} ({} of {} blocks)",
+            self.blocks,
+            self.known_author_blocks,
+            format_ratio(self.precision()),
+            self.correct_attributions,
+            self.attributions,
+            format_ratio(self.recall()),
+            self.recalled_blocks,
+            self.known_author_blocks
+        )
+    }
+
+    /// A row for every node, less the period and chain, with one for all of them first.
+    fn rows(&self) -> Vec<Vec<String>> {
+        let mut rows = vec![vec![
+            String::new(),
+            "all".to_string(),
+            self.attributions.to_string(),
+            self.correct_attributions.to_string(),
+            format_ratio(self.precision()),
+            self.known_author_blocks.to_string(),
+            self.recalled_blocks.to_string(),
+            format_ratio(self.recall()),
+            String::new(),
+            (self.attributions - self.correct_attributions).to_string(),
+        ]];
+
+        let mut nodes: Vec<_> = self.nodes.iter().collect();
+        nodes.sort_by(|a, b| {
+            (b.1.attributed + b.1.authored)
+                .cmp(&(a.1.attributed + a.1.authored))
+                .then(a.0.cmp(b.0))
+        });
+        for (node_id, tally) in nodes {
+            // Only the author that the node is most often mistaken for is given:
+            let confused = tally
+                .confused_with
+                .iter()
+                .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0)));
+            rows.push(vec![
+                tally.node_name.clone(),
+                node_id.clone(),
+                tally.attributed.to_string(),
+                tally.correct.to_string(),
+                format_ratio(ratio(tally.correct, tally.attributed)),
+                tally.authored.to_string(),
+                tally.recalled.to_string(),
+                format_ratio(ratio(tally.recalled, tally.authored)),
+                confused.map(|(who, _)| who.clone()).unwrap_or_default(),
+                confused.map(|(_, n)| n.to_string()).unwrap_or_default(),
+            ]);
+        }
+        rows
+    }
+
+    /// The rows of the tally as a table, for the terminal.
+    pub fn table(&self) -> String {
+        let mut out = format!(
+            "{:<32} {:>10} {:>8} {:>9} {:>8} {:>8} {:>7}  {}\n",
+            "node",
+            "attributed",
+            "correct",
+            "precision",
+            "authored",
+            "recalled",
+            "recall",
+            "most confused with"
+        );
+        for row in self.rows() {
+            let name = if row[0].is_empty() { &row[1] } else { &row[0] };
+            let confused = if row[8].is_empty() {
+                String::new()
+            } else {
+                format!("{} ({})", row[8], row[9])
+            };
+            out.push_str(&format!(
+                "{:<32} {:>10} {:>8} {:>9} {:>8} {:>8} {:>7}  {}\n",
+                name, row[2], row[3], row[4], row[5], row[6], row[7], confused
+            ));
+        }
+        out
+    }
+}
+
+fn ratio(n: u64, d: u64) -> Option<f64> {
+    (d > 0).then(|| n as f64 / d as f64)
+}
+
+fn format_ratio(ratio: Option<f64>) -> String {
+    ratio.map(|r| format!("{:.4}", r)).unwrap_or_default()
+}
+
+/// Keeps a tally of how well blocks are being attributed while the observer runs,
+/// appending it to the calibration report file at the end of each period.
+#[derive(Debug)]
+pub struct Calibration {
+    writer: Writer<File>,
+    period_secs: u64,
+    period_start: u64,
+    tally: Tally,
+}
+
+impl Calibration {
+    pub fn new(path: &Path, period_secs: u64, now: u64) -> Result<Self> {
+        Ok(Self {
+            writer: csv_file::open_with_header(path, CALIBRATION_HEADER)?,
+            period_secs,
+            period_start: now,
+            tally: Tally::default(),
+        })
+    }
+
+    pub fn record(&mut self, attributed: &[(&str, &str)], author: &TrueAuthor) {
+        self.tally.record(attributed, author);
+    }
+
+    /// Write out the report if the current period is over, and start a new one.
+    pub fn maybe_write(&mut self, now: u64, chain: &ChainIdentity) -> Result<()> {
+        if now.saturating_sub(self.period_start) < self.period_secs {
+            return Ok(());
+        }
+        self.finish_period(now, chain)
+    }
+
+    /// Write out the report for the current period so far, and start a new one.
+    pub fn finish_period(&mut self, now: u64, chain: &ChainIdentity) -> Result<()> {
+        if self.tally.is_empty() {
+            info!("No block authors were checked this period; skipping calibration report");
+        } else {
+            info!("Attribution calibration: {}", self.tally.summary());
+            for row in self.tally.rows() {
+                let mut record = vec![
+                    self.period_start.to_string(),
+                    now.to_string(),
+                    chain.label.clone(),
+                    chain.genesis_hash.clone(),
+                ];
+                record.extend(row);
+                self.writer.write_record(&record)?;
+            }
+            self.writer.flush()?;
+        }
+
+        self.period_start = now;
+        self.tally = Tally::default();
+        Ok(())
+    }
+}
+
+/// Tally up how well the blocks in output CSV files were attributed, going by the
+/// true authors in block author files. Only blocks in both are counted.
+pub fn calibrate(output_files: &[PathBuf], author_files: &[PathBuf]) -> Result<Tally> {
+    let mut authors: HashMap<String, TrueAuthor> = HashMap::new();
+    for path in author_files {
+        let mut reader = Reader::from_path(path)?;
+        let headers = reader.headers()?.clone();
+        let columns = [
+            "block_hash",
+            "author_account",
+            "author_node_name",
+            "author_node_id",
+        ]
+        .map(|name| headers.iter().position(|h| h == name));
+        let [Some(block_hash), Some(account), Some(node_name), Some(node_id)] = columns else {
+            return Err(anyhow!(
+                "{} doesn't look like a block authors file",
+                path.display()
+            ));
+        };
+        for record in reader.records().filter_map(|r| r.ok()) {
+            let field = |i: usize| record.get(i).unwrap_or_default().to_string();
+            authors.insert(
+                field(block_hash),
+                TrueAuthor {
+                    account: field(account),
+                    node_name: field(node_name),
+                    node_id: field(node_id),
+                },
+            );
+        }
+    }
+
+    // Blocks can be in more than one output file, so gather up who they were attributed
+    // to first:
+    let mut attributed: BTreeMap<String, BTreeSet<(String, String)>> = BTreeMap::new();
+    for path in output_files {
+        let mut reader = Reader::from_path(path)?;
+        let headers = reader.headers()?.clone();
+        let columns = ["block_hash", "node_name", "node_id"]
+            .map(|name| headers.iter().position(|h| h == name));
+        let [Some(block_hash), Some(node_name), Some(node_id)] = columns else {
+            return Err(anyhow!(
+                "{} doesn't look like an output file",
+                path.display()
+            ));
+        };
+        for record in reader.records().filter_map(|r| r.ok()) {
+            let field = |i: usize| record.get(i).unwrap_or_default().to_string();
+            if authors.contains_key(&field(block_hash)) {
+                attributed
+                    .entry(field(block_hash))
+                    .or_default()
+                    .insert((field(node_name), field(node_id)));
+            }
+        }
+    }
+
+    let mut tally = Tally::default();
+    for (block_hash, nodes) in &attributed {
+        let nodes: Vec<_> = nodes
+            .iter()
+            .map(|(name, id)| (name.as_str(), id.as_str()))
+            .collect();
+        tally.record(&nodes, &authors[block_hash]);
+    }
+    Ok(tally)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn author(node: Option<&str>, account: &str) -> TrueAuthor {
+        TrueAuthor {
+            account: account.to_string(),
+            node_name: node.unwrap_or_default().to_string(),
+            node_id: node.map(|n| format!("{}-id", n)).unwrap_or_default(),
+        }
+    }
+
+    #[test]
+    fn precision_recall_and_confusion_are_tallied() {
+        let mut tally = Tally::default();
+        // Right, wrong (the RPC node beat alice), a tie that includes the author, and
+        // a block by a validator that doesn't report to telemetry:
+        tally.record(&[("alice", "alice-id")], &author(Some("alice"), "0xaa"));
+        tally.record(&[("rpc", "rpc-id")], &author(Some("alice"), "0xaa"));
+        tally.record(&[("rpc", "rpc-id")], &author(Some("alice"), "0xaa"));
+        tally.record(
+            &[("bob", "bob-id"), ("rpc", "rpc-id")],
+            &author(Some("bob"), "0xbb"),
+        );
+        tally.record(&[("rpc", "rpc-id")], &author(None, "0xcc"));
+
+        assert_eq!(tally.precision(), Some(2.0 / 6.0));
+        assert_eq!(tally.recall(), Some(2.0 / 4.0));
+
+        let rows = tally.rows();
+        assert_eq!(rows[0][1], "all");
+        let rpc = rows.iter().find(|row| row[1] == "rpc-id").unwrap();
+        assert_eq!(&rpc[2..4], &["4", "0"]);
+        assert_eq!(&rpc[8..10], &["alice", "2"]);
+        let alice = rows.iter().find(|row| row[1] == "alice-id").unwrap();
+        assert_eq!(&alice[5..8], &["3", "1", "0.3333"]);
+    }
+
+    #[test]
+    fn calibrates_output_files_against_block_authors_files() {
+        let dir = std::env::temp_dir().join(format!("observer-calibration-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("out.csv");
+        std::fs::write(
+            &output,
+            "timestamp,node_name,node_id,block_number,block_hash\n\
+             1,alice,alice-id,1,0xb1\n\
+             2,rpc,rpc-id,2,0xb2\n\
+             3,rpc,rpc-id,3,0xb3\n",
+        )
+        .unwrap();
+        let authors = dir.join("authors.csv");
+        std::fs::write(
+            &authors,
+            "block_number,block_hash,chain,genesis_hash,author_account,author_node_name,author_node_id,attributed_node_ids\n\
+             1,0xb1,Test,0x01,0xaa,alice,alice-id,alice-id\n\
+             2,0xb2,Test,0x01,0xbb,bob,bob-id,rpc-id\n",
+        )
+        .unwrap();
+
+        // Overlapping output files don't count blocks twice, and blocks with no known
+        // author are left out:
+        let tally = calibrate(
+            &[output.clone(), output.clone()],
+            std::slice::from_ref(&authors),
+        )
+        .unwrap();
+        assert_eq!(tally.precision(), Some(0.5));
+        assert_eq!(tally.recall(), Some(0.5));
+        assert!(tally.table().contains("bob (1)"));
+        assert!(calibrate(&[authors], &[output]).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
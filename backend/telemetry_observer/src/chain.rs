@@ -0,0 +1,18 @@
+/// Which chain the observer is watching. Every output row is stamped with this, so
+/// that files from different chains (or differently configured runs) can't be
+/// confused once they're merged.
+#[derive(Debug, Clone, Default)]
+pub struct ChainIdentity {
+    /// The chain's name, as given by the feed. Empty until the feed has told us.
+    pub label: String,
+    pub genesis_hash: String,
+}
+
+impl ChainIdentity {
+    pub fn new(genesis_hash: &str) -> Self {
+        Self {
+            label: String::new(),
+            genesis_hash: genesis_hash.to_string(),
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use crate::manifest;
+use anyhow::Result;
+use csv::Writer;
+use log::{info, warn};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Open a CSV file to append rows to, writing the header if the file is new.
+///
+/// If the file already exists but was written with a different header (say, by an
+/// older version of the observer with fewer columns), it's moved aside so that we
+/// never mix rows of different shapes in one file.
+pub fn open_with_header(path: &Path, header: &[&str]) -> Result<Writer<File>> {
+    let write_header = prepare(path, header)?;
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut writer = Writer::from_writer(file);
+    if write_header {
+        writer.write_record(header)?;
+        writer.flush()?;
+    }
+    Ok(writer)
+}
+
+/// How much of the end of a file to look through for the last complete row. Rows are
+/// far shorter than this.
+const TAIL_BYTES: u64 = 64 * 1024;
+
+/// Move the file aside if it was written with a different header, returning whether
+/// the header needs writing. If the file is to be appended to, a partly written last
+/// row is removed first.
+pub fn prepare(path: &Path, header: &[&str]) -> Result<bool> {
+    let exists = path.exists() && path.metadata()?.len() > 0;
+    if !exists {
+        return Ok(true);
+    }
+
+    let mut first_line = String::new();
+    BufReader::new(File::open(path)?).read_line(&mut first_line)?;
+    if first_line.trim_end() == header.join(",") {
+        repair_torn_row(path)?;
+        return Ok(false);
+    }
+
+    info!("CSV header of {:?} doesn't match the current columns", path);
+    rotate(path)?;
+    Ok(true)
+}
+
+/// Rows are written a line at a time, so if the file doesn't end with a newline then
+/// the last row was cut short (say, by a crash mid-write). Appending to it would leave
+/// a malformed row in the middle of the file, so cut it off.
+fn repair_torn_row(path: &Path) -> Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let len = file.metadata()?.len();
+    let tail_start = len.saturating_sub(TAIL_BYTES);
+    file.seek(SeekFrom::Start(tail_start))?;
+    let mut tail = vec![];
+    file.read_to_end(&mut tail)?;
+
+    if tail.last() == Some(&b'\n') {
+        return Ok(());
+    }
+    let Some(last_newline) = tail.iter().rposition(|&b| b == b'\n') else {
+        // The header was checked to be complete, so this can only mean a very long row.
+        warn!(
+            "Couldn't find the end of the last complete row of {:?}",
+            path
+        );
+        return Ok(());
+    };
+    let torn = &tail[last_newline + 1..];
+    warn!(
+        "Removing a partly written row from the end of {:?}: {:?}",
+        path,
+        String::from_utf8_lossy(torn)
+    );
+    file.set_len(tail_start + last_newline as u64 + 1)?;
+    Ok(())
+}
+
+/// Move a CSV file (and its manifest, if it has one) aside, so that a new one can be started.
+pub fn rotate(path: &Path) -> Result<PathBuf> {
+    let rotated = rotated_path(path)?;
+    info!("Moving {:?} to {:?}", path, rotated);
+    std::fs::rename(path, &rotated)?;
+
+    let manifest_path = manifest::manifest_path(path);
+    if manifest_path.exists() {
+        std::fs::rename(&manifest_path, manifest::manifest_path(&rotated))?;
+    }
+    Ok(rotated)
+}
+
+/// Eg `./data/out.csv` becomes `./data/out.1700000000.csv`.
+fn rotated_path(path: &Path) -> Result<PathBuf> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let file_name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, now, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, now),
+    };
+    Ok(path.with_file_name(file_name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn removes_partly_written_last_row() {
+        let dir = std::env::temp_dir().join(format!("observer-csv-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.csv");
+        std::fs::write(&path, "a,b\n1,2\n3,").unwrap();
+
+        let mut writer = open_with_header(&path, &["a", "b"]).unwrap();
+        writer.write_record(["5", "6"]).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a,b\n1,2\n5,6\n");
+
+        // Complete rows are left alone:
+        drop(open_with_header(&path, &["a", "b"]).unwrap());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "a,b\n1,2\n5,6\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,150 @@
+use anyhow::{anyhow, Context, Result};
+use common::feed_client::{FeedClient, FeedMessage};
+use common::node_types::BlockHash;
+use futures::StreamExt;
+use std::time::Duration;
+
+/// How long to give the feed to list the chains it knows of.
+const LIST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// What we ping the feed with, to know when it's finished listing its chains.
+const LISTED_PING: &str = "chains-listed";
+
+/// A chain that the feed knows of.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FeedChain {
+    pub name: String,
+    pub genesis_hash: BlockHash,
+    pub node_count: usize,
+}
+
+/// Ask the feed which chains it knows of. The feed lists every chain as soon as we
+/// connect, and answers pings in order, so once the pong for a ping sent straight away
+/// arrives, we've heard about them all.
+pub async fn feed_chains(url: &str) -> Result<Vec<FeedChain>> {
+    let uri: http::Uri = url
+        .parse()
+        .with_context(|| format!("invalid telemetry URL '{}'", url))?;
+    let mut feed = FeedClient::connect(&uri).await?;
+    feed.ping(LISTED_PING)?;
+
+    let mut chains: Vec<FeedChain> = vec![];
+    let listed = tokio::time::timeout(LIST_TIMEOUT, async {
+        while let Some(msg) = feed.next().await {
+            match msg? {
+                // Chains are listed again whenever their node counts change.
+                FeedMessage::AddedChain {
+                    name,
+                    genesis_hash,
+                    node_count,
+                } => {
+                    chains.retain(|chain| chain.genesis_hash != genesis_hash);
+                    chains.push(FeedChain {
+                        name,
+                        genesis_hash,
+                        node_count,
+                    });
+                }
+                FeedMessage::RemovedChain { genesis_hash } => {
+                    chains.retain(|chain| chain.genesis_hash != genesis_hash)
+                }
+                FeedMessage::Pong { msg } if msg == LISTED_PING => return Ok(()),
+                _ => {}
+            }
+        }
+        Err(anyhow!("the feed closed before listing its chains"))
+    })
+    .await;
+    let _ = feed.close().await;
+    listed.map_err(|_| anyhow!("timed out waiting for the feed to list its chains"))??;
+    Ok(chains)
+}
+
+/// Chain names are compared ignoring case, spaces and punctuation, so that `asset-hub`
+/// finds "Asset Hub".
+fn normalize(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The chain that best matches `name`, and any others that match it as well. A name
+/// that's the same beats one that starts with `name`, which beats one that just
+/// contains it; between equally good matches, the chain with the most nodes wins.
+pub fn best_match<'a>(
+    chains: &'a [FeedChain],
+    name: &str,
+) -> Option<(&'a FeedChain, Vec<&'a FeedChain>)> {
+    let wanted = normalize(name);
+    let rank = |chain: &FeedChain| {
+        let name = normalize(&chain.name);
+        if name == wanted {
+            Some(0)
+        } else if name.starts_with(&wanted) {
+            Some(1)
+        } else if name.contains(&wanted) {
+            Some(2)
+        } else {
+            None
+        }
+    };
+    let best = chains.iter().filter_map(rank).min()?;
+    let mut matches: Vec<_> = chains.iter().filter(|c| rank(c) == Some(best)).collect();
+    matches.sort_by(|a, b| {
+        b.node_count
+            .cmp(&a.node_count)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    let chosen = matches.remove(0);
+    Some((chosen, matches))
+}
+
+/// Describe a chain well enough to tell it apart from others of the same name.
+pub fn describe(chain: &FeedChain) -> String {
+    format!(
+        "'{}' ({:?}, {} nodes)",
+        chain.name, chain.genesis_hash, chain.node_count
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn chain(name: &str, id: u64, node_count: usize) -> FeedChain {
+        FeedChain {
+            name: name.to_string(),
+            genesis_hash: BlockHash::from_low_u64_be(id),
+            node_count,
+        }
+    }
+
+    #[test]
+    fn picks_the_closest_name_then_the_most_nodes() {
+        let chains = vec![
+            chain("Polkadot", 1, 900),
+            chain("Polkadot Asset Hub", 2, 300),
+            chain("Polkadot Bridge Hub", 3, 100),
+            chain("Westend Asset Hub", 4, 50),
+            chain("polkadot", 5, 3),
+        ];
+
+        // Exact matches (ignoring case) come first, and are told apart by node count:
+        let (chosen, others) = best_match(&chains, "POLKADOT").unwrap();
+        assert_eq!(chosen.genesis_hash, BlockHash::from_low_u64_be(1));
+        assert_eq!(others, vec![&chains[4]]);
+
+        // Then names that start with it:
+        let (chosen, others) = best_match(&chains, "polkadot-asset").unwrap();
+        assert_eq!(chosen.name, "Polkadot Asset Hub");
+        assert!(others.is_empty());
+
+        // Then names that contain it:
+        let (chosen, others) = best_match(&chains, "asset hub").unwrap();
+        assert_eq!(chosen.name, "Polkadot Asset Hub");
+        assert_eq!(others, vec![&chains[3]]);
+
+        assert!(best_match(&chains, "kusama").is_none());
+    }
+}
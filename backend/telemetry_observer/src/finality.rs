@@ -0,0 +1,324 @@
+use crate::authorship::validator_account;
+use crate::chain::ChainIdentity;
+use crate::csv_file;
+use anyhow::Result;
+use csv::Writer;
+use log::info;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs::File;
+use std::path::Path;
+
+/// The columns written to the finality report CSV file.
+const FINALITY_HEADER: &[&str] = &[
+    "period_start",
+    "period_end",
+    "chain",
+    "genesis_hash",
+    "validator",
+    "node_name",
+    "node_id",
+    "authority_set_id",
+    "in_authority_set",
+    "finalized_blocks",
+    "prevotes",
+    "prevote_participation",
+    "precommits",
+    "precommit_participation",
+    "authored_blocks",
+];
+
+/// How one validator took part in finality, and in authoring, over a period.
+#[derive(Debug, Default)]
+struct ValidatorStats {
+    /// Finalizations that the validator was expected to vote towards.
+    finalized_blocks: u64,
+    /// How many of those it was seen to prevote and precommit towards.
+    prevotes: u64,
+    precommits: u64,
+    authored_blocks: u64,
+}
+
+/// The same validator can be given as SS58 or hex, and GRANDPA gives voters as
+/// `<SS58> (<hex prefix>...)`, so they're compared by the account that they decode to,
+/// where they can be decoded.
+fn validator_key(address: &str) -> String {
+    let address = address.split_whitespace().next().unwrap_or_default();
+    match validator_account(address) {
+        Some(account) => format!("0x{}", hex::encode(account)),
+        None => address.to_string(),
+    }
+}
+
+/// Periodically sum up how each validator takes part in GRANDPA finality, from the
+/// votes that nodes report receiving, alongside how many blocks it was attributed.
+///
+/// A validator is counted as having prevoted (or precommitted) towards a finalized block
+/// if any node saw it vote for that block or a later one since the previous
+/// finalization. Nodes only report the votes they receive, so a validator that none of
+/// the nodes on telemetry hear from looks like it isn't voting.
+#[derive(Debug)]
+pub struct FinalityReport {
+    writer: Writer<File>,
+    period_start: u64,
+    period_secs: u64,
+    /// The highest block that's been finalized, once we've seen one.
+    finalized: Option<u64>,
+    /// The latest authority set, and its ID, if a node has told us about one.
+    authority_set: Option<(String, BTreeSet<String>)>,
+    /// The highest block that each voter has voted for since the last finalization.
+    pending_prevotes: HashMap<String, u64>,
+    pending_precommits: HashMap<String, u64>,
+    validators: BTreeMap<String, ValidatorStats>,
+    /// The name and ID of the node reporting as each validator, for the report.
+    names: HashMap<String, (String, String)>,
+    /// Whether any GRANDPA messages have been seen this period.
+    saw_finality: bool,
+    /// Leave validators' addresses out of the report, when anonymizing.
+    hide_validators: bool,
+}
+
+impl FinalityReport {
+    pub fn new(path: &Path, period_secs: u64, now: u64, hide_validators: bool) -> Result<Self> {
+        Ok(Self {
+            writer: csv_file::open_with_header(path, FINALITY_HEADER)?,
+            period_start: now,
+            period_secs,
+            finalized: None,
+            authority_set: None,
+            pending_prevotes: HashMap::new(),
+            pending_precommits: HashMap::new(),
+            validators: BTreeMap::new(),
+            names: HashMap::new(),
+            saw_finality: false,
+            hide_validators,
+        })
+    }
+
+    /// A node with this name and ID reports as the given validator.
+    pub fn saw_node(&mut self, validator: &str, node_name: &str, node_id: &str) {
+        self.names.insert(
+            validator_key(validator),
+            (node_name.to_string(), node_id.to_string()),
+        );
+    }
+
+    /// A node has told us the current authority set. The authorities come as a JSON
+    /// list; anything else is ignored.
+    pub fn authority_set(&mut self, set_id: &str, authorities: &str) {
+        self.saw_finality = true;
+        let authorities: Vec<String> = match serde_json::from_str(authorities) {
+            Ok(authorities) => authorities,
+            Err(_) => return,
+        };
+        let authorities: BTreeSet<_> = authorities.iter().map(|a| validator_key(a)).collect();
+        for authority in &authorities {
+            self.validators.entry(authority.clone()).or_default();
+        }
+        self.authority_set = Some((set_id.to_string(), authorities));
+    }
+
+    pub fn prevote(&mut self, voter: &str, block_number: u64) {
+        self.vote(voter, block_number, false)
+    }
+
+    pub fn precommit(&mut self, voter: &str, block_number: u64) {
+        self.vote(voter, block_number, true)
+    }
+
+    fn vote(&mut self, voter: &str, block_number: u64, precommit: bool) {
+        self.saw_finality = true;
+        let voter = validator_key(voter);
+        let pending = if precommit {
+            &mut self.pending_precommits
+        } else {
+            &mut self.pending_prevotes
+        };
+        let target = pending.entry(voter.clone()).or_default();
+        *target = (*target).max(block_number);
+        self.validators.entry(voter).or_default();
+    }
+
+    /// A node has finalized this block. Every node reports the same finalizations, so
+    /// only the first report of each counts.
+    pub fn finalized(&mut self, block_number: u64) {
+        self.saw_finality = true;
+        let previous = match self.finalized {
+            Some(previous) if block_number <= previous => return,
+            previous => previous,
+        };
+        self.finalized = Some(block_number);
+        // The votes leading up to the first finalization we see were mostly cast before
+        // we started listening, so that one only tells us where to start from:
+        if previous.is_some() {
+            let expected = self.authority_set.as_ref().map(|(_, set)| set);
+            for (validator, stats) in self.validators.iter_mut() {
+                if expected.is_some_and(|set| !set.contains(validator)) {
+                    continue;
+                }
+                stats.finalized_blocks += 1;
+                let voted = |pending: &HashMap<String, u64>| {
+                    pending.get(validator).is_some_and(|&n| n >= block_number)
+                };
+                stats.prevotes += voted(&self.pending_prevotes) as u64;
+                stats.precommits += voted(&self.pending_precommits) as u64;
+            }
+        }
+        self.pending_prevotes.retain(|_, n| *n > block_number);
+        self.pending_precommits.retain(|_, n| *n > block_number);
+    }
+
+    /// A block was attributed to the node reporting as this validator.
+    pub fn authored(&mut self, validator: &str) {
+        self.validators
+            .entry(validator_key(validator))
+            .or_default()
+            .authored_blocks += 1;
+    }
+
+    /// Write out the report if the current period is over, and start a new one.
+    pub fn maybe_write(&mut self, now: u64, chain: &ChainIdentity) -> Result<()> {
+        if now.saturating_sub(self.period_start) < self.period_secs {
+            return Ok(());
+        }
+        self.finish_period(now, chain)
+    }
+
+    /// Write out the report for the current period so far, and start a new one.
+    pub fn finish_period(&mut self, now: u64, chain: &ChainIdentity) -> Result<()> {
+        let validators = std::mem::take(&mut self.validators);
+        if !self.saw_finality {
+            info!("No GRANDPA messages were seen this period; skipping finality report");
+        } else {
+            for (validator, stats) in &validators {
+                self.write_row(now, chain, validator, stats)?;
+            }
+            self.writer.flush()?;
+            let voting = validators.values().filter(|s| s.precommits > 0).count();
+            info!(
+                "Wrote finality report for {} validators, {} of them seen precommitting",
+                validators.len(),
+                voting
+            );
+        }
+
+        // The authority set carries over into the next period:
+        if let Some((_, set)) = &self.authority_set {
+            for authority in set {
+                self.validators.entry(authority.clone()).or_default();
+            }
+        }
+        self.saw_finality = false;
+        self.period_start = now;
+        Ok(())
+    }
+
+    fn write_row(
+        &mut self,
+        now: u64,
+        chain: &ChainIdentity,
+        validator: &str,
+        stats: &ValidatorStats,
+    ) -> Result<()> {
+        let (node_name, node_id) = self.names.get(validator).cloned().unwrap_or_default();
+        let (set_id, in_set) = match &self.authority_set {
+            Some((set_id, set)) => (set_id.clone(), set.contains(validator).to_string()),
+            None => (String::new(), String::new()),
+        };
+        let share = |votes: u64| {
+            if stats.finalized_blocks > 0 {
+                format!("{:.4}", votes as f64 / stats.finalized_blocks as f64)
+            } else {
+                String::new()
+            }
+        };
+        self.writer.write_record(&[
+            self.period_start.to_string(),
+            now.to_string(),
+            chain.label.clone(),
+            chain.genesis_hash.clone(),
+            if self.hide_validators {
+                String::new()
+            } else {
+                validator.to_string()
+            },
+            node_name,
+            node_id,
+            set_id,
+            in_set,
+            stats.finalized_blocks.to_string(),
+            stats.prevotes.to_string(),
+            share(stats.prevotes),
+            stats.precommits.to_string(),
+            share(stats.precommits),
+            stats.authored_blocks.to_string(),
+        ])?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ALICE: &str = "5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY";
+    const ALICE_HEX: &str = "0xd43593c715fdd31c61141abd04a99fd6822c8558854ccde39a5684e7a56da27d";
+
+    #[test]
+    fn counts_votes_towards_each_finalization() {
+        let dir = std::env::temp_dir().join(format!("observer-finality-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("finality.csv");
+        let mut report = FinalityReport::new(&path, 3600, 0, false).unwrap();
+        report.saw_node(ALICE, "alice-node", "12D3Alice");
+        report.authority_set("3", &format!(r#"["{}","bob"]"#, ALICE));
+
+        // The first finalization only sets where we count from:
+        report.prevote("bob", 10);
+        report.finalized(10);
+
+        // Alice votes for everything up to #14, as GRANDPA formats her; bob only prevotes
+        // for #15, and carol isn't in the authority set:
+        for n in 11..=14 {
+            report.prevote(&format!("{} (d4359...)", ALICE), n);
+            report.precommit(ALICE, n);
+            report.prevote("carol", n);
+            report.finalized(n);
+            // Other nodes finalizing the same block don't count again:
+            report.finalized(n);
+        }
+        report.prevote("bob", 15);
+        report.finalized(15);
+        report.authored(ALICE_HEX);
+        report.authored(ALICE);
+
+        report
+            .finish_period(3600, &ChainIdentity::new("0x1234"))
+            .unwrap();
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let rows: HashMap<&str, Vec<&str>> = contents
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let row: Vec<_> = line.split(',').collect();
+                (row[4], row)
+            })
+            .collect();
+        assert_eq!(rows.len(), 3);
+
+        let alice = &rows[ALICE_HEX];
+        assert_eq!(&alice[5..9], &["alice-node", "12D3Alice", "3", "true"]);
+        assert_eq!(&alice[9..], &["5", "4", "0.8000", "4", "0.8000", "2"]);
+        let bob = &rows["bob"];
+        assert_eq!(&bob[9..], &["5", "1", "0.2000", "0", "0.0000", "0"]);
+        let carol = &rows["carol"];
+        assert_eq!(&carol[8..], &["false", "0", "0", "", "0", "", "0"]);
+
+        // Nothing more was heard, so there's nothing to report:
+        report
+            .finish_period(7200, &ChainIdentity::new("0x1234"))
+            .unwrap();
+        let lines = std::fs::read_to_string(&path).unwrap().lines().count();
+        assert_eq!(lines, 4);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,172 @@
+use crate::alerts::Alert;
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How many heights below the best one to remember reports for. Forks deeper than
+/// this can't be measured, but would already have been alerted about on the way.
+const TRACKED_HEIGHTS: u64 = 256;
+
+/// The hashes reported at some height, and the nodes that reported each one.
+type Competitors = BTreeMap<String, BTreeSet<String>>;
+
+/// Spots nodes following competing chains. The feed doesn't tell us a block's parent,
+/// so a fork shows up as a run of consecutive heights at which nodes disagree about
+/// the block hash; the length of the run is how deep the fork goes.
+#[derive(Debug)]
+pub struct ForkTracker {
+    max_depth: u64,
+    heights: BTreeMap<u64, Competitors>,
+    /// The first height of each fork that we've already alerted about. Late reports can
+    /// extend a fork downwards, so a fork has been alerted about if any of these are in it.
+    alerted: BTreeSet<u64>,
+}
+
+impl ForkTracker {
+    /// Forks that go deeper than `max_depth` blocks are alerted about.
+    pub fn new(max_depth: u64) -> Self {
+        Self {
+            max_depth,
+            heights: BTreeMap::new(),
+            alerted: BTreeSet::new(),
+        }
+    }
+
+    /// Note that a node has imported a block, returning an alert if this shows
+    /// up a fork deeper than we're willing to tolerate.
+    pub fn saw_import(
+        &mut self,
+        block_number: u64,
+        block_hash: &str,
+        node_name: &str,
+        now: u64,
+    ) -> Option<Alert> {
+        self.heights
+            .entry(block_number)
+            .or_default()
+            .entry(block_hash.to_string())
+            .or_default()
+            .insert(node_name.to_string());
+        self.prune();
+
+        let (start, end) = self.fork_containing(block_number)?;
+        let depth = end - start + 1;
+        if depth <= self.max_depth || self.alerted.range(start..=end).next().is_some() {
+            return None;
+        }
+        self.alerted.insert(start);
+
+        // Describe the competing chains by their blocks at the highest contested height:
+        let competitors = &self.heights[&end];
+        let chains: Vec<_> = competitors
+            .iter()
+            .map(|(hash, nodes)| json!({ "block_hash": hash, "reporters": nodes }))
+            .collect();
+        let summary: Vec<_> = competitors
+            .iter()
+            .map(|(hash, nodes)| {
+                let nodes: Vec<_> = nodes.iter().map(|n| n.as_str()).collect();
+                format!("{} ({})", hash, nodes.join(", "))
+            })
+            .collect();
+
+        Some(Alert {
+            timestamp: now,
+            kind: "deep_fork",
+            node_name: None,
+            node_id: None,
+            message: format!(
+                "{} competing chains for {} blocks (#{} to #{}): {}",
+                competitors.len(),
+                depth,
+                start,
+                end,
+                summary.join(" vs ")
+            ),
+            details: Some(json!({
+                "depth": depth,
+                "from_block": start,
+                "to_block": end,
+                "chains": chains,
+            })),
+        })
+    }
+
+    /// The first and last heights of the run of contested heights around `block_number`.
+    fn fork_containing(&self, block_number: u64) -> Option<(u64, u64)> {
+        let contested = |h: u64| self.heights.get(&h).is_some_and(|c| c.len() > 1);
+        if !contested(block_number) {
+            return None;
+        }
+        let mut start = block_number;
+        while start > 0 && contested(start - 1) {
+            start -= 1;
+        }
+        let mut end = block_number;
+        while contested(end + 1) {
+            end += 1;
+        }
+        Some((start, end))
+    }
+
+    fn prune(&mut self) {
+        let best = match self.heights.keys().next_back() {
+            Some(&best) => best,
+            None => return,
+        };
+        let lowest = best.saturating_sub(TRACKED_HEIGHTS);
+        self.heights = self.heights.split_off(&lowest);
+        self.alerted = self.alerted.split_off(&lowest);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn hash(n: u64, branch: char) -> String {
+        format!("0x{}{}", branch, n)
+    }
+
+    #[test]
+    fn alerts_once_when_fork_is_deeper_than_max() {
+        let mut forks = ForkTracker::new(2);
+        for n in 1..=2 {
+            assert!(forks.saw_import(n, &hash(n, 'a'), "alice", n).is_none());
+            assert!(forks.saw_import(n, &hash(n, 'b'), "bob", n).is_none());
+        }
+        assert!(forks.saw_import(3, &hash(3, 'a'), "alice", 3).is_none());
+        let alert = forks.saw_import(3, &hash(3, 'b'), "bob", 3).unwrap();
+        assert_eq!(alert.kind, "deep_fork");
+        let details = alert.details.unwrap();
+        assert_eq!(details["depth"], 3);
+        assert_eq!(details["from_block"], 1);
+        assert_eq!(details["chains"][1]["reporters"][0], "bob");
+
+        // Don't keep alerting about the same fork as it grows:
+        assert!(forks.saw_import(4, &hash(4, 'a'), "alice", 4).is_none());
+        assert!(forks.saw_import(4, &hash(4, 'b'), "bob", 4).is_none());
+    }
+
+    #[test]
+    fn ignores_short_forks() {
+        let mut forks = ForkTracker::new(2);
+        for n in 1..=10 {
+            forks.saw_import(n, &hash(n, 'a'), "alice", n);
+            // Bob briefly follows a competing block every so often:
+            let branch = if n % 3 == 0 { 'b' } else { 'a' };
+            assert!(forks.saw_import(n, &hash(n, branch), "bob", n).is_none());
+        }
+    }
+
+    #[test]
+    fn late_reports_can_join_up_forks() {
+        let mut forks = ForkTracker::new(2);
+        for n in [1, 3] {
+            forks.saw_import(n, &hash(n, 'a'), "alice", n);
+            forks.saw_import(n, &hash(n, 'b'), "bob", n);
+        }
+        forks.saw_import(2, &hash(2, 'a'), "alice", 4);
+        let alert = forks.saw_import(2, &hash(2, 'b'), "charlie", 4).unwrap();
+        assert_eq!(alert.details.unwrap()["to_block"], 3);
+    }
+}
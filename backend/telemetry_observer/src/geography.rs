@@ -0,0 +1,395 @@
+use crate::chain::ChainIdentity;
+use crate::csv_file;
+use crate::state::MAX_TRACKED_BLOCKS;
+use anyhow::Result;
+use csv::Writer;
+use log::info;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
+use std::path::Path;
+
+/// The columns written to the geography report CSV file.
+const GEOGRAPHY_HEADER: &[&str] = &[
+    "period_start",
+    "period_end",
+    "chain",
+    "genesis_hash",
+    "region",
+    "nodes",
+    "imports",
+    "first_seen_blocks",
+    "first_seen_share",
+    "median_prop_ms",
+    "p90_prop_ms",
+    "located_imports",
+    "mean_distance_km",
+    "distance_latency_r",
+    "ms_per_1000km",
+];
+
+/// The region given to nodes that the feed hasn't located.
+const UNLOCATED: &str = "unknown";
+
+/// The row summing up every region.
+const ALL_REGIONS: &str = "all";
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Where the feed says a node is, from the core's GeoIP lookup of its address.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Location {
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl Location {
+    /// The great circle distance to another location.
+    fn distance_km(&self, other: &Location) -> f64 {
+        let (lat1, lat2) = (self.latitude.to_radians(), other.latitude.to_radians());
+        let d_lat = lat2 - lat1;
+        let d_long = (other.longitude - self.longitude).to_radians();
+        let a =
+            (d_lat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (d_long / 2.0).sin().powi(2);
+        2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+    }
+
+    /// Roughly which continent this is on. The feed only gives coordinates and a city,
+    /// so this goes by a few lines of latitude and longitude, which is plenty to see
+    /// where on the planet blocks come from.
+    fn region(&self) -> &'static str {
+        let (lat, long) = (self.latitude, self.longitude);
+        if long < -25.0 {
+            if lat >= 13.0 {
+                "north_america"
+            } else {
+                "south_america"
+            }
+        } else if long < 60.0 && lat >= 36.0 {
+            "europe"
+        } else if long < 34.0 || (long < 52.0 && lat < 12.0) {
+            "africa"
+        } else if lat < -10.0 && long >= 110.0 {
+            "oceania"
+        } else {
+            "asia"
+        }
+    }
+}
+
+/// Running sums for the correlation between the distance that blocks travelled and the
+/// time that they took to.
+#[derive(Debug, Default)]
+struct Fit {
+    n: f64,
+    sum_x: f64,
+    sum_y: f64,
+    sum_xx: f64,
+    sum_yy: f64,
+    sum_xy: f64,
+}
+
+impl Fit {
+    fn add(&mut self, x: f64, y: f64) {
+        self.n += 1.0;
+        self.sum_x += x;
+        self.sum_y += y;
+        self.sum_xx += x * x;
+        self.sum_yy += y * y;
+        self.sum_xy += x * y;
+    }
+
+    fn mean_x(&self) -> Option<f64> {
+        (self.n > 0.0).then(|| self.sum_x / self.n)
+    }
+
+    /// Pearson's correlation coefficient, if there's any variation to correlate.
+    fn r(&self) -> Option<f64> {
+        let cov = self.n * self.sum_xy - self.sum_x * self.sum_y;
+        let var_x = self.n * self.sum_xx - self.sum_x * self.sum_x;
+        let var_y = self.n * self.sum_yy - self.sum_y * self.sum_y;
+        (var_x > 0.0 && var_y > 0.0).then(|| cov / (var_x * var_y).sqrt())
+    }
+
+    /// The least squares slope of time against distance.
+    fn slope(&self) -> Option<f64> {
+        let cov = self.n * self.sum_xy - self.sum_x * self.sum_y;
+        let var_x = self.n * self.sum_xx - self.sum_x * self.sum_x;
+        (var_x > 0.0).then(|| cov / var_x)
+    }
+}
+
+#[derive(Debug, Default)]
+struct RegionStats {
+    nodes: HashSet<usize>,
+    /// Propagation times of the imports by nodes in the region, bar the first of each block.
+    prop_times: Vec<u64>,
+    /// How many blocks a node in the region imported before anyone else.
+    first_seen: u64,
+    fit: Fit,
+}
+
+impl RegionStats {
+    fn saw_import(&mut self, node_idx: usize, propagation_time: u64, distance_km: Option<f64>) {
+        self.nodes.insert(node_idx);
+        if propagation_time == 0 {
+            self.first_seen += 1;
+            return;
+        }
+        self.prop_times.push(propagation_time);
+        if let Some(distance_km) = distance_km {
+            self.fit.add(distance_km, propagation_time as f64);
+        }
+    }
+}
+
+/// Periodically sum up propagation by region: where blocks are first seen (which is
+/// where they're authored, give or take), how long they take to reach each region, and
+/// how much of that is down to the distance they have to travel.
+#[derive(Debug)]
+pub struct GeographyReport {
+    writer: Writer<File>,
+    period_secs: u64,
+    period_start: u64,
+    /// Keyed by feed index, which is what the feed locates nodes by.
+    locations: HashMap<usize, Location>,
+    /// Where each recent block was first seen, if we know, and its number.
+    origins: HashMap<String, (u64, Option<Location>)>,
+    regions: BTreeMap<&'static str, RegionStats>,
+    all: RegionStats,
+}
+
+impl GeographyReport {
+    pub fn new(path: &Path, period_secs: u64, now: u64) -> Result<Self> {
+        Ok(Self {
+            writer: csv_file::open_with_header(path, GEOGRAPHY_HEADER)?,
+            period_secs,
+            period_start: now,
+            locations: HashMap::new(),
+            origins: HashMap::new(),
+            regions: BTreeMap::new(),
+            all: RegionStats::default(),
+        })
+    }
+
+    /// The feed has located the node at this index.
+    pub fn located(&mut self, node_idx: usize, location: Location) {
+        self.locations.insert(node_idx, location);
+    }
+
+    /// The node at this index has gone away, and the index may be given to another.
+    pub fn forget(&mut self, node_idx: usize) {
+        self.locations.remove(&node_idx);
+    }
+
+    /// Note that the node at this index imported a block, `propagation_time` ms after
+    /// the first node to (or first, if it's 0).
+    pub fn saw_import(
+        &mut self,
+        node_idx: usize,
+        block_hash: &str,
+        block_number: u64,
+        propagation_time: u64,
+    ) {
+        let location = self.locations.get(&node_idx).copied();
+        if propagation_time == 0 {
+            self.origins
+                .insert(block_hash.to_string(), (block_number, location));
+            if self.origins.len() > MAX_TRACKED_BLOCKS {
+                let oldest = self
+                    .origins
+                    .iter()
+                    .min_by_key(|(_, (block_number, _))| *block_number)
+                    .map(|(block_hash, _)| block_hash.clone());
+                if let Some(oldest) = oldest {
+                    self.origins.remove(&oldest);
+                }
+            }
+        }
+
+        let origin = self.origins.get(block_hash).and_then(|(_, origin)| *origin);
+        let distance_km = match (location, origin) {
+            (Some(location), Some(origin)) => Some(location.distance_km(&origin)),
+            _ => None,
+        };
+        let region = location.map_or(UNLOCATED, |location| location.region());
+        self.regions
+            .entry(region)
+            .or_default()
+            .saw_import(node_idx, propagation_time, distance_km);
+        self.all.saw_import(node_idx, propagation_time, distance_km);
+    }
+
+    /// Write out the report if the current period is over, and start a new one.
+    pub fn maybe_write(&mut self, now: u64, chain: &ChainIdentity) -> Result<()> {
+        if now.saturating_sub(self.period_start) < self.period_secs {
+            return Ok(());
+        }
+        self.finish_period(now, chain)
+    }
+
+    /// Write out the report for the current period so far, and start a new one.
+    pub fn finish_period(&mut self, now: u64, chain: &ChainIdentity) -> Result<()> {
+        if self.all.nodes.is_empty() {
+            info!("No blocks were imported this period; skipping geography report");
+        } else {
+            let regions = std::mem::take(&mut self.regions);
+            let all = std::mem::take(&mut self.all);
+            for (region, stats) in regions.iter().chain([(&ALL_REGIONS, &all)]) {
+                self.write_row(now, chain, region, stats, all.first_seen)?;
+            }
+            self.writer.flush()?;
+            info!(
+                "Wrote geography report for {} regions; distance/latency correlation {}",
+                regions.len(),
+                all.fit
+                    .r()
+                    .map_or("unknown".to_string(), |r| format!("{:.3}", r))
+            );
+        }
+
+        self.period_start = now;
+        Ok(())
+    }
+
+    fn write_row(
+        &mut self,
+        now: u64,
+        chain: &ChainIdentity,
+        region: &str,
+        stats: &RegionStats,
+        first_seen_blocks: u64,
+    ) -> Result<()> {
+        let mut prop_times = stats.prop_times.clone();
+        prop_times.sort_unstable();
+        let first_seen_share =
+            (first_seen_blocks > 0).then(|| stats.first_seen as f64 / first_seen_blocks as f64);
+        self.writer.write_record(&[
+            self.period_start.to_string(),
+            now.to_string(),
+            chain.label.clone(),
+            chain.genesis_hash.clone(),
+            region.to_string(),
+            stats.nodes.len().to_string(),
+            (stats.prop_times.len() as u64 + stats.first_seen).to_string(),
+            stats.first_seen.to_string(),
+            or_empty(first_seen_share, 4),
+            percentile(&prop_times, 0.5).map_or(String::new(), |ms| ms.to_string()),
+            percentile(&prop_times, 0.9).map_or(String::new(), |ms| ms.to_string()),
+            (stats.fit.n as u64).to_string(),
+            or_empty(stats.fit.mean_x(), 0),
+            or_empty(stats.fit.r(), 4),
+            or_empty(stats.fit.slope().map(|slope| slope * 1000.0), 2),
+        ])?;
+        Ok(())
+    }
+}
+
+/// The value at the given fraction of the way through some sorted values.
+fn percentile(sorted: &[u64], fraction: f64) -> Option<u64> {
+    let last = sorted.len().checked_sub(1)?;
+    Some(sorted[(last as f64 * fraction).round() as usize])
+}
+
+fn or_empty(value: Option<f64>, decimals: usize) -> String {
+    value.map_or(String::new(), |value| format!("{:.*}", decimals, value))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const LONDON: Location = Location {
+        latitude: 51.5,
+        longitude: -0.1,
+    };
+    const PARIS: Location = Location {
+        latitude: 48.9,
+        longitude: 2.4,
+    };
+    const NEW_YORK: Location = Location {
+        latitude: 40.7,
+        longitude: -74.0,
+    };
+    const SINGAPORE: Location = Location {
+        latitude: 1.3,
+        longitude: 103.8,
+    };
+
+    #[test]
+    fn locations_have_regions_and_distances() {
+        assert_eq!(LONDON.region(), "europe");
+        assert_eq!(NEW_YORK.region(), "north_america");
+        assert_eq!(SINGAPORE.region(), "asia");
+        let sydney = Location {
+            latitude: -33.9,
+            longitude: 151.2,
+        };
+        assert_eq!(sydney.region(), "oceania");
+        let lagos = Location {
+            latitude: 6.5,
+            longitude: 3.4,
+        };
+        assert_eq!(lagos.region(), "africa");
+
+        let km = LONDON.distance_km(&NEW_YORK);
+        assert!((5550.0..5600.0).contains(&km), "{}", km);
+        assert_eq!(LONDON.distance_km(&LONDON), 0.0);
+    }
+
+    #[test]
+    fn blocks_that_travel_further_take_longer() {
+        let dir = std::env::temp_dir().join(format!("observer-geography-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("geography.csv");
+        let mut report = GeographyReport::new(&path, 3600, 0).unwrap();
+        report.located(0, LONDON);
+        report.located(1, PARIS);
+        report.located(2, NEW_YORK);
+        report.located(3, SINGAPORE);
+
+        // Every block is authored in London, and reaches people further away later.
+        // Node 4 hasn't been located:
+        for n in 0..10u64 {
+            let hash = format!("0x{:x}", n);
+            report.saw_import(0, &hash, n, 0);
+            report.saw_import(1, &hash, n, 20 + n);
+            report.saw_import(2, &hash, n, 150 + n);
+            report.saw_import(3, &hash, n, 300 + n);
+            report.saw_import(4, &hash, n, 100);
+        }
+        report
+            .finish_period(3600, &ChainIdentity::new("0x1234"))
+            .unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let rows: HashMap<&str, Vec<&str>> = contents
+            .lines()
+            .skip(1)
+            .map(|line| {
+                let row: Vec<_> = line.split(',').collect();
+                (row[4], row)
+            })
+            .collect();
+        let mut regions: Vec<_> = rows.keys().copied().collect();
+        regions.sort();
+        assert_eq!(
+            regions,
+            vec!["all", "asia", "europe", "north_america", "unknown"]
+        );
+
+        let europe = &rows["europe"];
+        assert_eq!(&europe[5..9], &["2", "20", "10", "1.0000"]);
+        assert_eq!(rows["asia"][8], "0.0000");
+        assert_eq!(rows["unknown"][11], "0");
+
+        let all = &rows["all"];
+        assert_eq!(all[5], "5");
+        assert_eq!(all[6], "50");
+        assert_eq!(all[11], "30");
+        let r: f64 = all[13].parse().unwrap();
+        assert!(r > 0.95, "{}", r);
+        let ms_per_1000km: f64 = all[14].parse().unwrap();
+        assert!((20.0..40.0).contains(&ms_per_1000km), "{}", ms_per_1000km);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,472 @@
+use crate::aggregate::FileSummary;
+use anyhow::{anyhow, Result};
+use csv::Reader;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+use std::str::FromStr;
+
+/// The file formats that graphs can be written in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GraphFormat {
+    /// Graphviz.
+    Dot,
+    /// Gephi's native format.
+    Gexf,
+    GraphMl,
+}
+
+impl GraphFormat {
+    /// The usual file extension for the format.
+    pub fn extension(self) -> &'static str {
+        match self {
+            GraphFormat::Dot => "dot",
+            GraphFormat::Gexf => "gexf",
+            GraphFormat::GraphMl => "graphml",
+        }
+    }
+
+    /// Work the format out from a file's extension.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        path.extension()?.to_str()?.parse().ok()
+    }
+}
+
+impl FromStr for GraphFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dot" | "gv" => Ok(GraphFormat::Dot),
+            "gexf" => Ok(GraphFormat::Gexf),
+            "graphml" => Ok(GraphFormat::GraphMl),
+            other => Err(format!(
+                "unknown graph format '{}' (expected dot, gexf or graphml)",
+                other
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum VertexKind {
+    /// A node reporting to telemetry.
+    Node,
+    Block,
+}
+
+impl VertexKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            VertexKind::Node => "node",
+            VertexKind::Block => "block",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Vertex {
+    kind: VertexKind,
+    label: String,
+    /// For nodes, how many blocks they were attributed; for blocks, how many nodes
+    /// they were attributed to.
+    blocks: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EdgeKind {
+    /// From a node to a block that was attributed to it.
+    Reported,
+    /// From a node to one that seems to hear about blocks from it.
+    Gossip,
+}
+
+impl EdgeKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            EdgeKind::Reported => "reported",
+            EdgeKind::Gossip => "gossip",
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Edge {
+    kind: EdgeKind,
+    /// For gossip links, how many blocks the "to" node imported straight after the
+    /// "from" node, and how many it imported after anyone at all.
+    followed: u64,
+    imports: u64,
+}
+
+impl Edge {
+    /// Reported edges all count the same; gossip links by how consistently they're followed.
+    fn weight(&self) -> f64 {
+        match self.kind {
+            EdgeKind::Reported => 1.0,
+            EdgeKind::Gossip => self.followed as f64 / self.imports.max(1) as f64,
+        }
+    }
+}
+
+/// The blocks attributed to each node, read from output CSV files, and the gossip links
+/// between nodes, read from topology files, as one graph to be written out for Gephi,
+/// Graphviz and the like.
+#[derive(Debug, Default)]
+pub struct PropagationGraph {
+    vertices: BTreeMap<String, Vertex>,
+    edges: BTreeMap<(String, String), Edge>,
+}
+
+impl PropagationGraph {
+    /// Read the rows of an output CSV file, adding an edge from each node to each block
+    /// attributed to it. Rows that can't be read are skipped, as when aggregating.
+    pub fn add_output_file(&mut self, path: &Path) -> Result<FileSummary> {
+        let mut reader = Reader::from_path(path)?;
+        let headers = reader.headers()?.clone();
+        let position = |name: &str| headers.iter().position(|h| h == name);
+        let (Some(node_name), Some(block_number), Some(block_hash)) = (
+            position("node_name"),
+            position("block_number"),
+            position("block_hash"),
+        ) else {
+            return Err(anyhow!(
+                "{} doesn't look like an output file",
+                path.display()
+            ));
+        };
+        let node_id = position("node_id");
+
+        let mut summary = FileSummary::default();
+        for record in reader.records() {
+            let added = record.map_err(anyhow::Error::from).and_then(|record| {
+                let field = |i: usize| record.get(i).ok_or_else(|| anyhow!("row is too short"));
+                let name = field(node_name)?;
+                let id = match node_id.and_then(|i| record.get(i)).unwrap_or_default() {
+                    "" => name,
+                    id => id,
+                };
+                let number: u64 = field(block_number)?.parse()?;
+                self.add_attribution(name, id, number, field(block_hash)?);
+                Ok(())
+            });
+            match added {
+                Ok(()) => summary.rows += 1,
+                Err(_) => summary.skipped += 1,
+            }
+        }
+        Ok(summary)
+    }
+
+    /// Read the rows of a topology file, adding an edge for each link between nodes.
+    /// Links seen in more than one period are added up.
+    pub fn add_topology_file(&mut self, path: &Path) -> Result<FileSummary> {
+        let mut reader = Reader::from_path(path)?;
+        let headers = reader.headers()?.clone();
+        let columns = [
+            "from_node_name",
+            "from_node_id",
+            "to_node_name",
+            "to_node_id",
+            "followed",
+            "imports",
+        ]
+        .map(|name| headers.iter().position(|h| h == name));
+        let [Some(from_name), Some(from_id), Some(to_name), Some(to_id), Some(followed), Some(imports)] =
+            columns
+        else {
+            return Err(anyhow!(
+                "{} doesn't look like a topology file",
+                path.display()
+            ));
+        };
+
+        let mut summary = FileSummary::default();
+        for record in reader.records() {
+            let added = record.map_err(anyhow::Error::from).and_then(|record| {
+                let field = |i: usize| record.get(i).ok_or_else(|| anyhow!("row is too short"));
+                let from = node_vertex_id(field(from_id)?);
+                let to = node_vertex_id(field(to_id)?);
+                let followed: u64 = field(followed)?.parse()?;
+                let imports: u64 = field(imports)?.parse()?;
+                self.add_vertex(&from, VertexKind::Node, field(from_name)?);
+                self.add_vertex(&to, VertexKind::Node, field(to_name)?);
+                let edge = self.edges.entry((from, to)).or_insert(Edge {
+                    kind: EdgeKind::Gossip,
+                    followed: 0,
+                    imports: 0,
+                });
+                edge.followed += followed;
+                edge.imports += imports;
+                Ok(())
+            });
+            match added {
+                Ok(()) => summary.rows += 1,
+                Err(_) => summary.skipped += 1,
+            }
+        }
+        Ok(summary)
+    }
+
+    fn add_attribution(
+        &mut self,
+        node_name: &str,
+        node_id: &str,
+        block_number: u64,
+        block_hash: &str,
+    ) {
+        let node = node_vertex_id(node_id);
+        let block = format!("block:{}", block_hash);
+        // The same block may be in more than one of the files given:
+        if self.edges.contains_key(&(node.clone(), block.clone())) {
+            return;
+        }
+        self.add_vertex(&node, VertexKind::Node, node_name).blocks += 1;
+        self.add_vertex(&block, VertexKind::Block, &format!("#{}", block_number))
+            .blocks += 1;
+        self.edges.insert(
+            (node, block),
+            Edge {
+                kind: EdgeKind::Reported,
+                followed: 0,
+                imports: 0,
+            },
+        );
+    }
+
+    fn add_vertex(&mut self, id: &str, kind: VertexKind, label: &str) -> &mut Vertex {
+        let vertex = self.vertices.entry(id.to_string()).or_insert(Vertex {
+            kind,
+            label: String::new(),
+            blocks: 0,
+        });
+        // Names can change; go with the most recent one.
+        if !label.is_empty() {
+            vertex.label = label.to_string();
+        }
+        vertex
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    /// How many vertices and edges the graph has.
+    pub fn size(&self) -> (usize, usize) {
+        (self.vertices.len(), self.edges.len())
+    }
+
+    /// The graph in the given format.
+    pub fn render(&self, format: GraphFormat) -> String {
+        match format {
+            GraphFormat::Dot => self.render_dot(),
+            GraphFormat::Gexf => self.render_gexf(),
+            GraphFormat::GraphMl => self.render_graphml(),
+        }
+    }
+
+    fn render_dot(&self) -> String {
+        let mut out = String::from("digraph propagation {\n");
+        for (id, vertex) in &self.vertices {
+            let shape = match vertex.kind {
+                VertexKind::Node => "ellipse",
+                VertexKind::Block => "box",
+            };
+            let _ = writeln!(
+                out,
+                "  {} [label={}, kind={}, blocks={}, shape={}];",
+                dot_quote(id),
+                dot_quote(&vertex.label),
+                vertex.kind.as_str(),
+                vertex.blocks,
+                shape
+            );
+        }
+        for ((from, to), edge) in &self.edges {
+            let style = match edge.kind {
+                EdgeKind::Reported => "solid",
+                EdgeKind::Gossip => "dashed",
+            };
+            let _ = writeln!(
+                out,
+                "  {} -> {} [kind={}, share={:.4}, followed={}, style={}];",
+                dot_quote(from),
+                dot_quote(to),
+                edge.kind.as_str(),
+                edge.weight(),
+                edge.followed,
+                style
+            );
+        }
+        out.push_str("}\n");
+        out
+    }
+
+    fn render_gexf(&self) -> String {
+        let mut out = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<gexf xmlns=\"http://gexf.net/1.3\" version=\"1.3\">\n",
+            "  <graph defaultedgetype=\"directed\">\n",
+            "    <attributes class=\"node\">\n",
+            "      <attribute id=\"kind\" title=\"kind\" type=\"string\"/>\n",
+            "      <attribute id=\"blocks\" title=\"blocks\" type=\"long\"/>\n",
+            "    </attributes>\n",
+            "    <attributes class=\"edge\">\n",
+            "      <attribute id=\"kind\" title=\"kind\" type=\"string\"/>\n",
+            "      <attribute id=\"followed\" title=\"followed\" type=\"long\"/>\n",
+            "    </attributes>\n",
+            "    <nodes>\n",
+        ));
+        for (id, vertex) in &self.vertices {
+            let _ = writeln!(
+                out,
+                "      <node id=\"{}\" label=\"{}\"><attvalues><attvalue for=\"kind\" value=\"{}\"/><attvalue for=\"blocks\" value=\"{}\"/></attvalues></node>",
+                xml_escape(id),
+                xml_escape(&vertex.label),
+                vertex.kind.as_str(),
+                vertex.blocks
+            );
+        }
+        out.push_str("    </nodes>\n    <edges>\n");
+        for (n, ((from, to), edge)) in self.edges.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "      <edge id=\"{}\" source=\"{}\" target=\"{}\" weight=\"{:.4}\"><attvalues><attvalue for=\"kind\" value=\"{}\"/><attvalue for=\"followed\" value=\"{}\"/></attvalues></edge>",
+                n,
+                xml_escape(from),
+                xml_escape(to),
+                edge.weight(),
+                edge.kind.as_str(),
+                edge.followed
+            );
+        }
+        out.push_str("    </edges>\n  </graph>\n</gexf>\n");
+        out
+    }
+
+    fn render_graphml(&self) -> String {
+        let mut out = String::from(concat!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+            "<graphml xmlns=\"http://graphml.graphdrawing.org/xmlns\">\n",
+            "  <key id=\"label\" for=\"node\" attr.name=\"label\" attr.type=\"string\"/>\n",
+            "  <key id=\"kind\" for=\"all\" attr.name=\"kind\" attr.type=\"string\"/>\n",
+            "  <key id=\"blocks\" for=\"node\" attr.name=\"blocks\" attr.type=\"long\"/>\n",
+            "  <key id=\"weight\" for=\"edge\" attr.name=\"weight\" attr.type=\"double\"/>\n",
+            "  <key id=\"followed\" for=\"edge\" attr.name=\"followed\" attr.type=\"long\"/>\n",
+            "  <graph id=\"propagation\" edgedefault=\"directed\">\n",
+        ));
+        for (id, vertex) in &self.vertices {
+            let _ = writeln!(
+                out,
+                "    <node id=\"{}\"><data key=\"label\">{}</data><data key=\"kind\">{}</data><data key=\"blocks\">{}</data></node>",
+                xml_escape(id),
+                xml_escape(&vertex.label),
+                vertex.kind.as_str(),
+                vertex.blocks
+            );
+        }
+        for ((from, to), edge) in &self.edges {
+            let _ = writeln!(
+                out,
+                "    <edge source=\"{}\" target=\"{}\"><data key=\"kind\">{}</data><data key=\"weight\">{:.4}</data><data key=\"followed\">{}</data></edge>",
+                xml_escape(from),
+                xml_escape(to),
+                edge.kind.as_str(),
+                edge.weight(),
+                edge.followed
+            );
+        }
+        out.push_str("  </graph>\n</graphml>\n");
+        out
+    }
+}
+
+/// Nodes and blocks share the one namespace of vertex IDs, so each is prefixed.
+fn node_vertex_id(node_id: &str) -> String {
+    format!("node:{}", node_id)
+}
+
+fn dot_quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn formats_are_parsed_from_names_and_extensions() {
+        assert_eq!("GEXF".parse::<GraphFormat>(), Ok(GraphFormat::Gexf));
+        assert_eq!(
+            GraphFormat::from_path(Path::new("out/graph.graphml")),
+            Some(GraphFormat::GraphMl)
+        );
+        assert_eq!(GraphFormat::from_path(Path::new("graph")), None);
+        assert!("png".parse::<GraphFormat>().is_err());
+    }
+
+    #[test]
+    fn attributions_and_gossip_links_make_one_graph() {
+        let dir = std::env::temp_dir().join(format!("observer-graph-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let output = dir.join("out.csv");
+        std::fs::write(
+            &output,
+            "timestamp,node_name,node_id,block_number,block_hash,propagation_time\n\
+             1,alice,a,1,0xb1,100\n\
+             2,bob,b,1,0xb1,100\n\
+             3,\"al & ice\",a,2,0xb2,80\n\
+             not,a,valid,row\n",
+        )
+        .unwrap();
+        let topology = dir.join("topology.csv");
+        std::fs::write(
+            &topology,
+            "period_start,period_end,chain,genesis_hash,from_node_name,from_node_id,to_node_name,to_node_id,followed,imports,share,mean_gap_ms\n\
+             0,3600,Test,0x01,al & ice,a,carol,c,3,4,0.7500,50\n\
+             3600,7200,Test,0x01,al & ice,a,carol,c,5,6,0.8333,40\n",
+        )
+        .unwrap();
+
+        let mut graph = PropagationGraph::default();
+        let summary = graph.add_output_file(&output).unwrap();
+        assert_eq!(
+            summary,
+            FileSummary {
+                rows: 3,
+                skipped: 1
+            }
+        );
+        // Files given twice don't add edges twice:
+        graph.add_output_file(&output).unwrap();
+        graph.add_topology_file(&topology).unwrap();
+        assert!(graph.add_topology_file(&output).is_err());
+        assert_eq!(graph.size(), (5, 4));
+
+        let dot = graph.render(GraphFormat::Dot);
+        assert!(
+            dot.contains("  \"node:a\" [label=\"al & ice\", kind=node, blocks=2, shape=ellipse];")
+        );
+        assert!(dot.contains("  \"block:0xb1\" [label=\"#1\", kind=block, blocks=2, shape=box];"));
+        assert!(dot.contains(
+            "  \"node:a\" -> \"node:c\" [kind=gossip, share=0.8000, followed=8, style=dashed];"
+        ));
+
+        let gexf = graph.render(GraphFormat::Gexf);
+        assert!(gexf.contains("label=\"al &amp; ice\""));
+        assert_eq!(gexf.matches("<edge ").count(), 4);
+        let graphml = graph.render(GraphFormat::GraphMl);
+        assert!(graphml.contains(
+            "<edge source=\"node:a\" target=\"block:0xb2\"><data key=\"kind\">reported</data>"
+        ));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
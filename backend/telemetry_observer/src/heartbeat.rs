@@ -0,0 +1,183 @@
+use crate::journal;
+use anyhow::Result;
+use common::ws_client::ConnectionStats;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+/// How often the heartbeat file is rewritten.
+const WRITE_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The state of the connection to the telemetry feed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ConnectionState {
+    Connecting,
+    Connected,
+    Disconnected,
+}
+
+/// A summary of what the observer is up to, written to a file so that watchdogs can
+/// check on it without needing to talk to it. Timestamps are in Unix seconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Heartbeat {
+    pub updated_at: u64,
+    pub pid: u32,
+    pub started_at: u64,
+    pub connection: ConnectionState,
+    pub connection_changed_at: u64,
+    pub last_message_at: Option<u64>,
+    pub last_block_number: Option<u64>,
+    pub last_block_at: Option<u64>,
+    pub reconnects: u64,
+    pub bytes_received: u64,
+    pub ping_rtt_ms: Option<f64>,
+}
+
+impl Heartbeat {
+    pub fn new(now: u64) -> Self {
+        Self {
+            updated_at: now,
+            pid: std::process::id(),
+            started_at: now,
+            connection: ConnectionState::Connecting,
+            connection_changed_at: now,
+            last_message_at: None,
+            last_block_number: None,
+            last_block_at: None,
+            reconnects: 0,
+            bytes_received: 0,
+            ping_rtt_ms: None,
+        }
+    }
+
+    pub fn set_connection(&mut self, connection: ConnectionState, now: u64) {
+        if self.connection != connection {
+            self.connection = connection;
+            self.connection_changed_at = now;
+        }
+    }
+
+    pub fn saw_message(&mut self, now: u64) {
+        self.last_message_at = Some(now);
+    }
+
+    /// Note a block import. Only new best blocks count, so that nodes catching up
+    /// don't make it look like the chain is progressing.
+    pub fn saw_block(&mut self, block_number: u64, now: u64) {
+        if self.last_block_number.is_none_or(|n| block_number > n) {
+            self.last_block_number = Some(block_number);
+            self.last_block_at = Some(now);
+        }
+    }
+
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+
+    /// Everything that looks wrong, given that nothing should be older than `max_age_secs`.
+    pub fn problems(&self, now: u64, max_age_secs: u64) -> Vec<String> {
+        let mut problems = Vec::new();
+        let age = |t: u64| now.saturating_sub(t);
+
+        if age(self.updated_at) > max_age_secs {
+            problems.push(format!(
+                "heartbeat was last written {}s ago; is the observer (pid {}) running?",
+                age(self.updated_at),
+                self.pid
+            ));
+        }
+        if self.connection != ConnectionState::Connected
+            && age(self.connection_changed_at) > max_age_secs
+        {
+            problems.push(format!(
+                "not connected to the feed for {}s",
+                age(self.connection_changed_at)
+            ));
+        }
+        match self.last_message_at {
+            Some(t) if age(t) > max_age_secs => {
+                problems.push(format!("no feed messages for {}s", age(t)))
+            }
+            None if age(self.started_at) > max_age_secs => {
+                problems.push("no feed messages since starting".to_string())
+            }
+            _ => {}
+        }
+        match self.last_block_at {
+            Some(t) if age(t) > max_age_secs => problems.push(format!(
+                "no new best block for {}s (last was #{})",
+                age(t),
+                self.last_block_number.unwrap_or_default()
+            )),
+            None if age(self.started_at) > max_age_secs => {
+                problems.push("no blocks since starting".to_string())
+            }
+            _ => {}
+        }
+        problems
+    }
+}
+
+/// Rewrite the heartbeat file every few seconds, in the background.
+pub fn spawn_writer(
+    path: PathBuf,
+    heartbeat: Arc<Mutex<Heartbeat>>,
+    feed_stats: Arc<ConnectionStats>,
+) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(WRITE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut heartbeat = heartbeat.lock().await;
+            let stats = feed_stats.snapshot();
+            heartbeat.updated_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            heartbeat.reconnects = stats.reconnects;
+            heartbeat.bytes_received = stats.bytes_received;
+            heartbeat.ping_rtt_ms = stats.ping_rtt.map(|rtt| rtt.as_secs_f64() * 1000.0);
+            if let Err(e) = journal::write_snapshot(&path, &*heartbeat) {
+                warn!("Failed to write heartbeat file {}: {}", path.display(), e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn healthy_heartbeat_has_no_problems() {
+        let mut heartbeat = Heartbeat::new(1000);
+        heartbeat.set_connection(ConnectionState::Connected, 1001);
+        heartbeat.saw_message(1100);
+        heartbeat.saw_block(10, 1100);
+        heartbeat.updated_at = 1100;
+        assert!(heartbeat.problems(1110, 60).is_empty());
+    }
+
+    #[test]
+    fn stale_heartbeat_has_problems() {
+        let mut heartbeat = Heartbeat::new(1000);
+        assert!(heartbeat.problems(1010, 60).is_empty());
+        assert_eq!(heartbeat.problems(1100, 60).len(), 4);
+
+        heartbeat.set_connection(ConnectionState::Connected, 1001);
+        heartbeat.saw_message(1100);
+        heartbeat.saw_block(10, 1010);
+        // Catching up on old blocks doesn't count as progress:
+        heartbeat.saw_block(9, 1100);
+        heartbeat.updated_at = 1100;
+        assert_eq!(
+            heartbeat.problems(1100, 60),
+            vec!["no new best block for 90s (last was #10)".to_string()]
+        );
+    }
+}
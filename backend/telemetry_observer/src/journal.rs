@@ -0,0 +1,198 @@
+use anyhow::Result;
+use log::{info, warn};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+/// How many compacted journal segments to keep around as an audit trail.
+const KEEP_SEGMENTS: usize = 24;
+
+/// An append-only log of state changes, one JSON event per line. State is recovered
+/// by loading the last snapshot and replaying the journal on top of it, so events
+/// need to be safe to apply more than once.
+///
+/// Once a snapshot has been written, [`Journal::compact`] moves the current journal
+/// aside and starts a new one. The last few segments are kept so that it's possible
+/// to see how state evolved.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+    events: u64,
+    last_compaction: u64,
+}
+
+impl Journal {
+    /// Open the journal at `path`, returning it along with the events it already holds.
+    pub fn open<E: DeserializeOwned>(path: &Path, now: u64) -> Result<(Self, Vec<E>)> {
+        let mut events = vec![];
+        let mut torn = false;
+        if path.exists() {
+            let contents = std::fs::read_to_string(path)?;
+            torn = !contents.is_empty() && !contents.ends_with('\n');
+            for (n, line) in contents.lines().enumerate() {
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match serde_json::from_str(line) {
+                    Ok(event) => events.push(event),
+                    // Most likely the last line, half written when we went down:
+                    Err(e) => warn!("Skipping unreadable line {} of {:?}: {}", n + 1, path, e),
+                }
+            }
+            info!("Replaying {} journal events from {:?}", events.len(), path);
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+        if torn {
+            // Make sure that the next event doesn't end up on the end of the torn line:
+            file.write_all(b"\n")?;
+        }
+        let journal = Self {
+            path: path.to_path_buf(),
+            file,
+            events: events.len() as u64,
+            last_compaction: now,
+        };
+        Ok((journal, events))
+    }
+
+    /// Append an event. Each is written out immediately, so a crash loses nothing
+    /// that's been appended.
+    pub fn append<E: Serialize>(&mut self, event: &E) -> Result<()> {
+        let mut line = serde_json::to_vec(event)?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.flush()?;
+        self.events += 1;
+        Ok(())
+    }
+
+    /// Make sure that everything appended so far has made it to disk.
+    pub fn sync(&self) -> Result<()> {
+        self.file.sync_all()?;
+        Ok(())
+    }
+
+    /// Has it been long enough since the last compaction that it's time for another?
+    pub fn should_compact(&self, now: u64, interval_secs: u64) -> bool {
+        self.events > 0 && now.saturating_sub(self.last_compaction) >= interval_secs
+    }
+
+    /// Start a new journal. Call this only once everything in the current one has
+    /// made it into a snapshot.
+    pub fn compact(&mut self, now: u64) -> Result<()> {
+        let archived = segment_path(&self.path, now);
+        std::fs::rename(&self.path, &archived)?;
+        self.file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        info!(
+            "Compacted {} journal events; previous journal moved to {:?}",
+            self.events, archived
+        );
+        self.events = 0;
+        self.last_compaction = now;
+        self.remove_old_segments()
+    }
+
+    fn remove_old_segments(&self) -> Result<()> {
+        let Some(dir) = self.path.parent() else {
+            return Ok(());
+        };
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let prefix = format!("{}.", file_stem(&self.path));
+        let mut segments: Vec<_> = std::fs::read_dir(dir)?
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path != &self.path)
+            .filter(|path| {
+                path.file_name()
+                    .is_some_and(|name| name.to_string_lossy().starts_with(&prefix))
+            })
+            .collect();
+        if segments.len() <= KEEP_SEGMENTS {
+            return Ok(());
+        }
+        // Segments are named by the time that they were compacted, so sort oldest first:
+        segments.sort();
+        for path in &segments[..segments.len() - KEEP_SEGMENTS] {
+            std::fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Write `value` to `path` as JSON without ever leaving a partially written file there.
+pub fn write_snapshot<T: Serialize>(path: &Path, value: &T) -> Result<()> {
+    let tmp_path = path.with_extension("tmp");
+    let mut file = File::create(&tmp_path)?;
+    serde_json::to_writer(&mut file, value)?;
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+fn file_stem(path: &Path) -> String {
+    path.file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Eg `./data/journal.jsonl` becomes `./data/journal.1700000000.jsonl`.
+fn segment_path(path: &Path, now: u64) -> PathBuf {
+    let file_name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", file_stem(path), now, ext.to_string_lossy()),
+        None => format!("{}.{}", file_stem(path), now),
+    };
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn replays_events_and_skips_torn_lines() {
+        let dir = std::env::temp_dir().join(format!("observer-journal-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("journal.jsonl");
+        let _ = std::fs::remove_file(&path);
+
+        let (mut journal, events) = Journal::open::<u64>(&path, 0).unwrap();
+        assert!(events.is_empty());
+        journal.append(&1u64).unwrap();
+        journal.append(&2u64).unwrap();
+        drop(journal);
+
+        // Simulate a crash part way through writing an event:
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"[1, 2")
+            .unwrap();
+
+        let (mut journal, events) = Journal::open::<u64>(&path, 0).unwrap();
+        assert_eq!(events, vec![1, 2]);
+        journal.append(&3u64).unwrap();
+        let (mut journal, events) = Journal::open::<u64>(&path, 0).unwrap();
+        assert_eq!(events, vec![1, 2, 3]);
+        assert!(journal.should_compact(10, 10));
+
+        journal.compact(10).unwrap();
+        assert!(!journal.should_compact(20, 10));
+        let (_, events) = Journal::open::<u64>(&path, 0).unwrap();
+        assert!(events.is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
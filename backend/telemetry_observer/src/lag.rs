@@ -0,0 +1,354 @@
+use crate::chain::ChainIdentity;
+use crate::csv_file;
+use anyhow::Result;
+use csv::Writer;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+/// Don't look for lagging nodes more often than this (in seconds).
+const CHECK_INTERVAL_SECS: u64 = 5;
+
+/// The columns written to the lag events CSV file.
+const LAG_HEADER: &[&str] = &[
+    "timestamp",
+    "chain",
+    "genesis_hash",
+    "node_name",
+    "node_id",
+    "event",
+    "best_block",
+    "finalized_block",
+    "chain_best_block",
+    "chain_finalized_block",
+];
+
+/// When a node counts as lagging: its best or finalized block is more than
+/// `max_lag_blocks` behind the chain's for at least `lag_secs`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LagRule {
+    pub max_lag_blocks: u64,
+    pub lag_secs: u64,
+}
+
+impl Default for LagRule {
+    fn default() -> Self {
+        Self {
+            max_lag_blocks: 10,
+            lag_secs: 60,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LagEventKind {
+    Lagging,
+    CaughtUp,
+    /// The feed has said that the node is stale: it hasn't imported a block in a while.
+    Stale,
+    /// A stale node has imported a new block.
+    Unstale,
+}
+
+impl LagEventKind {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LagEventKind::Lagging => "lagging",
+            LagEventKind::CaughtUp => "caught_up",
+            LagEventKind::Stale => "stale",
+            LagEventKind::Unstale => "unstale",
+        }
+    }
+}
+
+/// A node starting or stopping lagging behind the chain, or becoming stale or not.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LagEvent {
+    pub timestamp: u64,
+    pub kind: LagEventKind,
+    pub node_name: String,
+    pub node_id: String,
+    pub best_block: u64,
+    pub finalized_block: Option<u64>,
+    pub chain_best_block: u64,
+    pub chain_finalized_block: u64,
+}
+
+#[derive(Debug)]
+struct NodeHeights {
+    node_name: String,
+    best: u64,
+    finalized: Option<u64>,
+    behind_since: Option<u64>,
+    lagging: bool,
+    stale: bool,
+}
+
+/// Keeps track of how far each node is behind the chain. Nodes that are stuck or still
+/// syncing report imports long after everybody else, which makes their propagation
+/// times meaningless, so they shouldn't be attributed blocks. The same goes for nodes
+/// that the feed tells us are stale.
+#[derive(Debug)]
+pub struct LagTracker {
+    rule: LagRule,
+    chain_best: u64,
+    chain_finalized: u64,
+    /// Keyed by network ID.
+    nodes: HashMap<String, NodeHeights>,
+    last_check: u64,
+    /// Events that happened as we were told about blocks, to be returned by the next check.
+    pending: Vec<LagEvent>,
+}
+
+impl LagTracker {
+    pub fn new(rule: LagRule) -> Self {
+        Self {
+            rule,
+            chain_best: 0,
+            chain_finalized: 0,
+            nodes: HashMap::new(),
+            last_check: 0,
+            pending: vec![],
+        }
+    }
+
+    /// The feed's idea of the chain's best block.
+    pub fn saw_chain_best(&mut self, block_number: u64) {
+        self.chain_best = self.chain_best.max(block_number);
+    }
+
+    /// The feed's idea of the chain's finalized block.
+    pub fn saw_chain_finalized(&mut self, block_number: u64) {
+        self.chain_finalized = self.chain_finalized.max(block_number);
+    }
+
+    /// As with the feed, a stale node stops being stale once it imports a new best block.
+    pub fn saw_best(&mut self, node_id: &str, node_name: &str, block_number: u64, now: u64) {
+        self.saw_chain_best(block_number);
+        let node = self.node(node_id, node_name);
+        let unstale = node.stale && block_number > node.best;
+        node.best = block_number;
+        if unstale {
+            node.stale = false;
+            let event = self.event(node_id, LagEventKind::Unstale, now);
+            self.pending.extend(event);
+        }
+    }
+
+    /// The feed has told us that a node is stale. Returns the event to record, unless
+    /// we already knew.
+    pub fn saw_stale(&mut self, node_id: &str, node_name: &str, now: u64) -> Option<LagEvent> {
+        let node = self.node(node_id, node_name);
+        if node.stale {
+            return None;
+        }
+        node.stale = true;
+        self.event(node_id, LagEventKind::Stale, now)
+    }
+
+    pub fn saw_finalized(&mut self, node_id: &str, node_name: &str, block_number: u64) {
+        self.saw_chain_finalized(block_number);
+        self.node(node_id, node_name).finalized = Some(block_number);
+    }
+
+    fn node(&mut self, node_id: &str, node_name: &str) -> &mut NodeHeights {
+        self.nodes
+            .entry(node_id.to_string())
+            .or_insert_with(|| NodeHeights {
+                node_name: node_name.to_string(),
+                best: 0,
+                finalized: None,
+                behind_since: None,
+                lagging: false,
+                stale: false,
+            })
+    }
+
+    fn event(&self, node_id: &str, kind: LagEventKind, now: u64) -> Option<LagEvent> {
+        let node = self.nodes.get(node_id)?;
+        Some(LagEvent {
+            timestamp: now,
+            kind,
+            node_name: node.node_name.clone(),
+            node_id: node_id.to_string(),
+            best_block: node.best,
+            finalized_block: node.finalized,
+            chain_best_block: self.chain_best,
+            chain_finalized_block: self.chain_finalized,
+        })
+    }
+
+    /// A node has left the feed, so we can't tell how far behind it is any more.
+    pub fn forget(&mut self, node_id: &str) {
+        self.nodes.remove(node_id);
+    }
+
+    pub fn is_lagging(&self, node_id: &str) -> bool {
+        self.nodes.get(node_id).is_some_and(|n| n.lagging)
+    }
+
+    pub fn is_stale(&self, node_id: &str) -> bool {
+        self.nodes.get(node_id).is_some_and(|n| n.stale)
+    }
+
+    /// Return an event for each node that has started or stopped lagging, along with
+    /// any that have stopped being stale. Nodes that are stuck don't tell us anything,
+    /// so every node is looked at each time.
+    pub fn check(&mut self, now: u64) -> Vec<LagEvent> {
+        let mut events = std::mem::take(&mut self.pending);
+        if now.saturating_sub(self.last_check) < CHECK_INTERVAL_SECS {
+            return events;
+        }
+        self.last_check = now;
+
+        for (node_id, node) in &mut self.nodes {
+            let best_lag = self.chain_best.saturating_sub(node.best);
+            let finalized_lag = node
+                .finalized
+                .map_or(0, |f| self.chain_finalized.saturating_sub(f));
+            let behind = best_lag.max(finalized_lag) > self.rule.max_lag_blocks;
+
+            let kind = if behind {
+                let since = *node.behind_since.get_or_insert(now);
+                if node.lagging || now.saturating_sub(since) < self.rule.lag_secs {
+                    continue;
+                }
+                node.lagging = true;
+                LagEventKind::Lagging
+            } else {
+                node.behind_since = None;
+                if !node.lagging {
+                    continue;
+                }
+                node.lagging = false;
+                LagEventKind::CaughtUp
+            };
+            events.push(LagEvent {
+                timestamp: now,
+                kind,
+                node_name: node.node_name.clone(),
+                node_id: node_id.clone(),
+                best_block: node.best,
+                finalized_block: node.finalized,
+                chain_best_block: self.chain_best,
+                chain_finalized_block: self.chain_finalized,
+            });
+        }
+        events
+    }
+}
+
+/// Where lag (and staleness) events are written to.
+#[derive(Debug)]
+pub struct LagLog {
+    writer: Writer<File>,
+}
+
+impl LagLog {
+    pub fn open(path: &Path) -> Result<Self> {
+        Ok(Self {
+            writer: csv_file::open_with_header(path, LAG_HEADER)?,
+        })
+    }
+
+    pub fn write(&mut self, event: &LagEvent, chain: &ChainIdentity) -> Result<()> {
+        self.writer.write_record(&[
+            event.timestamp.to_string(),
+            chain.label.clone(),
+            chain.genesis_hash.clone(),
+            event.node_name.clone(),
+            event.node_id.clone(),
+            event.kind.as_str().to_string(),
+            event.best_block.to_string(),
+            event
+                .finalized_block
+                .map(|n| n.to_string())
+                .unwrap_or_default(),
+            event.chain_best_block.to_string(),
+            event.chain_finalized_block.to_string(),
+        ])?;
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds(events: &[LagEvent]) -> Vec<(&str, LagEventKind)> {
+        events
+            .iter()
+            .map(|e| (e.node_name.as_str(), e.kind))
+            .collect()
+    }
+
+    #[test]
+    fn flags_nodes_that_stay_behind() {
+        let mut lag = LagTracker::new(LagRule {
+            max_lag_blocks: 10,
+            lag_secs: 60,
+        });
+        lag.saw_best("a", "alice", 100, 1000);
+        lag.saw_best("b", "bob", 85, 1000);
+        lag.saw_finalized("a", "alice", 98);
+        lag.saw_finalized("b", "bob", 84);
+        assert!(lag.check(1000).is_empty());
+
+        // Bob has to stay behind for a while:
+        lag.saw_best("b", "bob", 95, 1000);
+        assert!(lag.check(1030).is_empty());
+        assert!(!lag.is_lagging("b"));
+        let events = lag.check(1060);
+        assert_eq!(kinds(&events), vec![("bob", LagEventKind::Lagging)]);
+        assert_eq!(events[0].finalized_block, Some(84));
+        assert!(lag.is_lagging("b"));
+        assert!(lag.check(1120).is_empty());
+
+        lag.saw_finalized("b", "bob", 97);
+        let events = lag.check(1130);
+        assert_eq!(kinds(&events), vec![("bob", LagEventKind::CaughtUp)]);
+        assert!(!lag.is_lagging("b"));
+    }
+
+    #[test]
+    fn stuck_nodes_fall_behind() {
+        let mut lag = LagTracker::new(LagRule::default());
+        lag.saw_best("a", "alice", 100, 1000);
+        lag.saw_best("b", "bob", 100, 1000);
+        lag.check(1000);
+        // Bob stops reporting anything at all:
+        for n in 101..=120 {
+            lag.saw_best("a", "alice", n, 1000);
+        }
+        lag.check(1010);
+        let events = lag.check(1080);
+        assert_eq!(kinds(&events), vec![("bob", LagEventKind::Lagging)]);
+        assert_eq!(events[0].chain_best_block, 120);
+    }
+
+    #[test]
+    fn stale_nodes_are_excluded_until_they_import_a_block() {
+        let mut lag = LagTracker::new(LagRule::default());
+        lag.saw_best("a", "alice", 100, 1000);
+        lag.saw_best("b", "bob", 100, 1000);
+
+        let event = lag.saw_stale("b", "bob", 1200).unwrap();
+        assert_eq!(event.kind, LagEventKind::Stale);
+        assert_eq!(event.best_block, 100);
+        assert!(lag.is_stale("b"));
+        // We're only told once about each change:
+        assert_eq!(lag.saw_stale("b", "bob", 1210), None);
+
+        // Hearing about the same block again doesn't count:
+        lag.saw_best("b", "bob", 100, 1220);
+        assert!(lag.check(1220).is_empty());
+        assert!(lag.is_stale("b"));
+
+        lag.saw_best("b", "bob", 101, 1230);
+        assert!(!lag.is_stale("b"));
+        let events = lag.check(1230);
+        assert_eq!(kinds(&events), vec![("bob", LagEventKind::Unstale)]);
+        assert_eq!(events[0].timestamp, 1230);
+    }
+}
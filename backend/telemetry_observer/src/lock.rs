@@ -0,0 +1,92 @@
+use anyhow::{anyhow, Context, Result};
+use std::fs::{File, OpenOptions, TryLockError};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+
+/// What the lock file in each data directory is called.
+pub const LOCK_FILE: &str = "observer.lock";
+
+/// Exclusive locks on the directories that an observer writes to, held until this is
+/// dropped (or the process goes away, however it goes). Each lock file holds the PID of
+/// the observer holding it, so that whoever runs into it knows which one to look for.
+#[derive(Debug)]
+pub struct DataDirLock {
+    _files: Vec<File>,
+}
+
+impl DataDirLock {
+    /// Lock each of the given directories, creating them if need be, or fail if another
+    /// observer already holds any of them.
+    pub fn acquire(dirs: &[PathBuf]) -> Result<Self> {
+        let mut files = vec![];
+        for dir in dirs {
+            files.push(lock_dir(dir)?);
+        }
+        Ok(Self { _files: files })
+    }
+}
+
+fn lock_dir(dir: &Path) -> Result<File> {
+    std::fs::create_dir_all(dir).with_context(|| format!("creating {:?}", dir))?;
+    let path = dir.join(LOCK_FILE);
+    let mut file = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(&path)
+        .with_context(|| format!("opening {:?}", path))?;
+
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => {
+            let mut pid = String::new();
+            let _ = file.read_to_string(&mut pid);
+            let holder = match pid.trim() {
+                "" => "Another observer".to_string(),
+                pid => format!("Another observer (PID {})", pid),
+            };
+            return Err(anyhow!(
+                "{} is already writing to {:?}. Running two at once would corrupt what they write; \
+                 stop the other one, or give this one different files to write to",
+                holder,
+                dir
+            ));
+        }
+        Err(TryLockError::Error(e)) => {
+            return Err(e).with_context(|| format!("locking {:?}", path));
+        }
+    }
+
+    file.set_len(0)?;
+    file.rewind()?;
+    writeln!(file, "{}", std::process::id())?;
+    Ok(file)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn a_second_observer_is_turned_away() {
+        let dir = std::env::temp_dir().join(format!("observer-lock-{}", std::process::id()));
+        let dirs = vec![dir.clone()];
+
+        let lock = DataDirLock::acquire(&dirs).unwrap();
+        let contents = std::fs::read_to_string(dir.join(LOCK_FILE)).unwrap();
+        assert_eq!(contents.trim(), std::process::id().to_string());
+
+        let err = DataDirLock::acquire(&dirs).unwrap_err().to_string();
+        assert!(
+            err.contains(&format!("PID {}", std::process::id())),
+            "{}",
+            err
+        );
+
+        // Once it's let go of, the next one gets in:
+        drop(lock);
+        DataDirLock::acquire(&dirs).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,19 +1,108 @@
+mod aggregate;
+mod alerts;
+mod anonymize;
+mod authorship;
+mod block_rate;
+mod calibration;
+mod chain;
+mod csv_file;
+mod discover;
+mod finality;
+mod fork;
+mod geography;
+mod graph;
+mod heartbeat;
+mod journal;
+mod lag;
+mod lock;
+mod manifest;
+mod propagation;
+mod registry;
+mod replay;
+mod report;
+mod rpc;
+mod run_manifest;
+mod runtime;
+mod schema;
+mod scoring;
+mod sinks;
+mod spill;
+mod staking;
+mod stall;
+mod state;
+mod store;
+mod topology;
+mod tui;
+mod workers;
+
+use aggregate::DailyRollup;
+use alerts::AlertLog;
+use anonymize::Anonymizer;
 use anyhow::Result;
-use common::ws_client::{self, RecvMessage, SentMessage};
-use csv::Writer;
+use authorship::DecidedBlock;
+use block_rate::{BlockRate, SlowdownRule};
+use calibration::Calibration;
+use chain::ChainIdentity;
+use common::byte_size::ByteSize;
+use common::feed_client::{FeedClient, FeedError, FeedMessage, NodeDetails};
+use common::node_types::BlockHash;
+use common::ws_client::{ConnectionStats, StatsSnapshot};
+use finality::FinalityReport;
+use fork::ForkTracker;
 use futures::StreamExt;
+use geography::{GeographyReport, Location};
+use graph::{GraphFormat, PropagationGraph};
+use heartbeat::{ConnectionState, Heartbeat};
+use lag::{LagEvent, LagEventKind, LagLog, LagRule, LagTracker};
+use lock::DataDirLock;
 use log::{debug, error, info, trace, warn};
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-use std::collections::HashMap;
+use manifest::ManifestedCsv;
+use propagation::{PropagationRule, SlowNodeDetector};
+use registry::{ChainDefaults, ChainRegistry};
+use report::AuthorReport;
+use run_manifest::{RunCounters, RunManifest};
+use scoring::{AuthorScorer, AuthorWeights};
+use sinks::{AttributionRow, SinkKind, SinkSpec, Sinks, CSV_HEADER};
+use staking::StakingInfo;
+use stall::StallDetector;
+use state::{BlockInfo, BlockReporter, Candidate, NodeInfo, Nodes, StateEvent, UNKNOWN_NODE_ID};
 use std::env;
-use std::fs::{File, OpenOptions};
-use std::io::{BufReader, BufWriter};
+use std::ops::RangeInclusive;
 use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, SystemTime, UNIX_EPOCH};
-use tokio::sync::Mutex;
+use store::{JsonStore, SledStore, StateBackend, StateStore};
+use tokio::sync::{mpsc, watch, Mutex};
 use tokio::time::sleep;
+use topology::TopologyInference;
+use tui::LiveView;
+use workers::{BlockImport, Job, ShardedBlocks, WorkerPool};
+
+/// Where the heartbeat file is written, unless configured otherwise.
+const DEFAULT_HEARTBEAT_FILE: &str = "./data/heartbeat.json";
+
+/// How old the heartbeat can be before `healthcheck` complains, unless configured otherwise.
+const DEFAULT_HEALTHCHECK_MAX_AGE_SECS: u64 = 120;
+
+/// Where logs go while the live view has taken over the terminal.
+const TUI_LOG_FILE: &str = "./data/observer.log";
+
+/// Where chain defaults are read from (over the bundled ones), unless told otherwise.
+const DEFAULT_CHAINS_FILE: &str = "./chains.toml";
+
+/// Where the aggregate subcommand writes daily rollups, unless told otherwise.
+const DEFAULT_AGGREGATE_DIR: &str = "./data/daily";
+
+/// Where the graph subcommand writes to, less the extension, unless told otherwise.
+const DEFAULT_GRAPH_FILE: &str = "./data/propagation-graph";
+
+/// How often to ping the feed and log statistics about the connection to it.
+const FEED_STATS_INTERVAL: Duration = Duration::from_secs(60);
+
+/// How often to pass the chain's block rate on to the sinks, and check it for slowdowns.
+const BLOCK_RATE_INTERVAL: Duration = Duration::from_secs(10);
 
 struct Config {
     genesis_hash: String,
@@ -21,6 +110,191 @@ struct Config {
     output_path: PathBuf,
     nodes_file: PathBuf,
     blocks_file: PathBuf,
+    journal_file: PathBuf,
+    state_backend: StateBackend,
+    state_db: PathBuf,
+    alerts_file: PathBuf,
+    stall_hours: f64,
+    fork_depth: u64,
+    /// How often the chain expects to produce a block, if known.
+    slot_duration_ms: Option<u64>,
+    slowdown_rule: SlowdownRule,
+    watch_nodes: Vec<String>,
+    propagation_rule: PropagationRule,
+    author_weights: AuthorWeights,
+    rpc_url: Option<String>,
+    report_file: PathBuf,
+    report_hours: f64,
+    authors_file: PathBuf,
+    calibration_file: PathBuf,
+    calibration_hours: f64,
+    geography_file: PathBuf,
+    geography_hours: f64,
+    finality_file: PathBuf,
+    finality_hours: f64,
+    topology_file: PathBuf,
+    topology_hours: f64,
+    topology_window_ms: u64,
+    upgrades_file: PathBuf,
+    anonymize_salt: Option<String>,
+    anonymize_map: PathBuf,
+    heartbeat_file: PathBuf,
+    lag_file: PathBuf,
+    lag_rule: LagRule,
+    tui: bool,
+    duration: Option<Duration>,
+    max_blocks: Option<u64>,
+    start_block: Option<u64>,
+    end_block: Option<u64>,
+    sinks: Vec<SinkSpec>,
+    workers: usize,
+    /// How many bytes of blocks to keep in memory, if limited by size rather than number.
+    memory_budget: Option<usize>,
+    spill_dir: PathBuf,
+    /// Recordings (or glob patterns matching them) to replay instead of following the feed.
+    replay: Vec<String>,
+}
+
+impl Config {
+    /// A hash of the settings that affect what's written to the output CSV file.
+    fn fingerprint(&self) -> String {
+        let mut config = serde_json::json!({
+            "genesis_hash": self.genesis_hash,
+            "telemetry_url": self.telemetry_url,
+            "rpc_url": self.rpc_url,
+            // Not the salt itself; that would give the pseudonyms away.
+            "anonymize_salt": self.anonymize_salt.as_deref().map(manifest::config_hash_str),
+        });
+        // Only when given, so that files written before block ranges existed still match.
+        if self.start_block.is_some() || self.end_block.is_some() {
+            config["start_block"] = self.start_block.into();
+            config["end_block"] = self.end_block.into();
+        }
+        // Likewise for weights, which files written before there were any used the
+        // lowest propagation time for:
+        if self.author_weights != AuthorWeights::default() {
+            let weights = self.author_weights;
+            config["author_weights"] = serde_json::json!({
+                "self_report": weights.self_report,
+                "ordering": weights.ordering,
+                "win_rate": weights.win_rate,
+                "validator": weights.validator,
+            });
+        }
+        manifest::config_hash(&config)
+    }
+
+    /// Every setting, for the run manifest. Like the fingerprint, the salt is hashed, and
+    /// so is the RPC URL, which may well have an API key in it.
+    fn effective(&self) -> serde_json::Value {
+        let path = |path: &PathBuf| path.display().to_string();
+        // In a few pieces, since it's too much for one `json!`:
+        let state = serde_json::json!({
+            "genesis_hash": self.genesis_hash,
+            "telemetry_url": self.telemetry_url,
+            "output_path": path(&self.output_path),
+            "nodes_file": path(&self.nodes_file),
+            "blocks_file": path(&self.blocks_file),
+            "journal_file": path(&self.journal_file),
+            "state_backend": match self.state_backend {
+                StateBackend::Json => "json",
+                StateBackend::Sled => "sled",
+            },
+            "state_db": path(&self.state_db),
+        });
+        let alerts = serde_json::json!({
+            "alerts_file": path(&self.alerts_file),
+            "stall_hours": self.stall_hours,
+            "fork_depth": self.fork_depth,
+            "slot_duration_ms": self.slot_duration_ms,
+            "slowdown_fraction": self.slowdown_rule.fraction,
+            "slowdown_secs": self.slowdown_rule.sustained_secs,
+            "watch_nodes": self.watch_nodes,
+            "propagation_threshold_ms": self.propagation_rule.threshold_ms,
+            "propagation_slow_blocks": self.propagation_rule.slow_blocks,
+            "propagation_window_blocks": self.propagation_rule.window_blocks,
+            "propagation_cooldown_secs": self.propagation_rule.cooldown_secs,
+            "author_weights": {
+                "self_report": self.author_weights.self_report,
+                "ordering": self.author_weights.ordering,
+                "win_rate": self.author_weights.win_rate,
+                "validator": self.author_weights.validator,
+            },
+            "rpc_url": self.rpc_url.as_deref().map(manifest::config_hash_str),
+        });
+        let reports = serde_json::json!({
+            "report_file": path(&self.report_file),
+            "report_hours": self.report_hours,
+            "authors_file": path(&self.authors_file),
+            "calibration_file": path(&self.calibration_file),
+            "calibration_hours": self.calibration_hours,
+            "geography_file": path(&self.geography_file),
+            "geography_hours": self.geography_hours,
+            "finality_file": path(&self.finality_file),
+            "finality_hours": self.finality_hours,
+            "topology_file": path(&self.topology_file),
+            "topology_hours": self.topology_hours,
+            "topology_window_ms": self.topology_window_ms,
+            "upgrades_file": path(&self.upgrades_file),
+        });
+        let run = serde_json::json!({
+            "anonymize_salt": self.anonymize_salt.as_deref().map(manifest::config_hash_str),
+            "anonymize_map": path(&self.anonymize_map),
+            "heartbeat_file": path(&self.heartbeat_file),
+            "lag_file": path(&self.lag_file),
+            "max_lag_blocks": self.lag_rule.max_lag_blocks,
+            "lag_secs": self.lag_rule.lag_secs,
+            "tui": self.tui,
+            "duration_secs": self.duration.map(|d| d.as_secs()),
+            "max_blocks": self.max_blocks,
+            "start_block": self.start_block,
+            "end_block": self.end_block,
+            "sinks": self.sinks.iter().map(|sink| {
+                let kind = match sink.kind {
+                    SinkKind::Csv => "csv",
+                    SinkKind::JsonLines => "jsonl",
+                    SinkKind::Prometheus => "prom",
+                };
+                format!("{}:{}", kind, sink.path.display())
+            }).collect::<Vec<_>>(),
+            "workers": self.workers,
+            "memory_budget": self.memory_budget,
+            "spill_dir": path(&self.spill_dir),
+            "replay": self.replay,
+        });
+
+        let mut config = serde_json::Map::new();
+        for piece in [state, alerts, reports, run] {
+            if let serde_json::Value::Object(piece) = piece {
+                config.extend(piece);
+            }
+        }
+        serde_json::Value::Object(config)
+    }
+
+    /// The blocks to track and write out.
+    fn block_range(&self) -> RangeInclusive<u64> {
+        self.start_block.unwrap_or(0)..=self.end_block.unwrap_or(u64::MAX)
+    }
+
+    /// The directories holding the output CSV file and the state, which no two
+    /// observers should be writing to at once.
+    fn data_dirs(&self) -> Vec<PathBuf> {
+        let state_files = match self.state_backend {
+            StateBackend::Json => vec![&self.nodes_file, &self.blocks_file, &self.journal_file],
+            StateBackend::Sled => vec![&self.state_db],
+        };
+        let mut dirs: Vec<PathBuf> = std::iter::once(&self.output_path)
+            .chain(state_files)
+            .map(|path| match path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir.to_path_buf(),
+                _ => PathBuf::from("."),
+            })
+            .collect();
+        dirs.sort();
+        dirs.dedup();
+        dirs
+    }
 }
 
 impl Default for Config {
@@ -32,243 +306,493 @@ impl Default for Config {
             output_path: PathBuf::from("./data/res-likely-authors.csv"),
             nodes_file: PathBuf::from("./data/telemetry-nodes.json"),
             blocks_file: PathBuf::from("./data/telemetry-blocks.json"),
+            journal_file: PathBuf::from("./data/telemetry-journal.jsonl"),
+            state_backend: StateBackend::Json,
+            state_db: PathBuf::from("./data/telemetry-state.sled"),
+            alerts_file: PathBuf::from("./data/alerts.jsonl"),
+            stall_hours: 4.0,
+            fork_depth: 2,
+            slot_duration_ms: None,
+            slowdown_rule: SlowdownRule::default(),
+            watch_nodes: vec![],
+            propagation_rule: PropagationRule::default(),
+            author_weights: AuthorWeights::default(),
+            rpc_url: None,
+            report_file: PathBuf::from("./data/author-report.csv"),
+            report_hours: 24.0,
+            authors_file: PathBuf::from("./data/block-authors.csv"),
+            calibration_file: PathBuf::from("./data/calibration.csv"),
+            calibration_hours: 24.0,
+            geography_file: PathBuf::from("./data/geography.csv"),
+            geography_hours: 24.0,
+            finality_file: PathBuf::from("./data/finality.csv"),
+            finality_hours: 24.0,
+            topology_file: PathBuf::from("./data/topology.csv"),
+            topology_hours: 1.0,
+            topology_window_ms: 250,
+            upgrades_file: PathBuf::from("./data/runtime-upgrades.csv"),
+            anonymize_salt: None,
+            anonymize_map: PathBuf::from("./data/anonymized-nodes.csv"),
+            heartbeat_file: PathBuf::from(DEFAULT_HEARTBEAT_FILE),
+            lag_file: PathBuf::from("./data/node-lag.csv"),
+            lag_rule: LagRule::default(),
+            tui: false,
+            duration: None,
+            max_blocks: None,
+            start_block: None,
+            end_block: None,
+            sinks: vec![],
+            workers: 1,
+            memory_budget: None,
+            spill_dir: PathBuf::from("./data/spill"),
+            replay: vec![],
         }
     }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct NodeInfo {
-    name: String,
-    node_id: String,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct BlockReporter {
-    node_idx: u64,
-    node_name: String,
-    node_id: String,
-    timestamp: u64,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct BlockInfo {
-    block_number: u64,
-    lowest_prop_time: u64,
-    reporters: Vec<BlockReporter>,
-    first_seen: u64,
-    report_count: u64,
-    output: bool,
-}
-
 #[derive(Debug)]
 struct TelemetryObserver {
-    genesis_hash: String,
-    nodes_file: PathBuf,
-    blocks_file: PathBuf,
-    nodes: Arc<Mutex<HashMap<String, NodeInfo>>>,
-    blocks: Arc<Mutex<HashMap<String, BlockInfo>>>,
-    csv_writer: Arc<Mutex<Writer<File>>>,
+    genesis_hash: BlockHash,
+    nodes: Arc<Mutex<Nodes>>,
+    blocks: ShardedBlocks,
+    scorer: Mutex<AuthorScorer>,
+    /// The highest block that's been imported by anyone.
+    max_block: AtomicU64,
+    store: Mutex<Box<dyn StateStore>>,
+    sinks: Mutex<Sinks>,
+    alerts: Arc<Mutex<AlertLog>>,
+    stall_detector: Arc<Mutex<StallDetector>>,
+    slow_nodes: Arc<Mutex<SlowNodeDetector>>,
+    forks: Arc<Mutex<ForkTracker>>,
+    block_rate: Mutex<BlockRate>,
+    lag: Arc<Mutex<LagTracker>>,
+    lag_log: Mutex<LagLog>,
+    staking: Arc<Mutex<Option<StakingInfo>>>,
+    report: Arc<Mutex<AuthorReport>>,
+    calibration: Arc<Mutex<Calibration>>,
+    /// Where decided blocks go to have their true authors looked up, if there's an RPC node.
+    authorship: Option<mpsc::Sender<DecidedBlock>>,
+    topology: Mutex<TopologyInference>,
+    geography: Mutex<GeographyReport>,
+    finality: Mutex<FinalityReport>,
+    spec_version: Arc<Mutex<Option<u32>>>,
+    chain: Arc<Mutex<ChainIdentity>>,
+    anonymizer: Option<Mutex<Anonymizer>>,
+    feed_stats: Arc<ConnectionStats>,
+    heartbeat: Arc<Mutex<Heartbeat>>,
+    live: Option<Arc<Mutex<LiveView>>>,
+    /// Set to the reason for stopping once it's time to stop.
+    stop: Arc<watch::Sender<Option<&'static str>>>,
+    max_blocks: Option<u64>,
+    counters: Mutex<RunCounters>,
+    block_range: RangeInclusive<u64>,
 }
 
 impl TelemetryObserver {
     async fn new(config: Config) -> Result<Self> {
         debug!("TelemetryObserver::new() called");
-        // Load or initialize nodes
-        let nodes = if config.nodes_file.exists() {
-            let file = File::open(&config.nodes_file)?;
-            let reader = BufReader::new(file);
-            serde_json::from_reader(reader).unwrap_or_default()
-        } else {
-            HashMap::new()
+        let genesis_hash: BlockHash = config
+            .genesis_hash
+            .parse()
+            .map_err(|e| anyhow::anyhow!("Invalid genesis hash: {}", e))?;
+        let anonymizer = match &config.anonymize_salt {
+            Some(salt) => Some(Anonymizer::open(salt, &config.anonymize_map)?),
+            None => None,
         };
 
-        // Load or initialize blocks
-        let blocks = if config.blocks_file.exists() {
-            let file = File::open(&config.blocks_file)?;
-            let reader = BufReader::new(file);
-            serde_json::from_reader(reader).unwrap_or_default()
-        } else {
-            HashMap::new()
-        };
+        let block_range = config.block_range();
 
-        // Initialize CSV writer
-        info!("Initializing CSV writer at {:?}", config.output_path);
-        let csv_exists = config.output_path.exists() && config.output_path.metadata()?.len() > 0;
-        let file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(&config.output_path)?;
-        let mut csv_writer = Writer::from_writer(file);
-
-        // Write header if file is new
-        if !csv_exists {
-            csv_writer.write_record(&[
-                "timestamp",
-                "node_name",
-                "node_id",
-                "block_number",
-                "block_hash",
-                "propagation_time",
-            ])?;
-            csv_writer.flush()?;
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut store: Box<dyn StateStore> = match config.state_backend {
+            StateBackend::Json => Box::new(JsonStore::open(
+                &config.nodes_file,
+                &config.blocks_file,
+                &config.journal_file,
+                now,
+            )?),
+            StateBackend::Sled => Box::new(SledStore::open(&config.state_db)?),
+        };
+        let (mut nodes, blocks) = store.load()?;
+        let max_block = blocks.values().map(|b| b.block_number).max().unwrap_or(0);
+        let mut blocks = ShardedBlocks::new(blocks, config.workers);
+        if let Some(budget) = config.memory_budget {
+            info!(
+                "Keeping blocks within {} bytes, spilling undecided ones over it to {:?}",
+                budget, config.spill_dir
+            );
+            blocks = blocks.with_memory_budget(budget, &config.spill_dir)?;
         }
 
-        Ok(Self {
-            genesis_hash: config.genesis_hash,
-            nodes_file: config.nodes_file,
-            blocks_file: config.blocks_file,
-            nodes: Arc::new(Mutex::new(nodes)),
-            blocks: Arc::new(Mutex::new(blocks)),
-            csv_writer: Arc::new(Mutex::new(csv_writer)),
-        })
-    }
+        // If we're anonymizing, saved nodes may hold real identities from an earlier
+        // run, so start afresh; the feed sends us every node again when we subscribe anyway.
+        if anonymizer.is_some() {
+            nodes.clear();
+        }
 
-    async fn process_message(&self, msg: &str) -> Result<()> {
-        trace!("Processing message: {}", msg);
-        let value: Value = serde_json::from_str(msg)?;
+        // The main CSV file is always the first sink:
+        let config_hash = config.fingerprint();
+        let mut sinks = Sinks::default();
+        sinks.add(
+            format!("csv:{}", config.output_path.display()),
+            Box::new(ManifestedCsv::open(
+                &config.output_path,
+                CSV_HEADER,
+                &config_hash,
+            )?),
+        )?;
+        for spec in &config.sinks {
+            sinks.add(spec.name(), spec.open(&config_hash)?)?;
+        }
 
-        if let Some(arr) = value.as_array() {
-            if arr.is_empty() {
-                trace!("Empty array message, ignoring");
-                return Ok(());
-            }
+        info!("Writing alerts to {:?}", config.alerts_file);
+        let alerts = AlertLog::open(&config.alerts_file)?;
+        let stall_detector = StallDetector::new((config.stall_hours * 3600.0) as u64);
 
-            trace!(
-                "Message array length: {}, first element: {:?}",
-                arr.len(),
-                arr[0]
-            );
-            match arr[0].as_u64() {
-                Some(3) => {
-                    debug!("Processing node message (type 3)");
-                    self.process_node_message(arr).await?
-                }
-                Some(6) => {
-                    debug!("Processing block import message (type 6)");
-                    self.process_block_import(arr).await?
-                }
-                Some(msg_type) => {
-                    trace!("Ignoring message type: {}", msg_type);
-                }
-                None => {
-                    warn!("First element is not a number: {:?}", arr[0]);
-                }
-            }
-        } else {
-            warn!("Message is not an array: {:?}", value);
-        }
+        info!("Writing node lag events to {:?}", config.lag_file);
+        let lag_log = LagLog::open(&config.lag_file)?;
 
-        Ok(())
-    }
+        info!("Writing author reports to {:?}", config.report_file);
+        let report = AuthorReport::new(
+            &config.report_file,
+            (config.report_hours * 3600.0) as u64,
+            now,
+        )?;
 
-    async fn process_node_message(&self, arr: &[Value]) -> Result<()> {
-        debug!(
-            "process_node_message called with array length: {}",
-            arr.len()
+        info!(
+            "Writing calibration reports to {:?}",
+            config.calibration_file
         );
-        if arr.len() < 2 {
-            debug!("Node message array too short, need at least 2 elements");
-            return Ok(());
-        }
+        let calibration = Arc::new(Mutex::new(Calibration::new(
+            &config.calibration_file,
+            (config.calibration_hours * 3600.0) as u64,
+            now,
+        )?));
 
-        trace!("Node message structure: {:?}", arr);
+        info!("Writing geography reports to {:?}", config.geography_file);
+        let geography = GeographyReport::new(
+            &config.geography_file,
+            (config.geography_hours * 3600.0) as u64,
+            now,
+        )?;
 
-        // The structure is [3, [node_idx, [node_data...]]]
-        if let Some(node_array) = arr[1].as_array() {
-            if node_array.len() >= 2 {
-                if let (Some(node_idx), Some(node_data)) =
-                    (node_array[0].as_u64(), node_array[1].as_array())
-                {
-                    debug!(
-                        "Node idx: {}, node data array length: {}",
-                        node_idx,
-                        node_data.len()
-                    );
-                    if node_data.len() >= 5 {
-                        let node_name = node_data[0].as_str().unwrap_or("unknown").to_string();
-                        let node_id = if let Some(arr) = node_data[4].as_array() {
-                            arr.iter()
-                                .filter_map(|v| v.as_str())
-                                .collect::<Vec<_>>()
-                                .join(",")
-                        } else {
-                            node_data[4].as_str().unwrap_or("unknown").to_string()
-                        };
+        info!("Writing finality reports to {:?}", config.finality_file);
+        let finality = FinalityReport::new(
+            &config.finality_file,
+            (config.finality_hours * 3600.0) as u64,
+            now,
+            anonymizer.is_some(),
+        )?;
 
-                        info!(
-                            "Storing node: idx={}, name={}, id={}",
-                            node_idx, node_name, node_id
-                        );
-                        let mut nodes = self.nodes.lock().await;
-                        nodes.insert(
-                            node_idx.to_string(),
-                            NodeInfo {
-                                name: node_name,
-                                node_id,
-                            },
-                        );
-                    }
+        info!("Writing inferred topology to {:?}", config.topology_file);
+        let topology = TopologyInference::new(
+            &config.topology_file,
+            (config.topology_hours * 3600.0) as u64,
+            config.topology_window_ms,
+            now,
+        )?;
+
+        let nodes = Arc::new(Mutex::new(nodes));
+        let chain = Arc::new(Mutex::new(ChainIdentity::new(&format!(
+            "{:?}",
+            genesis_hash
+        ))));
+        let authorship = match &config.rpc_url {
+            Some(rpc_url) => {
+                info!(
+                    "Looking up block authors via {}, and writing them to {:?}",
+                    rpc_url, config.authors_file
+                );
+                Some(authorship::spawn_checker(
+                    rpc_url,
+                    nodes.clone(),
+                    chain.clone(),
+                    &config.authors_file,
+                    calibration.clone(),
+                    anonymizer.is_some(),
+                )?)
+            }
+            None => None,
+        };
+
+        Ok(Self {
+            chain,
+            anonymizer: anonymizer.map(Mutex::new),
+            genesis_hash,
+            nodes,
+            max_block: AtomicU64::new(max_block),
+            blocks,
+            scorer: Mutex::new(AuthorScorer::new(config.author_weights)),
+            store: Mutex::new(store),
+            sinks: Mutex::new(sinks),
+            alerts: Arc::new(Mutex::new(alerts)),
+            stall_detector: Arc::new(Mutex::new(stall_detector)),
+            forks: Arc::new(Mutex::new(ForkTracker::new(config.fork_depth))),
+            block_rate: Mutex::new(BlockRate::new(
+                config.slot_duration_ms,
+                config.slowdown_rule,
+            )),
+            lag: Arc::new(Mutex::new(LagTracker::new(config.lag_rule))),
+            lag_log: Mutex::new(lag_log),
+            slow_nodes: Arc::new(Mutex::new(SlowNodeDetector::new(
+                config.propagation_rule,
+                config.watch_nodes,
+            ))),
+            staking: Arc::new(Mutex::new(None)),
+            report: Arc::new(Mutex::new(report)),
+            calibration,
+            authorship,
+            topology: Mutex::new(topology),
+            geography: Mutex::new(geography),
+            finality: Mutex::new(finality),
+            spec_version: Arc::new(Mutex::new(None)),
+            feed_stats: ConnectionStats::new(),
+            heartbeat: Arc::new(Mutex::new(Heartbeat::new(now))),
+            live: config
+                .tui
+                .then(|| Arc::new(Mutex::new(LiveView::default()))),
+            stop: Arc::new(watch::channel(None).0),
+            max_blocks: config.max_blocks,
+            counters: Mutex::new(RunCounters::default()),
+            block_range,
+        })
+    }
+
+    async fn process_message(&self, msg: FeedMessage, workers: &WorkerPool) -> Result<()> {
+        trace!("Processing message: {:?}", msg);
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        self.heartbeat.lock().await.saw_message(now);
+        match msg {
+            FeedMessage::AddedChain {
+                name, genesis_hash, ..
+            } => self.process_added_chain(name, genesis_hash).await,
+            FeedMessage::AddedNode {
+                node_id,
+                node,
+                block_details,
+                location,
+                ..
+            } => {
+                debug!("Processing added node");
+                // Forget where the last node at this index was, unless the feed already
+                // knows where this one is:
+                let mut geography = self.geography.lock().await;
+                geography.forget(node_id);
+                if let Some(location) = location {
+                    geography.located(
+                        node_id,
+                        Location {
+                            latitude: location.latitude.into(),
+                            longitude: location.longitude.into(),
+                        },
+                    );
                 }
+                drop(geography);
+                self.process_added_node(node_id, node, block_details.block.height)
+                    .await?
+            }
+            FeedMessage::LocatedNode {
+                node_id, lat, long, ..
+            } => self.geography.lock().await.located(
+                node_id,
+                Location {
+                    latitude: lat.into(),
+                    longitude: long.into(),
+                },
+            ),
+            FeedMessage::RemovedNode { node_id } => self.process_removed_node(node_id).await?,
+            FeedMessage::ImportedBlock {
+                node_id,
+                block_details,
+            } => {
+                debug!("Processing block import");
+                // The core gives the first import of a new best block a propagation time
+                // of zero, and imports of anything but the best block none at all.
+                let first_import = block_details.propagation_time == Some(0);
+                let import = self
+                    .process_block_import(
+                        node_id as u64,
+                        block_details.block.height,
+                        format!("{:?}", block_details.block.hash),
+                        block_details.propagation_time.unwrap_or(0),
+                        first_import,
+                    )
+                    .await?;
+                if let Some(import) = import {
+                    self.dispatch_block_import(import, workers).await?
+                }
+            }
+            FeedMessage::FinalizedBlock {
+                node_id,
+                block_number,
+                ..
+            } => self.process_finalized_block(node_id, block_number).await,
+            FeedMessage::StaleNode { node_id } => self.process_stale_node(node_id, now).await?,
+            FeedMessage::BestBlock { block_number, .. } => {
+                self.block_rate.lock().await.saw_best(block_number, now);
+                self.lag.lock().await.saw_chain_best(block_number)
+            }
+            FeedMessage::BestFinalized { block_number, .. } => {
+                self.lag.lock().await.saw_chain_finalized(block_number)
+            }
+            FeedMessage::AfgAuthoritySet {
+                authorities,
+                authority_set_id,
+                ..
+            } => self
+                .finality
+                .lock()
+                .await
+                .authority_set(&authority_set_id, &authorities),
+            FeedMessage::AfgReceivedPrevote {
+                block_number,
+                voter: Some(voter),
+                ..
+            } => self.finality.lock().await.prevote(&voter, block_number),
+            FeedMessage::AfgReceivedPrecommit {
+                block_number,
+                voter: Some(voter),
+                ..
+            } => self.finality.lock().await.precommit(&voter, block_number),
+            FeedMessage::AfgFinalized { block_number, .. } => {
+                let mut finality = self.finality.lock().await;
+                finality.finalized(block_number);
+                finality.maybe_write(now, &self.chain.lock().await.clone())?;
+            }
+            msg => {
+                trace!("Ignoring message: {:?}", msg);
             }
         }
-
-        // Save nodes to file
-        self.save_nodes().await?;
         Ok(())
     }
 
-    async fn process_block_import(&self, arr: &[Value]) -> Result<()> {
-        trace!("process_block_import called with array: {:?}", arr);
-        if arr.len() < 2 || !arr[1].is_array() {
-            debug!(
-                "Block import validation failed: arr.len()={}, arr[1].is_array()={}",
-                arr.len(),
-                arr.get(1).map_or(false, |v| v.is_array())
-            );
-            return Ok(());
+    /// The feed tells us about every chain it knows of, which is where we find out the
+    /// name of the one we're subscribed to.
+    async fn process_added_chain(&self, label: String, genesis_hash: BlockHash) {
+        if genesis_hash != self.genesis_hash {
+            return;
         }
 
-        let data = arr[1].as_array().unwrap();
-        trace!("Block import data array: {:?}", data);
-        if data.len() < 2 || !data[1].is_array() {
-            debug!(
-                "Block import inner validation failed: data.len()={}, data[1].is_array()={}",
-                data.len(),
-                data.get(1).map_or(false, |v| v.is_array())
-            );
-            return Ok(());
+        let mut chain = self.chain.lock().await;
+        if chain.label != label {
+            info!("Observing chain '{}' ({:?})", label, genesis_hash);
+            chain.label = label;
         }
+    }
 
-        let node_idx = data[0].as_u64().unwrap_or(0);
-        let block_data = data[1].as_array().unwrap();
-        trace!("Node idx: {}, block_data: {:?}", node_idx, block_data);
+    async fn process_added_node(
+        &self,
+        node_idx: usize,
+        details: NodeDetails,
+        best_block: u64,
+    ) -> Result<()> {
+        let node_name = details.name;
 
-        if block_data.len() < 5 {
-            debug!("Block data too short: len={}", block_data.len());
-            return Ok(());
+        // Swap in pseudonyms before the node is stored, so that real
+        // identities never make it into any output.
+        let network_id = details.network_id;
+        let (node_name, node_id) = match &self.anonymizer {
+            Some(anonymizer) => {
+                let mut anonymizer = anonymizer.lock().await;
+                let node_id = match network_id {
+                    Some(network_id) => anonymizer.node_id(&network_id)?,
+                    // Nodes without a network ID are told apart by name, which needs this intact.
+                    None => UNKNOWN_NODE_ID.to_string(),
+                };
+                (anonymizer.node_name(&node_name)?, node_id)
+            }
+            None => (
+                node_name,
+                network_id.unwrap_or_else(|| UNKNOWN_NODE_ID.to_string()),
+            ),
+        };
+
+        info!(
+            "Storing node: idx={}, name={}, id={}",
+            node_idx, node_name, node_id
+        );
+        if let Some(validator) = &details.validator {
+            self.finality
+                .lock()
+                .await
+                .saw_node(validator, &node_name, &node_id);
+        }
+        let node = NodeInfo {
+            name: node_name,
+            node_id,
+            validator: details.validator,
+            implementation: details.implementation,
+            version: details.version,
+        };
+        self.nodes
+            .lock()
+            .await
+            .insert(node_idx.to_string(), node.clone());
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        self.lag
+            .lock()
+            .await
+            .saw_best(&node.node_id, &node.name, best_block, now);
+        if let Some(live) = &self.live {
+            live.lock().await.node_joined(&node.name, now);
         }
+        self.record(StateEvent::NodeAdded {
+            node_idx: node_idx.to_string(),
+            node,
+        })
+        .await
+    }
 
-        let block_number = block_data[0].as_u64().unwrap_or(0);
-        let block_hash = block_data[1].as_str().unwrap_or("").to_string();
-        let propagation_time = block_data[4].as_u64().unwrap_or(0);
+    async fn process_removed_node(&self, node_idx: usize) -> Result<()> {
+        // The feed reuses the indices of removed nodes, so forget about this one rather
+        // than attribute blocks from whichever node takes its place to it.
+        let removed = self
+            .nodes
+            .lock()
+            .await
+            .remove(&node_idx.to_string())
+            .cloned();
+        if let Some(node) = removed {
+            debug!("Removed node: idx={}", node_idx);
+            self.lag.lock().await.forget(&node.node_id);
+            self.geography.lock().await.forget(node_idx);
+            if let Some(live) = &self.live {
+                let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+                live.lock().await.node_left(&node.name, now);
+            }
+            self.record(StateEvent::NodeRemoved {
+                node_idx: node_idx.to_string(),
+            })
+            .await?;
+        }
+        Ok(())
+    }
 
+    /// Find out who reported an import and whether it counts towards attributing its
+    /// block, keeping track of everything that's about nodes rather than blocks along
+    /// the way. This happens in the order that the feed sends imports in; the import is
+    /// then handed to the worker for its block, unless there's nothing more to do.
+    async fn process_block_import(
+        &self,
+        node_idx: u64,
+        block_number: u64,
+        block_hash: String,
+        propagation_time: u64,
+        first_import: bool,
+    ) -> Result<Option<BlockImport>> {
         debug!(
-            "Block details: number={}, hash={}, prop_time={}",
-            block_number, block_hash, propagation_time
+            "Block details: node={}, number={}, hash={}, prop_time={}",
+            node_idx, block_number, block_hash, propagation_time
         );
 
-        if block_hash.is_empty() || propagation_time == 0 {
-            debug!("Invalid block data: empty hash or zero prop time");
-            return Ok(());
-        }
-
-        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        let now = now_ms / 1000;
+        self.heartbeat.lock().await.saw_block(block_number, now);
 
         let nodes = self.nodes.lock().await;
         debug!(
-            "Looking up node idx {} in nodes map with {} entries",
+            "Looking up node idx {} among {} connected nodes",
             node_idx,
-            nodes.len()
+            nodes.connected()
         );
         let node_info = nodes.get(&node_idx.to_string());
         let node_name = node_info
@@ -277,95 +801,254 @@ impl TelemetryObserver {
         let node_id = node_info
             .map(|n| n.node_id.clone())
             .unwrap_or_else(|| "unknown_id".to_string());
+        let implementation = node_info
+            .map(|n| n.implementation.clone())
+            .unwrap_or_default();
+        let version = node_info.map(|n| n.version.clone()).unwrap_or_default();
+        let is_validator = node_info.is_some_and(|n| n.validator.is_some());
         debug!("Node lookup result: name={}, id={}", node_name, node_id);
+        let known_node = node_info.is_some();
         drop(nodes);
 
-        let mut blocks = self.blocks.lock().await;
-        let block = blocks.entry(block_hash.clone()).or_insert(BlockInfo {
-            block_number,
-            lowest_prop_time: 999999,
-            reporters: vec![],
-            first_seen: now,
-            report_count: 0,
-            output: false,
-        });
+        // Nodes that are stuck or syncing import blocks long after everyone else, so
+        // their propagation times say nothing about who authored a block.
+        let mut lag = self.lag.lock().await;
+        if known_node {
+            lag.saw_best(&node_id, &node_name, block_number, now);
+        }
+        let lagging = lag.is_lagging(&node_id) || lag.is_stale(&node_id);
+        let lag_events = lag.check(now);
+        drop(lag);
+        self.write_lag_events(&lag_events).await?;
+
+        // Blocks outside of the range we're interested in still show that nodes are
+        // alive, and move things along so that blocks inside it get decided.
+        let in_range = self.block_range.contains(&block_number);
+
+        // Every import counts towards spotting forks, including those from the
+        // first node to see a block (which don't have a propagation time).
+        if in_range {
+            let fork =
+                self.forks
+                    .lock()
+                    .await
+                    .saw_import(block_number, &block_hash, &node_name, now);
+            if let Some(alert) = fork {
+                self.alerts.lock().await.raise(&alert)?;
+            }
+        }
+
+        // As does working out who hears about blocks from whom.
+        if in_range && known_node {
+            let chain = self.chain.lock().await.clone();
+            let mut topology = self.topology.lock().await;
+            topology.saw_import(
+                &block_hash,
+                block_number,
+                &node_name,
+                &node_id,
+                propagation_time,
+            );
+            topology.maybe_write(now, &chain)?;
+            drop(topology);
+            let mut geography = self.geography.lock().await;
+            geography.saw_import(
+                node_idx as usize,
+                &block_hash,
+                block_number,
+                propagation_time,
+            );
+            geography.maybe_write(now, &chain)?;
+        }
+
+        // The first import of a block counts towards attributing it, as a node that
+        // might be the author reporting its own block. Other imports without a
+        // propagation time tell us nothing.
+        if propagation_time == 0 && !first_import {
+            debug!("Invalid block data: zero prop time");
+            return Ok(None);
+        }
+
+        if let Some(live) = self.live.as_ref().filter(|_| in_range && !first_import) {
+            live.lock()
+                .await
+                .saw_import(block_number, &block_hash, propagation_time);
+        }
 
-        block.report_count += 1;
+        let slow_node = if in_range {
+            self.slow_nodes.lock().await.saw_import(
+                &node_name,
+                &node_id,
+                block_number,
+                propagation_time,
+                now,
+            )
+        } else {
+            None
+        };
+        if let Some(alert) = slow_node {
+            self.alerts.lock().await.raise(&alert)?;
+        }
 
-        if propagation_time < block.lowest_prop_time {
-            block.lowest_prop_time = propagation_time;
-            block.reporters = vec![BlockReporter {
+        self.stall_detector
+            .lock()
+            .await
+            .saw_node(&node_name, &node_id, is_validator, now);
+        if is_validator {
+            self.report.lock().await.saw_validator(&node_name, &node_id);
+        }
+
+        Ok(Some(BlockImport {
+            block_number,
+            block_hash,
+            propagation_time,
+            validator: is_validator,
+            reporter: BlockReporter {
                 node_idx,
                 node_name,
                 node_id,
+                implementation,
+                version,
                 timestamp: now,
-            }];
-        } else if propagation_time == block.lowest_prop_time {
-            if !block.reporters.iter().any(|r| r.node_idx == node_idx) {
-                block.reporters.push(BlockReporter {
-                    node_idx,
-                    node_name,
-                    node_id,
-                    timestamp: now,
+            },
+            counts: in_range && !lagging,
+        }))
+    }
+
+    /// Hand an import to the worker for its block. When the chain moves on, every other
+    /// worker is told too, since blocks it's been waiting on may now be ready to decide.
+    async fn dispatch_block_import(&self, import: BlockImport, workers: &WorkerPool) -> Result<()> {
+        let now_ms = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as u64;
+        let shard = self.blocks.shard_of(&import.block_hash);
+        let previous_max = self
+            .max_block
+            .fetch_max(import.block_number, Ordering::Relaxed);
+        let new_max = import.block_number > previous_max;
+        workers.send(shard, Job::Import { import, now_ms }).await;
+        if new_max {
+            workers.broadcast_except(shard, Job::Tick { now_ms }).await;
+        }
+        Ok(())
+    }
+
+    /// Add an import to its block, and write out any of the blocks in the same shard
+    /// that are ready to be decided. Each shard is only ever looked at by one worker.
+    async fn decide_blocks(&self, shard: usize, job: Job) -> Result<()> {
+        let (import, now_ms) = match job {
+            Job::Import { import, now_ms } => (Some(import), now_ms),
+            Job::Tick { now_ms } => (None, now_ms),
+        };
+        let now = now_ms / 1000;
+        let max_block = self.max_block.load(Ordering::Relaxed);
+
+        let mut blocks = self.blocks.shard(shard).lock().await;
+        let mut spilled = match self.blocks.spilled(shard) {
+            Some(spilled) => Some(spilled.lock().await),
+            None => None,
+        };
+        if let Some(spilled) = &mut spilled {
+            // Bring back the block being imported, if it was spilled, along with any
+            // blocks that are now due to be decided:
+            let mut wanted = spilled.due(now, max_block);
+            if let Some(import) = import.as_ref().filter(|i| spilled.contains(&i.block_hash)) {
+                wanted.push(import.block_hash.clone());
+            }
+            for block_hash in wanted {
+                if let Some(block) = spilled.load(&block_hash)? {
+                    blocks.insert(block_hash, block);
+                }
+            }
+        }
+        if let Some(import) = import.filter(|import| import.counts) {
+            let block = blocks
+                .entry(import.block_hash.clone())
+                .or_insert(BlockInfo {
+                    block_number: import.block_number,
+                    lowest_prop_time: 999999,
+                    reporters: vec![],
+                    candidates: vec![],
+                    first_seen: now,
+                    first_seen_ms: now_ms,
+                    report_count: 0,
+                    output: false,
+                });
+
+            block.report_count += 1;
+
+            if import.propagation_time > 0 {
+                block.lowest_prop_time = block.lowest_prop_time.min(import.propagation_time);
+            }
+            if !block
+                .candidates
+                .iter()
+                .any(|c| c.reporter.node_idx == import.reporter.node_idx)
+            {
+                block.candidates.push(Candidate {
+                    reporter: import.reporter,
+                    propagation_time: import.propagation_time,
+                    validator: import.validator,
                 });
             }
         }
 
         // Check if any blocks are ready for output
-        let max_block = blocks.values().map(|b| b.block_number).max().unwrap_or(0);
         debug!(
-            "Checking blocks for output: current block={}, max_block={}, total blocks={}",
-            block_number,
+            "Checking blocks for output: max_block={}, blocks in shard {}={}",
             max_block,
+            shard,
             blocks.len()
         );
 
         let mut outputs = vec![];
+        let mut decided = vec![];
+        let mut scorer = self.scorer.lock().await;
         for (hash, block) in blocks.iter_mut() {
-            let time_since_first = now - block.first_seen;
-            let should_output = !block.output
-                && (block.report_count >= 3
-                    || time_since_first > 3
-                    || block.block_number < max_block.saturating_sub(1));
-
-            if should_output {
+            let time_since_first = now.saturating_sub(block.first_seen);
+            if block.ready_to_decide(now, max_block) {
                 debug!(
                     "Block {} ready for output: report_count={}, time_since_first={}, block_num={}, max_block={}",
                     hash, block.report_count, time_since_first, block.block_number, max_block
                 );
-            }
-
-            if should_output {
-                for reporter in &block.reporters {
+                let decision_latency_ms = if block.first_seen_ms > 0 {
+                    now_ms.saturating_sub(block.first_seen_ms)
+                } else {
+                    time_since_first * 1000
+                };
+                let candidates = std::mem::take(&mut block.candidates);
+                let winners = scorer.decide(&candidates);
+                block.reporters = winners
+                    .iter()
+                    .map(|&i| candidates[i].reporter.clone())
+                    .collect();
+                for &i in &winners {
+                    let winner = &candidates[i];
                     debug!(
                         "Adding output for block {}: node={}, prop_time={}",
-                        block.block_number, reporter.node_name, block.lowest_prop_time
+                        block.block_number, winner.reporter.node_name, winner.propagation_time
                     );
                     outputs.push((
-                        reporter.timestamp,
-                        reporter.node_name.clone(),
-                        reporter.node_id.clone(),
+                        winner.reporter.clone(),
                         block.block_number,
                         hash.clone(),
-                        block.lowest_prop_time,
+                        winner.propagation_time,
+                        decision_latency_ms,
+                        block.report_count,
                     ));
                 }
                 block.output = true;
+                decided.push((hash.clone(), block.clone()));
             }
         }
+        drop(scorer);
 
         debug!("Total outputs to write: {}", outputs.len());
 
         // Log tracking status
-        let now_time = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
         let time_str = format!(
             "[{:02}:{:02}:{:02}]",
-            (now_time / 3600) % 24,
-            (now_time / 60) % 60,
-            now_time % 60
+            (now / 3600) % 24,
+            (now / 60) % 60,
+            now % 60
         );
         info!(
             "{} Tracking {} blocks, {} outputs ready",
@@ -374,145 +1057,440 @@ impl TelemetryObserver {
             outputs.len()
         );
 
-        // Clean up old blocks (keep only recent 100)
-        let mut block_list: Vec<_> = blocks
+        // Clean up old blocks, keeping only the most recent ones, or as many as fit in
+        // the memory budget if there is one
+        match (self.blocks.budget_per_shard(), &mut spilled) {
+            (Some(budget), Some(spilled)) => spill::keep_within(&mut blocks, budget, spilled)?,
+            _ => {
+                let mut block_list: Vec<_> = blocks
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.block_number))
+                    .collect();
+                block_list.sort_by_key(|(_, num)| std::cmp::Reverse(*num));
+                if block_list.len() > self.blocks.max_per_shard() {
+                    for (hash, _) in &block_list[self.blocks.max_per_shard()..] {
+                        blocks.remove(hash);
+                    }
+                }
+            }
+        }
+        drop(spilled);
+        drop(blocks);
+
+        let end_block = *self.block_range.end();
+        let past_end_block =
+            max_block > end_block && self.blocks.all_decided_up_to(end_block).await;
+
+        // Count each block towards the validator it was attributed to, if its node says:
+        let nodes = self.nodes.lock().await;
+        let authors: Vec<_> = decided
             .iter()
-            .map(|(k, v)| (k.clone(), v.block_number))
+            .flat_map(|(_, block)| &block.reporters)
+            .filter_map(|r| nodes.get(&r.node_idx.to_string())?.validator.clone())
             .collect();
-        block_list.sort_by_key(|(_, num)| std::cmp::Reverse(*num));
-        if block_list.len() > 100 {
-            for (hash, _) in &block_list[100..] {
-                blocks.remove(hash);
-            }
+        drop(nodes);
+        let mut finality = self.finality.lock().await;
+        for author in &authors {
+            finality.authored(author);
         }
+        drop(finality);
 
-        drop(blocks);
+        let mut stall_detector = self.stall_detector.lock().await;
+        let mut report = self.report.lock().await;
+        for (_, block) in &decided {
+            let reporters: Vec<_> = block
+                .reporters
+                .iter()
+                .map(|r| (r.node_name.as_str(), r.node_id.as_str()))
+                .collect();
+            report.record_block(block.block_number, &reporters);
+            for reporter in &block.reporters {
+                stall_detector.attributed(&reporter.node_id, now);
+            }
+        }
+        let new_alerts = stall_detector.check(now);
+        drop(stall_detector);
+        let chain = self.chain.lock().await.clone();
+        report.maybe_write(now, *self.staking.lock().await, &chain)?;
+        drop(report);
+        if !new_alerts.is_empty() {
+            let mut alerts = self.alerts.lock().await;
+            for alert in &new_alerts {
+                alerts.raise(alert)?;
+            }
+        }
 
-        // Write outputs to CSV
+        // Write outputs to the sinks
+        let mut counters = self.counters.lock().await;
+        counters.blocks_decided += decided.len() as u64;
+        counters.rows_written += outputs.len() as u64;
+        let blocks_decided = counters.blocks_decided;
+        drop(counters);
         if !outputs.is_empty() {
-            info!("Writing {} records to CSV", outputs.len());
-            let mut csv_writer = self.csv_writer.lock().await;
-            for (timestamp, node_name, node_id, block_number, block_hash, prop_time) in outputs {
-                debug!(
-                    "CSV write: timestamp={}, node={}, block={}",
-                    timestamp, node_name, block_number
-                );
-                csv_writer.write_record(&[
-                    timestamp.to_string(),
-                    node_name,
-                    node_id,
-                    block_number.to_string(),
-                    block_hash,
-                    prop_time.to_string(),
-                ])?;
-            }
-            csv_writer.flush()?;
-            debug!("CSV flush complete");
-        }
-
-        // Save blocks to file
-        self.save_blocks().await?;
-
-        // Log tracking info
-        let blocks = self.blocks.lock().await;
-        let block_count = blocks.len();
-        let output_count = blocks.values().filter(|b| !b.output).count();
-        drop(blocks);
+            info!("Writing {} records", outputs.len());
+            let staking = *self.staking.lock().await;
+            let spec_version = *self.spec_version.lock().await;
+            let rows = outputs
+                .into_iter()
+                .map(
+                    |(
+                        reporter,
+                        block_number,
+                        block_hash,
+                        prop_time,
+                        decision_latency_ms,
+                        reports_at_decision,
+                    )| AttributionRow {
+                        timestamp: reporter.timestamp,
+                        chain: chain.label.clone(),
+                        genesis_hash: chain.genesis_hash.clone(),
+                        node_name: reporter.node_name,
+                        node_id: reporter.node_id,
+                        node_implementation: reporter.implementation,
+                        node_version: reporter.version,
+                        block_number,
+                        block_hash,
+                        propagation_time: prop_time,
+                        decision_latency_ms,
+                        reports_at_decision,
+                        validator_count: staking.map(|info| info.validator_count),
+                        expected_share: staking.map(|info| info.expected_share()),
+                        spec_version,
+                    },
+                )
+                .collect();
+            self.sinks.lock().await.write(rows);
+        }
 
-        let now = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_secs();
-        let hours = (now % 86400) / 3600;
-        let minutes = (now % 3600) / 60;
-        let seconds = now % 60;
-        info!(
-            "[{:02}:{:02}:{:02}] Tracking {} blocks, {} outputs ready",
-            hours, minutes, seconds, block_count, output_count
-        );
+        if let Some(authorship) = &self.authorship {
+            for (block_hash, block) in &decided {
+                let block = DecidedBlock {
+                    block_number: block.block_number,
+                    block_hash: block_hash.clone(),
+                    attributed: block
+                        .reporters
+                        .iter()
+                        .map(|r| (r.node_name.clone(), r.node_id.clone()))
+                        .collect(),
+                };
+                if authorship.try_send(block).is_err() {
+                    debug!("Not looking up the author of a block; the RPC node is behind");
+                }
+            }
+        }
+
+        if let Some(live) = &self.live {
+            let mut live = live.lock().await;
+            for (block_hash, block) in &decided {
+                let authors: Vec<_> = block
+                    .reporters
+                    .iter()
+                    .map(|r| r.node_name.as_str())
+                    .collect();
+                live.decided(block_hash, &authors);
+            }
+        }
+        if self
+            .max_blocks
+            .is_some_and(|max_blocks| blocks_decided >= max_blocks)
+        {
+            self.stop
+                .send_replace(Some("the maximum number of blocks was reached"));
+        }
+        if past_end_block {
+            self.stop
+                .send_replace(Some("every block up to the end block was written out"));
+        }
+        for (block_hash, block) in decided {
+            self.record(StateEvent::BlockDecided { block_hash, block })
+                .await?;
+        }
+        self.maybe_compact(now).await
+    }
+    async fn process_finalized_block(&self, node_idx: usize, block_number: u64) {
+        let nodes = self.nodes.lock().await;
+        if let Some(node) = nodes.get(&node_idx.to_string()) {
+            self.lag
+                .lock()
+                .await
+                .saw_finalized(&node.node_id, &node.name, block_number);
+        }
+    }
 
+    /// The feed marks nodes as stale once they've gone a while without importing a
+    /// block. They stop being stale once they import one.
+    async fn process_stale_node(&self, node_idx: usize, now: u64) -> Result<()> {
+        let nodes = self.nodes.lock().await;
+        let event = match nodes.get(&node_idx.to_string()) {
+            Some(node) => self
+                .lag
+                .lock()
+                .await
+                .saw_stale(&node.node_id, &node.name, now),
+            None => None,
+        };
+        drop(nodes);
+        self.write_lag_events(event.as_slice()).await
+    }
+
+    async fn write_lag_events(&self, events: &[LagEvent]) -> Result<()> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let chain = self.chain.lock().await.clone();
+        let mut lag_log = self.lag_log.lock().await;
+        for event in events {
+            match event.kind {
+                LagEventKind::Lagging => info!(
+                    "Node {} is lagging behind the chain (best #{} vs #{}); not attributing blocks to it",
+                    event.node_name, event.best_block, event.chain_best_block
+                ),
+                LagEventKind::CaughtUp => {
+                    info!("Node {} has caught up with the chain", event.node_name)
+                }
+                LagEventKind::Stale => info!(
+                    "Node {} is stale (best #{}); not attributing blocks to it",
+                    event.node_name, event.best_block
+                ),
+                LagEventKind::Unstale => info!(
+                    "Node {} is no longer stale (best #{})",
+                    event.node_name, event.best_block
+                ),
+            }
+            lag_log.write(event, &chain)?;
+        }
         Ok(())
     }
 
-    async fn save_nodes(&self) -> Result<()> {
+    /// Persist a state change.
+    async fn record(&self, event: StateEvent) -> Result<()> {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        self.store.lock().await.record(timestamp, &event)
+    }
+
+    /// Give the state store a chance to tidy up.
+    async fn maybe_compact(&self, now: u64) -> Result<()> {
+        let mut store = self.store.lock().await;
+        if !store.should_compact(now) {
+            return Ok(());
+        }
         let nodes = self.nodes.lock().await;
-        let file = File::create(&self.nodes_file)?;
-        let writer = BufWriter::new(file);
-        serde_json::to_writer(writer, &*nodes)?;
+        let blocks = self.blocks.merged().await;
+        store.maybe_compact(now, &nodes, &blocks)
+    }
+
+    /// Every so often, pass the chain's block rate on to the sinks and check that it
+    /// hasn't slowed down. This doesn't wait on blocks arriving, since a chain that's
+    /// stopped altogether won't be sending any.
+    fn spawn_block_rate_checker(self: &Arc<Self>) {
+        let observer = Arc::clone(self);
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(BLOCK_RATE_INTERVAL);
+            loop {
+                interval.tick().await;
+                if let Err(e) = observer.check_block_rate().await {
+                    warn!("Failed to check the block rate: {:#}", e);
+                }
+            }
+        });
+    }
+
+    async fn check_block_rate(&self) -> Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let mut block_rate = self.block_rate.lock().await;
+        let alert = block_rate.check(now);
+        let blocks_per_minute = block_rate.per_minute(now);
+        drop(block_rate);
+        if let Some(blocks_per_minute) = blocks_per_minute {
+            let chain = self.chain.lock().await.label.clone();
+            self.sinks
+                .lock()
+                .await
+                .block_rate(&chain, blocks_per_minute);
+        }
+        if let Some(alert) = alert {
+            self.alerts.lock().await.raise(&alert)?;
+        }
         Ok(())
     }
 
-    async fn save_blocks(&self) -> Result<()> {
-        let blocks = self.blocks.lock().await;
-        let file = File::create(&self.blocks_file)?;
-        let writer = BufWriter::new(file);
-        serde_json::to_writer(writer, &*blocks)?;
+    async fn set_connection_state(&self, state: ConnectionState) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.heartbeat.lock().await.set_connection(state, now);
+    }
+
+    /// Flush everything out before exiting.
+    async fn shutdown(&self) -> Result<()> {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        self.sinks.lock().await.close();
+        let chain = self.chain.lock().await.clone();
+        let staking = *self.staking.lock().await;
+        self.report
+            .lock()
+            .await
+            .finish_period(now, staking, &chain)?;
+        self.calibration.lock().await.finish_period(now, &chain)?;
+        self.topology.lock().await.finish_period(now, &chain)?;
+        self.geography.lock().await.finish_period(now, &chain)?;
+        self.finality.lock().await.finish_period(now, &chain)?;
+        self.store.lock().await.flush()?;
         Ok(())
     }
 
-    async fn run(&self, url: &str) -> Result<()> {
+    /// Everything counted so far, for the run manifest.
+    async fn counters(&self) -> RunCounters {
+        let stats = self.feed_stats.snapshot();
+        RunCounters {
+            nodes_seen: self.nodes.lock().await.records().len() as u64,
+            feed_messages: stats.messages_received,
+            feed_bytes: stats.bytes_received,
+            reconnects: stats.reconnects,
+            ..self.counters.lock().await.clone()
+        }
+    }
+
+    /// Follow the feed, with imports handed off to a worker for each shard of blocks,
+    /// until asked to stop. Anything handed to the workers is finished before returning.
+    async fn run(self: &Arc<Self>, url: &str) -> Result<()> {
         debug!("run() method called with URL: {}", url);
+        let workers = self.spawn_workers();
+        let result = self.follow(url, &workers).await;
+        workers.close().await;
+        result
+    }
+
+    /// Like [`TelemetryObserver::run`], but with the feed read back from recordings
+    /// rather than followed, until they run out or we're asked to stop.
+    async fn replay(self: &Arc<Self>, paths: Vec<PathBuf>) -> Result<()> {
+        let workers = self.spawn_workers();
+        let result = self.replay_recordings(paths, &workers).await;
+        workers.close().await;
+        result
+    }
+
+    fn spawn_workers(self: &Arc<Self>) -> WorkerPool {
+        let observer = Arc::clone(self);
+        WorkerPool::spawn(self.blocks.count(), move |shard, job| {
+            let observer = Arc::clone(&observer);
+            async move { observer.decide_blocks(shard, job).await }
+        })
+    }
+
+    async fn replay_recordings(&self, paths: Vec<PathBuf>, workers: &WorkerPool) -> Result<()> {
+        let mut stop = self.stop.subscribe();
+        let mut lines = replay::spawn_reader(paths);
+        loop {
+            let line = tokio::select! {
+                line = lines.recv() => line,
+                _ = stop.wait_for(Option::is_some) => return Ok(()),
+            };
+            let line = match line {
+                Some(line) => line?,
+                None => break,
+            };
+            // As when reconnecting, each recording starts by telling us about every
+            // node again, so indices from the one before don't count.
+            if line.first_in_file {
+                self.nodes.lock().await.forget_indices();
+            }
+            let messages = match FeedMessage::from_bytes(&line.bytes) {
+                Ok(messages) => messages,
+                Err(e) => {
+                    warn!(
+                        "Failed to decode messages recorded at {}: {:#}",
+                        line.timestamp, e
+                    );
+                    continue;
+                }
+            };
+            for msg in messages {
+                if let Err(e) = self.process_message(msg, workers).await {
+                    warn!("Failed to process message: {}", e);
+                }
+            }
+        }
+        if self.stop.borrow().is_none() {
+            self.stop
+                .send_replace(Some("every recording has been replayed"));
+        }
+        Ok(())
+    }
+
+    /// Follow the feed, reconnecting whenever we lose it, until asked to stop. We only
+    /// stop in between messages, so that each one is either fully processed or not at all.
+    async fn follow(&self, url: &str, workers: &WorkerPool) -> Result<()> {
+        let mut stop = self.stop.subscribe();
         loop {
             debug!("Starting telemetry monitoring loop iteration...");
             info!("Starting telemetry monitoring...");
             debug!(
-                "Connecting to {} with genesis hash {}",
+                "Connecting to {} with genesis hash {:?}",
                 url, self.genesis_hash
             );
 
             // Parse the URL to http::Uri
             let uri: http::Uri = url.parse().expect("Invalid WebSocket URL");
             info!("Attempting WebSocket connection to: {}", uri);
+            self.set_connection_state(ConnectionState::Connecting).await;
 
-            match ws_client::connect(&uri).await {
-                Ok(connection) => {
+            let feed = tokio::select! {
+                feed = FeedClient::connect_with_stats(&uri, Arc::clone(&self.feed_stats)) => feed,
+                _ = stop.wait_for(Option::is_some) => return Ok(()),
+            };
+            let feed = feed.and_then(|feed| {
+                feed.subscribe(self.genesis_hash)?;
+                // For the finality report; feeds that don't forward GRANDPA messages
+                // ignore this:
+                feed.send_finality(self.genesis_hash)?;
+                Ok(feed)
+            });
+            match feed {
+                Ok(mut feed) => {
                     info!("WebSocket connection established!");
-                    let (sender, mut receiver) = connection.into_channels();
-
-                    // Send subscription message
-                    let subscribe_msg = format!("subscribe:{}", self.genesis_hash);
-                    debug!("Sending subscription message: {}", subscribe_msg);
-                    if let Err(e) =
-                        sender.unbounded_send(SentMessage::Text(subscribe_msg.to_string()))
-                    {
-                        error!("Failed to send subscription: {}", e);
-                        continue;
-                    }
+                    self.set_connection_state(ConnectionState::Connected).await;
+                    // Indices from any earlier connection are stale; the feed is about to
+                    // tell us about every node again.
+                    self.nodes.lock().await.forget_indices();
                     debug!("Subscription message sent successfully");
 
+                    let mut stats_interval = tokio::time::interval_at(
+                        tokio::time::Instant::now() + FEED_STATS_INTERVAL,
+                        FEED_STATS_INTERVAL,
+                    );
+                    let mut last_stats = feed.stats();
+                    if let Err(e) = feed.ping_connection() {
+                        warn!("Failed to ping the feed: {}", e);
+                    }
+
                     // Read messages
                     debug!("Starting message receive loop...");
                     loop {
                         trace!("Waiting for next message...");
-                        match receiver.next().await {
-                            Some(Ok(RecvMessage::Text(text))) => {
-                                trace!("Received text message: {}", text);
-                                debug!(
-                                    "Received line: {}...",
-                                    &text.chars().take(100).collect::<String>()
-                                );
-                                if let Err(e) = self.process_message(&text).await {
-                                    warn!("Failed to process message: {}", e);
+                        let msg = tokio::select! {
+                            msg = feed.next() => msg,
+                            _ = stop.wait_for(Option::is_some) => return Ok(()),
+                            _ = stats_interval.tick() => {
+                                let stats = feed.stats();
+                                log_feed_stats(&last_stats, &stats);
+                                last_stats = stats;
+                                if let Err(e) = feed.ping_connection() {
+                                    warn!("Failed to ping the feed: {}", e);
                                 }
+                                continue;
                             }
-                            Some(Ok(RecvMessage::Binary(data))) => {
-                                trace!("Received binary message, converting to text...");
-                                match String::from_utf8(data) {
-                                    Ok(text) => {
-                                        trace!("Binary message converted to text: {}", text);
-                                        debug!(
-                                            "Received binary line: {}...",
-                                            &text.chars().take(100).collect::<String>()
-                                        );
-                                        if let Err(e) = self.process_message(&text).await {
-                                            error!("Failed to process binary message: {}", e);
-                                        }
-                                    }
-                                    Err(e) => {
-                                        error!("Failed to convert binary message to UTF-8: {}", e);
-                                    }
+                        };
+                        match msg {
+                            Some(Ok(msg)) => {
+                                if let Err(e) = self.process_message(msg, workers).await {
+                                    warn!("Failed to process message: {}", e);
                                 }
                             }
+                            Some(Err(FeedError::Decode(e))) => {
+                                warn!("Failed to decode messages: {:#}", e);
+                            }
                             Some(Err(e)) => {
                                 error!("WebSocket error: {}", e);
                                 break;
@@ -527,64 +1505,359 @@ impl TelemetryObserver {
                 Err(e) => {
                     error!("Failed to connect: {}", e);
                     debug!("Sleeping for 5 seconds before retry...");
-                    tokio::time::sleep(std::time::Duration::from_secs(5)).await;
+                    if stopped_during(&mut stop, Duration::from_secs(5)).await {
+                        return Ok(());
+                    }
                 }
             }
 
+            self.set_connection_state(ConnectionState::Disconnected)
+                .await;
             info!("Connection lost or error occurred. Reconnecting in 5 seconds...");
-            sleep(Duration::from_secs(5)).await;
+            if stopped_during(&mut stop, Duration::from_secs(5)).await {
+                return Ok(());
+            }
         }
     }
 }
 
+/// Wait for a while, returning true early if asked to stop.
+async fn stopped_during(
+    stop: &mut watch::Receiver<Option<&'static str>>,
+    duration: Duration,
+) -> bool {
+    tokio::select! {
+        _ = stop.wait_for(Option::is_some) => true,
+        _ = sleep(duration) => false,
+    }
+}
+
+/// Log how much has come over the feed connection since the last time we looked.
+fn log_feed_stats(last: &StatsSnapshot, stats: &StatsSnapshot) {
+    if stats.messages_received == 0 {
+        return;
+    }
+    let secs = FEED_STATS_INTERVAL.as_secs_f64();
+    let ping_rtt = stats
+        .ping_rtt
+        .map(|rtt| format!("{:.1}ms", rtt.as_secs_f64() * 1000.0))
+        .unwrap_or_else(|| "unknown".to_string());
+    info!(
+        "Feed: {:.1} KiB/s, {:.1} msgs/s ({} text, {} binary in total), ping {}, {} reconnects",
+        (stats.bytes_received - last.bytes_received) as f64 / 1024.0 / secs,
+        (stats.messages_received - last.messages_received) as f64 / secs,
+        stats.text_received,
+        stats.binary_received,
+        ping_rtt,
+        stats.reconnects
+    );
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let args: Vec<String> = env::args().collect();
 
+    if args.len() > 1 && args[1] == "verify" {
+        return verify_manifests(&args[2..]);
+    }
+    if args.len() > 1 && args[1] == "healthcheck" {
+        return healthcheck(&args[2..]);
+    }
+    if args.len() > 1 && args[1] == "aggregate" {
+        return aggregate(&args[2..]);
+    }
+    if args.len() > 1 && args[1] == "graph" {
+        return export_graph(&args[2..]);
+    }
+    if args.len() > 1 && args[1] == "calibrate" {
+        return calibrate(&args[2..]);
+    }
+    if args.len() > 1 && args[1] == "chains" {
+        return list_chains(&args[2..]);
+    }
+
     // Check for help flag
     if args.len() > 1 && (args[1] == "--help" || args[1] == "-h") {
         println!("Telemetry Observer - Monitor block production and propagation times");
         println!();
         println!("USAGE:");
         println!("    {} [OPTIONS]", args[0]);
+        println!("    {} verify <CSV FILE>...", args[0]);
+        println!("    {} healthcheck [OPTIONS]", args[0]);
+        println!("    {} aggregate [--out-dir <DIR>] <CSV FILE>...", args[0]);
+        println!(
+            "    {} graph [--format <FORMAT>] [--out <PATH>] [--topology <CSV FILE>]... [<CSV FILE>...]",
+            args[0]
+        );
+        println!(
+            "    {} calibrate --authors <CSV FILE>... <CSV FILE>...",
+            args[0]
+        );
+        println!("    {} chains [--chains-file <PATH>]", args[0]);
+        println!();
+        println!("COMMANDS:");
+        println!("    verify                  Check CSV output files against their manifests");
+        println!("    healthcheck             Check the heartbeat file of a running observer");
+        println!("                            [--heartbeat-file <PATH>] [--max-age <SECS>]");
+        println!("    aggregate               Roll output CSV files up into a file per day of blocks per author");
+        println!("                            (default output directory: ./data/daily)");
+        println!("    graph                   Write the blocks attributed to each node, and inferred topology, as a graph");
+        println!("                            in dot, gexf or graphml format (default: from --out, or dot)");
+        println!("    calibrate               Check the blocks attributed in output CSV files against block authors files");
+        println!("    chains                  List the chains that can be picked with --chain");
         println!();
         println!("OPTIONS:");
         println!("    -h, --help              Print help information");
+        println!("    --chain <NAME>          Use the defaults for a known chain (see the chains command); other options override them");
+        println!("                            Chains not in the registry are looked up by name on the feed");
+        println!("    --chains-file <PATH>    File of chain defaults to merge over the bundled ones (default: ./chains.toml)");
         println!(
             "    --genesis-hash <HASH>   Genesis hash to monitor (default: {})",
             "0x91b171bb158e2d3848fa23a9f1c25182fb8e20313b2c1eb49219da7a70ce90c3"
         );
         println!("    --telemetry-url <URL>   Telemetry WebSocket URL (default: wss://telemetry.polkadot.io/feed/0)");
+        println!("    --alerts-file <PATH>    File that alerts are appended to (default: ./data/alerts.jsonl)");
+        println!("    --rpc-url <URL>         Node RPC WebSocket URL used to fetch staking information (optional)");
+        println!("    --report-file <PATH>    File that expected-vs-observed author reports are appended to (default: ./data/author-report.csv)");
+        println!("    --report-hours <HOURS>  How often to write an author report (default: 24)");
+        println!("    --block-authors-file <PATH>  File that the true author of each block is appended to, with --rpc-url (default: ./data/block-authors.csv)");
+        println!("    --calibration-file <PATH>  File that attribution precision and recall are appended to (default: ./data/calibration.csv)");
+        println!(
+            "    --calibration-hours <HOURS>  How often to write a calibration report (default: 24)"
+        );
+        println!("    --geography-file <PATH>  File that propagation by region is appended to (default: ./data/geography.csv)");
+        println!(
+            "    --geography-hours <HOURS>  How often to write a geography report (default: 24)"
+        );
+        println!("    --finality-file <PATH>  File that GRANDPA participation by validator is appended to (default: ./data/finality.csv)");
+        println!(
+            "    --finality-hours <HOURS>  How often to write a finality report (default: 24)"
+        );
+        println!("    --topology-file <PATH>  File that the inferred gossip topology is appended to (default: ./data/topology.csv)");
+        println!("    --topology-hours <HOURS>  How often to write out the inferred topology (default: 1)");
+        println!("    --topology-window-ms <MS>  Longest gap between imports that counts as one hop of gossip (default: 250)");
+        println!("    --upgrades-file <PATH>  File that runtime upgrades seen via RPC are appended to (default: ./data/runtime-upgrades.csv)");
+        println!("    --anonymize-salt <SALT> Replace node names and IDs in all outputs with hashes salted with this (optional)");
+        println!("    --anonymize-map <PATH>  File that pseudonyms are mapped back to real names and IDs in (default: ./data/anonymized-nodes.csv)");
+        println!("    --state-backend <NAME>  Where to keep state between runs: json or sled (default: json)");
+        println!("    --state-db <PATH>       Database directory used by the sled state backend (default: ./data/telemetry-state.sled)");
+        println!("    --fork-depth <BLOCKS>   Alert when competing chains are longer than this (default: 2)");
+        println!("    --watch-node <NAME|ID>  Alert when this node's blocks arrive slowly; may be given more than once");
+        println!("    --slow-prop-ms <MS>     Propagation time over which a watched node's block is slow (default: 1000)");
+        println!("    --slow-prop-blocks <M/N> Alert when M of a watched node's last N blocks are slow (default: 3/10)");
+        println!("    --slow-prop-cooldown-mins <MINS> Wait this long before alerting about the same node again (default: 30)");
+        println!("    --author-weights <WEIGHTS> How much each signal counts towards attributing a block, as name=weight pairs");
+        println!("                            (default: self_report=1,ordering=1,win_rate=0.5,validator=0.5; ordering=1 for the");
+        println!("                            lowest propagation time alone)");
+        println!("    --duration <MINS>       Stop after running for this long (optional)");
+        println!("    --max-blocks <BLOCKS>   Stop once this many blocks have been written out (optional)");
+        println!("    --start-block <NUMBER>  Only track and write out blocks from this one onwards (optional)");
+        println!("    --end-block <NUMBER>    Only track and write out blocks up to this one, and stop once it's passed (optional)");
+        println!("    --tui                   Show a live view of authors, blocks and nodes instead of logging to the terminal");
+        println!("    --sink <KIND>:<PATH>    Also write out rows to a csv, jsonl or prom (Prometheus textfile) sink; may be given more than once");
+        println!("    --replay <FILE|GLOB>    Replay feed recordings (which may be gzip or zstd compressed) instead of following the feed; may be given more than once");
+        println!("    --workers <N>           How many workers to share out block imports between, by block hash (default: 1)");
+        println!("    --memory-budget <SIZE>  Keep as many blocks in memory as fit in this many bytes (eg 64MiB), rather than a fixed number, spilling undecided ones to disk (optional)");
+        println!("    --spill-dir <DIR>       Where blocks over the memory budget are spilled to; cleared at startup (default: ./data/spill)");
+        println!("    --lag-file <PATH>       File that nodes lagging behind the chain or going stale, and recovering, are recorded in (default: ./data/node-lag.csv)");
+        println!("    --max-lag-blocks <BLOCKS> How far behind the chain's best or finalized block a node can be before it's lagging (default: 10)");
+        println!("    --lag-secs <SECS>       How long a node must be too far behind to count as lagging (default: 60)");
+        println!("    --heartbeat-file <PATH> File that a summary of the observer's status is kept in (default: ./data/heartbeat.json)");
+        println!("    --stall-hours <HOURS>   Alert when a validator is not attributed a block for this long (default: 4)");
+        println!("    --slot-duration-ms <MS> How often the chain expects to produce a block; needed for slowdown alerts (default: from --chain)");
+        println!("    --slowdown-fraction <FRACTION> Alert when the chain produces blocks at under this fraction of the expected rate (default: 0.5)");
+        println!("    --slowdown-mins <MINS>  ...for at least this long (default: 10)");
         return Ok(());
     }
 
-    env_logger::init();
+    // Logs would scribble all over the live view, so send them to a file instead.
+    if args.iter().any(|arg| arg == "--tui") {
+        let log_file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(TUI_LOG_FILE)?;
+        env_logger::Builder::from_default_env()
+            .target(env_logger::Target::Pipe(Box::new(log_file)))
+            .init();
+    } else {
+        env_logger::init();
+    }
     debug!("Logger initialized");
 
     let mut config = Config::default();
 
+    // Apply a chain's defaults first, so that any other options override them.
+    let chains_file = flag_value(&args, "--chains-file").unwrap_or(DEFAULT_CHAINS_FILE);
+    let registry = ChainRegistry::load(std::path::Path::new(chains_file))?;
+    // Chains that we don't know the genesis hash of are looked up on the feed, once we
+    // know which feed that is.
+    let mut discover_chain = None;
+    if let Some(name) = flag_value(&args, "--chain") {
+        let chain = registry.get(name);
+        if let Some(chain) = chain {
+            apply_chain_defaults(&mut config, chain);
+        }
+        if chain
+            .and_then(|chain| chain.genesis_hash.as_ref())
+            .is_none()
+        {
+            discover_chain = Some(name.to_string());
+        }
+    }
+
     // Parse command line arguments
     let mut i = 1;
     while i < args.len() {
         match args[i].as_str() {
-            "--genesis-hash" => {
-                if i + 1 < args.len() {
-                    config.genesis_hash = args[i + 1].clone();
-                    i += 2;
-                } else {
-                    eprintln!("Error: --genesis-hash requires a value");
-                    std::process::exit(1);
+            "--genesis-hash" => config.genesis_hash = take_value(&args, &mut i).to_string(),
+            "--telemetry-url" => config.telemetry_url = take_value(&args, &mut i).to_string(),
+            // Already dealt with above.
+            "--chain" | "--chains-file" => {
+                take_value(&args, &mut i);
+            }
+            "--alerts-file" => config.alerts_file = PathBuf::from(take_value(&args, &mut i)),
+            "--rpc-url" => config.rpc_url = Some(take_value(&args, &mut i).to_string()),
+            "--report-file" => config.report_file = PathBuf::from(take_value(&args, &mut i)),
+            "--report-hours" => config.report_hours = parse_value(&args, &mut i, "a number"),
+            "--block-authors-file" => {
+                config.authors_file = PathBuf::from(take_value(&args, &mut i))
+            }
+            "--calibration-file" => {
+                config.calibration_file = PathBuf::from(take_value(&args, &mut i))
+            }
+            "--calibration-hours" => {
+                config.calibration_hours = parse_value(&args, &mut i, "a number")
+            }
+            "--geography-file" => config.geography_file = PathBuf::from(take_value(&args, &mut i)),
+            "--geography-hours" => config.geography_hours = parse_value(&args, &mut i, "a number"),
+            "--finality-file" => config.finality_file = PathBuf::from(take_value(&args, &mut i)),
+            "--finality-hours" => config.finality_hours = parse_value(&args, &mut i, "a number"),
+            "--topology-file" => config.topology_file = PathBuf::from(take_value(&args, &mut i)),
+            "--topology-hours" => config.topology_hours = parse_value(&args, &mut i, "a number"),
+            "--topology-window-ms" => {
+                config.topology_window_ms = parse_value(&args, &mut i, "a whole number")
+            }
+            "--upgrades-file" => config.upgrades_file = PathBuf::from(take_value(&args, &mut i)),
+            "--anonymize-salt" => {
+                config.anonymize_salt = Some(take_value(&args, &mut i).to_string())
+            }
+            "--anonymize-map" => config.anonymize_map = PathBuf::from(take_value(&args, &mut i)),
+            "--state-backend" => {
+                config.state_backend = match take_value(&args, &mut i).parse() {
+                    Ok(backend) => backend,
+                    Err(e) => {
+                        eprintln!("Error: {}", e);
+                        std::process::exit(1);
+                    }
                 }
             }
-            "--telemetry-url" => {
-                if i + 1 < args.len() {
-                    config.telemetry_url = args[i + 1].clone();
-                    i += 2;
-                } else {
-                    eprintln!("Error: --telemetry-url requires a value");
+            "--state-db" => config.state_db = PathBuf::from(take_value(&args, &mut i)),
+            "--slot-duration-ms" => {
+                config.slot_duration_ms = Some(parse_value_where(
+                    &args,
+                    &mut i,
+                    "a positive whole number",
+                    |ms| *ms > 0,
+                ))
+            }
+            "--slowdown-fraction" => {
+                config.slowdown_rule.fraction =
+                    parse_value_where(&args, &mut i, "a number over 0 and up to 1", |fraction| {
+                        *fraction > 0.0 && *fraction <= 1.0
+                    })
+            }
+            "--slowdown-mins" => {
+                let mins: f64 = parse_value(&args, &mut i, "a number");
+                config.slowdown_rule.sustained_secs = (mins * 60.0) as u64;
+            }
+            "--stall-hours" => config.stall_hours = parse_value(&args, &mut i, "a number"),
+            "--fork-depth" => config.fork_depth = parse_value(&args, &mut i, "a whole number"),
+            "--watch-node" => config
+                .watch_nodes
+                .push(take_value(&args, &mut i).to_string()),
+            "--slow-prop-ms" => {
+                config.propagation_rule.threshold_ms = parse_value(&args, &mut i, "a whole number")
+            }
+            "--slow-prop-blocks" => {
+                if let Err(e) = config
+                    .propagation_rule
+                    .set_window(take_value(&args, &mut i))
+                {
+                    eprintln!("Error: --slow-prop-blocks: {}", e);
                     std::process::exit(1);
                 }
             }
+            "--slow-prop-cooldown-mins" => {
+                let mins: f64 = parse_value(&args, &mut i, "a number");
+                config.propagation_rule.cooldown_secs = (mins * 60.0) as u64;
+            }
+            "--author-weights" => {
+                config.author_weights = match take_value(&args, &mut i).parse() {
+                    Ok(weights) => weights,
+                    Err(e) => {
+                        eprintln!("Error: --author-weights: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--duration" => {
+                let mins: f64 =
+                    parse_value_where(&args, &mut i, "a positive number of minutes", |mins| {
+                        *mins > 0.0
+                    });
+                config.duration = Some(Duration::from_secs_f64(mins * 60.0));
+            }
+            "--max-blocks" => {
+                config.max_blocks = Some(parse_value_where(
+                    &args,
+                    &mut i,
+                    "a positive whole number",
+                    |blocks| *blocks > 0,
+                ))
+            }
+            "--start-block" => {
+                config.start_block = Some(parse_value(&args, &mut i, "a block number"))
+            }
+            "--end-block" => config.end_block = Some(parse_value(&args, &mut i, "a block number")),
+            "--tui" => {
+                config.tui = true;
+                i += 1;
+            }
+            "--sink" => {
+                let value = take_value(&args, &mut i);
+                match value.parse() {
+                    Ok(spec) => config.sinks.push(spec),
+                    Err(e) => {
+                        eprintln!("Error: --sink {}: {}", value, e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--replay" => config.replay.push(take_value(&args, &mut i).to_string()),
+            "--workers" => {
+                config.workers =
+                    parse_value_where(&args, &mut i, "a positive whole number", |workers| {
+                        *workers > 0
+                    })
+            }
+            "--memory-budget" => {
+                let size = parse_value_where(
+                    &args,
+                    &mut i,
+                    "a size in bytes, such as 64MiB",
+                    |size: &ByteSize| size.num_bytes() > 0,
+                );
+                config.memory_budget = Some(size.num_bytes());
+            }
+            "--spill-dir" => config.spill_dir = PathBuf::from(take_value(&args, &mut i)),
+            "--lag-file" => config.lag_file = PathBuf::from(take_value(&args, &mut i)),
+            "--max-lag-blocks" => {
+                config.lag_rule.max_lag_blocks = parse_value(&args, &mut i, "a whole number")
+            }
+            "--lag-secs" => {
+                config.lag_rule.lag_secs = parse_value(&args, &mut i, "a whole number of seconds")
+            }
+            "--heartbeat-file" => config.heartbeat_file = PathBuf::from(take_value(&args, &mut i)),
             _ => {
                 eprintln!("Error: Unknown option '{}'", args[i]);
                 eprintln!("Try '{} --help' for more information", args[0]);
@@ -592,13 +1865,479 @@ async fn main() -> Result<()> {
             }
         }
     }
+    if config.block_range().is_empty() {
+        eprintln!("Error: --end-block must not be before --start-block");
+        std::process::exit(1);
+    }
+
+    let replay = if config.replay.is_empty() {
+        None
+    } else {
+        match replay::expand(&config.replay) {
+            Ok(paths) => Some(paths),
+            Err(e) => {
+                eprintln!("Error: --replay: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+    };
+
+    // Look the chain up by name on the feed, unless we were given its genesis hash too:
+    if let Some(name) = discover_chain.filter(|_| flag_value(&args, "--genesis-hash").is_none()) {
+        match find_chain(&config.telemetry_url, &name).await {
+            Ok(genesis_hash) => config.genesis_hash = genesis_hash,
+            Err(e) => {
+                eprintln!("Error: {:#}", e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    // Held until we exit, so that a second observer can't write to the same files:
+    let _data_lock = match DataDirLock::acquire(&config.data_dirs()) {
+        Ok(lock) => lock,
+        Err(e) => {
+            eprintln!("Error: {:#}", e);
+            std::process::exit(1);
+        }
+    };
 
     let url = config.telemetry_url.clone();
+    let rpc_url = config.rpc_url.clone();
+    let upgrades_file = config.upgrades_file.clone();
+    let heartbeat_file = config.heartbeat_file.clone();
+    let duration = config.duration;
+    let run_manifest_path = run_manifest::run_manifest_path(&config.output_path);
+    let mut run_manifest = RunManifest::start(
+        &run_manifest_path,
+        config.effective(),
+        &config.fingerprint(),
+        &config.genesis_hash,
+        SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+    )?;
+    info!("Recording this run in {:?}", run_manifest_path);
     info!(
         "Creating TelemetryObserver with URL: {} and genesis hash: {}",
         url, config.genesis_hash
     );
-    let observer = TelemetryObserver::new(config).await?;
+    let observer = Arc::new(TelemetryObserver::new(config).await?);
+    if let Some(rpc_url) = rpc_url {
+        info!("Fetching staking information from {}", rpc_url);
+        staking::spawn_poller(&rpc_url, observer.staking.clone())?;
+        runtime::spawn_poller(
+            &rpc_url,
+            observer.spec_version.clone(),
+            observer.chain.clone(),
+            &upgrades_file,
+        )?;
+    }
+    heartbeat::spawn_writer(
+        heartbeat_file,
+        observer.heartbeat.clone(),
+        observer.feed_stats.clone(),
+    );
+    observer.spawn_block_rate_checker();
     info!("TelemetryObserver created, starting run loop...");
-    observer.run(&url).await
+
+    if let Some(duration) = duration {
+        let stop = observer.stop.clone();
+        tokio::spawn(async move {
+            sleep(duration).await;
+            stop.send_replace(Some("the run duration has elapsed"));
+        });
+    }
+    let live = observer.live.as_ref().map(|live| {
+        tui::spawn(tui::Sources {
+            live: live.clone(),
+            heartbeat: observer.heartbeat.clone(),
+            feed_stats: observer.feed_stats.clone(),
+            chain: observer.chain.clone(),
+            stop: observer.stop.clone(),
+        })
+    });
+
+    let result = match replay {
+        Some(paths) => {
+            info!("Replaying {} recordings", paths.len());
+            observer.replay(paths).await
+        }
+        None => observer.run(&url).await,
+    };
+    if let Some(live) = live {
+        live.close();
+    }
+    let (result, stop_reason) = match result {
+        Ok(()) => {
+            let reason = *observer.stop.borrow();
+            if let Some(reason) = reason {
+                info!("Stopping because {}", reason);
+            }
+            (observer.shutdown().await, reason.map(str::to_string))
+        }
+        Err(e) => {
+            let reason = format!("{:#}", e);
+            (Err(e), Some(reason))
+        }
+    };
+
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let chain = observer.chain.lock().await.clone();
+    if let Err(e) = run_manifest.finish(
+        &run_manifest_path,
+        now,
+        &chain,
+        stop_reason,
+        observer.counters().await,
+    ) {
+        warn!("Failed to finish the run manifest: {}", e);
+    }
+    result
+}
+
+/// Check each of the given CSV files against its manifest, exiting with an error if
+/// any of them don't match.
+fn verify_manifests(paths: &[String]) -> Result<()> {
+    if paths.is_empty() {
+        eprintln!("Error: verify requires at least one CSV file");
+        std::process::exit(1);
+    }
+
+    let mut failed = 0;
+    for path in paths {
+        match manifest::verify(std::path::Path::new(path)) {
+            Ok(problems) if problems.is_empty() => println!("OK      {}", path),
+            Ok(problems) => {
+                failed += 1;
+                for problem in problems {
+                    println!("FAILED  {}: {}", path, problem);
+                }
+            }
+            Err(e) => {
+                failed += 1;
+                println!("FAILED  {}: {}", path, e);
+            }
+        }
+    }
+
+    if failed > 0 {
+        eprintln!("{} of {} file(s) failed verification", failed, paths.len());
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+/// The value given for an option, if it was given.
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    let i = args.iter().position(|arg| arg == flag)?;
+    args.get(i + 1).map(|value| value.as_str())
+}
+
+/// The value given to the flag at `args[*i]`, moving `i` on past both. Exits if the flag
+/// is the last argument.
+fn take_value<'a>(args: &'a [String], i: &mut usize) -> &'a str {
+    match args.get(*i + 1) {
+        Some(value) => {
+            *i += 2;
+            value
+        }
+        None => {
+            eprintln!("Error: {} requires a value", args[*i]);
+            std::process::exit(1);
+        }
+    }
+}
+
+/// Like [`take_value`], but parsed; exits saying what the value `must_be` if it can't be.
+fn parse_value<T: FromStr>(args: &[String], i: &mut usize, must_be: &str) -> T {
+    parse_value_where(args, i, must_be, |_| true)
+}
+
+/// Like [`parse_value`], but the value must also be `valid`.
+fn parse_value_where<T: FromStr>(
+    args: &[String],
+    i: &mut usize,
+    must_be: &str,
+    valid: impl Fn(&T) -> bool,
+) -> T {
+    let flag = &args[*i];
+    match take_value(args, i).parse() {
+        Ok(value) if valid(&value) => value,
+        _ => {
+            eprintln!("Error: {} must be {}", flag, must_be);
+            std::process::exit(1);
+        }
+    }
+}
+
+fn apply_chain_defaults(config: &mut Config, chain: &ChainDefaults) {
+    if let Some(genesis_hash) = &chain.genesis_hash {
+        config.genesis_hash = genesis_hash.clone();
+    }
+    if let Some(telemetry_url) = &chain.telemetry_url {
+        config.telemetry_url = telemetry_url.clone();
+    }
+    if let Some(stall_hours) = chain.stall_hours {
+        config.stall_hours = stall_hours;
+    }
+    if let Some(fork_depth) = chain.fork_depth {
+        config.fork_depth = fork_depth;
+    }
+    if let Some(slot_duration_ms) = chain.slot_duration_ms {
+        config.slot_duration_ms = Some(slot_duration_ms);
+    }
+    if let Some(slow_prop_ms) = chain.slow_prop_ms {
+        config.propagation_rule.threshold_ms = slow_prop_ms;
+    }
+}
+
+/// Find the genesis hash of the chain on the feed whose name best matches `name`.
+async fn find_chain(telemetry_url: &str, name: &str) -> Result<String> {
+    let mut chains = discover::feed_chains(telemetry_url).await?;
+    chains.sort_by_key(|chain| std::cmp::Reverse(chain.node_count));
+    let Some((chosen, others)) = discover::best_match(&chains, name) else {
+        let known: Vec<_> = chains.iter().map(|chain| chain.name.as_str()).collect();
+        let known = if known.is_empty() {
+            format!("there are no chains on {}", telemetry_url)
+        } else {
+            format!("the chains on {} are: {}", telemetry_url, known.join(", "))
+        };
+        return Err(anyhow::anyhow!(
+            "Unknown chain '{}'; it's not in the registry, and {}",
+            name,
+            known
+        ));
+    };
+    info!("Chain '{}' is {}", name, discover::describe(chosen));
+    if !others.is_empty() {
+        let others: Vec<_> = others.into_iter().map(discover::describe).collect();
+        eprintln!(
+            "Warning: '{}' matches more than one chain; observing {}, not {}. \
+             Pass --genesis-hash to pick another",
+            name,
+            discover::describe(chosen),
+            others.join(", ")
+        );
+    }
+    Ok(format!("{:?}", chosen.genesis_hash))
+}
+
+/// List the chains in the registry, and what they default to.
+fn list_chains(args: &[String]) -> Result<()> {
+    let chains_file = match args {
+        [] => DEFAULT_CHAINS_FILE,
+        [flag, path] if flag == "--chains-file" => path,
+        _ => {
+            eprintln!("Error: Usage: chains [--chains-file <PATH>]");
+            std::process::exit(1);
+        }
+    };
+    let registry = ChainRegistry::load(std::path::Path::new(chains_file))?;
+    let or_dash = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    for (name, chain) in registry.iter() {
+        println!("{}", name);
+        println!("    genesis hash:  {}", or_dash(chain.genesis_hash.clone()));
+        println!(
+            "    telemetry URL: {}",
+            or_dash(chain.telemetry_url.clone())
+        );
+        println!(
+            "    slot duration: {}",
+            or_dash(chain.slot_duration_ms.map(|ms| format!("{}ms", ms)))
+        );
+        println!(
+            "    thresholds:    stall {}h, fork depth {}, slow propagation {}ms",
+            or_dash(chain.stall_hours.map(|h| h.to_string())),
+            or_dash(chain.fork_depth.map(|d| d.to_string())),
+            or_dash(chain.slow_prop_ms.map(|ms| ms.to_string()))
+        );
+    }
+    Ok(())
+}
+
+/// Roll the rows of output CSV files up into per-day, per-author files.
+fn aggregate(args: &[String]) -> Result<()> {
+    let mut out_dir = PathBuf::from(DEFAULT_AGGREGATE_DIR);
+    let mut paths = vec![];
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--out-dir" => out_dir = PathBuf::from(take_value(args, &mut i)),
+            path => {
+                paths.push(PathBuf::from(path));
+                i += 1;
+            }
+        }
+    }
+    if paths.is_empty() {
+        eprintln!("Error: aggregate requires at least one CSV file");
+        std::process::exit(1);
+    }
+
+    let mut rollup = DailyRollup::default();
+    for path in &paths {
+        let summary = rollup.add_file(path)?;
+        println!("Read {} rows from {}", summary.rows, path.display());
+        if summary.skipped > 0 {
+            eprintln!(
+                "Skipped {} unreadable row(s) of {}",
+                summary.skipped,
+                path.display()
+            );
+        }
+    }
+    for path in rollup.write(&out_dir)? {
+        println!("Wrote {}", path.display());
+    }
+    Ok(())
+}
+
+/// Write the blocks attributed to each node in output CSV files, along with the links
+/// between nodes in topology files, out as a graph.
+fn export_graph(args: &[String]) -> Result<()> {
+    let mut format = None;
+    let mut out = None;
+    let mut output_files = vec![];
+    let mut topology_files = vec![];
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--format" => {
+                format = match take_value(args, &mut i).parse::<GraphFormat>() {
+                    Ok(format) => Some(format),
+                    Err(e) => {
+                        eprintln!("Error: --format: {}", e);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--out" => out = Some(PathBuf::from(take_value(args, &mut i))),
+            "--topology" => topology_files.push(PathBuf::from(take_value(args, &mut i))),
+            path => {
+                output_files.push(PathBuf::from(path));
+                i += 1;
+            }
+        }
+    }
+    if output_files.is_empty() && topology_files.is_empty() {
+        eprintln!("Error: graph requires at least one CSV or topology file");
+        std::process::exit(1);
+    }
+    let format = format
+        .or_else(|| out.as_deref().and_then(GraphFormat::from_path))
+        .unwrap_or(GraphFormat::Dot);
+    let out = out
+        .unwrap_or_else(|| PathBuf::from(format!("{}.{}", DEFAULT_GRAPH_FILE, format.extension())));
+
+    let mut graph = PropagationGraph::default();
+    let files = output_files
+        .iter()
+        .map(|path| (path, false))
+        .chain(topology_files.iter().map(|path| (path, true)));
+    for (path, topology) in files {
+        let summary = if topology {
+            graph.add_topology_file(path)?
+        } else {
+            graph.add_output_file(path)?
+        };
+        println!("Read {} rows from {}", summary.rows, path.display());
+        if summary.skipped > 0 {
+            eprintln!(
+                "Skipped {} unreadable row(s) of {}",
+                summary.skipped,
+                path.display()
+            );
+        }
+    }
+    if graph.is_empty() {
+        eprintln!("Error: there was nothing to put in the graph");
+        std::process::exit(1);
+    }
+
+    if let Some(dir) = out.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(&out, graph.render(format))?;
+    let (vertices, edges) = graph.size();
+    println!(
+        "Wrote {} vertices and {} edges to {}",
+        vertices,
+        edges,
+        out.display()
+    );
+    Ok(())
+}
+
+/// Check the blocks attributed in output CSV files against the true authors recorded in
+/// block authors files, and print how precise the attribution was.
+fn calibrate(args: &[String]) -> Result<()> {
+    let mut output_files = vec![];
+    let mut author_files = vec![];
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--authors" => author_files.push(PathBuf::from(take_value(args, &mut i))),
+            path => {
+                output_files.push(PathBuf::from(path));
+                i += 1;
+            }
+        }
+    }
+    if output_files.is_empty() || author_files.is_empty() {
+        eprintln!("Error: calibrate requires at least one block authors file and one CSV file");
+        std::process::exit(1);
+    }
+
+    let tally = calibration::calibrate(&output_files, &author_files)?;
+    if tally.is_empty() {
+        eprintln!("Error: none of the blocks in the CSV files are in the block authors files");
+        std::process::exit(1);
+    }
+    println!("{}", tally.summary());
+    println!();
+    print!("{}", tally.table());
+    Ok(())
+}
+
+/// Check the heartbeat file written by a running observer, exiting with an error if it
+/// looks like the observer is stuck or not running.
+fn healthcheck(args: &[String]) -> Result<()> {
+    let mut heartbeat_file = PathBuf::from(DEFAULT_HEARTBEAT_FILE);
+    let mut max_age_secs = DEFAULT_HEALTHCHECK_MAX_AGE_SECS;
+
+    let mut i = 0;
+    while i < args.len() {
+        match args[i].as_str() {
+            "--heartbeat-file" => heartbeat_file = PathBuf::from(take_value(args, &mut i)),
+            "--max-age" => max_age_secs = parse_value(args, &mut i, "a whole number of seconds"),
+            _ => {
+                eprintln!("Error: Unknown healthcheck option '{}'", args[i]);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let heartbeat = match Heartbeat::read(&heartbeat_file) {
+        Ok(heartbeat) => heartbeat,
+        Err(e) => {
+            println!("UNHEALTHY  can't read {}: {}", heartbeat_file.display(), e);
+            std::process::exit(1);
+        }
+    };
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    let problems = heartbeat.problems(now, max_age_secs);
+    if problems.is_empty() {
+        println!(
+            "OK  last block #{} {}s ago",
+            heartbeat.last_block_number.unwrap_or_default(),
+            now.saturating_sub(heartbeat.last_block_at.unwrap_or(now))
+        );
+        return Ok(());
+    }
+    for problem in problems {
+        println!("UNHEALTHY  {}", problem);
+    }
+    std::process::exit(1);
 }
@@ -0,0 +1,301 @@
+use crate::csv_file;
+use crate::journal;
+use anyhow::{anyhow, Result};
+use csv::Writer;
+use log::info;
+use ring::digest;
+use serde::{Deserialize, Serialize};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A sidecar file describing a CSV file, so that anyone given a copy of it can tell
+/// whether it's been truncated or tampered with.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Manifest {
+    pub file: String,
+    /// The number of rows, not counting the header.
+    pub rows: u64,
+    pub bytes: u64,
+    pub first_block: Option<u64>,
+    pub last_block: Option<u64>,
+    /// A hex encoded SHA-256 of the whole file.
+    pub sha256: String,
+    pub observer_version: String,
+    /// A hash of the configuration that affects what is written to the file. A new file
+    /// is started whenever this changes, so one file never mixes rows from different setups.
+    pub config_hash: String,
+    pub updated_at: u64,
+}
+
+/// Eg `./data/out.csv` has the manifest `./data/out.csv.manifest.json`.
+pub fn manifest_path(path: &Path) -> PathBuf {
+    let mut file_name = path.file_name().unwrap_or_default().to_os_string();
+    file_name.push(".manifest.json");
+    path.with_file_name(file_name)
+}
+
+/// A hex encoded SHA-256 of some configuration.
+pub fn config_hash(config: &serde_json::Value) -> String {
+    config_hash_str(&config.to_string())
+}
+
+pub fn config_hash_str(config: &str) -> String {
+    hex::encode(digest::digest(&digest::SHA256, config.as_bytes()).as_ref())
+}
+
+/// A file that keeps a running hash of everything written to it.
+struct HashingFile {
+    file: File,
+    digest: digest::Context,
+    bytes: u64,
+}
+
+impl Write for HashingFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.digest.update(&buf[..n]);
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// A CSV file that's kept alongside an up to date manifest.
+pub struct ManifestedCsv {
+    path: PathBuf,
+    writer: Writer<HashingFile>,
+    block_column: Option<usize>,
+    rows: u64,
+    first_block: Option<u64>,
+    last_block: Option<u64>,
+    config_hash: String,
+}
+
+impl std::fmt::Debug for ManifestedCsv {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ManifestedCsv")
+            .field("path", &self.path)
+            .field("rows", &self.rows)
+            .finish()
+    }
+}
+
+impl ManifestedCsv {
+    /// Open a CSV file to append rows to, as [`csv_file::open_with_header`] does. If
+    /// the file was written with different configuration, it's moved aside and a new
+    /// one started.
+    pub fn open(path: &Path, header: &[&str], config_hash: &str) -> Result<Self> {
+        if let Ok(manifest) = read_manifest(path) {
+            if manifest.config_hash != config_hash && path.exists() {
+                info!("Configuration has changed since {:?} was written", path);
+                csv_file::rotate(path)?;
+            }
+        }
+        let write_header = csv_file::prepare(path, header)?;
+
+        // Pick up where we left off, rather than trust that the last manifest is up to date:
+        let block_column = header.iter().position(|h| *h == "block_number");
+        let mut digest = digest::Context::new(&digest::SHA256);
+        let scanned = if path.exists() {
+            scan(path, &mut digest)?
+        } else {
+            Scan::default()
+        };
+
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut writer = Writer::from_writer(HashingFile {
+            file,
+            digest,
+            bytes: scanned.bytes,
+        });
+        if write_header {
+            writer.write_record(header)?;
+        }
+
+        let mut csv = Self {
+            path: path.to_path_buf(),
+            writer,
+            block_column,
+            rows: scanned.rows,
+            first_block: scanned.first_block,
+            last_block: scanned.last_block,
+            config_hash: config_hash.to_string(),
+        };
+        csv.flush()?;
+        Ok(csv)
+    }
+
+    pub fn write_row(&mut self, record: &[String]) -> Result<()> {
+        self.writer.write_record(record)?;
+        self.rows += 1;
+        if let Some(block) = self
+            .block_column
+            .and_then(|c| record.get(c))
+            .and_then(|b| b.parse().ok())
+        {
+            self.first_block.get_or_insert(block);
+            self.last_block = Some(block);
+        }
+        Ok(())
+    }
+
+    /// Flush any rows written so far, and bring the manifest up to date with them.
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        let file = self.writer.get_ref();
+        let manifest = Manifest {
+            file: file_name(&self.path),
+            rows: self.rows,
+            bytes: file.bytes,
+            first_block: self.first_block,
+            last_block: self.last_block,
+            sha256: hex::encode(file.digest.clone().finish().as_ref()),
+            observer_version: env!("CARGO_PKG_VERSION").to_string(),
+            config_hash: self.config_hash.clone(),
+            updated_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+        };
+        journal::write_snapshot(&manifest_path(&self.path), &manifest)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Scan {
+    rows: u64,
+    bytes: u64,
+    first_block: Option<u64>,
+    last_block: Option<u64>,
+}
+
+/// Read through a CSV file, feeding its contents into `digest`.
+fn scan(path: &Path, digest: &mut digest::Context) -> Result<Scan> {
+    let mut scanned = Scan::default();
+
+    let mut file = File::open(path)?;
+    let mut buf = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        digest.update(&buf[..n]);
+        scanned.bytes += n as u64;
+    }
+
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_path(path)?;
+    let block_column = reader.headers()?.iter().position(|h| h == "block_number");
+    for record in reader.records() {
+        let record = record?;
+        scanned.rows += 1;
+        if let Some(block) = block_column
+            .and_then(|c| record.get(c))
+            .and_then(|b| b.parse().ok())
+        {
+            scanned.first_block.get_or_insert(block);
+            scanned.last_block = Some(block);
+        }
+    }
+    Ok(scanned)
+}
+
+fn read_manifest(path: &Path) -> Result<Manifest> {
+    let manifest_path = manifest_path(path);
+    let file = File::open(&manifest_path)
+        .map_err(|e| anyhow!("Can't open manifest {:?}: {}", manifest_path, e))?;
+    Ok(serde_json::from_reader(file)?)
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// Check a CSV file against its manifest, returning a description of each way in
+/// which they differ.
+pub fn verify(path: &Path) -> Result<Vec<String>> {
+    let manifest = read_manifest(path)?;
+    let mut digest = digest::Context::new(&digest::SHA256);
+    let scanned = scan(path, &mut digest)?;
+    let sha256 = hex::encode(digest.finish().as_ref());
+
+    let mut problems = vec![];
+    if manifest.file != file_name(path) {
+        problems.push(format!(
+            "manifest is for '{}', not '{}'",
+            manifest.file,
+            file_name(path)
+        ));
+    }
+    if scanned.rows != manifest.rows {
+        problems.push(format!(
+            "has {} rows, but the manifest says {}",
+            scanned.rows, manifest.rows
+        ));
+    }
+    if scanned.bytes != manifest.bytes {
+        problems.push(format!(
+            "is {} bytes, but the manifest says {}",
+            scanned.bytes, manifest.bytes
+        ));
+    }
+    if (scanned.first_block, scanned.last_block) != (manifest.first_block, manifest.last_block) {
+        problems.push(format!(
+            "covers blocks {:?} to {:?}, but the manifest says {:?} to {:?}",
+            scanned.first_block, scanned.last_block, manifest.first_block, manifest.last_block
+        ));
+    }
+    if sha256 != manifest.sha256 {
+        problems.push(format!(
+            "has SHA-256 {}, but the manifest says {}",
+            sha256, manifest.sha256
+        ));
+    }
+    Ok(problems)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn detects_changes_to_manifested_files() {
+        let dir = std::env::temp_dir().join(format!("observer-manifest-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("out.csv");
+        let _ = std::fs::remove_file(&path);
+        let header = &["timestamp", "block_number"];
+
+        let mut csv = ManifestedCsv::open(&path, header, "a").unwrap();
+        csv.write_row(&["1".into(), "10".into()]).unwrap();
+        csv.write_row(&["2".into(), "11".into()]).unwrap();
+        csv.flush().unwrap();
+        drop(csv);
+        assert!(verify(&path).unwrap().is_empty());
+
+        // Reopening picks up where we left off:
+        let mut csv = ManifestedCsv::open(&path, header, "a").unwrap();
+        csv.write_row(&["3".into(), "12".into()]).unwrap();
+        csv.flush().unwrap();
+        drop(csv);
+        assert!(verify(&path).unwrap().is_empty());
+        let manifest = read_manifest(&path).unwrap();
+        assert_eq!(manifest.rows, 3);
+        assert_eq!(
+            (manifest.first_block, manifest.last_block),
+            (Some(10), Some(12))
+        );
+
+        // Truncation is noticed:
+        let contents = std::fs::read(&path).unwrap();
+        std::fs::write(&path, &contents[..contents.len() - 5]).unwrap();
+        assert!(!verify(&path).unwrap().is_empty());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
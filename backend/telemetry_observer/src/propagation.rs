@@ -0,0 +1,178 @@
+use crate::alerts::Alert;
+use anyhow::{anyhow, Result};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+/// When a watched node counts as slow: when at least `slow_blocks` of the last
+/// `window_blocks` blocks it imported took longer than `threshold_ms` to reach it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PropagationRule {
+    pub threshold_ms: u64,
+    pub slow_blocks: usize,
+    pub window_blocks: usize,
+    /// Don't alert about the same node again for this long (in seconds).
+    pub cooldown_secs: u64,
+}
+
+impl Default for PropagationRule {
+    fn default() -> Self {
+        Self {
+            threshold_ms: 1000,
+            slow_blocks: 3,
+            window_blocks: 10,
+            cooldown_secs: 1800,
+        }
+    }
+}
+
+impl PropagationRule {
+    /// Set `slow_blocks` and `window_blocks` from a string like `3/10`.
+    pub fn set_window(&mut self, s: &str) -> Result<()> {
+        let parse = |n: &str| n.trim().parse::<usize>().ok();
+        match s.split_once('/').map(|(m, n)| (parse(m), parse(n))) {
+            Some((Some(m), Some(n))) if m > 0 && m <= n => {
+                self.slow_blocks = m;
+                self.window_blocks = n;
+                Ok(())
+            }
+            _ => Err(anyhow!(
+                "expected M/N, where 0 < M <= N (eg 3/10), but got '{}'",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct NodeHistory {
+    /// Whether each of the most recent imports was slow, oldest first.
+    recent: VecDeque<bool>,
+    last_alerted: Option<u64>,
+}
+
+/// Spots watched nodes whose blocks keep arriving late. Operators care about their own
+/// nodes getting slow well before it shows up in who gets attributed blocks.
+#[derive(Debug)]
+pub struct SlowNodeDetector {
+    rule: PropagationRule,
+    /// Names or network IDs of the nodes to keep an eye on.
+    watched: HashSet<String>,
+    history: HashMap<String, NodeHistory>,
+}
+
+impl SlowNodeDetector {
+    pub fn new(rule: PropagationRule, watched: impl IntoIterator<Item = String>) -> Self {
+        Self {
+            rule,
+            watched: watched.into_iter().collect(),
+            history: HashMap::new(),
+        }
+    }
+
+    /// Note how long a block took to reach a node, returning an alert if this
+    /// makes a watched node slow.
+    pub fn saw_import(
+        &mut self,
+        node_name: &str,
+        node_id: &str,
+        block_number: u64,
+        propagation_time: u64,
+        now: u64,
+    ) -> Option<Alert> {
+        if !self.watched.contains(node_name) && !self.watched.contains(node_id) {
+            return None;
+        }
+
+        let rule = self.rule;
+        let history = self.history.entry(node_id.to_string()).or_default();
+        history
+            .recent
+            .push_back(propagation_time > rule.threshold_ms);
+        while history.recent.len() > rule.window_blocks {
+            history.recent.pop_front();
+        }
+
+        let slow = history.recent.iter().filter(|&&slow| slow).count();
+        let cooling_down = history
+            .last_alerted
+            .is_some_and(|t| now.saturating_sub(t) < rule.cooldown_secs);
+        if slow < rule.slow_blocks || cooling_down {
+            return None;
+        }
+
+        history.last_alerted = Some(now);
+        Some(Alert {
+            timestamp: now,
+            kind: "slow_propagation",
+            node_name: Some(node_name.to_string()),
+            node_id: Some(node_id.to_string()),
+            message: format!(
+                "Node {} took over {}ms to import {} of its last {} blocks (#{} took {}ms)",
+                node_name,
+                rule.threshold_ms,
+                slow,
+                history.recent.len(),
+                block_number,
+                propagation_time
+            ),
+            details: None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn detector() -> SlowNodeDetector {
+        let rule = PropagationRule {
+            threshold_ms: 500,
+            slow_blocks: 2,
+            window_blocks: 3,
+            cooldown_secs: 60,
+        };
+        SlowNodeDetector::new(rule, ["mine".to_string()])
+    }
+
+    #[test]
+    fn alerts_when_m_of_last_n_are_slow() {
+        let mut detector = detector();
+        assert!(detector.saw_import("mine", "a", 1, 600, 0).is_none());
+        assert!(detector.saw_import("mine", "a", 2, 100, 6).is_none());
+        let alert = detector.saw_import("mine", "a", 3, 700, 12).unwrap();
+        assert_eq!(alert.kind, "slow_propagation");
+        assert_eq!(alert.node_id.as_deref(), Some("a"));
+
+        // Other nodes aren't watched:
+        for n in 1..10 {
+            assert!(detector.saw_import("theirs", "b", n, 5000, n).is_none());
+        }
+    }
+
+    #[test]
+    fn slow_blocks_fall_out_of_the_window() {
+        let mut detector = detector();
+        assert!(detector.saw_import("mine", "a", 1, 600, 0).is_none());
+        assert!(detector.saw_import("mine", "a", 2, 100, 6).is_none());
+        assert!(detector.saw_import("mine", "a", 3, 100, 12).is_none());
+        assert!(detector.saw_import("mine", "a", 4, 600, 18).is_none());
+    }
+
+    #[test]
+    fn waits_for_cooldown_before_alerting_again() {
+        let mut detector = detector();
+        detector.saw_import("mine", "a", 1, 600, 0);
+        assert!(detector.saw_import("mine", "a", 2, 600, 6).is_some());
+        assert!(detector.saw_import("mine", "a", 3, 600, 12).is_none());
+        assert!(detector.saw_import("mine", "a", 4, 600, 66).is_some());
+    }
+
+    #[test]
+    fn parses_window() {
+        let mut rule = PropagationRule::default();
+        rule.set_window("4/12").unwrap();
+        assert_eq!((rule.slow_blocks, rule.window_blocks), (4, 12));
+        assert!(rule.set_window("5/4").is_err());
+        assert!(rule.set_window("0/4").is_err());
+        assert!(rule.set_window("4").is_err());
+    }
+}
@@ -0,0 +1,123 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// The registry of well known chains that's built into the observer.
+const BUNDLED_CHAINS: &str = include_str!("../chains.toml");
+
+/// Defaults for observing a chain. Everything is optional, so that a chains file
+/// can override just the settings it cares about.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ChainDefaults {
+    pub genesis_hash: Option<String>,
+    pub telemetry_url: Option<String>,
+    pub slot_duration_ms: Option<u64>,
+    pub stall_hours: Option<f64>,
+    pub fork_depth: Option<u64>,
+    pub slow_prop_ms: Option<u64>,
+}
+
+impl ChainDefaults {
+    /// Take any settings that `other` has over our own.
+    fn merge(&mut self, other: ChainDefaults) {
+        self.genesis_hash = other.genesis_hash.or(self.genesis_hash.take());
+        self.telemetry_url = other.telemetry_url.or(self.telemetry_url.take());
+        self.slot_duration_ms = other.slot_duration_ms.or(self.slot_duration_ms);
+        self.stall_hours = other.stall_hours.or(self.stall_hours);
+        self.fork_depth = other.fork_depth.or(self.fork_depth);
+        self.slow_prop_ms = other.slow_prop_ms.or(self.slow_prop_ms);
+    }
+}
+
+/// Chains that can be picked by name, keyed by their lowercased name.
+#[derive(Debug, Default)]
+pub struct ChainRegistry {
+    chains: BTreeMap<String, ChainDefaults>,
+}
+
+impl ChainRegistry {
+    /// The bundled registry, with the chains file at `path` merged over it if it exists.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut registry = Self::default();
+        registry.extend(BUNDLED_CHAINS)?;
+        if path.exists() {
+            let chains = std::fs::read_to_string(path)?;
+            registry
+                .extend(&chains)
+                .map_err(|e| anyhow!("Failed to read chains from {:?}: {}", path, e))?;
+        }
+        Ok(registry)
+    }
+
+    fn extend(&mut self, chains: &str) -> Result<()> {
+        let chains: BTreeMap<String, ChainDefaults> = toml::from_str(chains)?;
+        for (name, chain) in chains {
+            self.chains
+                .entry(name.to_lowercase())
+                .or_default()
+                .merge(chain);
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<&ChainDefaults> {
+        self.chains.get(&name.to_lowercase())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &ChainDefaults)> {
+        self.chains
+            .iter()
+            .map(|(name, chain)| (name.as_str(), chain))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn bundled_chains_are_usable() {
+        let registry = ChainRegistry::load(Path::new("/nonexistent/chains.toml")).unwrap();
+        assert!(registry.iter().count() > 0);
+        for (name, chain) in registry.iter() {
+            let genesis_hash = chain.genesis_hash.as_deref().unwrap_or_default();
+            assert_eq!(genesis_hash.len(), 66, "{} has a bad genesis hash", name);
+            assert!(
+                chain.telemetry_url.is_some(),
+                "{} has no telemetry URL",
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn chains_files_override_setting_by_setting() {
+        let mut registry = ChainRegistry::default();
+        registry.extend(BUNDLED_CHAINS).unwrap();
+        let polkadot = registry.get("polkadot").unwrap().clone();
+        registry
+            .extend(
+                r#"
+                [Polkadot]
+                telemetry_url = "ws://localhost:8000/feed"
+
+                [local]
+                genesis_hash = "0x01"
+                "#,
+            )
+            .unwrap();
+
+        let overridden = registry.get("POLKADOT").unwrap();
+        assert_eq!(
+            overridden.telemetry_url.as_deref(),
+            Some("ws://localhost:8000/feed")
+        );
+        assert_eq!(overridden.genesis_hash, polkadot.genesis_hash);
+        assert_eq!(overridden.stall_hours, polkadot.stall_hours);
+        assert_eq!(registry.get("local").unwrap().fork_depth, None);
+
+        assert!(registry.extend("[typo]\nstal_hours = 1").is_err());
+    }
+}
@@ -0,0 +1,221 @@
+use anyhow::{anyhow, Context, Result};
+use flate2::read::MultiGzDecoder;
+use log::warn;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc;
+
+/// How many lines the reader can get ahead of the observer by.
+const QUEUE_LINES: usize = 1024;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// How a recording is compressed, as told by its first few bytes rather than its
+/// name, so that files that have been renamed along the way are still read.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    fn detect(path: &Path) -> Result<Self> {
+        let mut magic = [0u8; 4];
+        let mut file = File::open(path)?;
+        let mut read = 0;
+        while read < magic.len() {
+            match file.read(&mut magic[read..])? {
+                0 => break,
+                n => read += n,
+            }
+        }
+        let magic = &magic[..read];
+        Ok(if magic.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if magic.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        })
+    }
+}
+
+/// A line from a feed recording: a batch of feed messages, and when (in unix MS) the
+/// core sent it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub timestamp: u64,
+    pub bytes: Vec<u8>,
+    /// Whether this is the first line of a file. Each file starts with everything the
+    /// feed knows about the chain, just like a new connection would.
+    pub first_in_file: bool,
+}
+
+/// Open a recording, decompressing it if need be. Compressed files may hold several
+/// gzip members or zstd frames one after the other, as appending to them leaves.
+pub fn open(path: &Path) -> Result<Box<dyn BufRead + Send>> {
+    let file = File::open(path)?;
+    Ok(match Compression::detect(path)? {
+        Compression::None => Box::new(BufReader::new(file)),
+        Compression::Gzip => Box::new(BufReader::new(MultiGzDecoder::new(file))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::stream::read::Decoder::new(file)?)),
+    })
+}
+
+fn parse_line(line: &[u8]) -> Result<(u64, &[u8])> {
+    let tab = line
+        .iter()
+        .position(|&b| b == b'\t')
+        .ok_or_else(|| anyhow!("expected a timestamp and a tab"))?;
+    let timestamp = std::str::from_utf8(&line[..tab])?.parse()?;
+    Ok((timestamp, &line[tab + 1..]))
+}
+
+/// When the first line of a recording was sent, or `None` if there are no lines.
+fn first_timestamp(path: &Path) -> Result<Option<u64>> {
+    let mut line = vec![];
+    if open(path)?.read_until(b'\n', &mut line)? == 0 {
+        return Ok(None);
+    }
+    Ok(Some(parse_line(trim_newline(&line))?.0))
+}
+
+fn trim_newline(line: &[u8]) -> &[u8] {
+    line.strip_suffix(b"\n").unwrap_or(line)
+}
+
+/// Turn the given files and glob patterns into a list of recordings, in the order
+/// that they were recorded in (going by their first lines, not their names). It's an
+/// error for a pattern to match nothing, since that's almost always a typo.
+pub fn expand(patterns: &[String]) -> Result<Vec<PathBuf>> {
+    let mut paths = vec![];
+    for pattern in patterns {
+        let matched: Vec<PathBuf> = glob::glob(pattern)
+            .with_context(|| format!("invalid pattern '{}'", pattern))?
+            .collect::<Result<_, _>>()?;
+        if matched.is_empty() {
+            return Err(anyhow!("no recordings match '{}'", pattern));
+        }
+        paths.extend(matched.into_iter().filter(|path| path.is_file()));
+    }
+    paths.sort();
+    paths.dedup();
+
+    let mut recordings = vec![];
+    for path in paths {
+        let first = first_timestamp(&path).with_context(|| format!("{}", path.display()))?;
+        recordings.push((first, path));
+    }
+    recordings.sort();
+    Ok(recordings.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Read the lines of each recording in turn on a thread of its own, so that reading
+/// and decompressing don't hold the observer up. The channel ends after the last
+/// line, or after the first error.
+pub fn spawn_reader(paths: Vec<PathBuf>) -> mpsc::Receiver<Result<Line>> {
+    let (tx, rx) = mpsc::channel(QUEUE_LINES);
+    std::thread::spawn(move || {
+        for path in paths {
+            if let Err(e) = read_recording(&path, &tx) {
+                let _ = tx.blocking_send(Err(e.context(format!("{}", path.display()))));
+                return;
+            }
+        }
+    });
+    rx
+}
+
+fn read_recording(path: &Path, tx: &mpsc::Sender<Result<Line>>) -> Result<()> {
+    let mut reader = open(path)?;
+    let mut buf = vec![];
+    let mut first_in_file = true;
+    loop {
+        buf.clear();
+        if reader.read_until(b'\n', &mut buf)? == 0 {
+            return Ok(());
+        }
+        // Recordings that were still being written to when they were copied (or that
+        // were cut short) can end part way through a line.
+        if !buf.ends_with(b"\n") {
+            warn!("Skipping a partly written last line in {}", path.display());
+            return Ok(());
+        }
+        let (timestamp, bytes) = parse_line(trim_newline(&buf))?;
+        let line = Line {
+            timestamp,
+            bytes: bytes.to_vec(),
+            first_in_file,
+        };
+        first_in_file = false;
+        if tx.blocking_send(Ok(line)).is_err() {
+            // The observer has stopped; there's no-one to read for.
+            return Ok(());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::Write;
+
+    fn lines(start: u64) -> String {
+        (start..start + 3)
+            .map(|ts| format!("{}\t[0,{}]\n", ts, ts))
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn reads_compressed_recordings_in_time_order() {
+        let dir = std::env::temp_dir().join(format!("observer-replay-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+
+        // Named so that sorting by name would get them in the wrong order:
+        std::fs::write(dir.join("c.feed"), lines(100) + "103\t[0,").unwrap();
+
+        let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        gz.write_all(lines(200).as_bytes()).unwrap();
+        // A second member, as appending to a gzip file leaves:
+        let mut more = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+        more.write_all(lines(203).as_bytes()).unwrap();
+        let mut bytes = gz.finish().unwrap();
+        bytes.extend(more.finish().unwrap());
+        std::fs::write(dir.join("b.feed.gz"), bytes).unwrap();
+
+        let zst = zstd::stream::encode_all(lines(300).as_bytes(), 0).unwrap();
+        std::fs::write(dir.join("a.feed.zst"), zst).unwrap();
+
+        let pattern = format!("{}/*.feed*", dir.display());
+        let paths = expand(&[pattern, dir.join("c.feed").display().to_string()]).unwrap();
+        let names: Vec<_> = paths
+            .iter()
+            .map(|p| p.file_name().unwrap().to_str().unwrap())
+            .collect();
+        assert_eq!(names, vec!["c.feed", "b.feed.gz", "a.feed.zst"]);
+
+        let mut rx = spawn_reader(paths);
+        let mut seen = vec![];
+        while let Some(line) = rx.recv().await {
+            let line = line.unwrap();
+            assert_eq!(line.bytes, format!("[0,{}]", line.timestamp).into_bytes());
+            seen.push((line.timestamp, line.first_in_file));
+        }
+        let timestamps: Vec<_> = seen.iter().map(|(ts, _)| *ts).collect();
+        let expected: Vec<u64> = (100..103).chain(200..206).chain(300..303).collect();
+        assert_eq!(timestamps, expected);
+        let firsts: Vec<_> = seen
+            .iter()
+            .filter(|(_, first)| *first)
+            .map(|(ts, _)| *ts)
+            .collect();
+        assert_eq!(firsts, vec![100, 200, 300]);
+
+        assert!(expand(&[format!("{}/*.nothing", dir.display())]).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
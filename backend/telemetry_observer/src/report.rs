@@ -0,0 +1,322 @@
+use crate::chain::ChainIdentity;
+use crate::csv_file;
+use crate::staking::StakingInfo;
+use anyhow::Result;
+use csv::Writer;
+use log::{info, warn};
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+/// The columns written to the report CSV file.
+const REPORT_HEADER: &[&str] = &[
+    "period_start",
+    "period_end",
+    "chain",
+    "genesis_hash",
+    "node_name",
+    "node_id",
+    "observed_blocks",
+    "attributed_blocks",
+    "expected_blocks",
+    "observed_share",
+    "expected_share",
+    "share_ci_low",
+    "share_ci_high",
+    "coverage",
+    "status",
+];
+
+/// The z-score for a 95% confidence interval.
+const Z_95: f64 = 1.96;
+
+/// Below this coverage, too many blocks are missing to say whether anyone is under or
+/// over producing. Blocks go missing while the observer or the feed is down, or when no
+/// node reports them, and that needn't affect every validator equally.
+const MIN_COVERAGE: f64 = 0.8;
+
+#[derive(Debug, Default)]
+struct NodeTally {
+    node_name: String,
+    /// Blocks attributed to a node. When several nodes tie for a block, each gets a
+    /// fraction of it so that the total across nodes is the number of blocks observed.
+    attributed: f64,
+}
+
+/// Periodically compare how many blocks each validator was attributed with how many
+/// we'd expect them to author, given the size of the active set.
+#[derive(Debug)]
+pub struct AuthorReport {
+    writer: Writer<File>,
+    period_secs: u64,
+    period_start: u64,
+    lowest_block: Option<u64>,
+    highest_block: Option<u64>,
+    observed_blocks: u64,
+    nodes: HashMap<String, NodeTally>,
+}
+
+impl AuthorReport {
+    pub fn new(path: &Path, period_secs: u64, now: u64) -> Result<Self> {
+        Ok(Self {
+            writer: csv_file::open_with_header(path, REPORT_HEADER)?,
+            period_secs,
+            period_start: now,
+            lowest_block: None,
+            highest_block: None,
+            observed_blocks: 0,
+            nodes: HashMap::new(),
+        })
+    }
+
+    /// Make sure that a validator appears in the report even if it's never attributed a block.
+    pub fn saw_validator(&mut self, node_name: &str, node_id: &str) {
+        self.nodes
+            .entry(node_id.to_string())
+            .or_insert_with(|| NodeTally {
+                node_name: node_name.to_string(),
+                attributed: 0.0,
+            });
+    }
+
+    /// Record the node(s) that a block was attributed to.
+    pub fn record_block(&mut self, block_number: u64, reporters: &[(&str, &str)]) {
+        if reporters.is_empty() {
+            return;
+        }
+        self.observed_blocks += 1;
+        self.lowest_block = Some(
+            self.lowest_block
+                .map_or(block_number, |n| n.min(block_number)),
+        );
+        self.highest_block = Some(
+            self.highest_block
+                .map_or(block_number, |n| n.max(block_number)),
+        );
+
+        let weight = 1.0 / reporters.len() as f64;
+        for (node_name, node_id) in reporters {
+            let tally = self
+                .nodes
+                .entry(node_id.to_string())
+                .or_insert_with(|| NodeTally {
+                    node_name: node_name.to_string(),
+                    attributed: 0.0,
+                });
+            tally.attributed += weight;
+        }
+    }
+
+    /// Write out the report if the current period is over, and start a new one.
+    pub fn maybe_write(
+        &mut self,
+        now: u64,
+        staking: Option<StakingInfo>,
+        chain: &ChainIdentity,
+    ) -> Result<()> {
+        if now.saturating_sub(self.period_start) < self.period_secs {
+            return Ok(());
+        }
+        self.finish_period(now, staking, chain)
+    }
+
+    /// Write out the report for the current period so far, and start a new one.
+    pub fn finish_period(
+        &mut self,
+        now: u64,
+        staking: Option<StakingInfo>,
+        chain: &ChainIdentity,
+    ) -> Result<()> {
+        match staking {
+            Some(staking) if self.observed_blocks > 0 => self.write(now, staking, chain)?,
+            Some(_) => info!("No blocks were observed this period; skipping author report"),
+            None => warn!("No staking information is available; skipping author report"),
+        }
+
+        self.period_start = now;
+        self.lowest_block = None;
+        self.highest_block = None;
+        self.observed_blocks = 0;
+        self.nodes.clear();
+        Ok(())
+    }
+
+    fn write(&mut self, now: u64, staking: StakingInfo, chain: &ChainIdentity) -> Result<()> {
+        let n = self.observed_blocks as f64;
+        let expected_share = staking.expected_share();
+
+        // What fraction of the blocks produced in this period did we see?
+        let span = match (self.lowest_block, self.highest_block) {
+            (Some(low), Some(high)) => high - low + 1,
+            _ => 0,
+        };
+        let coverage = if span == 0 { 0.0 } else { n / span as f64 };
+
+        let mut nodes: Vec<_> = self.nodes.iter().collect();
+        nodes.sort_by(|a, b| b.1.attributed.total_cmp(&a.1.attributed));
+
+        let mut flagged = 0;
+        for (node_id, tally) in nodes {
+            let observed_share = tally.attributed / n;
+            let (ci_low, ci_high) = wilson_interval(observed_share, n, Z_95);
+            let status = if coverage < MIN_COVERAGE {
+                Status::LowCoverage
+            } else {
+                Status::from_interval(ci_low, ci_high, expected_share)
+            };
+            if matches!(status, Status::Under | Status::Over) {
+                flagged += 1;
+            }
+
+            self.writer.write_record(&[
+                self.period_start.to_string(),
+                now.to_string(),
+                chain.label.clone(),
+                chain.genesis_hash.clone(),
+                tally.node_name.clone(),
+                node_id.clone(),
+                self.observed_blocks.to_string(),
+                format!("{:.2}", tally.attributed),
+                format!("{:.2}", expected_share * n),
+                format!("{:.6}", observed_share),
+                format!("{:.6}", expected_share),
+                format!("{:.6}", ci_low),
+                format!("{:.6}", ci_high),
+                format!("{:.4}", coverage),
+                status.as_str().to_string(),
+            ])?;
+        }
+        self.writer.flush()?;
+
+        if coverage < MIN_COVERAGE {
+            warn!(
+                "Wrote author report for {} blocks, but with only {:.1}% coverage, nobody can be said to be under or over producing",
+                self.observed_blocks,
+                coverage * 100.0
+            );
+        } else {
+            info!(
+                "Wrote author report for {} blocks ({:.1}% coverage); {} node(s) significantly under or over producing",
+                self.observed_blocks,
+                coverage * 100.0,
+                flagged
+            );
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Status {
+    Under,
+    Expected,
+    Over,
+    /// Too few of the period's blocks were observed to say.
+    LowCoverage,
+}
+
+impl Status {
+    /// If the expected share falls outside of the confidence interval for the observed
+    /// share, then the difference is significant.
+    fn from_interval(ci_low: f64, ci_high: f64, expected_share: f64) -> Self {
+        if ci_high < expected_share {
+            Status::Under
+        } else if ci_low > expected_share {
+            Status::Over
+        } else {
+            Status::Expected
+        }
+    }
+
+    fn as_str(&self) -> &'static str {
+        match self {
+            Status::Under => "under",
+            Status::Expected => "expected",
+            Status::Over => "over",
+            Status::LowCoverage => "low_coverage",
+        }
+    }
+}
+
+/// The Wilson score interval for a proportion `p` observed over `n` trials. Unlike the
+/// normal approximation, this behaves well for the small proportions we deal with here.
+fn wilson_interval(p: f64, n: f64, z: f64) -> (f64, f64) {
+    if n <= 0.0 {
+        return (0.0, 1.0);
+    }
+    let z2 = z * z;
+    let denominator = 1.0 + z2 / n;
+    let centre = (p + z2 / (2.0 * n)) / denominator;
+    let half_width = z * (p * (1.0 - p) / n + z2 / (4.0 * n * n)).sqrt() / denominator;
+    (
+        (centre - half_width).max(0.0),
+        (centre + half_width).min(1.0),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn wilson_interval_contains_observed_share() {
+        let (low, high) = wilson_interval(0.01, 1000.0, Z_95);
+        assert!(low < 0.01 && 0.01 < high);
+        assert!(low > 0.004 && high < 0.02);
+
+        // Nothing observed still says something, given enough trials:
+        let (low, high) = wilson_interval(0.0, 1000.0, Z_95);
+        assert_eq!(low, 0.0);
+        assert!(high < 0.005);
+    }
+
+    #[test]
+    fn status_from_interval() {
+        let expected = 0.01;
+        let (low, high) = wilson_interval(0.0, 2000.0, Z_95);
+        assert_eq!(Status::from_interval(low, high, expected), Status::Under);
+        let (low, high) = wilson_interval(0.03, 2000.0, Z_95);
+        assert_eq!(Status::from_interval(low, high, expected), Status::Over);
+        let (low, high) = wilson_interval(0.011, 2000.0, Z_95);
+        assert_eq!(Status::from_interval(low, high, expected), Status::Expected);
+        // Too few observations to say anything:
+        let (low, high) = wilson_interval(0.0, 10.0, Z_95);
+        assert_eq!(Status::from_interval(low, high, expected), Status::Expected);
+    }
+
+    #[test]
+    fn low_coverage_flags_nobody() {
+        let dir = std::env::temp_dir().join(format!("observer-report-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("report.csv");
+        let staking = StakingInfo { validator_count: 2 };
+        let chain = ChainIdentity::new("0x1234");
+
+        // Only every other block is seen, and all of those are from one validator:
+        let mut report = AuthorReport::new(&path, 3600, 0).unwrap();
+        report.saw_validator("quiet", "b");
+        for n in 0..200 {
+            report.record_block(2 * n, &[("loud", "a")]);
+        }
+        report.finish_period(3600, Some(staking), &chain).unwrap();
+
+        // Every block is seen:
+        for n in 0..200 {
+            report.record_block(n, &[("loud", "a")]);
+        }
+        report.saw_validator("quiet", "b");
+        report.finish_period(7200, Some(staking), &chain).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let statuses: Vec<_> = contents
+            .lines()
+            .skip(1)
+            .map(|line| line.rsplit(',').next().unwrap())
+            .collect();
+        assert_eq!(
+            statuses,
+            vec!["low_coverage", "low_coverage", "over", "under"]
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
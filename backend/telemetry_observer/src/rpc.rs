@@ -0,0 +1,210 @@
+use anyhow::{anyhow, Result};
+use common::ws_client::{self, RecvMessage, SentMessage};
+use futures::StreamExt;
+use log::debug;
+use serde_json::{json, Value};
+use std::time::Duration;
+
+/// The storage key for `Session::Validators`, ie `twox128("Session") ++ twox128("Validators")`.
+const SESSION_VALIDATORS_KEY: &str =
+    "0xcec5070d609dd3497f72bde07fc96ba088dcde934c658227ee1dfafcd6e16903";
+
+/// How long to wait for a response to an RPC request.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The version of the runtime that a node is running.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeVersion {
+    pub spec_name: String,
+    pub spec_version: u32,
+}
+
+/// A minimal JSON-RPC client for querying a node over a WebSocket.
+#[derive(Debug)]
+pub struct RpcClient {
+    uri: http::Uri,
+    next_id: u64,
+}
+
+impl RpcClient {
+    pub fn new(url: &str) -> Result<Self> {
+        Ok(Self {
+            uri: url.parse()?,
+            next_id: 1,
+        })
+    }
+
+    /// Make a single request. We connect afresh each time, since requests are infrequent
+    /// and this saves us from having to notice and recover from dropped connections.
+    pub async fn call(&mut self, method: &str, params: Value) -> Result<Value> {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let (sender, mut receiver) = ws_client::connect(&self.uri).await?.into_channels();
+        let request = json!({ "jsonrpc": "2.0", "id": id, "method": method, "params": params });
+        debug!("RPC request: {}", request);
+        sender.unbounded_send(SentMessage::Text(request.to_string()))?;
+
+        let response = tokio::time::timeout(REQUEST_TIMEOUT, async {
+            while let Some(msg) = receiver.next().await {
+                let bytes = match msg? {
+                    RecvMessage::Text(text) => text.into_bytes(),
+                    RecvMessage::Binary(bytes) => bytes,
+                };
+                let value: Value = serde_json::from_slice(&bytes)?;
+                if value["id"].as_u64() == Some(id) {
+                    return Ok(value);
+                }
+            }
+            Err(anyhow!(
+                "Connection closed before a response to '{}'",
+                method
+            ))
+        })
+        .await
+        .map_err(|_| anyhow!("Timed out waiting for a response to '{}'", method))??;
+
+        let _ = receiver.close().await;
+
+        if let Some(error) = response.get("error") {
+            return Err(anyhow!("RPC error calling '{}': {}", method, error));
+        }
+        Ok(response["result"].clone())
+    }
+
+    /// Fetch the number of validators in the active set.
+    pub async fn validator_count(&mut self) -> Result<u64> {
+        let result = self
+            .call("state_getStorage", json!([SESSION_VALIDATORS_KEY]))
+            .await?;
+        let hex_str = result
+            .as_str()
+            .ok_or_else(|| anyhow!("Session validators not found in storage"))?;
+        let bytes = hex::decode(hex_str.trim_start_matches("0x"))?;
+
+        // The value is a SCALE encoded Vec<AccountId>, which starts with its length:
+        let (count, _) = decode_compact(&bytes)
+            .ok_or_else(|| anyhow!("Could not decode the number of session validators"))?;
+        Ok(count)
+    }
+
+    /// Fetch the version of the runtime at the best block.
+    pub async fn runtime_version(&mut self) -> Result<RuntimeVersion> {
+        let result = self.call("state_getRuntimeVersion", json!([])).await?;
+        let spec_version = result["specVersion"]
+            .as_u64()
+            .ok_or_else(|| anyhow!("Runtime version has no specVersion"))?;
+        Ok(RuntimeVersion {
+            spec_name: result["specName"].as_str().unwrap_or_default().to_string(),
+            spec_version: spec_version as u32,
+        })
+    }
+
+    /// Fetch the accounts of the active validator set, at the given block or else the
+    /// best one. These are in the order that authority indices refer to.
+    pub async fn session_validators(&mut self, at: Option<&str>) -> Result<Vec<[u8; 32]>> {
+        let params = match at {
+            Some(block_hash) => json!([SESSION_VALIDATORS_KEY, block_hash]),
+            None => json!([SESSION_VALIDATORS_KEY]),
+        };
+        let result = self.call("state_getStorage", params).await?;
+        let hex_str = result
+            .as_str()
+            .ok_or_else(|| anyhow!("Session validators not found in storage"))?;
+        let bytes = hex::decode(hex_str.trim_start_matches("0x"))?;
+        decode_accounts(&bytes).ok_or_else(|| anyhow!("Could not decode the session validators"))
+    }
+
+    /// Fetch the digest logs of a block's header, each as hex.
+    pub async fn digest_logs(&mut self, block_hash: &str) -> Result<Vec<String>> {
+        let result = self.call("chain_getHeader", json!([block_hash])).await?;
+        let logs = result["digest"]["logs"]
+            .as_array()
+            .ok_or_else(|| anyhow!("Block header has no digest"))?;
+        Ok(logs
+            .iter()
+            .filter_map(|log| log.as_str().map(str::to_string))
+            .collect())
+    }
+
+    /// Fetch the number of the best block.
+    pub async fn best_block_number(&mut self) -> Result<u64> {
+        let result = self.call("chain_getHeader", json!([])).await?;
+        let number = result["number"]
+            .as_str()
+            .ok_or_else(|| anyhow!("Block header has no number"))?;
+        Ok(u64::from_str_radix(number.trim_start_matches("0x"), 16)?)
+    }
+}
+
+/// Decode a SCALE compact encoded integer, returning it and the number of bytes it took up.
+pub fn decode_compact(bytes: &[u8]) -> Option<(u64, usize)> {
+    let first = *bytes.first()?;
+    match first & 0b11 {
+        0b00 => Some(((first >> 2) as u64, 1)),
+        0b01 => {
+            let b = bytes.get(..2)?;
+            Some(((u16::from_le_bytes([b[0], b[1]]) >> 2) as u64, 2))
+        }
+        0b10 => {
+            let b = bytes.get(..4)?;
+            Some((
+                (u32::from_le_bytes([b[0], b[1], b[2], b[3]]) >> 2) as u64,
+                4,
+            ))
+        }
+        _ => {
+            let len = (first >> 2) as usize + 4;
+            if len > 8 {
+                return None;
+            }
+            let b = bytes.get(1..1 + len)?;
+            let mut buf = [0u8; 8];
+            buf[..len].copy_from_slice(b);
+            Some((u64::from_le_bytes(buf), 1 + len))
+        }
+    }
+}
+
+/// Decode a SCALE encoded `Vec<AccountId32>`.
+pub fn decode_accounts(bytes: &[u8]) -> Option<Vec<[u8; 32]>> {
+    let (count, offset) = decode_compact(bytes)?;
+    let accounts = bytes.get(offset..)?;
+    if accounts.len() as u64 != count * 32 {
+        return None;
+    }
+    Some(
+        accounts
+            .chunks_exact(32)
+            .map(|account| account.try_into().expect("chunks are 32 bytes"))
+            .collect(),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn decodes_compact_integers() {
+        assert_eq!(decode_compact(&[0x00]), Some((0, 1)));
+        assert_eq!(decode_compact(&[0xfc]), Some((63, 1)));
+        assert_eq!(decode_compact(&[0x15, 0x01]), Some((69, 2)));
+        assert_eq!(decode_compact(&[0xfe, 0xff, 0x03, 0x00]), Some((65535, 4)));
+        assert_eq!(
+            decode_compact(&[0x03, 0x00, 0x00, 0x00, 0x40]),
+            Some((1 << 30, 5))
+        );
+        assert_eq!(decode_compact(&[0x01]), None);
+        assert_eq!(decode_compact(&[]), None);
+    }
+
+    #[test]
+    fn decodes_accounts() {
+        let mut bytes = vec![0x08];
+        bytes.extend([1u8; 32]);
+        bytes.extend([2u8; 32]);
+        assert_eq!(decode_accounts(&bytes), Some(vec![[1u8; 32], [2u8; 32]]));
+        assert_eq!(decode_accounts(&bytes[..40]), None);
+    }
+}
@@ -0,0 +1,146 @@
+use crate::chain::ChainIdentity;
+use crate::csv_file;
+use crate::journal;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+/// The file name of the run manifest, which is kept in the same directory as the output.
+const RUN_MANIFEST_FILE: &str = "run-manifest.json";
+
+/// Eg `./data/out.csv` has its run manifest at `./data/run-manifest.json`.
+pub fn run_manifest_path(output_path: &Path) -> PathBuf {
+    output_path.with_file_name(RUN_MANIFEST_FILE)
+}
+
+/// Running totals of what the observer has done, for the run manifest.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunCounters {
+    pub blocks_decided: u64,
+    /// Rows written to the output, across all of the blocks decided.
+    pub rows_written: u64,
+    pub nodes_seen: u64,
+    pub feed_messages: u64,
+    pub feed_bytes: u64,
+    pub reconnects: u64,
+}
+
+/// A record of one run of the observer: what it was, how it was configured and what it
+/// did, so that the data it wrote can be reproduced. Timestamps are in Unix seconds.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RunManifest {
+    pub observer_version: String,
+    pub pid: u32,
+    /// Every setting, including defaults, with secrets replaced by hashes of them.
+    pub config: serde_json::Value,
+    /// The same hash as in the output's manifest.
+    pub config_hash: String,
+    /// The chain's name, as given by the feed. Empty until the run has finished.
+    pub chain: String,
+    pub genesis_hash: String,
+    pub started_at: u64,
+    /// `None` while running, or if the observer didn't get to shut down.
+    pub ended_at: Option<u64>,
+    pub stop_reason: Option<String>,
+    pub counters: RunCounters,
+}
+
+impl RunManifest {
+    /// Record the start of a run at `path`. The manifest of an earlier run there is
+    /// moved aside, rather than overwritten, since the outputs are shared between them.
+    pub fn start(
+        path: &Path,
+        config: serde_json::Value,
+        config_hash: &str,
+        genesis_hash: &str,
+        now: u64,
+    ) -> Result<Self> {
+        if path.exists() {
+            csv_file::rotate(path)?;
+        }
+        let manifest = Self {
+            observer_version: env!("CARGO_PKG_VERSION").to_string(),
+            pid: std::process::id(),
+            config,
+            config_hash: config_hash.to_string(),
+            chain: String::new(),
+            genesis_hash: genesis_hash.to_string(),
+            started_at: now,
+            ended_at: None,
+            stop_reason: None,
+            counters: RunCounters::default(),
+        };
+        journal::write_snapshot(path, &manifest)?;
+        Ok(manifest)
+    }
+
+    /// Record the end of the run, and why it ended.
+    pub fn finish(
+        &mut self,
+        path: &Path,
+        now: u64,
+        chain: &ChainIdentity,
+        stop_reason: Option<String>,
+        counters: RunCounters,
+    ) -> Result<()> {
+        self.chain = chain.label.clone();
+        self.ended_at = Some(now);
+        self.stop_reason = stop_reason;
+        self.counters = counters;
+        journal::write_snapshot(path, self)
+    }
+
+    #[cfg(test)]
+    pub fn read(path: &Path) -> Result<Self> {
+        let bytes = std::fs::read(path)?;
+        Ok(serde_json::from_slice(&bytes)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finishes_the_run_and_keeps_earlier_ones() {
+        let dir = std::env::temp_dir().join(format!("observer-run-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = run_manifest_path(&dir.join("out.csv"));
+        assert_eq!(path, dir.join("run-manifest.json"));
+
+        let config = serde_json::json!({ "workers": 1 });
+        let mut manifest = RunManifest::start(&path, config.clone(), "abc", "0x12", 100).unwrap();
+        assert_eq!(RunManifest::read(&path).unwrap().ended_at, None);
+
+        let chain = ChainIdentity {
+            label: "Chain".to_string(),
+            genesis_hash: "0x12".to_string(),
+        };
+        let counters = RunCounters {
+            blocks_decided: 3,
+            rows_written: 4,
+            ..RunCounters::default()
+        };
+        manifest
+            .finish(
+                &path,
+                200,
+                &chain,
+                Some("done".to_string()),
+                counters.clone(),
+            )
+            .unwrap();
+        let finished = RunManifest::read(&path).unwrap();
+        assert_eq!(finished.config, config);
+        assert_eq!(finished.chain, "Chain");
+        assert_eq!(finished.ended_at, Some(200));
+        assert_eq!(finished.counters, counters);
+
+        // The next run's manifest doesn't replace this one:
+        RunManifest::start(&path, config, "abc", "0x12", 300).unwrap();
+        let manifests = std::fs::read_dir(&dir).unwrap().count();
+        assert_eq!(manifests, 2);
+        assert_eq!(RunManifest::read(&path).unwrap().started_at, 300);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,105 @@
+use crate::chain::ChainIdentity;
+use crate::csv_file;
+use crate::rpc::RpcClient;
+use anyhow::Result;
+use csv::Writer;
+use log::{info, warn};
+use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::sync::Mutex;
+
+/// How often to check the runtime version. Upgrades are recorded at the block we
+/// notice them, so this bounds how far from the real boundary that can be.
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The columns written to the runtime upgrades CSV file.
+const UPGRADES_HEADER: &[&str] = &[
+    "timestamp",
+    "chain",
+    "genesis_hash",
+    "detected_at_block",
+    "spec_name",
+    "old_spec_version",
+    "new_spec_version",
+];
+
+/// Keep `spec_version` up to date by polling the given RPC node in the background,
+/// and record an event each time it changes.
+pub fn spawn_poller(
+    rpc_url: &str,
+    spec_version: Arc<Mutex<Option<u32>>>,
+    chain: Arc<Mutex<ChainIdentity>>,
+    upgrades_file: &Path,
+) -> Result<()> {
+    let mut client = RpcClient::new(rpc_url)?;
+    let last_recorded = last_recorded_spec_version(upgrades_file);
+    let mut writer = csv_file::open_with_header(upgrades_file, UPGRADES_HEADER)?;
+
+    tokio::spawn(async move {
+        // Carry on from whatever we saw last time, so that upgrades that happen while
+        // we aren't running are still recorded once we start up again:
+        let mut known = last_recorded;
+        loop {
+            let chain = chain.lock().await.clone();
+            if let Err(e) = check_version(&mut client, &chain, &mut known, &mut writer).await {
+                warn!("Failed to check the runtime version: {}", e);
+            }
+            *spec_version.lock().await = known;
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+    Ok(())
+}
+
+async fn check_version(
+    client: &mut RpcClient,
+    chain: &ChainIdentity,
+    known: &mut Option<u32>,
+    writer: &mut Writer<File>,
+) -> Result<()> {
+    let version = client.runtime_version().await?;
+    if *known == Some(version.spec_version) {
+        return Ok(());
+    }
+
+    let block_number = client.best_block_number().await?;
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    match *known {
+        Some(old) => info!(
+            "Runtime upgraded from spec version {} to {} (noticed at block {})",
+            old, version.spec_version, block_number
+        ),
+        None => info!(
+            "Runtime is {} spec version {}",
+            version.spec_name, version.spec_version
+        ),
+    }
+
+    writer.write_record(&[
+        now.to_string(),
+        chain.label.clone(),
+        chain.genesis_hash.clone(),
+        block_number.to_string(),
+        version.spec_name,
+        known.map(|v| v.to_string()).unwrap_or_default(),
+        version.spec_version.to_string(),
+    ])?;
+    writer.flush()?;
+
+    *known = Some(version.spec_version);
+    Ok(())
+}
+
+/// The spec version in the last row of the upgrades file, if there is one.
+fn last_recorded_spec_version(path: &Path) -> Option<u32> {
+    let mut reader = csv::Reader::from_path(path).ok()?;
+    let headers = reader.headers().ok()?.clone();
+    let column = headers.iter().position(|h| h == "new_spec_version")?;
+    reader
+        .records()
+        .filter_map(|r| r.ok())
+        .last()
+        .and_then(|r| r.get(column)?.parse().ok())
+}
@@ -0,0 +1,244 @@
+use anyhow::{anyhow, Result};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// The version of the format that state is persisted in. Bump this, and add a step
+/// to [`MIGRATIONS`], whenever a change to `NodeInfo` or `BlockInfo` (or to how they're
+/// keyed) means that state saved by an older observer would no longer load as it should.
+pub const SCHEMA_VERSION: u32 = 3;
+
+/// The kinds of record that are persisted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordKind {
+    Node,
+    Block,
+}
+
+type Migration = fn(RecordKind, &mut Value) -> Result<()>;
+
+/// `MIGRATIONS[n]` upgrades a record from schema version `n` to `n + 1`.
+const MIGRATIONS: &[Migration] = &[v0_to_v1, v1_to_v2, v2_to_v3];
+
+/// Version 0 is anything saved before state was versioned. Blocks have since gained
+/// `first_seen_ms`, which we can work out (to the second) from `first_seen`.
+fn v0_to_v1(kind: RecordKind, record: &mut Value) -> Result<()> {
+    if kind == RecordKind::Block {
+        let first_seen = record["first_seen"].as_u64().unwrap_or(0);
+        if let Some(record) = record.as_object_mut() {
+            record
+                .entry("first_seen_ms")
+                .or_insert(json!(first_seen * 1000));
+        }
+    }
+    Ok(())
+}
+
+/// Version 2 keys nodes by network ID rather than by feed index. The records themselves
+/// haven't changed; `Nodes::from_records` re-keys them (merging duplicates) on load.
+fn v1_to_v2(_kind: RecordKind, _record: &mut Value) -> Result<()> {
+    Ok(())
+}
+
+/// Version 3 keeps every node that imported an undecided block as a candidate, and only
+/// fills in `reporters` once it's decided. Undecided blocks saved before then only kept
+/// the nodes tied for the lowest propagation time, so those become the candidates.
+fn v2_to_v3(kind: RecordKind, record: &mut Value) -> Result<()> {
+    if kind != RecordKind::Block || record["output"].as_bool() != Some(false) {
+        return Ok(());
+    }
+    let propagation_time = record["lowest_prop_time"].clone();
+    if let Some(record) = record.as_object_mut() {
+        let reporters = record.insert("reporters".to_string(), json!([]));
+        let candidates: Vec<Value> = match reporters {
+            Some(Value::Array(reporters)) => reporters
+                .into_iter()
+                .map(|reporter| {
+                    json!({
+                        "reporter": reporter,
+                        "propagation_time": propagation_time,
+                        "validator": false,
+                    })
+                })
+                .collect(),
+            _ => vec![],
+        };
+        record.insert("candidates".to_string(), Value::Array(candidates));
+    }
+    Ok(())
+}
+
+/// Upgrade a record saved with schema version `from` to the current version.
+pub fn migrate(kind: RecordKind, mut record: Value, from: u32) -> Result<Value> {
+    check_supported(kind, from)?;
+    for migration in &MIGRATIONS[from as usize..] {
+        migration(kind, &mut record)?;
+    }
+    Ok(record)
+}
+
+fn check_supported(kind: RecordKind, from: u32) -> Result<()> {
+    if from > SCHEMA_VERSION {
+        return Err(anyhow!(
+            "{:?} record has schema version {}, but this observer only understands up to version {}; was it saved by a newer observer?",
+            kind,
+            from,
+            SCHEMA_VERSION
+        ));
+    }
+    Ok(())
+}
+
+/// Decode a map of records, as written by [`encode_map`].
+pub fn decode_map<T: DeserializeOwned>(
+    kind: RecordKind,
+    document: Value,
+) -> Result<HashMap<String, T>> {
+    let (from, records) = match document {
+        Value::Object(mut document) if document.contains_key("schema_version") => (
+            schema_version(&document)?,
+            document.remove("records").unwrap_or_default(),
+        ),
+        // Before state was versioned, the records were saved on their own:
+        records => (0, records),
+    };
+    check_supported(kind, from)?;
+    let Value::Object(records) = records else {
+        return Err(anyhow!("Expected a map of {:?} records", kind));
+    };
+
+    records
+        .into_iter()
+        .map(|(key, record)| {
+            let record = serde_json::from_value(migrate(kind, record, from)?)?;
+            Ok((key, record))
+        })
+        .collect()
+}
+
+/// Encode a map of records along with the schema version that they're written in.
+pub fn encode_map<T: Serialize>(records: &HashMap<String, T>) -> Value {
+    json!({ "schema_version": SCHEMA_VERSION, "records": records })
+}
+
+/// Decode a single record, as written by [`encode_record`].
+pub fn decode_record<T: DeserializeOwned>(kind: RecordKind, bytes: &[u8]) -> Result<T> {
+    let (from, record) = match serde_json::from_slice(bytes)? {
+        Value::Object(mut document) if document.contains_key("schema_version") => (
+            schema_version(&document)?,
+            document.remove("record").unwrap_or_default(),
+        ),
+        record => (0, record),
+    };
+    Ok(serde_json::from_value(migrate(kind, record, from)?)?)
+}
+
+/// Encode a single record along with the schema version that it's written in.
+pub fn encode_record<T: Serialize>(record: &T) -> Result<Vec<u8>> {
+    Ok(serde_json::to_vec(
+        &json!({ "schema_version": SCHEMA_VERSION, "record": record }),
+    )?)
+}
+
+pub fn schema_version(document: &serde_json::Map<String, Value>) -> Result<u32> {
+    match document.get("schema_version") {
+        None => Ok(0),
+        Some(version) => version
+            .as_u64()
+            .map(|v| v as u32)
+            .ok_or_else(|| anyhow!("Invalid schema version: {}", version)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::{BlockInfo, NodeInfo};
+
+    #[test]
+    fn migrates_unversioned_state() {
+        let nodes = json!({ "1": { "name": "a", "node_id": "a-id" } });
+        let nodes: HashMap<String, NodeInfo> = decode_map(RecordKind::Node, nodes).unwrap();
+        assert_eq!(nodes["1"].name, "a");
+        assert_eq!(nodes["1"].implementation, "");
+
+        let blocks = json!({ "0x1": {
+            "block_number": 1,
+            "lowest_prop_time": 100,
+            "reporters": [],
+            "first_seen": 10,
+            "report_count": 1,
+            "output": true
+        }});
+        let blocks: HashMap<String, BlockInfo> = decode_map(RecordKind::Block, blocks).unwrap();
+        assert_eq!(blocks["0x1"].first_seen_ms, 10_000);
+
+        let block = serde_json::to_vec(&json!({
+            "block_number": 1,
+            "lowest_prop_time": 100,
+            "reporters": [],
+            "first_seen": 10,
+            "report_count": 1,
+            "output": true
+        }))
+        .unwrap();
+        let block: BlockInfo = decode_record(RecordKind::Block, &block).unwrap();
+        assert_eq!(block.first_seen_ms, 10_000);
+    }
+
+    #[test]
+    fn undecided_blocks_keep_their_reporters_as_candidates() {
+        let reporter = json!({
+            "node_idx": 1,
+            "node_name": "a",
+            "node_id": "a-id",
+            "timestamp": 10
+        });
+        let blocks = json!({ "schema_version": 2, "records": {
+            "0x1": {
+                "block_number": 1,
+                "lowest_prop_time": 100,
+                "reporters": [reporter],
+                "first_seen": 10,
+                "first_seen_ms": 10_000,
+                "report_count": 1,
+                "output": false
+            },
+            "0x2": {
+                "block_number": 2,
+                "lowest_prop_time": 100,
+                "reporters": [reporter],
+                "first_seen": 10,
+                "first_seen_ms": 10_000,
+                "report_count": 1,
+                "output": true
+            }
+        }});
+        let blocks: HashMap<String, BlockInfo> = decode_map(RecordKind::Block, blocks).unwrap();
+        assert!(blocks["0x1"].reporters.is_empty());
+        assert_eq!(blocks["0x1"].candidates[0].reporter.node_id, "a-id");
+        assert_eq!(blocks["0x1"].candidates[0].propagation_time, 100);
+        assert_eq!(blocks["0x2"].reporters[0].node_id, "a-id");
+        assert!(blocks["0x2"].candidates.is_empty());
+    }
+
+    #[test]
+    fn round_trips_current_state() {
+        let mut nodes = HashMap::new();
+        nodes.insert("1".to_string(), json!({ "name": "a", "node_id": "a-id" }));
+        let decoded: HashMap<String, NodeInfo> =
+            decode_map(RecordKind::Node, encode_map(&nodes)).unwrap();
+        assert_eq!(decoded["1"].node_id, "a-id");
+
+        let bytes = encode_record(&nodes["1"]).unwrap();
+        let decoded: NodeInfo = decode_record(RecordKind::Node, &bytes).unwrap();
+        assert_eq!(decoded.name, "a");
+    }
+
+    #[test]
+    fn rejects_newer_state() {
+        let nodes = json!({ "schema_version": SCHEMA_VERSION + 1, "records": {} });
+        assert!(decode_map::<NodeInfo>(RecordKind::Node, nodes).is_err());
+    }
+}
@@ -0,0 +1,256 @@
+use crate::state::Candidate;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Scores within this of the best are ties.
+const TIE_EPSILON: f64 = 1e-9;
+
+/// A node's win rate is worked out as if it had also reported this many blocks and won
+/// none of them, so that a node that's won the only block it's reported doesn't look
+/// like a certain winner.
+const WIN_RATE_PRIOR_BLOCKS: u64 = 10;
+
+/// How much each signal counts towards a node's score for a block. The node(s) with
+/// the highest score are attributed it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AuthorWeights {
+    /// For the node that first imported the block, which the core gives a propagation
+    /// time of zero. This is often the author itself.
+    pub self_report: f64,
+    /// For how early a node imported the block, after the first: 1 for the earliest, 1/2
+    /// for the next earliest, and so on.
+    pub ordering: f64,
+    /// For how often the node has been attributed the blocks it's reported before.
+    pub win_rate: f64,
+    /// For nodes that are running as validators, and so can author blocks at all.
+    pub validator: f64,
+}
+
+impl AuthorWeights {
+    /// Only the earliest import after the first counts, which is how blocks were
+    /// attributed before there were weights.
+    #[cfg(test)]
+    pub const LOWEST_PROPAGATION: AuthorWeights = AuthorWeights {
+        self_report: 0.0,
+        ordering: 1.0,
+        win_rate: 0.0,
+        validator: 0.0,
+    };
+}
+
+impl Default for AuthorWeights {
+    fn default() -> Self {
+        Self {
+            self_report: 1.0,
+            ordering: 1.0,
+            win_rate: 0.5,
+            validator: 0.5,
+        }
+    }
+}
+
+impl FromStr for AuthorWeights {
+    type Err = String;
+
+    /// Weights given as `name=weight` pairs separated by commas. Signals that aren't
+    /// named don't count.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut weights = AuthorWeights {
+            self_report: 0.0,
+            ordering: 0.0,
+            win_rate: 0.0,
+            validator: 0.0,
+        };
+        for pair in s.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+            let (name, weight) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("expected name=weight, got '{}'", pair))?;
+            let weight: f64 = match weight.trim().parse() {
+                Ok(weight) if weight >= 0.0 => weight,
+                _ => return Err(format!("'{}' isn't a weight of 0 or more", weight)),
+            };
+            let field = match name.trim() {
+                "self_report" => &mut weights.self_report,
+                "ordering" => &mut weights.ordering,
+                "win_rate" => &mut weights.win_rate,
+                "validator" => &mut weights.validator,
+                other => {
+                    return Err(format!(
+                    "unknown signal '{}' (expected self_report, ordering, win_rate or validator)",
+                    other
+                ))
+                }
+            };
+            *field = weight;
+        }
+        Ok(weights)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct History {
+    reported: u64,
+    won: u64,
+}
+
+/// Decides which of the nodes that imported a block to attribute it to, by combining
+/// several signals with configurable weights. Win rates are only kept for as long as
+/// the observer runs.
+#[derive(Debug)]
+pub struct AuthorScorer {
+    weights: AuthorWeights,
+    /// Keyed by node ID.
+    history: HashMap<String, History>,
+}
+
+impl AuthorScorer {
+    pub fn new(weights: AuthorWeights) -> Self {
+        Self {
+            weights,
+            history: HashMap::new(),
+        }
+    }
+
+    /// Each candidate's score for the block, in the same order as the candidates.
+    pub fn scores(&self, candidates: &[Candidate]) -> Vec<f64> {
+        let mut later_times: Vec<u64> = candidates
+            .iter()
+            .map(|c| c.propagation_time)
+            .filter(|&t| t > 0)
+            .collect();
+        later_times.sort_unstable();
+        later_times.dedup();
+
+        candidates
+            .iter()
+            .map(|candidate| {
+                let self_report = if candidate.propagation_time == 0 {
+                    1.0
+                } else {
+                    0.0
+                };
+                // Nodes that imported the block at the same moment are ranked the same:
+                let ordering = match later_times.binary_search(&candidate.propagation_time) {
+                    Ok(rank) => 1.0 / (rank + 1) as f64,
+                    Err(_) => 0.0,
+                };
+                let validator = if candidate.validator { 1.0 } else { 0.0 };
+                self.weights.self_report * self_report
+                    + self.weights.ordering * ordering
+                    + self.weights.win_rate * self.win_rate(&candidate.reporter.node_id)
+                    + self.weights.validator * validator
+            })
+            .collect()
+    }
+
+    /// The candidates to attribute a block to, by index, which are those with the best
+    /// score (if it's above zero). Every candidate's win rate is updated to match.
+    pub fn decide(&mut self, candidates: &[Candidate]) -> Vec<usize> {
+        let scores = self.scores(candidates);
+        let best = scores.iter().copied().fold(0.0, f64::max);
+        let winners: Vec<usize> = if best > 0.0 {
+            (0..candidates.len())
+                .filter(|&i| scores[i] >= best - TIE_EPSILON)
+                .collect()
+        } else {
+            vec![]
+        };
+
+        for (i, candidate) in candidates.iter().enumerate() {
+            let history = self
+                .history
+                .entry(candidate.reporter.node_id.clone())
+                .or_default();
+            history.reported += 1;
+            if winners.contains(&i) {
+                history.won += 1;
+            }
+        }
+        winners
+    }
+
+    fn win_rate(&self, node_id: &str) -> f64 {
+        match self.history.get(node_id) {
+            Some(history) => history.won as f64 / (history.reported + WIN_RATE_PRIOR_BLOCKS) as f64,
+            None => 0.0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::BlockReporter;
+
+    fn candidate(node_id: &str, propagation_time: u64, validator: bool) -> Candidate {
+        Candidate {
+            reporter: BlockReporter {
+                node_idx: 0,
+                node_name: node_id.to_string(),
+                node_id: node_id.to_string(),
+                implementation: String::new(),
+                version: String::new(),
+                timestamp: 0,
+            },
+            propagation_time,
+            validator,
+        }
+    }
+
+    #[test]
+    fn weights_are_parsed_from_name_weight_pairs() {
+        assert_eq!(
+            "ordering=1".parse::<AuthorWeights>(),
+            Ok(AuthorWeights::LOWEST_PROPAGATION)
+        );
+        assert_eq!(
+            "self_report=2, validator=0.25".parse::<AuthorWeights>(),
+            Ok(AuthorWeights {
+                self_report: 2.0,
+                ordering: 0.0,
+                win_rate: 0.0,
+                validator: 0.25,
+            })
+        );
+        assert!("speed=1".parse::<AuthorWeights>().is_err());
+        assert!("ordering=-1".parse::<AuthorWeights>().is_err());
+        assert!("ordering".parse::<AuthorWeights>().is_err());
+    }
+
+    #[test]
+    fn lowest_propagation_weights_pick_the_earliest_later_import() {
+        let mut scorer = AuthorScorer::new(AuthorWeights::LOWEST_PROPAGATION);
+        let candidates = [
+            candidate("first", 0, false),
+            candidate("a", 40, false),
+            candidate("b", 40, false),
+            candidate("c", 90, false),
+        ];
+        assert_eq!(scorer.decide(&candidates), vec![1, 2]);
+        // With only the first import, there's nothing to go on:
+        assert!(scorer.decide(&candidates[..1]).is_empty());
+    }
+
+    #[test]
+    fn a_fast_rpc_node_loses_to_the_validator_that_authored_the_block() {
+        // The validator imports its own block first, but the RPC node is always the
+        // first to hear of it from there, beating the validator's other neighbours:
+        let candidates = [
+            candidate("validator", 0, true),
+            candidate("rpc", 20, false),
+            candidate("neighbour", 60, true),
+        ];
+        let mut scorer = AuthorScorer::new(AuthorWeights::default());
+        assert_eq!(scorer.decide(&candidates), vec![0]);
+
+        // And the validator's win rate keeps it ahead even when it's later to report:
+        for _ in 0..20 {
+            scorer.decide(&candidates);
+        }
+        let scores = scorer.scores(&[
+            candidate("validator", 30, true),
+            candidate("rpc", 20, false),
+        ]);
+        assert!(scores[0] > scores[1], "{:?}", scores);
+    }
+}
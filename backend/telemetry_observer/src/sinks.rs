@@ -0,0 +1,576 @@
+use crate::manifest::ManifestedCsv;
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+/// How many batches of rows can be waiting for a sink before we start dropping them.
+const QUEUE_BATCHES: usize = 256;
+
+/// The columns written to CSV output files.
+pub const CSV_HEADER: &[&str] = &[
+    "timestamp",
+    "chain",
+    "genesis_hash",
+    "node_name",
+    "node_id",
+    "node_implementation",
+    "node_version",
+    "block_number",
+    "block_hash",
+    "propagation_time",
+    "decision_latency_ms",
+    "reports_at_decision",
+    "validator_count",
+    "expected_share",
+    "spec_version",
+];
+
+/// A block, and a node that it was attributed to. Blocks that several nodes tie
+/// for have a row for each of them.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AttributionRow {
+    pub timestamp: u64,
+    pub chain: String,
+    pub genesis_hash: String,
+    pub node_name: String,
+    pub node_id: String,
+    pub node_implementation: String,
+    pub node_version: String,
+    pub block_number: u64,
+    pub block_hash: String,
+    pub propagation_time: u64,
+    pub decision_latency_ms: u64,
+    pub reports_at_decision: u64,
+    pub validator_count: Option<u64>,
+    pub expected_share: Option<f64>,
+    pub spec_version: Option<u32>,
+}
+
+impl AttributionRow {
+    /// The row as CSV fields, in the order of [`CSV_HEADER`].
+    pub fn to_record(&self) -> Vec<String> {
+        let or_empty = |v: Option<String>| v.unwrap_or_default();
+        vec![
+            self.timestamp.to_string(),
+            self.chain.clone(),
+            self.genesis_hash.clone(),
+            self.node_name.clone(),
+            self.node_id.clone(),
+            self.node_implementation.clone(),
+            self.node_version.clone(),
+            self.block_number.to_string(),
+            self.block_hash.clone(),
+            self.propagation_time.to_string(),
+            self.decision_latency_ms.to_string(),
+            self.reports_at_decision.to_string(),
+            or_empty(self.validator_count.map(|n| n.to_string())),
+            or_empty(self.expected_share.map(|s| format!("{:.6}", s))),
+            or_empty(self.spec_version.map(|v| v.to_string())),
+        ]
+    }
+}
+
+/// Somewhere that attribution rows are written to.
+pub trait OutputSink: Send {
+    /// Write out the rows for some newly decided blocks.
+    fn write(&mut self, rows: &[AttributionRow]) -> Result<()>;
+
+    /// Make sure that everything written so far has been saved.
+    fn flush(&mut self) -> Result<()>;
+
+    /// Note how many new best blocks a minute the chain is producing. Only sinks
+    /// that keep metrics have any use for this.
+    fn block_rate(&mut self, _chain: &str, _blocks_per_minute: f64) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl OutputSink for ManifestedCsv {
+    fn write(&mut self, rows: &[AttributionRow]) -> Result<()> {
+        for row in rows {
+            self.write_row(&row.to_record())?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        ManifestedCsv::flush(self)
+    }
+}
+
+/// Appends each row to a file as a line of JSON.
+#[derive(Debug)]
+pub struct JsonLinesSink {
+    writer: BufWriter<File>,
+}
+
+impl JsonLinesSink {
+    pub fn open(path: &Path) -> Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self {
+            writer: BufWriter::new(file),
+        })
+    }
+}
+
+impl OutputSink for JsonLinesSink {
+    fn write(&mut self, rows: &[AttributionRow]) -> Result<()> {
+        for row in rows {
+            serde_json::to_writer(&mut self.writer, row)?;
+            self.writer.write_all(b"\n")?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default)]
+struct NodeMetrics {
+    attributed_blocks: u64,
+    propagation_ms_sum: u64,
+}
+
+/// Keeps per-node attribution metrics in a file in the Prometheus text format, for
+/// node_exporter's textfile collector to pick up. Counts start again from zero
+/// whenever the observer restarts, which Prometheus copes with for counters.
+#[derive(Debug)]
+pub struct PrometheusSink {
+    path: PathBuf,
+    /// Keyed by chain, node name and node ID.
+    nodes: BTreeMap<(String, String, String), NodeMetrics>,
+    /// The last block attributed on each chain.
+    last_block: BTreeMap<String, u64>,
+    blocks_per_minute: BTreeMap<String, f64>,
+}
+
+impl PrometheusSink {
+    pub fn new(path: &Path) -> Self {
+        Self {
+            path: path.to_path_buf(),
+            nodes: BTreeMap::new(),
+            last_block: BTreeMap::new(),
+            blocks_per_minute: BTreeMap::new(),
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str(
+            "# HELP telemetry_observer_attributed_blocks_total Blocks attributed to each node.\n",
+        );
+        out.push_str("# TYPE telemetry_observer_attributed_blocks_total counter\n");
+        for ((chain, node_name, node_id), metrics) in &self.nodes {
+            out.push_str(&format!(
+                "telemetry_observer_attributed_blocks_total{{chain=\"{}\",node_name=\"{}\",node_id=\"{}\"}} {}\n",
+                escape_label(chain),
+                escape_label(node_name),
+                escape_label(node_id),
+                metrics.attributed_blocks
+            ));
+        }
+        out.push_str("# HELP telemetry_observer_winning_propagation_ms_sum Total propagation time of the blocks attributed to each node.\n");
+        out.push_str("# TYPE telemetry_observer_winning_propagation_ms_sum counter\n");
+        for ((chain, node_name, node_id), metrics) in &self.nodes {
+            out.push_str(&format!(
+                "telemetry_observer_winning_propagation_ms_sum{{chain=\"{}\",node_name=\"{}\",node_id=\"{}\"}} {}\n",
+                escape_label(chain),
+                escape_label(node_name),
+                escape_label(node_id),
+                metrics.propagation_ms_sum
+            ));
+        }
+        out.push_str("# HELP telemetry_observer_last_attributed_block The most recent block attributed to any node.\n");
+        out.push_str("# TYPE telemetry_observer_last_attributed_block gauge\n");
+        for (chain, block_number) in &self.last_block {
+            out.push_str(&format!(
+                "telemetry_observer_last_attributed_block{{chain=\"{}\"}} {}\n",
+                escape_label(chain),
+                block_number
+            ));
+        }
+        out.push_str("# HELP telemetry_observer_blocks_per_minute New best blocks a minute, over the last five minutes.\n");
+        out.push_str("# TYPE telemetry_observer_blocks_per_minute gauge\n");
+        for (chain, blocks_per_minute) in &self.blocks_per_minute {
+            out.push_str(&format!(
+                "telemetry_observer_blocks_per_minute{{chain=\"{}\"}} {}\n",
+                escape_label(chain),
+                blocks_per_minute
+            ));
+        }
+        out
+    }
+}
+
+impl OutputSink for PrometheusSink {
+    fn write(&mut self, rows: &[AttributionRow]) -> Result<()> {
+        for row in rows {
+            let key = (
+                row.chain.clone(),
+                row.node_name.clone(),
+                row.node_id.clone(),
+            );
+            let metrics = self.nodes.entry(key).or_default();
+            metrics.attributed_blocks += 1;
+            metrics.propagation_ms_sum += row.propagation_time;
+            let last_block = self.last_block.entry(row.chain.clone()).or_default();
+            *last_block = (*last_block).max(row.block_number);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        // The collector may read the file at any moment, so replace it in one go.
+        let tmp_path = self.path.with_extension("tmp");
+        std::fs::write(&tmp_path, self.render())?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        Ok(())
+    }
+
+    fn block_rate(&mut self, chain: &str, blocks_per_minute: f64) -> Result<()> {
+        self.blocks_per_minute
+            .insert(chain.to_string(), blocks_per_minute);
+        Ok(())
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+/// An extra sink to write to, as given on the command line (eg `jsonl:./data/out.jsonl`).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SinkSpec {
+    pub kind: SinkKind,
+    pub path: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SinkKind {
+    Csv,
+    JsonLines,
+    Prometheus,
+}
+
+impl FromStr for SinkSpec {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        let (kind, path) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow!("Expected <KIND>:<PATH>, eg jsonl:./data/out.jsonl"))?;
+        let kind = match kind {
+            "csv" => SinkKind::Csv,
+            "jsonl" => SinkKind::JsonLines,
+            "prom" => SinkKind::Prometheus,
+            _ => {
+                return Err(anyhow!(
+                    "Unknown sink '{}'; expected 'csv', 'jsonl' or 'prom'",
+                    kind
+                ))
+            }
+        };
+        if path.is_empty() {
+            return Err(anyhow!("No path given for the {} sink", s));
+        }
+        Ok(Self {
+            kind,
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+impl SinkSpec {
+    /// Open the sink. CSV sinks are manifested, as the main output is.
+    pub fn open(&self, config_hash: &str) -> Result<Box<dyn OutputSink>> {
+        Ok(match self.kind {
+            SinkKind::Csv => Box::new(ManifestedCsv::open(&self.path, CSV_HEADER, config_hash)?),
+            SinkKind::JsonLines => Box::new(JsonLinesSink::open(&self.path)?),
+            SinkKind::Prometheus => Box::new(PrometheusSink::new(&self.path)),
+        })
+    }
+
+    pub fn name(&self) -> String {
+        let kind = match self.kind {
+            SinkKind::Csv => "csv",
+            SinkKind::JsonLines => "jsonl",
+            SinkKind::Prometheus => "prom",
+        };
+        format!("{}:{}", kind, self.path.display())
+    }
+}
+
+/// What's handed to each sink's thread.
+enum Batch {
+    Rows(Arc<[AttributionRow]>),
+    BlockRate(Arc<str>, f64),
+}
+
+struct SinkHandle {
+    name: String,
+    queue: Option<SyncSender<Batch>>,
+    thread: Option<JoinHandle<()>>,
+    /// Batches dropped since the sink last kept up.
+    dropped: u64,
+}
+
+/// Hands rows to any number of sinks. Each sink writes from a thread of its own with
+/// a queue in front of it, so a sink that's slow or failing only loses its own rows
+/// (with a warning) rather than holding up collection or the other sinks.
+#[derive(Default)]
+pub struct Sinks {
+    sinks: Vec<SinkHandle>,
+}
+
+impl std::fmt::Debug for Sinks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let names: Vec<_> = self.sinks.iter().map(|s| s.name.as_str()).collect();
+        f.debug_struct("Sinks").field("sinks", &names).finish()
+    }
+}
+
+impl Sinks {
+    pub fn add(&mut self, name: String, mut sink: Box<dyn OutputSink>) -> Result<()> {
+        let (queue, batches) = mpsc::sync_channel::<Batch>(QUEUE_BATCHES);
+        let thread_name = name.clone();
+        let thread = std::thread::Builder::new()
+            .name(format!("sink {}", name))
+            .spawn(move || {
+                for batch in batches {
+                    match batch {
+                        Batch::Rows(rows) => {
+                            if let Err(e) = sink.write(&rows).and_then(|_| sink.flush()) {
+                                warn!(
+                                    "Failed to write {} row(s) to {}: {:#}",
+                                    rows.len(),
+                                    thread_name,
+                                    e
+                                );
+                            }
+                        }
+                        Batch::BlockRate(chain, blocks_per_minute) => {
+                            if let Err(e) = sink
+                                .block_rate(&chain, blocks_per_minute)
+                                .and_then(|_| sink.flush())
+                            {
+                                warn!("Failed to write the block rate to {}: {:#}", thread_name, e);
+                            }
+                        }
+                    }
+                }
+                if let Err(e) = sink.flush() {
+                    warn!("Failed to flush {}: {:#}", thread_name, e);
+                }
+            })?;
+        info!("Writing output to {}", name);
+        self.sinks.push(SinkHandle {
+            name,
+            queue: Some(queue),
+            thread: Some(thread),
+            dropped: 0,
+        });
+        Ok(())
+    }
+
+    /// Queue rows up to be written to every sink.
+    pub fn write(&mut self, rows: Vec<AttributionRow>) {
+        let rows: Arc<[AttributionRow]> = rows.into();
+        for sink in &mut self.sinks {
+            let Some(queue) = &sink.queue else {
+                continue;
+            };
+            match queue.try_send(Batch::Rows(Arc::clone(&rows))) {
+                Ok(()) if sink.dropped > 0 => {
+                    info!(
+                        "{} has caught up, after {} batch(es) of rows were dropped",
+                        sink.name, sink.dropped
+                    );
+                    sink.dropped = 0;
+                }
+                Ok(()) => {}
+                Err(TrySendError::Full(_)) => {
+                    if sink.dropped == 0 {
+                        warn!(
+                            "{} is falling behind; dropping rows until it catches up",
+                            sink.name
+                        );
+                    }
+                    sink.dropped += 1;
+                }
+                Err(TrySendError::Disconnected(_)) => {
+                    warn!(
+                        "{} has stopped; no more rows will be written to it",
+                        sink.name
+                    );
+                    sink.queue = None;
+                }
+            }
+        }
+    }
+
+    /// Queue the chain's block rate up for every sink. Sinks that are behind miss out
+    /// on it, since there'll be another along shortly.
+    pub fn block_rate(&mut self, chain: &str, blocks_per_minute: f64) {
+        let chain: Arc<str> = chain.into();
+        for sink in &self.sinks {
+            if let Some(queue) = &sink.queue {
+                let _ = queue.try_send(Batch::BlockRate(Arc::clone(&chain), blocks_per_minute));
+            }
+        }
+    }
+
+    /// Wait for every sink to write out what it's been given.
+    pub fn close(&mut self) {
+        for sink in &mut self.sinks {
+            sink.queue = None;
+            if let Some(thread) = sink.thread.take() {
+                if thread.join().is_err() {
+                    warn!("{} stopped unexpectedly", sink.name);
+                }
+            }
+        }
+    }
+}
+
+impl Drop for Sinks {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+    use std::time::Duration;
+
+    fn row(block_number: u64, node_name: &str) -> AttributionRow {
+        AttributionRow {
+            timestamp: 1000 + block_number,
+            chain: "Test".to_string(),
+            genesis_hash: "0x01".to_string(),
+            node_name: node_name.to_string(),
+            node_id: format!("{}-id", node_name),
+            node_implementation: String::new(),
+            node_version: String::new(),
+            block_number,
+            block_hash: format!("0x{:x}", block_number),
+            propagation_time: 100,
+            decision_latency_ms: 10,
+            reports_at_decision: 3,
+            validator_count: Some(4),
+            expected_share: Some(0.25),
+            spec_version: None,
+        }
+    }
+
+    /// Keeps rows in memory, or fails to. Writes wait for the gate to be open.
+    struct TestSink {
+        rows: Arc<Mutex<Vec<AttributionRow>>>,
+        fail: bool,
+        gate: Arc<Mutex<()>>,
+    }
+
+    impl OutputSink for TestSink {
+        fn write(&mut self, rows: &[AttributionRow]) -> Result<()> {
+            let _open = self.gate.lock().unwrap();
+            if self.fail {
+                return Err(anyhow!("broken"));
+            }
+            self.rows.lock().unwrap().extend_from_slice(rows);
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn broken_or_stuck_sinks_dont_hold_up_others() {
+        let healthy = Arc::new(Mutex::new(vec![]));
+        let stuck = Arc::new(Mutex::new(vec![]));
+        let open = Arc::new(Mutex::new(()));
+        let closed = Arc::new(Mutex::new(()));
+        let closed_guard = closed.lock().unwrap();
+
+        let mut sinks = Sinks::default();
+        let test_sink = |rows: &Arc<Mutex<_>>, fail, gate: &Arc<Mutex<()>>| {
+            Box::new(TestSink {
+                rows: Arc::clone(rows),
+                fail,
+                gate: Arc::clone(gate),
+            })
+        };
+        sinks
+            .add("healthy".into(), test_sink(&healthy, false, &open))
+            .unwrap();
+        sinks
+            .add("broken".into(), test_sink(&healthy, true, &open))
+            .unwrap();
+        sinks
+            .add("stuck".into(), test_sink(&stuck, false, &closed))
+            .unwrap();
+
+        let batches = QUEUE_BATCHES + 10;
+        for n in 0..batches {
+            sinks.write(vec![row(n as u64, "alice")]);
+            // Give the healthy sink time to keep up:
+            while healthy.lock().unwrap().len() <= n {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+        assert_eq!(stuck.lock().unwrap().len(), 0);
+
+        // Once it's unstuck, it writes what it had queued up and nothing more:
+        drop(closed_guard);
+        sinks.close();
+        assert_eq!(healthy.lock().unwrap().len(), batches);
+        let stuck = stuck.lock().unwrap().len();
+        assert!(
+            (QUEUE_BATCHES..=QUEUE_BATCHES + 1).contains(&stuck),
+            "stuck sink wrote {} rows",
+            stuck
+        );
+    }
+
+    #[test]
+    fn prometheus_sink_counts_attributions() {
+        let mut sink = PrometheusSink::new(Path::new("unused.prom"));
+        sink.write(&[row(1, "alice"), row(2, "alice"), row(2, "b\"ob")])
+            .unwrap();
+        let rendered = sink.render();
+        assert!(rendered.contains(
+            "telemetry_observer_attributed_blocks_total{chain=\"Test\",node_name=\"alice\",node_id=\"alice-id\"} 2\n"
+        ));
+        assert!(rendered.contains("node_name=\"b\\\"ob\""));
+        assert!(rendered.contains("telemetry_observer_last_attributed_block{chain=\"Test\"} 2\n"));
+
+        sink.block_rate("Test", 9.6).unwrap();
+        assert!(sink
+            .render()
+            .contains("telemetry_observer_blocks_per_minute{chain=\"Test\"} 9.6\n"));
+    }
+
+    #[test]
+    fn parses_sink_specs() {
+        let spec: SinkSpec = "jsonl:./data/out.jsonl".parse().unwrap();
+        assert_eq!(spec.kind, SinkKind::JsonLines);
+        assert_eq!(spec.path, PathBuf::from("./data/out.jsonl"));
+        assert!("postgres:./x".parse::<SinkSpec>().is_err());
+        assert!("csv:".parse::<SinkSpec>().is_err());
+    }
+}
@@ -0,0 +1,195 @@
+use crate::state::{BlockInfo, Blocks};
+use anyhow::{Context, Result};
+use log::info;
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Undecided blocks that have been moved out of memory to keep within the memory
+/// budget, each in a file of its own. A stub of each (one without the nodes that
+/// imported it, which is where the memory goes) is kept, so that we know when they're
+/// due to be decided and can load them back in for it.
+#[derive(Debug)]
+pub struct SpillStore {
+    dir: PathBuf,
+    stubs: HashMap<String, BlockInfo>,
+}
+
+impl SpillStore {
+    /// Blocks are only persisted once they've been decided, so anything spilled by an
+    /// earlier run is of no use to this one and is cleared out. Only the files that we
+    /// would have written are removed, in case the directory is shared with anything else.
+    pub fn open(dir: &Path) -> Result<Self> {
+        fs::create_dir_all(dir).with_context(|| format!("creating {:?}", dir))?;
+        for entry in fs::read_dir(dir)? {
+            let path = entry?.path();
+            let is_spilled_block = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("0x") && name.ends_with(".json"));
+            if is_spilled_block && path.is_file() {
+                fs::remove_file(&path).with_context(|| format!("clearing {:?}", path))?;
+            }
+        }
+        Ok(Self {
+            dir: dir.to_path_buf(),
+            stubs: HashMap::new(),
+        })
+    }
+
+    fn path(&self, block_hash: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", block_hash))
+    }
+
+    pub fn spill(&mut self, block_hash: String, block: &BlockInfo) -> Result<()> {
+        fs::write(self.path(&block_hash), serde_json::to_vec(block)?)?;
+        let stub = BlockInfo {
+            reporters: vec![],
+            candidates: vec![],
+            ..block.clone()
+        };
+        self.stubs.insert(block_hash, stub);
+        Ok(())
+    }
+
+    pub fn contains(&self, block_hash: &str) -> bool {
+        self.stubs.contains_key(block_hash)
+    }
+
+    /// Take a block back out of the store.
+    pub fn load(&mut self, block_hash: &str) -> Result<Option<BlockInfo>> {
+        if self.stubs.remove(block_hash).is_none() {
+            return Ok(None);
+        }
+        let path = self.path(block_hash);
+        let block = serde_json::from_slice(&fs::read(&path)?)?;
+        fs::remove_file(&path)?;
+        Ok(Some(block))
+    }
+
+    /// The blocks that are ready to be decided, and so need loading back in.
+    pub fn due(&self, now: u64, max_block: u64) -> Vec<String> {
+        self.stubs
+            .iter()
+            .filter(|(_, stub)| stub.ready_to_decide(now, max_block))
+            .map(|(block_hash, _)| block_hash.clone())
+            .collect()
+    }
+
+    /// Is any spilled block at or below `block_number`? Everything in here is undecided.
+    pub fn any_up_to(&self, block_number: u64) -> bool {
+        self.stubs
+            .values()
+            .any(|stub| stub.block_number <= block_number)
+    }
+
+    pub fn len(&self) -> usize {
+        self.stubs.len()
+    }
+}
+
+/// Bring the blocks in memory down to within `budget` bytes. Decided blocks go first,
+/// oldest first; they've already been saved, and are only kept so that late imports of
+/// them aren't counted again. If that's not enough, undecided blocks are spilled to
+/// disk, again oldest first, rather than thrown away.
+pub fn keep_within(blocks: &mut Blocks, budget: usize, spilled: &mut SpillStore) -> Result<()> {
+    let mut size: usize = blocks.iter().map(|(k, v)| entry_size(k, v)).sum();
+    if size <= budget {
+        return Ok(());
+    }
+
+    let mut coldest: Vec<_> = blocks
+        .iter()
+        .map(|(k, v)| (!v.output, v.block_number, k.clone()))
+        .collect();
+    coldest.sort();
+
+    let mut newly_spilled = 0;
+    for (undecided, _, block_hash) in coldest {
+        if size <= budget {
+            break;
+        }
+        let block = blocks.remove(&block_hash).expect("block was listed above");
+        size -= entry_size(&block_hash, &block);
+        if undecided {
+            spilled.spill(block_hash, &block)?;
+            newly_spilled += 1;
+        }
+    }
+    if newly_spilled > 0 {
+        info!(
+            "Over the memory budget; spilled {} undecided blocks to disk ({} spilled in all)",
+            newly_spilled,
+            spilled.len()
+        );
+    }
+    Ok(())
+}
+
+fn entry_size(block_hash: &str, block: &BlockInfo) -> usize {
+    block_hash.len() + block.estimated_size()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::{BlockReporter, Candidate};
+
+    fn block(block_number: u64, output: bool) -> BlockInfo {
+        BlockInfo {
+            block_number,
+            lowest_prop_time: 100,
+            reporters: vec![],
+            candidates: vec![Candidate {
+                reporter: BlockReporter {
+                    node_idx: 1,
+                    node_name: "alice".to_string(),
+                    node_id: "a-id".to_string(),
+                    implementation: String::new(),
+                    version: String::new(),
+                    timestamp: 0,
+                },
+                propagation_time: 100,
+                validator: false,
+            }],
+            first_seen: block_number,
+            first_seen_ms: block_number * 1000,
+            report_count: 1,
+            output,
+        }
+    }
+
+    #[test]
+    fn decided_blocks_go_before_undecided_ones_are_spilled() {
+        let dir = std::env::temp_dir().join(format!("observer-spill-{}", std::process::id()));
+        let mut spilled = SpillStore::open(&dir).unwrap();
+
+        let mut blocks: Blocks = (0..10)
+            .map(|n| (format!("0x{:x}", n), block(n, n % 2 == 0)))
+            .collect();
+        let each = entry_size("0x0", &blocks["0x0"]);
+        keep_within(&mut blocks, each * 3, &mut spilled).unwrap();
+
+        // The five decided blocks are dropped, then the two oldest undecided ones spilled:
+        let mut kept: Vec<_> = blocks.values().map(|b| b.block_number).collect();
+        kept.sort();
+        assert_eq!(kept, vec![5, 7, 9]);
+        assert_eq!(spilled.len(), 2);
+        assert!(spilled.contains("0x1") && spilled.contains("0x3"));
+        assert!(spilled.any_up_to(3));
+        assert!(!spilled.any_up_to(0));
+
+        // Only blocks that are old enough are due, and they come back whole:
+        assert_eq!(spilled.due(3, 3), vec!["0x1".to_string()]);
+        let block = spilled.load("0x1").unwrap().unwrap();
+        assert_eq!(block.candidates[0].reporter.node_name, "alice");
+        assert!(spilled.load("0x1").unwrap().is_none());
+        assert_eq!(spilled.len(), 1);
+
+        // Nothing spilled last time is kept:
+        let spilled = SpillStore::open(&dir).unwrap();
+        assert_eq!(spilled.len(), 0);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
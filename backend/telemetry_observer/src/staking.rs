@@ -0,0 +1,52 @@
+use crate::rpc::RpcClient;
+use anyhow::Result;
+use log::{info, warn};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// How often to refresh staking information from the RPC node.
+const POLL_INTERVAL: Duration = Duration::from_secs(600);
+
+/// What we know about the active validator set.
+#[derive(Debug, Clone, Copy)]
+pub struct StakingInfo {
+    pub validator_count: u64,
+}
+
+impl StakingInfo {
+    /// The share of blocks that each validator is expected to author. Block production
+    /// slots are assigned evenly across the active set, so this is the same for each.
+    pub fn expected_share(&self) -> f64 {
+        if self.validator_count == 0 {
+            0.0
+        } else {
+            1.0 / self.validator_count as f64
+        }
+    }
+}
+
+/// Keep `staking` up to date by polling the given RPC node in the background.
+pub fn spawn_poller(rpc_url: &str, staking: Arc<Mutex<Option<StakingInfo>>>) -> Result<()> {
+    let mut client = RpcClient::new(rpc_url)?;
+    tokio::spawn(async move {
+        loop {
+            match client.validator_count().await {
+                Ok(validator_count) => {
+                    let info = StakingInfo { validator_count };
+                    info!(
+                        "Active validator count is {}; expected share per validator is {:.5}",
+                        validator_count,
+                        info.expected_share()
+                    );
+                    *staking.lock().await = Some(info);
+                }
+                Err(e) => {
+                    warn!("Failed to fetch staking information: {}", e);
+                }
+            }
+            tokio::time::sleep(POLL_INTERVAL).await;
+        }
+    });
+    Ok(())
+}
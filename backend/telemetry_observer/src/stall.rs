@@ -0,0 +1,185 @@
+use crate::alerts::Alert;
+use std::collections::HashMap;
+
+/// A node that has stopped sending us anything for this long (in seconds) is no
+/// longer considered to be "up", and so can't be stalled.
+const ACTIVE_WITHIN_SECS: u64 = 300;
+
+/// Don't look for stalled validators more often than this (in seconds).
+const CHECK_INTERVAL_SECS: u64 = 60;
+
+#[derive(Debug)]
+struct ValidatorActivity {
+    node_name: String,
+    node_id: String,
+    first_seen: u64,
+    last_seen: u64,
+    last_attributed: Option<u64>,
+    alerted: bool,
+}
+
+/// Spots validators that keep reporting telemetry but are never attributed a
+/// block, while other validators are. Uptime checks won't catch a node that is
+/// up but not authoring.
+///
+/// Every other validator counts as a peer, whatever its stake: BABE and Aura give
+/// each validator in the active set the same share of slots, so stake makes no
+/// difference to how often one should be authoring.
+#[derive(Debug)]
+pub struct StallDetector {
+    window_secs: u64,
+    /// Keyed by network ID, so that reconnecting doesn't reset a validator's activity.
+    validators: HashMap<String, ValidatorActivity>,
+    last_check: u64,
+}
+
+impl StallDetector {
+    pub fn new(window_secs: u64) -> Self {
+        Self {
+            window_secs,
+            validators: HashMap::new(),
+            last_check: 0,
+        }
+    }
+
+    /// Note that a node has reported telemetry. Only validators are tracked.
+    pub fn saw_node(&mut self, node_name: &str, node_id: &str, is_validator: bool, now: u64) {
+        if !is_validator {
+            self.validators.remove(node_id);
+            return;
+        }
+        let activity = self
+            .validators
+            .entry(node_id.to_string())
+            .or_insert_with(|| ValidatorActivity {
+                node_name: node_name.to_string(),
+                node_id: node_id.to_string(),
+                first_seen: now,
+                last_seen: now,
+                last_attributed: None,
+                alerted: false,
+            });
+        activity.last_seen = now;
+    }
+
+    /// Note that a block was attributed to a node.
+    pub fn attributed(&mut self, node_id: &str, now: u64) {
+        if let Some(activity) = self.validators.get_mut(node_id) {
+            activity.last_attributed = Some(now);
+            activity.alerted = false;
+        }
+    }
+
+    /// Return an alert for each validator that has newly stalled.
+    pub fn check(&mut self, now: u64) -> Vec<Alert> {
+        if now.saturating_sub(self.last_check) < CHECK_INTERVAL_SECS {
+            return vec![];
+        }
+        self.last_check = now;
+
+        let window_secs = self.window_secs;
+        let attributed_recently = |a: &ValidatorActivity| {
+            a.last_attributed
+                .is_some_and(|t| now.saturating_sub(t) < window_secs)
+        };
+
+        // If nobody is being attributed blocks, the problem is elsewhere (or we're not seeing
+        // enough of the network to say), so don't single out any one validator.
+        let producing = self
+            .validators
+            .values()
+            .filter(|a| attributed_recently(a))
+            .count();
+        if producing == 0 {
+            return vec![];
+        }
+
+        let mut alerts = vec![];
+        for activity in self.validators.values_mut() {
+            let is_active = now.saturating_sub(activity.last_seen) <= ACTIVE_WITHIN_SECS;
+            let observed_long_enough = now.saturating_sub(activity.first_seen) >= window_secs;
+            if activity.alerted
+                || !is_active
+                || !observed_long_enough
+                || attributed_recently(activity)
+            {
+                continue;
+            }
+
+            activity.alerted = true;
+            alerts.push(Alert {
+                timestamp: now,
+                kind: "validator_stall",
+                node_name: Some(activity.node_name.clone()),
+                node_id: Some(activity.node_id.clone()),
+                message: format!(
+                    "Validator {} is reporting telemetry but has not been attributed a block in {:.1} hours, while {} other validators have",
+                    activity.node_name,
+                    window_secs as f64 / 3600.0,
+                    producing
+                ),
+                details: None,
+            });
+        }
+        alerts
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const HOUR: u64 = 3600;
+
+    #[test]
+    fn alerts_once_for_validator_without_blocks() {
+        let mut detector = StallDetector::new(HOUR);
+        detector.saw_node("producing", "a", true, 0);
+        detector.saw_node("stalled", "b", true, 0);
+        detector.saw_node("not a validator", "c", false, 0);
+
+        detector.saw_node("producing", "a", true, HOUR + 10);
+        detector.saw_node("stalled", "b", true, HOUR + 10);
+        detector.saw_node("not a validator", "c", false, HOUR + 10);
+        detector.attributed("a", HOUR + 10);
+
+        let alerts = detector.check(HOUR + 10);
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(alerts[0].node_name.as_deref(), Some("stalled"));
+
+        // Don't keep alerting about the same stall:
+        assert!(detector.check(HOUR + 10 + CHECK_INTERVAL_SECS).is_empty());
+    }
+
+    #[test]
+    fn no_alerts_if_nobody_is_producing() {
+        let mut detector = StallDetector::new(HOUR);
+        detector.saw_node("a", "a", true, 0);
+        detector.saw_node("b", "b", true, 0);
+        detector.saw_node("a", "a", true, 2 * HOUR);
+        detector.saw_node("b", "b", true, 2 * HOUR);
+
+        assert!(detector.check(2 * HOUR).is_empty());
+    }
+
+    #[test]
+    fn no_alerts_for_validators_that_are_down() {
+        let mut detector = StallDetector::new(HOUR);
+        detector.saw_node("producing", "a", true, 0);
+        detector.saw_node("down", "b", true, 0);
+        detector.saw_node("producing", "a", true, 2 * HOUR);
+        detector.attributed("a", 2 * HOUR);
+
+        assert!(detector.check(2 * HOUR).is_empty());
+    }
+
+    #[test]
+    fn copes_with_the_clock_going_backwards() {
+        let mut detector = StallDetector::new(HOUR);
+        detector.saw_node("producing", "a", true, 0);
+        detector.attributed("a", 3 * HOUR);
+        detector.saw_node("producing", "a", true, 2 * HOUR);
+
+        assert!(detector.check(2 * HOUR).is_empty());
+    }
+}
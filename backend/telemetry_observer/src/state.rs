@@ -0,0 +1,280 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// How many of the most recent blocks to keep track of.
+pub const MAX_TRACKED_BLOCKS: usize = 100;
+
+/// The network ID given to nodes that don't report one.
+pub const UNKNOWN_NODE_ID: &str = "unknown";
+
+/// Blocks that we're tracking, keyed by block hash.
+pub type Blocks = HashMap<String, BlockInfo>;
+
+/// Nodes, keyed by their network ID. The feed refers to nodes by an index, but it
+/// reuses indices and starts them again whenever the core restarts, so indices are
+/// only kept as aliases for the nodes that are connected right now, and never saved.
+#[derive(Debug, Default)]
+pub struct Nodes {
+    nodes: HashMap<String, NodeInfo>,
+    /// Feed index to the key of the node in `nodes`.
+    indices: HashMap<String, String>,
+}
+
+impl Nodes {
+    /// Nodes as they were saved, under whatever keys they were saved with. Older
+    /// observers saved them by feed index, so the same node may appear more than once;
+    /// those records are merged.
+    pub fn from_records(records: HashMap<String, NodeInfo>) -> Self {
+        let mut records: Vec<_> = records.into_iter().collect();
+        records.sort_by(|a, b| a.0.cmp(&b.0));
+
+        let mut nodes: HashMap<String, NodeInfo> = HashMap::new();
+        for (_, node) in records {
+            match nodes.get_mut(&node.key()) {
+                Some(existing) => existing.merge(node),
+                None => {
+                    nodes.insert(node.key(), node);
+                }
+            }
+        }
+        Self {
+            nodes,
+            indices: HashMap::new(),
+        }
+    }
+
+    /// Every node we know of, keyed by network ID, for saving.
+    pub fn records(&self) -> &HashMap<String, NodeInfo> {
+        &self.nodes
+    }
+
+    /// The node currently at this index in the feed.
+    pub fn get(&self, node_idx: &str) -> Option<&NodeInfo> {
+        self.nodes.get(self.indices.get(node_idx)?)
+    }
+
+    /// A node has connected (or reconnected) at this index in the feed.
+    pub fn insert(&mut self, node_idx: String, node: NodeInfo) {
+        let key = node.key();
+        self.nodes.insert(key.clone(), node);
+        self.indices.insert(node_idx, key);
+    }
+
+    /// The node at this index in the feed has gone away. Its details are kept, but
+    /// the index will no longer find it.
+    pub fn remove(&mut self, node_idx: &str) -> Option<&NodeInfo> {
+        let key = self.indices.remove(node_idx)?;
+        self.nodes.get(&key)
+    }
+
+    /// The number of nodes currently connected to the feed.
+    pub fn connected(&self) -> usize {
+        self.indices.len()
+    }
+
+    /// Forget which nodes are at which feed indices; the feed tells us about every
+    /// node again whenever we subscribe.
+    pub fn forget_indices(&mut self) {
+        self.indices.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.nodes.clear();
+        self.indices.clear();
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NodeInfo {
+    pub name: String,
+    pub node_id: String,
+    #[serde(default)]
+    pub validator: Option<String>,
+    #[serde(default)]
+    pub implementation: String,
+    #[serde(default)]
+    pub version: String,
+}
+
+impl NodeInfo {
+    /// What the node is keyed by in [`Nodes`]. Nodes without a network ID can only
+    /// be told apart by name.
+    pub fn key(&self) -> String {
+        if self.node_id.is_empty() || self.node_id == UNKNOWN_NODE_ID {
+            format!("name:{}", self.name)
+        } else {
+            self.node_id.clone()
+        }
+    }
+
+    /// Fill in anything this record is missing from another one for the same node.
+    fn merge(&mut self, other: NodeInfo) {
+        if self.validator.is_none() {
+            self.validator = other.validator;
+        }
+        if self.implementation.is_empty() {
+            self.implementation = other.implementation;
+        }
+        if self.version.is_empty() {
+            self.version = other.version;
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockReporter {
+    pub node_idx: u64,
+    pub node_name: String,
+    pub node_id: String,
+    #[serde(default)]
+    pub implementation: String,
+    #[serde(default)]
+    pub version: String,
+    pub timestamp: u64,
+}
+
+/// A node that imported a block, and so might have authored it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Candidate {
+    pub reporter: BlockReporter,
+    pub propagation_time: u64,
+    #[serde(default)]
+    pub validator: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BlockInfo {
+    pub block_number: u64,
+    /// The lowest propagation time of any import after the first.
+    pub lowest_prop_time: u64,
+    /// The node(s) that the block was attributed to, once it's been decided.
+    pub reporters: Vec<BlockReporter>,
+    /// Every node that's imported the block so far, in the order they did. These are
+    /// let go of once the block has been decided.
+    #[serde(default)]
+    pub candidates: Vec<Candidate>,
+    pub first_seen: u64,
+    /// As `first_seen`, but in milliseconds. Missing from state saved by older versions.
+    #[serde(default)]
+    pub first_seen_ms: u64,
+    pub report_count: u64,
+    pub output: bool,
+}
+
+impl BlockInfo {
+    /// Whether the block is yet to be decided, but has had enough reports, or has waited
+    /// long enough (or been built on enough), to be decided now.
+    pub fn ready_to_decide(&self, now: u64, max_block: u64) -> bool {
+        !self.output
+            && (self.report_count >= 3
+                || now.saturating_sub(self.first_seen) > 3
+                || self.block_number < max_block.saturating_sub(1))
+    }
+
+    /// Roughly how much memory the block takes up, for keeping within a memory budget.
+    pub fn estimated_size(&self) -> usize {
+        let reporter_size = |r: &BlockReporter| {
+            r.node_name.len() + r.node_id.len() + r.implementation.len() + r.version.len()
+        };
+        let reporters: usize = self
+            .reporters
+            .iter()
+            .map(|r| std::mem::size_of::<BlockReporter>() + reporter_size(r))
+            .sum();
+        let candidates: usize = self
+            .candidates
+            .iter()
+            .map(|c| std::mem::size_of::<Candidate>() + reporter_size(&c.reporter))
+            .sum();
+        std::mem::size_of::<Self>() + reporters + candidates
+    }
+}
+
+/// A change to the observer's state. These are handed to the state store as they
+/// happen, so that it can persist them.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum StateEvent {
+    NodeAdded {
+        node_idx: String,
+        node: NodeInfo,
+    },
+    NodeRemoved {
+        node_idx: String,
+    },
+    BlockDecided {
+        block_hash: String,
+        block: BlockInfo,
+    },
+}
+
+impl StateEvent {
+    pub fn apply(self, nodes: &mut Nodes, blocks: &mut Blocks) {
+        match self {
+            StateEvent::NodeAdded { node_idx, node } => {
+                nodes.insert(node_idx, node);
+            }
+            StateEvent::NodeRemoved { node_idx } => {
+                nodes.remove(&node_idx);
+            }
+            StateEvent::BlockDecided { block_hash, block } => {
+                blocks.insert(block_hash, block);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn node(name: &str, node_id: &str) -> NodeInfo {
+        NodeInfo {
+            name: name.to_string(),
+            node_id: node_id.to_string(),
+            validator: None,
+            implementation: String::new(),
+            version: String::new(),
+        }
+    }
+
+    #[test]
+    fn merges_nodes_saved_under_several_indices() {
+        let mut records = HashMap::new();
+        records.insert("1".to_string(), node("alice", "a-id"));
+        records.insert(
+            "7".to_string(),
+            NodeInfo {
+                validator: Some("5Alice".to_string()),
+                version: "1.0.0".to_string(),
+                ..node("alice", "a-id")
+            },
+        );
+        records.insert("2".to_string(), node("bob", UNKNOWN_NODE_ID));
+        records.insert("3".to_string(), node("charlie", UNKNOWN_NODE_ID));
+
+        let nodes = Nodes::from_records(records);
+        assert_eq!(nodes.records().len(), 3);
+        let alice = &nodes.records()["a-id"];
+        assert_eq!(alice.validator.as_deref(), Some("5Alice"));
+        assert_eq!(alice.version, "1.0.0");
+        // Indices from an earlier run mean nothing now:
+        assert!(nodes.get("1").is_none());
+        assert_eq!(nodes.connected(), 0);
+    }
+
+    #[test]
+    fn feed_indices_are_aliases() {
+        let mut nodes = Nodes::default();
+        nodes.insert("1".to_string(), node("alice", "a-id"));
+        assert_eq!(nodes.get("1").unwrap().name, "alice");
+
+        // Alice reconnects at another index, and someone else takes her old one:
+        assert_eq!(nodes.remove("1").unwrap().name, "alice");
+        nodes.insert("2".to_string(), node("alice", "a-id"));
+        nodes.insert("1".to_string(), node("bob", "b-id"));
+        assert_eq!(nodes.get("1").unwrap().name, "bob");
+        assert_eq!(nodes.get("2").unwrap().name, "alice");
+        assert_eq!(nodes.records().len(), 2);
+    }
+}
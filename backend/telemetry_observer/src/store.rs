@@ -0,0 +1,320 @@
+use crate::journal::{self, Journal};
+use crate::schema::{self, RecordKind, SCHEMA_VERSION};
+use crate::state::{BlockInfo, Blocks, NodeInfo, Nodes, StateEvent, MAX_TRACKED_BLOCKS};
+use anyhow::{anyhow, Result};
+use log::info;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// How often (in seconds) the JSON store writes out a snapshot and starts a new journal.
+const COMPACT_INTERVAL_SECS: u64 = 3600;
+
+/// Which [`StateStore`] to persist state with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateBackend {
+    Json,
+    Sled,
+}
+
+impl FromStr for StateBackend {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(StateBackend::Json),
+            "sled" => Ok(StateBackend::Sled),
+            _ => Err(anyhow!(
+                "Unknown state backend '{}'; expected 'json' or 'sled'",
+                s
+            )),
+        }
+    }
+}
+
+/// Somewhere to persist the observer's state between runs.
+pub trait StateStore: Send + std::fmt::Debug {
+    /// Load the state saved by an earlier run. This is called once, at startup.
+    fn load(&mut self) -> Result<(Nodes, Blocks)>;
+
+    /// Persist a single change to the state.
+    fn record(&mut self, timestamp: u64, event: &StateEvent) -> Result<()>;
+
+    /// Whether [`StateStore::maybe_compact`] has anything to do, so that the full state
+    /// isn't gathered up each time for nothing.
+    fn should_compact(&self, now: u64) -> bool;
+
+    /// Called every so often with the full current state, so that the store can
+    /// tidy up after itself.
+    fn maybe_compact(&mut self, now: u64, nodes: &Nodes, blocks: &Blocks) -> Result<()>;
+
+    /// Make sure that everything recorded so far has made it to disk.
+    fn flush(&mut self) -> Result<()>;
+}
+
+/// A journal entry, as written by [`JsonStore`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct JournalEntry {
+    schema_version: u32,
+    timestamp: u64,
+    #[serde(flatten)]
+    event: StateEvent,
+}
+
+/// Keeps state in JSON snapshot files, with changes since the last snapshot
+/// appended to a journal. This is simple and easy to inspect, but every snapshot
+/// rewrites everything, so it's best suited to smaller amounts of state.
+#[derive(Debug)]
+pub struct JsonStore {
+    nodes_file: PathBuf,
+    blocks_file: PathBuf,
+    journal: Journal,
+    replay: Vec<Value>,
+}
+
+impl JsonStore {
+    pub fn open(
+        nodes_file: &Path,
+        blocks_file: &Path,
+        journal_file: &Path,
+        now: u64,
+    ) -> Result<Self> {
+        let (journal, replay) = Journal::open(journal_file, now)?;
+        Ok(Self {
+            nodes_file: nodes_file.to_path_buf(),
+            blocks_file: blocks_file.to_path_buf(),
+            journal,
+            replay,
+        })
+    }
+}
+
+impl StateStore for JsonStore {
+    fn load(&mut self) -> Result<(Nodes, Blocks)> {
+        let mut nodes = Nodes::from_records(read_snapshot(&self.nodes_file, RecordKind::Node)?);
+        let mut blocks: Blocks = read_snapshot(&self.blocks_file, RecordKind::Block)?;
+
+        // Bring the snapshots up to date with anything that happened since they were written
+        for entry in self.replay.drain(..) {
+            decode_journal_entry(entry)?
+                .event
+                .apply(&mut nodes, &mut blocks);
+        }
+        nodes.forget_indices();
+        Ok((nodes, blocks))
+    }
+
+    fn record(&mut self, timestamp: u64, event: &StateEvent) -> Result<()> {
+        self.journal.append(&JournalEntry {
+            schema_version: SCHEMA_VERSION,
+            timestamp,
+            event: event.clone(),
+        })
+    }
+
+    fn should_compact(&self, now: u64) -> bool {
+        self.journal.should_compact(now, COMPACT_INTERVAL_SECS)
+    }
+
+    fn maybe_compact(&mut self, now: u64, nodes: &Nodes, blocks: &Blocks) -> Result<()> {
+        if !self.should_compact(now) {
+            return Ok(());
+        }
+        journal::write_snapshot(&self.nodes_file, &schema::encode_map(nodes.records()))?;
+        journal::write_snapshot(&self.blocks_file, &schema::encode_map(blocks))?;
+        self.journal.compact(now)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.journal.sync()
+    }
+}
+
+/// Read a snapshot, upgrading it if it was written by an older observer. Rather than
+/// start afresh if the snapshot can't be read, we bail, so that nobody's accumulated
+/// state is thrown away without them noticing.
+fn read_snapshot<T: serde::de::DeserializeOwned>(
+    path: &Path,
+    kind: RecordKind,
+) -> Result<HashMap<String, T>> {
+    if !path.exists() {
+        return Ok(HashMap::new());
+    }
+    let reader = BufReader::new(File::open(path)?);
+    let document = serde_json::from_reader(reader)
+        .map_err(|e| anyhow!("Failed to read state from {:?}: {}", path, e))?;
+    schema::decode_map(kind, document)
+        .map_err(|e| anyhow!("Failed to load state from {:?}: {}", path, e))
+}
+
+/// Upgrade the record in a journal entry, if it was written by an older observer.
+fn decode_journal_entry(entry: Value) -> Result<JournalEntry> {
+    let Value::Object(mut entry) = entry else {
+        return Err(anyhow!("Journal entry is not an object: {}", entry));
+    };
+    let from = schema::schema_version(&entry)?;
+    for (field, kind) in [("node", RecordKind::Node), ("block", RecordKind::Block)] {
+        if let Some(record) = entry.remove(field) {
+            entry.insert(field.to_string(), schema::migrate(kind, record, from)?);
+        }
+    }
+    entry.insert("schema_version".to_string(), SCHEMA_VERSION.into());
+    Ok(serde_json::from_value(Value::Object(entry))?)
+}
+
+/// Keeps state in an embedded sled database. Each change is written as it happens,
+/// so nothing ever needs rewriting in full, which makes this the better choice once
+/// there's a lot of state.
+///
+/// As in memory, nodes are keyed by their network ID. Decided blocks are keyed by
+/// hash and kept indefinitely; only the most recent are loaded at startup.
+#[derive(Debug)]
+pub struct SledStore {
+    db: sled::Db,
+    /// Network ID to [`NodeInfo`].
+    nodes: sled::Tree,
+    /// Block hash to [`BlockInfo`].
+    blocks: sled::Tree,
+}
+
+impl SledStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let db = sled::open(path)?;
+        info!("Opened sled state database at {:?}", path);
+        Self::from_db(db)
+    }
+
+    fn from_db(db: sled::Db) -> Result<Self> {
+        // Older observers kept track of feed indices here, but they don't outlive a run.
+        db.drop_tree("node_indices")?;
+        Ok(Self {
+            nodes: db.open_tree("nodes")?,
+            blocks: db.open_tree("blocks")?,
+            db,
+        })
+    }
+}
+
+impl StateStore for SledStore {
+    fn load(&mut self) -> Result<(Nodes, Blocks)> {
+        let mut records = HashMap::new();
+        for entry in self.nodes.iter() {
+            let (node_id, node) = entry?;
+            let node: NodeInfo = schema::decode_record(RecordKind::Node, &node)?;
+            records.insert(String::from_utf8_lossy(&node_id).into_owned(), node);
+        }
+        let nodes = Nodes::from_records(records);
+
+        let mut blocks = vec![];
+        for entry in self.blocks.iter() {
+            let (block_hash, block) = entry?;
+            let block: BlockInfo = schema::decode_record(RecordKind::Block, &block)?;
+            blocks.push((String::from_utf8_lossy(&block_hash).into_owned(), block));
+        }
+        blocks.sort_by_key(|(_, block)| std::cmp::Reverse(block.block_number));
+        blocks.truncate(MAX_TRACKED_BLOCKS);
+
+        Ok((nodes, blocks.into_iter().collect()))
+    }
+
+    fn record(&mut self, _timestamp: u64, event: &StateEvent) -> Result<()> {
+        match event {
+            StateEvent::NodeAdded { node, .. } => {
+                self.nodes
+                    .insert(node.key().as_bytes(), schema::encode_record(node)?)?;
+            }
+            // Nodes' details are kept after they disconnect.
+            StateEvent::NodeRemoved { .. } => {}
+            StateEvent::BlockDecided { block_hash, block } => {
+                self.blocks
+                    .insert(block_hash.as_bytes(), schema::encode_record(block)?)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn should_compact(&self, _now: u64) -> bool {
+        false
+    }
+
+    fn maybe_compact(&mut self, _now: u64, _nodes: &Nodes, _blocks: &Blocks) -> Result<()> {
+        // sled flushes to disk and compacts itself in the background.
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.db.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn node(name: &str) -> NodeInfo {
+        NodeInfo {
+            name: name.to_string(),
+            node_id: format!("{}-id", name),
+            validator: None,
+            implementation: String::new(),
+            version: String::new(),
+        }
+    }
+
+    fn block(block_number: u64) -> BlockInfo {
+        BlockInfo {
+            block_number,
+            lowest_prop_time: 100,
+            reporters: vec![],
+            candidates: vec![],
+            first_seen: 0,
+            first_seen_ms: 0,
+            report_count: 1,
+            output: true,
+        }
+    }
+
+    #[test]
+    fn sled_store_round_trips_state() {
+        let db = sled::Config::new().temporary(true).open().unwrap();
+        let mut store = SledStore::from_db(db).unwrap();
+
+        let events = [
+            StateEvent::NodeAdded {
+                node_idx: "1".into(),
+                node: node("a"),
+            },
+            StateEvent::NodeAdded {
+                node_idx: "2".into(),
+                node: node("b"),
+            },
+            StateEvent::NodeRemoved {
+                node_idx: "1".into(),
+            },
+        ];
+        for event in &events {
+            store.record(0, event).unwrap();
+        }
+        for n in 0..MAX_TRACKED_BLOCKS as u64 + 10 {
+            let event = StateEvent::BlockDecided {
+                block_hash: format!("0x{:x}", n),
+                block: block(n),
+            };
+            store.record(0, &event).unwrap();
+        }
+
+        let (nodes, blocks) = store.load().unwrap();
+        // Removed nodes are remembered too, but feed indices aren't:
+        assert_eq!(nodes.records().len(), 2);
+        assert_eq!(nodes.records()["b-id"].name, "b");
+        assert_eq!(nodes.connected(), 0);
+
+        // Only the most recent blocks are loaded:
+        assert_eq!(blocks.len(), MAX_TRACKED_BLOCKS);
+        assert!(blocks.values().all(|b| b.block_number >= 10));
+    }
+}
@@ -0,0 +1,251 @@
+use crate::chain::ChainIdentity;
+use crate::csv_file;
+use crate::state::MAX_TRACKED_BLOCKS;
+use anyhow::Result;
+use csv::Writer;
+use log::info;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+
+/// The columns written to the topology CSV file.
+const TOPOLOGY_HEADER: &[&str] = &[
+    "period_start",
+    "period_end",
+    "chain",
+    "genesis_hash",
+    "from_node_name",
+    "from_node_id",
+    "to_node_name",
+    "to_node_id",
+    "followed",
+    "imports",
+    "share",
+    "mean_gap_ms",
+];
+
+/// Links seen fewer times than this in a period are left out; a node can import a
+/// block just after any other now and again by chance.
+const MIN_FOLLOWED: u64 = 3;
+
+/// One node's import of a block.
+#[derive(Debug)]
+struct Arrival {
+    node_id: String,
+    propagation_time: u64,
+}
+
+#[derive(Debug)]
+struct BlockArrivals {
+    block_number: u64,
+    arrivals: Vec<Arrival>,
+}
+
+#[derive(Debug, Default)]
+struct Link {
+    /// How many blocks the "to" node imported straight after the "from" node.
+    followed: u64,
+    total_gap_ms: u64,
+}
+
+/// Guesses at which nodes gossip blocks to which, from the order that they import them
+/// in. A node that keeps importing blocks shortly after the same other node is likely
+/// to be hearing about them from it. Each period, the links seen during it are
+/// appended to the topology file.
+#[derive(Debug)]
+pub struct TopologyInference {
+    writer: Writer<File>,
+    period_secs: u64,
+    period_start: u64,
+    /// Imports further apart than this are too far apart to be one hop of gossip.
+    window_ms: u64,
+    blocks: HashMap<String, BlockArrivals>,
+    node_names: HashMap<String, String>,
+    /// How many blocks each node imported after someone else did, this period.
+    imports: HashMap<String, u64>,
+    /// Keyed by the IDs of the "from" and "to" nodes.
+    links: HashMap<(String, String), Link>,
+}
+
+impl TopologyInference {
+    pub fn new(path: &Path, period_secs: u64, window_ms: u64, now: u64) -> Result<Self> {
+        Ok(Self {
+            writer: csv_file::open_with_header(path, TOPOLOGY_HEADER)?,
+            period_secs,
+            period_start: now,
+            window_ms,
+            blocks: HashMap::new(),
+            node_names: HashMap::new(),
+            imports: HashMap::new(),
+            links: HashMap::new(),
+        })
+    }
+
+    /// Note that a node imported a block, `propagation_time` ms after the first node to.
+    /// Whoever imported it most recently before this node, if that was recently enough,
+    /// is taken to be who it heard about the block from.
+    pub fn saw_import(
+        &mut self,
+        block_hash: &str,
+        block_number: u64,
+        node_name: &str,
+        node_id: &str,
+        propagation_time: u64,
+    ) {
+        let block = self
+            .blocks
+            .entry(block_hash.to_string())
+            .or_insert_with(|| BlockArrivals {
+                block_number,
+                arrivals: vec![],
+            });
+        if block.arrivals.iter().any(|a| a.node_id == node_id) {
+            return;
+        }
+
+        let previous = block
+            .arrivals
+            .iter()
+            .filter(|a| a.propagation_time < propagation_time)
+            .map(|a| a.propagation_time)
+            .max();
+        if let Some(previous) = previous {
+            *self.imports.entry(node_id.to_string()).or_default() += 1;
+            let gap_ms = propagation_time - previous;
+            if gap_ms <= self.window_ms {
+                // Nodes that imported it at the same moment are equally likely sources.
+                for from in block
+                    .arrivals
+                    .iter()
+                    .filter(|a| a.propagation_time == previous)
+                {
+                    let link = self
+                        .links
+                        .entry((from.node_id.clone(), node_id.to_string()))
+                        .or_default();
+                    link.followed += 1;
+                    link.total_gap_ms += gap_ms;
+                }
+            }
+        }
+        block.arrivals.push(Arrival {
+            node_id: node_id.to_string(),
+            propagation_time,
+        });
+        self.node_names
+            .insert(node_id.to_string(), node_name.to_string());
+
+        if self.blocks.len() > MAX_TRACKED_BLOCKS {
+            let oldest = self
+                .blocks
+                .iter()
+                .min_by_key(|(_, block)| block.block_number)
+                .map(|(block_hash, _)| block_hash.clone());
+            if let Some(oldest) = oldest {
+                self.blocks.remove(&oldest);
+            }
+        }
+    }
+
+    /// Write out the links seen if the current period is over, and start a new one.
+    pub fn maybe_write(&mut self, now: u64, chain: &ChainIdentity) -> Result<()> {
+        if now.saturating_sub(self.period_start) < self.period_secs {
+            return Ok(());
+        }
+        self.finish_period(now, chain)
+    }
+
+    /// Write out the links seen in the current period so far, and start a new one.
+    pub fn finish_period(&mut self, now: u64, chain: &ChainIdentity) -> Result<()> {
+        let mut links: Vec<_> = self
+            .links
+            .iter()
+            .filter(|(_, link)| link.followed >= MIN_FOLLOWED)
+            .map(|((from, to), link)| {
+                let imports = self.imports.get(to).copied().unwrap_or(0).max(1);
+                (
+                    from,
+                    to,
+                    link,
+                    link.followed as f64 / imports as f64,
+                    imports,
+                )
+            })
+            .collect();
+        links.sort_by(|a, b| a.1.cmp(b.1).then(b.3.total_cmp(&a.3)).then(a.0.cmp(b.0)));
+
+        let name = |node_id: &String| self.node_names.get(node_id).cloned().unwrap_or_default();
+        for (from, to, link, share, imports) in &links {
+            self.writer.write_record(&[
+                self.period_start.to_string(),
+                now.to_string(),
+                chain.label.clone(),
+                chain.genesis_hash.clone(),
+                name(from),
+                from.to_string(),
+                name(to),
+                to.to_string(),
+                link.followed.to_string(),
+                imports.to_string(),
+                format!("{:.4}", share),
+                (link.total_gap_ms / link.followed).to_string(),
+            ])?;
+        }
+        self.writer.flush()?;
+        info!("Wrote {} inferred links to the topology file", links.len());
+
+        self.period_start = now;
+        self.imports.clear();
+        self.links.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn nodes_are_linked_to_whoever_they_keep_importing_just_after() {
+        let dir = std::env::temp_dir().join(format!("observer-topology-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("topology.csv");
+        let mut topology = TopologyInference::new(&path, 3600, 100, 0).unwrap();
+
+        // Bob always hears from Alice, and Carol from Bob, except once when she's too
+        // late to have heard from anyone in particular:
+        for n in 0..4u64 {
+            let hash = format!("0x{:x}", n);
+            topology.saw_import(&hash, n, "alice", "a", 0);
+            topology.saw_import(&hash, n, "bob", "b", 40);
+            topology.saw_import(&hash, n, "carol", "c", if n == 0 { 500 } else { 90 });
+        }
+        // And a second import of the same block changes nothing:
+        topology.saw_import("0x3", 3, "bob", "b", 300);
+
+        let chain = ChainIdentity::new("0x1234");
+        topology.maybe_write(60, &chain).unwrap();
+        topology.finish_period(3600, &chain).unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let rows: Vec<Vec<&str>> = contents
+            .lines()
+            .skip(1)
+            .map(|line| line.split(',').collect())
+            .collect();
+        let links: Vec<_> = rows
+            .iter()
+            .map(|row| (row[5], row[7], row[8], row[9], row[10], row[11]))
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                ("a", "b", "4", "4", "1.0000", "40"),
+                ("b", "c", "3", "4", "0.7500", "50"),
+            ]
+        );
+        assert_eq!(rows[0][0], "0");
+        assert_eq!(rows[0][1], "3600");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
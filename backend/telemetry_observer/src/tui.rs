@@ -0,0 +1,368 @@
+use crate::chain::ChainIdentity;
+use crate::heartbeat::{ConnectionState, Heartbeat};
+use common::ws_client::ConnectionStats;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::{Line, Span};
+use ratatui::widgets::{Block, List, ListItem, Paragraph, Row, Table};
+use ratatui::{DefaultTerminal, Frame};
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
+
+/// How many recent blocks and node events to keep around for display.
+const RECENT_BLOCKS: usize = 100;
+const RECENT_NODE_EVENTS: usize = 100;
+
+/// How often to redraw the screen.
+const REDRAW_INTERVAL: Duration = Duration::from_millis(250);
+
+#[derive(Debug)]
+struct RecentBlock {
+    number: u64,
+    hash: String,
+    fastest_ms: u64,
+    slowest_ms: u64,
+    reports: usize,
+    /// Who the block was attributed to, once that's been decided.
+    authors: Option<Vec<String>>,
+}
+
+#[derive(Debug)]
+struct NodeEvent {
+    timestamp: u64,
+    joined: bool,
+    node_name: String,
+}
+
+/// What the live view shows, kept up to date by the observer as messages arrive.
+#[derive(Debug, Default)]
+pub struct LiveView {
+    /// Blocks attributed to each node; ties are split between the nodes involved.
+    leaderboard: HashMap<String, f64>,
+    decided_blocks: u64,
+    recent_blocks: VecDeque<RecentBlock>,
+    node_events: VecDeque<NodeEvent>,
+}
+
+impl LiveView {
+    pub fn node_joined(&mut self, node_name: &str, now: u64) {
+        self.push_node_event(node_name, true, now);
+    }
+
+    pub fn node_left(&mut self, node_name: &str, now: u64) {
+        self.push_node_event(node_name, false, now);
+    }
+
+    fn push_node_event(&mut self, node_name: &str, joined: bool, timestamp: u64) {
+        self.node_events.push_front(NodeEvent {
+            timestamp,
+            joined,
+            node_name: node_name.to_string(),
+        });
+        self.node_events.truncate(RECENT_NODE_EVENTS);
+    }
+
+    /// Note that a node reported a block, along with how long it took to reach it.
+    pub fn saw_import(&mut self, block_number: u64, block_hash: &str, propagation_time: u64) {
+        if let Some(block) = self.recent_blocks.iter_mut().find(|b| b.hash == block_hash) {
+            block.fastest_ms = block.fastest_ms.min(propagation_time);
+            block.slowest_ms = block.slowest_ms.max(propagation_time);
+            block.reports += 1;
+            return;
+        }
+
+        // Keep the most recent blocks at the front:
+        let position = self
+            .recent_blocks
+            .iter()
+            .position(|b| b.number <= block_number)
+            .unwrap_or(self.recent_blocks.len());
+        self.recent_blocks.insert(
+            position,
+            RecentBlock {
+                number: block_number,
+                hash: block_hash.to_string(),
+                fastest_ms: propagation_time,
+                slowest_ms: propagation_time,
+                reports: 1,
+                authors: None,
+            },
+        );
+        self.recent_blocks.truncate(RECENT_BLOCKS);
+    }
+
+    /// Note who a block was attributed to.
+    pub fn decided(&mut self, block_hash: &str, authors: &[&str]) {
+        if authors.is_empty() {
+            return;
+        }
+        self.decided_blocks += 1;
+        let weight = 1.0 / authors.len() as f64;
+        for author in authors {
+            *self.leaderboard.entry(author.to_string()).or_default() += weight;
+        }
+        if let Some(block) = self.recent_blocks.iter_mut().find(|b| b.hash == block_hash) {
+            block.authors = Some(authors.iter().map(|a| a.to_string()).collect());
+        }
+    }
+}
+
+/// Everything the live view needs to draw itself.
+pub struct Sources {
+    pub live: Arc<Mutex<LiveView>>,
+    pub heartbeat: Arc<Mutex<Heartbeat>>,
+    pub feed_stats: Arc<ConnectionStats>,
+    pub chain: Arc<Mutex<ChainIdentity>>,
+    /// Told to stop the observer when the user quits.
+    pub stop: Arc<watch::Sender<Option<&'static str>>>,
+}
+
+/// The live view, which is drawn on its own thread.
+pub struct LiveViewHandle {
+    close: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl LiveViewHandle {
+    /// Stop drawing and give the terminal back.
+    pub fn close(self) {
+        self.close.store(true, Ordering::Relaxed);
+        let _ = self.thread.join();
+    }
+}
+
+/// Take over the terminal and draw the live view until it's closed. If the user
+/// quits first, the observer is asked to stop.
+pub fn spawn(sources: Sources) -> LiveViewHandle {
+    let close = Arc::new(AtomicBool::new(false));
+    let should_close = Arc::clone(&close);
+    let thread = std::thread::spawn(move || {
+        let mut terminal = ratatui::init();
+        let result = run(&mut terminal, &sources, &should_close);
+        ratatui::restore();
+        if let Err(e) = result {
+            eprintln!("Live view failed: {}", e);
+        }
+        // Unless we're closing because the observer is already stopping:
+        sources.stop.send_if_modified(|stop| {
+            if stop.is_some() {
+                return false;
+            }
+            *stop = Some("the live view was closed");
+            true
+        });
+    });
+    LiveViewHandle { close, thread }
+}
+
+fn run(
+    terminal: &mut DefaultTerminal,
+    sources: &Sources,
+    should_close: &AtomicBool,
+) -> std::io::Result<()> {
+    while !should_close.load(Ordering::Relaxed) {
+        terminal.draw(|frame| draw(frame, sources))?;
+        if !event::poll(REDRAW_INTERVAL)? {
+            continue;
+        }
+        if let Event::Key(key) = event::read()? {
+            let ctrl_c =
+                key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL);
+            if key.kind == KeyEventKind::Press
+                && (matches!(key.code, KeyCode::Char('q') | KeyCode::Esc) || ctrl_c)
+            {
+                return Ok(());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn draw(frame: &mut Frame, sources: &Sources) {
+    // This runs on its own thread, outside of the async runtime, so blocking is fine.
+    let live = sources.live.blocking_lock();
+    let heartbeat = sources.heartbeat.blocking_lock().clone();
+    let chain = sources.chain.blocking_lock().clone();
+    let stats = sources.feed_stats.snapshot();
+
+    let [status_area, main_area, events_area] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Min(8),
+        Constraint::Length(10),
+    ])
+    .areas(frame.area());
+    let [leaderboard_area, blocks_area] =
+        Layout::horizontal([Constraint::Percentage(40), Constraint::Percentage(60)])
+            .areas(main_area);
+
+    // Connection status:
+    let (state, colour) = match heartbeat.connection {
+        ConnectionState::Connected => ("connected", Color::Green),
+        ConnectionState::Connecting => ("connecting", Color::Yellow),
+        ConnectionState::Disconnected => ("disconnected", Color::Red),
+    };
+    let ping = stats
+        .ping_rtt
+        .map(|rtt| format!("{:.1}ms", rtt.as_secs_f64() * 1000.0))
+        .unwrap_or_else(|| "-".to_string());
+    let status = Line::from(vec![
+        Span::styled(state, Style::new().fg(colour).add_modifier(Modifier::BOLD)),
+        Span::raw(format!(
+            " since {}  |  best #{}  |  ping {}  |  {} reconnects  |  {:.1} MiB received  |  q to quit",
+            clock(heartbeat.connection_changed_at),
+            heartbeat.last_block_number.unwrap_or_default(),
+            ping,
+            stats.reconnects,
+            stats.bytes_received as f64 / (1024.0 * 1024.0),
+        )),
+    ]);
+    let title = if chain.label.is_empty() {
+        chain.genesis_hash.clone()
+    } else {
+        chain.label.clone()
+    };
+    frame.render_widget(
+        Paragraph::new(status).block(Block::bordered().title(title)),
+        status_area,
+    );
+
+    // Author leaderboard:
+    let mut leaders: Vec<_> = live.leaderboard.iter().collect();
+    leaders.sort_by(|a, b| b.1.total_cmp(a.1).then_with(|| a.0.cmp(b.0)));
+    let rows = leaders.iter().enumerate().map(|(i, (name, blocks))| {
+        let share = **blocks / live.decided_blocks.max(1) as f64;
+        Row::new(vec![
+            format!("{}", i + 1),
+            name.to_string(),
+            format!("{:.1}", blocks),
+            format!("{:.1}%", share * 100.0),
+        ])
+    });
+    let leaderboard = Table::new(
+        rows,
+        [
+            Constraint::Length(4),
+            Constraint::Min(10),
+            Constraint::Length(8),
+            Constraint::Length(7),
+        ],
+    )
+    .header(header(&["#", "Node", "Blocks", "Share"]))
+    .block(Block::bordered().title(format!("Authors ({} blocks)", live.decided_blocks)));
+    frame.render_widget(leaderboard, leaderboard_area);
+
+    // Recent blocks:
+    let rows = live.recent_blocks.iter().map(|block| {
+        let authors = match &block.authors {
+            Some(authors) => authors.join(", "),
+            None => "...".to_string(),
+        };
+        Row::new(vec![
+            format!("#{}", block.number),
+            short_hash(&block.hash),
+            authors,
+            format!("{}", block.fastest_ms),
+            format!("{}", block.slowest_ms - block.fastest_ms),
+            format!("{}", block.reports),
+        ])
+    });
+    let blocks = Table::new(
+        rows,
+        [
+            Constraint::Length(10),
+            Constraint::Length(12),
+            Constraint::Min(10),
+            Constraint::Length(8),
+            Constraint::Length(8),
+            Constraint::Length(7),
+        ],
+    )
+    .header(header(&[
+        "Block", "Hash", "Author", "Best ms", "Spread", "Reports",
+    ]))
+    .block(Block::bordered().title("Recent blocks"));
+    frame.render_widget(blocks, blocks_area);
+
+    // Nodes joining and leaving:
+    let events = live.node_events.iter().map(|event| {
+        let (verb, colour) = if event.joined {
+            ("joined", Color::Green)
+        } else {
+            ("left", Color::Red)
+        };
+        ListItem::new(Line::from(vec![
+            Span::raw(format!("{}  ", clock(event.timestamp))),
+            Span::styled(verb, Style::new().fg(colour)),
+            Span::raw(format!("  {}", event.node_name)),
+        ]))
+    });
+    frame.render_widget(
+        List::new(events).block(Block::bordered().title("Nodes")),
+        events_area,
+    );
+}
+
+fn header(titles: &[&'static str]) -> Row<'static> {
+    Row::new(titles.to_vec()).style(Style::new().add_modifier(Modifier::BOLD))
+}
+
+/// Eg `0x1234…abcd`.
+fn short_hash(hash: &str) -> String {
+    if hash.len() <= 12 {
+        return hash.to_string();
+    }
+    format!("{}…{}", &hash[..6], &hash[hash.len() - 4..])
+}
+
+/// A Unix timestamp as a UTC time of day, eg `14:03:59`.
+fn clock(timestamp: u64) -> String {
+    format!(
+        "{:02}:{:02}:{:02}",
+        (timestamp / 3600) % 24,
+        (timestamp / 60) % 60,
+        timestamp % 60
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn keeps_recent_blocks_newest_first() {
+        let mut live = LiveView::default();
+        live.saw_import(2, "0xb", 100);
+        live.saw_import(1, "0xa", 300);
+        live.saw_import(3, "0xc", 50);
+        live.saw_import(2, "0xb", 400);
+
+        let numbers: Vec<_> = live.recent_blocks.iter().map(|b| b.number).collect();
+        assert_eq!(numbers, vec![3, 2, 1]);
+        let block = &live.recent_blocks[1];
+        assert_eq!(
+            (block.fastest_ms, block.slowest_ms, block.reports),
+            (100, 400, 2)
+        );
+    }
+
+    #[test]
+    fn splits_ties_on_the_leaderboard() {
+        let mut live = LiveView::default();
+        live.saw_import(1, "0xa", 100);
+        live.decided("0xa", &["alice", "bob"]);
+        live.decided("0xb", &["alice"]);
+
+        assert_eq!(live.decided_blocks, 2);
+        assert_eq!(live.leaderboard["alice"], 1.5);
+        assert_eq!(live.leaderboard["bob"], 0.5);
+        assert_eq!(
+            live.recent_blocks[0].authors.as_deref(),
+            Some(&["alice".to_string(), "bob".to_string()][..])
+        );
+    }
+}
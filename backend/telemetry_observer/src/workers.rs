@@ -0,0 +1,268 @@
+use crate::spill::SpillStore;
+use crate::state::{BlockReporter, Blocks, MAX_TRACKED_BLOCKS};
+use futures::future::join_all;
+use log::warn;
+use std::collections::hash_map::DefaultHasher;
+use std::future::Future;
+use std::hash::{Hash, Hasher};
+use std::path::Path;
+use tokio::sync::{mpsc, Mutex};
+use tokio::task::JoinHandle;
+
+/// How many jobs can be waiting for each worker before the feed is made to wait.
+const QUEUE_JOBS: usize = 1024;
+
+/// The blocks being tracked, split up by block hash so that each worker has blocks
+/// of its own and doesn't contend with the others for them.
+#[derive(Debug)]
+pub struct ShardedBlocks {
+    shards: Vec<Mutex<Blocks>>,
+    /// Given a memory budget, each shard keeps to its part of it (in bytes) rather than
+    /// to a number of blocks, and spills undecided blocks over it to disk.
+    budget_per_shard: Option<usize>,
+    spilled: Vec<Mutex<SpillStore>>,
+}
+
+impl ShardedBlocks {
+    pub fn new(blocks: Blocks, shards: usize) -> Self {
+        let mut split: Vec<Blocks> = (0..shards.max(1)).map(|_| Blocks::new()).collect();
+        let count = split.len();
+        for (block_hash, block) in blocks {
+            split[shard_of(&block_hash, count)].insert(block_hash, block);
+        }
+        Self {
+            shards: split.into_iter().map(Mutex::new).collect(),
+            budget_per_shard: None,
+            spilled: vec![],
+        }
+    }
+
+    /// Keep the blocks within `budget` bytes between them, spilling what doesn't fit
+    /// into a directory of its own for each shard under `spill_dir`.
+    pub fn with_memory_budget(mut self, budget: usize, spill_dir: &Path) -> anyhow::Result<Self> {
+        self.budget_per_shard = Some(budget / self.shards.len());
+        self.spilled = (0..self.shards.len())
+            .map(|shard| SpillStore::open(&spill_dir.join(shard.to_string())).map(Mutex::new))
+            .collect::<anyhow::Result<_>>()?;
+        Ok(self)
+    }
+
+    pub fn count(&self) -> usize {
+        self.shards.len()
+    }
+
+    pub fn shard_of(&self, block_hash: &str) -> usize {
+        shard_of(block_hash, self.shards.len())
+    }
+
+    pub fn shard(&self, shard: usize) -> &Mutex<Blocks> {
+        &self.shards[shard]
+    }
+
+    /// How many blocks each shard keeps track of, so that between them they keep
+    /// about as many as a single shard would.
+    pub fn max_per_shard(&self) -> usize {
+        MAX_TRACKED_BLOCKS.div_ceil(self.shards.len())
+    }
+
+    /// How many bytes of blocks each shard keeps in memory, if there's a memory budget.
+    pub fn budget_per_shard(&self) -> Option<usize> {
+        self.budget_per_shard
+    }
+
+    /// The blocks that a shard has spilled to disk, if there's a memory budget. Lock
+    /// the shard itself first.
+    pub fn spilled(&self, shard: usize) -> Option<&Mutex<SpillStore>> {
+        self.spilled.get(shard)
+    }
+
+    /// Every block, from every shard. This locks each shard in turn.
+    pub async fn merged(&self) -> Blocks {
+        let mut blocks = Blocks::new();
+        for shard in &self.shards {
+            let shard = shard.lock().await;
+            blocks.extend(shard.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        blocks
+    }
+
+    /// Have all of the blocks up to `block_number` been decided, across all shards?
+    /// Blocks that have been spilled to disk haven't been.
+    pub async fn all_decided_up_to(&self, block_number: u64) -> bool {
+        for (n, shard) in self.shards.iter().enumerate() {
+            let shard = shard.lock().await;
+            if shard
+                .values()
+                .any(|b| !b.output && b.block_number <= block_number)
+            {
+                return false;
+            }
+            if let Some(spilled) = self.spilled(n) {
+                if spilled.lock().await.any_up_to(block_number) {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+fn shard_of(block_hash: &str, shards: usize) -> usize {
+    let mut hasher = DefaultHasher::new();
+    block_hash.hash(&mut hasher);
+    (hasher.finish() % shards as u64) as usize
+}
+
+/// An import that's been checked against everything we know about the node that
+/// reported it, ready to be added to its block.
+#[derive(Debug, Clone)]
+pub struct BlockImport {
+    pub block_number: u64,
+    pub block_hash: String,
+    pub propagation_time: u64,
+    pub reporter: BlockReporter,
+    /// Whether the node that reported it is running as a validator.
+    pub validator: bool,
+    /// Whether the import counts towards attributing the block. Lagging and stale nodes,
+    /// and blocks outside of the block range, don't.
+    pub counts: bool,
+}
+
+#[derive(Debug, Clone)]
+pub enum Job {
+    Import {
+        import: BlockImport,
+        now_ms: u64,
+    },
+    /// The chain has moved on, so blocks that no import has come in for might be
+    /// ready to decide.
+    Tick {
+        now_ms: u64,
+    },
+}
+
+/// A worker for each shard of blocks. Workers handle the jobs for their shard in
+/// the order they were sent, but independently of each other.
+pub struct WorkerPool {
+    senders: Vec<mpsc::Sender<Job>>,
+    handles: Vec<JoinHandle<()>>,
+}
+
+impl WorkerPool {
+    /// Start `workers` workers, each calling `handle` with its shard and each job.
+    pub fn spawn<F, Fut>(workers: usize, handle: F) -> Self
+    where
+        F: Fn(usize, Job) -> Fut + Clone + Send + 'static,
+        Fut: Future<Output = anyhow::Result<()>> + Send,
+    {
+        let mut senders = vec![];
+        let mut handles = vec![];
+        for shard in 0..workers.max(1) {
+            let (tx, mut rx) = mpsc::channel(QUEUE_JOBS);
+            let handle = handle.clone();
+            handles.push(tokio::spawn(async move {
+                while let Some(job) = rx.recv().await {
+                    if let Err(e) = handle(shard, job).await {
+                        warn!("Failed to process block import: {}", e);
+                    }
+                }
+            }));
+            senders.push(tx);
+        }
+        Self { senders, handles }
+    }
+
+    /// Hand a job to the worker for the given shard, waiting for room in its queue.
+    pub async fn send(&self, shard: usize, job: Job) {
+        // Only fails once the worker has stopped, which only happens once we've closed.
+        let _ = self.senders[shard].send(job).await;
+    }
+
+    /// Hand a job to every worker but the one for the given shard.
+    pub async fn broadcast_except(&self, shard: usize, job: Job) {
+        let others = self
+            .senders
+            .iter()
+            .enumerate()
+            .filter(|(n, _)| *n != shard)
+            .map(|(_, tx)| tx.send(job.clone()));
+        join_all(others).await;
+    }
+
+    /// Wait for every job that's been sent so far to be handled.
+    pub async fn close(self) {
+        drop(self.senders);
+        for handle in self.handles {
+            if let Err(e) = handle.await {
+                warn!("Block import worker stopped unexpectedly: {}", e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::state::BlockInfo;
+    use std::sync::Arc;
+
+    fn block(block_number: u64, output: bool) -> BlockInfo {
+        BlockInfo {
+            block_number,
+            lowest_prop_time: 100,
+            reporters: vec![],
+            candidates: vec![],
+            first_seen: 0,
+            first_seen_ms: 0,
+            report_count: 1,
+            output,
+        }
+    }
+
+    #[tokio::test]
+    async fn blocks_are_split_between_shards_by_hash() {
+        let blocks: Blocks = (0..40)
+            .map(|n| (format!("0x{:x}", n), block(n, n < 30)))
+            .collect();
+        let sharded = ShardedBlocks::new(blocks.clone(), 4);
+        assert_eq!(sharded.count(), 4);
+        for block_hash in blocks.keys() {
+            let shard = sharded.shard(sharded.shard_of(block_hash)).lock().await;
+            assert!(shard.contains_key(block_hash));
+        }
+        assert_eq!(sharded.merged().await.len(), blocks.len());
+        assert!(sharded.all_decided_up_to(29).await);
+        assert!(!sharded.all_decided_up_to(30).await);
+    }
+
+    #[tokio::test]
+    async fn workers_handle_every_job_in_order_for_their_shard() {
+        let seen = Arc::new(Mutex::new(vec![vec![]; 3]));
+        let pool = WorkerPool::spawn(3, {
+            let seen = seen.clone();
+            move |shard, job| {
+                let seen = seen.clone();
+                async move {
+                    if let Job::Tick { now_ms } = job {
+                        seen.lock().await[shard].push(now_ms);
+                    }
+                    Ok(())
+                }
+            }
+        });
+        for n in 0..100 {
+            pool.send((n % 3) as usize, Job::Tick { now_ms: n }).await;
+        }
+        pool.broadcast_except(1, Job::Tick { now_ms: 1000 }).await;
+        pool.close().await;
+
+        let seen = seen.lock().await;
+        for (shard, jobs) in seen.iter().enumerate() {
+            let mut expected: Vec<u64> = (0..100).filter(|n| n % 3 == shard as u64).collect();
+            if shard != 1 {
+                expected.push(1000);
+            }
+            assert_eq!(jobs, &expected);
+        }
+    }
+}
@@ -16,6 +16,7 @@
 
 use crate::connection::{create_ws_connection_to_core, Message};
 use common::{
+    compression,
     internal_messages::{self, ShardNodeId},
     node_message,
     node_types::BlockHash,
@@ -102,12 +103,17 @@ struct AggregatorInternal {
 
 impl Aggregator {
     /// Spawn a new Aggregator. This connects to the telemetry backend
-    pub async fn spawn(telemetry_uri: http::Uri) -> anyhow::Result<Aggregator> {
+    pub async fn spawn(
+        telemetry_uri: http::Uri,
+        compression: Option<compression::Kind>,
+        stats_deltas: Option<u32>,
+        finality: bool,
+    ) -> anyhow::Result<Aggregator> {
         let (tx_to_aggregator, rx_from_external) = flume::bounded(10);
 
         // Establish a resilient connection to the core (this retries as needed):
         let (tx_to_telemetry_core, rx_from_telemetry_core) =
-            create_ws_connection_to_core(telemetry_uri).await;
+            create_ws_connection_to_core(telemetry_uri, compression).await;
 
         // Forward messages from the telemetry core into the aggregator:
         let tx_to_aggregator2 = tx_to_aggregator.clone();
@@ -129,6 +135,8 @@ impl Aggregator {
         tokio::spawn(Aggregator::handle_messages(
             rx_from_external,
             tx_to_telemetry_core,
+            stats_deltas,
+            finality,
         ));
 
         // Return a handle to our aggregator so that we can send in messages to it:
@@ -141,9 +149,15 @@ impl Aggregator {
     // This is spawned into a separate task and handles any messages coming
     // in to the aggregator. If nobody is holding the tx side of the channel
     // any more, this task will gracefully end.
+    //
+    // If `stats_deltas` is given, only what's changed in each node's periodic stats is sent
+    // to the core, with them sent in full every `stats_deltas` intervals. GRANDPA messages
+    // are only passed on to the core if `finality` is set.
     async fn handle_messages(
         rx_from_external: flume::Receiver<ToAggregator>,
         tx_to_telemetry_core: flume::Sender<FromAggregator>,
+        stats_deltas: Option<u32>,
+        finality: bool,
     ) {
         use internal_messages::{FromShardAggregator, FromTelemetryCore};
 
@@ -162,6 +176,11 @@ impl Aggregator {
         // Any messages coming from nodes that have been muted are ignored:
         let mut muted: HashSet<ShardNodeId> = HashSet::new();
 
+        // The last periodic stats sent to the core for each node, and how many deltas
+        // have been sent since they were last sent in full:
+        let mut last_intervals: HashMap<ShardNodeId, (node_message::SystemInterval, u32)> =
+            HashMap::new();
+
         // Now, loop and receive messages to handle.
         while let Ok(msg) = rx_from_external.recv_async().await {
             match msg {
@@ -178,6 +197,7 @@ impl Aggregator {
                     close_connections = HashMap::new();
                     to_local_id.clear();
                     muted.clear();
+                    last_intervals.clear();
 
                     connected_to_telemetry_core = true;
                     log::info!("Connected to telemetry core");
@@ -246,10 +266,20 @@ impl Aggregator {
                         continue;
                     }
 
+                    let payload = match finality_payload(payload, finality) {
+                        Some(payload) => payload,
+                        None => continue,
+                    };
+
+                    let msg = match (stats_deltas, payload) {
+                        (Some(refresh), node_message::Payload::SystemInterval(interval)) => {
+                            interval_update(&mut last_intervals, local_id, interval, refresh)
+                        }
+                        (_, payload) => FromShardAggregator::UpdateNode { local_id, payload },
+                    };
+
                     // Send the message to the telemetry core with this local ID:
-                    let _ = tx_to_telemetry_core
-                        .send_async(FromShardAggregator::UpdateNode { local_id, payload })
-                        .await;
+                    let _ = tx_to_telemetry_core.send_async(msg).await;
                 }
                 ToAggregator::FromWebsocket(conn_id, FromWebsocket::Remove { message_id }) => {
                     // Get the local ID, ignoring the message if none match:
@@ -261,6 +291,7 @@ impl Aggregator {
                     // Remove references to this single node:
                     to_local_id.remove_by_id(local_id);
                     muted.remove(&local_id);
+                    last_intervals.remove(&local_id);
 
                     // If we're not connected to the core, don't buffer up remove messages. The core will remove
                     // all nodes associated with this shard anyway, so the remove message would be redundant.
@@ -285,6 +316,7 @@ impl Aggregator {
                     for local_id in local_ids_disconnected {
                         to_local_id.remove_by_id(local_id);
                         muted.remove(&local_id);
+                        last_intervals.remove(&local_id);
 
                         // If we're not connected to the core, don't buffer up remove messages. The core will remove
                         // all nodes associated with this shard anyway, so the remove message would be redundant.
@@ -325,3 +357,121 @@ impl Aggregator {
         )
     }
 }
+
+/// Work out what to send the core for a node's latest periodic stats: just what's
+/// changed since the last ones, unless they're due to be sent in full again (a core that
+/// somehow lost track of what we'd sent is then only wrong for a while).
+fn interval_update(
+    last_intervals: &mut HashMap<ShardNodeId, (node_message::SystemInterval, u32)>,
+    local_id: ShardNodeId,
+    interval: node_message::SystemInterval,
+    refresh: u32,
+) -> FromAggregator {
+    match last_intervals.get_mut(&local_id) {
+        Some((last, deltas_sent)) if *deltas_sent + 1 < refresh => {
+            let delta = node_message::SystemIntervalDelta::between(last, &interval);
+            *last = interval;
+            *deltas_sent += 1;
+            FromAggregator::UpdateNodeInterval { local_id, delta }
+        }
+        _ => {
+            last_intervals.insert(local_id, (interval.clone(), 0));
+            FromAggregator::UpdateNode {
+                local_id,
+                payload: node_message::Payload::SystemInterval(interval),
+            }
+        }
+    }
+}
+
+/// Cores that don't know about GRANDPA messages would boot us for sending them, so
+/// unless we've been told that the core understands them, they're left out (or, for the
+/// authority set, cut down to what every core understands).
+fn finality_payload(
+    payload: node_message::Payload,
+    finality: bool,
+) -> Option<node_message::Payload> {
+    use node_message::Payload;
+    match payload {
+        Payload::AfgAuthorities(authorities) if !finality => {
+            Some(Payload::AfgAuthoritySet(node_message::AfgAuthoritySet {
+                authority_id: authorities.authority_id,
+            }))
+        }
+        Payload::AfgFinalized(_)
+        | Payload::AfgReceivedPrevote(_)
+        | Payload::AfgReceivedPrecommit(_)
+            if !finality =>
+        {
+            None
+        }
+        payload => Some(payload),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn intervals_are_sent_in_full_every_so_often() {
+        let mut last_intervals = HashMap::new();
+        let local_id = ShardNodeId::new(1);
+        let sent: Vec<_> = (0..7)
+            .map(|peers| {
+                let interval = node_message::SystemInterval {
+                    peers: Some(peers),
+                    ..Default::default()
+                };
+                interval_update(&mut last_intervals, local_id, interval, 3)
+            })
+            .collect();
+        let full: Vec<_> = sent
+            .iter()
+            .map(|msg| matches!(msg, FromAggregator::UpdateNode { .. }))
+            .collect();
+        assert_eq!(full, [true, false, false, true, false, false, true]);
+        match &sent[1] {
+            FromAggregator::UpdateNodeInterval { delta, .. } => {
+                assert_eq!(delta.peers, Some(Some(1)));
+                assert_eq!(delta.txcount, None);
+            }
+            msg => panic!("expected a delta, got {msg:?}"),
+        }
+    }
+
+    #[test]
+    fn finality_is_only_sent_when_asked_for() {
+        use node_message::Payload;
+        let authorities = || {
+            Payload::AfgAuthorities(node_message::AfgAuthorities {
+                authority_id: "alice".into(),
+                authorities: "[]".into(),
+                authority_set_id: "1".into(),
+                hash: Default::default(),
+                number: "2".into(),
+            })
+        };
+        let prevote = || {
+            Payload::AfgReceivedPrevote(node_message::AfgReceived {
+                target_hash: Default::default(),
+                target_number: "2".into(),
+                voter: None,
+            })
+        };
+
+        assert!(matches!(
+            finality_payload(authorities(), false),
+            Some(Payload::AfgAuthoritySet(set)) if &*set.authority_id == "alice"
+        ));
+        assert!(finality_payload(prevote(), false).is_none());
+        assert!(matches!(
+            finality_payload(authorities(), true),
+            Some(Payload::AfgAuthorities(_))
+        ));
+        assert!(matches!(
+            finality_payload(prevote(), true),
+            Some(Payload::AfgReceivedPrevote(_))
+        ));
+    }
+}
@@ -15,6 +15,7 @@
 // along with this program. If not, see <https://www.gnu.org/licenses/>.
 
 use bincode::Options;
+use common::compression::{self, Compressor};
 use common::ws_client;
 use futures::StreamExt;
 
@@ -30,16 +31,25 @@ pub enum Message<Out> {
 /// - Returns a channel that allows you to send messages to the connection.
 /// - Messages are all encoded/decoded to/from bincode, and so need to support being (de)serialized from
 ///   a non self-describing encoding.
+/// - If `compression` is given, messages sent to the core are compressed. Messages from the
+///   core are not.
 ///
 /// Note: have a look at [`common::internal_messages`] to see the different message types exchanged
 /// between aggregator and core.
 pub async fn create_ws_connection_to_core<In, Out>(
     telemetry_uri: http::Uri,
+    compression: Option<compression::Kind>,
 ) -> (flume::Sender<In>, flume::Receiver<Message<Out>>)
 where
     In: serde::Serialize + Send + 'static,
     Out: serde::de::DeserializeOwned + Send + 'static,
 {
+    // Let the core know which compression we'll be using:
+    let telemetry_uri = match compression {
+        Some(kind) => with_compression_query(telemetry_uri, kind),
+        None => telemetry_uri,
+    };
+
     let (tx_in, rx_in) = flume::bounded::<In>(10);
     let (tx_out, rx_out) = flume::bounded(10);
 
@@ -59,6 +69,8 @@ where
                 Ok(connection) => {
                     let (tx_to_core, mut rx_from_core) = connection.into_channels();
                     is_connected = true;
+                    // Each connection gets a fresh compression context:
+                    let mut compressor = compression.map(Compressor::new);
                     let tx_out = tx_out.clone();
 
                     if let Err(e) = tx_out.send_async(Message::Connected).await {
@@ -102,9 +114,12 @@ where
                                     }
                                 };
 
-                                let bytes = bincode::options()
+                                let mut bytes = bincode::options()
                                     .serialize(&msg)
                                     .expect("internal messages must be serializable");
+                                if let Some(compressor) = &mut compressor {
+                                    bytes = compressor.compress(&bytes);
+                                }
                                 let ws_msg = ws_client::SentMessage::Binary(bytes);
 
                                 if let Err(e) = tx_to_core.unbounded_send(ws_msg) {
@@ -139,3 +154,20 @@ where
 
     (tx_in, rx_out)
 }
+
+/// Append the query parameter that asks the core to decompress our messages.
+fn with_compression_query(uri: http::Uri, kind: compression::Kind) -> http::Uri {
+    let param = format!("{}={}", compression::QUERY_PARAM, kind.as_str());
+    let path_and_query = match uri.query() {
+        Some(query) => format!("{}?{query}&{param}", uri.path()),
+        None => format!("{}?{param}", uri.path()),
+    };
+
+    let mut parts = uri.into_parts();
+    parts.path_and_query = Some(
+        path_and_query
+            .parse()
+            .expect("path and query built from a valid URI should be valid"),
+    );
+    http::Uri::from_parts(parts).expect("URI parts from a valid URI should be valid")
+}
@@ -0,0 +1,281 @@
+// Source code for the Substrate Telemetry Server.
+// Copyright (C) 2021 Parity Technologies (UK) Ltd.
+//
+// This program is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program. If not, see <https://www.gnu.org/licenses/>.
+
+//! Node operators want to know whether their telemetry is actually arriving at the
+//! shard they point their nodes at. Each `/submit` connection registers itself with
+//! [`IngestStats`] and keeps counters up to date as messages come in, so that they
+//! can be looked up again by address.
+
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use common::node_message::NodeMessageId;
+use common::node_types::BlockHash;
+use common::rolling_total::{RollingTotal, RollingTotalBuilder, SystemTimeSource};
+use serde::Serialize;
+
+/// How far back the message rate of each node looks.
+const RATE_WINDOW: Duration = Duration::from_secs(60);
+
+/// Every `/submit` connection that's currently open.
+#[derive(Clone, Default)]
+pub struct IngestStats {
+    next_id: Arc<AtomicU64>,
+    connections: Arc<Mutex<BTreeMap<u64, Arc<Mutex<Connection>>>>>,
+}
+
+impl IngestStats {
+    /// Register a new connection. It's forgotten about when the returned guard is dropped.
+    pub fn add(&self, addr: IpAddr) -> ConnectionStats {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed) + 1;
+        let connection = Arc::new(Mutex::new(Connection {
+            addr,
+            connected_at: Instant::now(),
+            messages_received: 0,
+            bytes_received: 0,
+            payload_errors: 0,
+            ignored_messages: 0,
+            nodes: HashMap::new(),
+        }));
+        self.connections
+            .lock()
+            .unwrap()
+            .insert(id, connection.clone());
+        ConnectionStats {
+            id,
+            connection,
+            connections: self.connections.clone(),
+        }
+    }
+
+    /// A summary of every open connection from the given address, oldest first.
+    pub fn list_for(&self, addr: IpAddr) -> Vec<ConnectionSummary> {
+        self.connections
+            .lock()
+            .unwrap()
+            .values()
+            .map(|c| c.lock().unwrap())
+            .filter(|c| c.addr == addr)
+            .map(|c| c.summary())
+            .collect()
+    }
+}
+
+/// Keeps the stats for a single connection up to date, and removes them when dropped.
+pub struct ConnectionStats {
+    id: u64,
+    connection: Arc<Mutex<Connection>>,
+    connections: Arc<Mutex<BTreeMap<u64, Arc<Mutex<Connection>>>>>,
+}
+
+impl ConnectionStats {
+    /// A message has arrived, whether or not it turns out to make sense.
+    pub fn received(&self, bytes: usize) {
+        let mut connection = self.connection.lock().unwrap();
+        connection.messages_received += 1;
+        connection.bytes_received += bytes as u64;
+    }
+
+    /// A message couldn't be decoded. We can't tell which node sent it, so this is
+    /// counted against the connection.
+    pub fn payload_error(&self) {
+        self.connection.lock().unwrap().payload_errors += 1;
+    }
+
+    /// A message was ignored, because the node it's about was one too many for the
+    /// connection or was never added.
+    pub fn ignored(&self) {
+        self.connection.lock().unwrap().ignored_messages += 1;
+    }
+
+    pub fn node_added(&self, message_id: NodeMessageId, name: &str, genesis_hash: BlockHash) {
+        let now = Instant::now();
+        let mut messages = RollingTotalBuilder::new()
+            .granularity(Duration::from_secs(1))
+            .window_size_multiple(RATE_WINDOW.as_secs() as usize)
+            .start();
+        messages.push(1);
+        self.connection.lock().unwrap().nodes.insert(
+            message_id,
+            Node {
+                name: name.to_owned(),
+                genesis_hash,
+                connected_at: now,
+                last_message_at: now,
+                messages_received: 1,
+                messages,
+            },
+        );
+    }
+
+    /// A message about a node that's been added has been passed on to the core.
+    pub fn node_message(&self, message_id: NodeMessageId) {
+        let mut connection = self.connection.lock().unwrap();
+        if let Some(node) = connection.nodes.get_mut(&message_id) {
+            node.last_message_at = Instant::now();
+            node.messages_received += 1;
+            node.messages.push(1);
+        }
+    }
+
+    pub fn node_removed(&self, message_id: NodeMessageId) {
+        self.connection.lock().unwrap().nodes.remove(&message_id);
+    }
+}
+
+impl Drop for ConnectionStats {
+    fn drop(&mut self) {
+        self.connections.lock().unwrap().remove(&self.id);
+    }
+}
+
+struct Connection {
+    addr: IpAddr,
+    connected_at: Instant,
+    messages_received: u64,
+    bytes_received: u64,
+    payload_errors: u64,
+    ignored_messages: u64,
+    nodes: HashMap<NodeMessageId, Node>,
+}
+
+impl Connection {
+    fn summary(&self) -> ConnectionSummary {
+        let mut nodes: Vec<_> = self
+            .nodes
+            .iter()
+            .map(|(&message_id, node)| node.summary(message_id))
+            .collect();
+        nodes.sort_by_key(|n| n.message_id);
+        ConnectionSummary {
+            addr: self.addr,
+            connected_secs: self.connected_at.elapsed().as_secs(),
+            messages_received: self.messages_received,
+            bytes_received: self.bytes_received,
+            payload_errors: self.payload_errors,
+            ignored_messages: self.ignored_messages,
+            nodes,
+        }
+    }
+}
+
+struct Node {
+    name: String,
+    genesis_hash: BlockHash,
+    connected_at: Instant,
+    last_message_at: Instant,
+    messages_received: u64,
+    messages: RollingTotal<u64, SystemTimeSource>,
+}
+
+impl Node {
+    fn summary(&self, message_id: NodeMessageId) -> NodeSummary {
+        let since_last_message = self.last_message_at.elapsed();
+        NodeSummary {
+            message_id,
+            name: self.name.clone(),
+            genesis_hash: self.genesis_hash,
+            connected_secs: self.connected_at.elapsed().as_secs(),
+            messages_received: self.messages_received,
+            // The rolling total only moves on when messages arrive, so a node that's
+            // gone quiet would otherwise look as busy as it last was.
+            messages_per_minute: if since_last_message < RATE_WINDOW {
+                self.messages.total()
+            } else {
+                0
+            },
+            last_message_secs_ago: since_last_message.as_secs(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ConnectionSummary {
+    pub addr: IpAddr,
+    pub connected_secs: u64,
+    /// Every message received on the connection, including those that couldn't be
+    /// decoded or were ignored.
+    pub messages_received: u64,
+    pub bytes_received: u64,
+    /// Messages that weren't valid node telemetry.
+    pub payload_errors: u64,
+    /// Messages about nodes beyond `--max-nodes-per-connection`, or about nodes that
+    /// were never added with a `system.connected` message.
+    pub ignored_messages: u64,
+    pub nodes: Vec<NodeSummary>,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct NodeSummary {
+    /// The ID that the node's messages are sent with on this connection.
+    pub message_id: NodeMessageId,
+    pub name: String,
+    pub genesis_hash: BlockHash,
+    pub connected_secs: u64,
+    pub messages_received: u64,
+    /// Messages received in the minute up to the last one, or 0 if there have been none
+    /// in the last minute.
+    pub messages_per_minute: u64,
+    pub last_message_secs_ago: u64,
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn connections_are_listed_by_address_until_they_go() {
+        let stats = IngestStats::default();
+        let home: IpAddr = "127.0.0.1".parse().unwrap();
+        let away: IpAddr = "127.0.0.2".parse().unwrap();
+        let first = stats.add(home);
+        let second = stats.add(home);
+        let _other = stats.add(away);
+
+        first.received(100);
+        first.node_added(1, "Alice", BlockHash::from_low_u64_be(1));
+        first.received(50);
+        first.node_message(1);
+        first.received(10);
+        first.payload_error();
+        first.received(20);
+        first.ignored();
+        // Messages about nodes that haven't been added don't count towards any node:
+        first.node_message(2);
+
+        let list = stats.list_for(home);
+        assert_eq!(list.len(), 2);
+        assert_eq!(list[0].messages_received, 4);
+        assert_eq!(list[0].bytes_received, 180);
+        assert_eq!(list[0].payload_errors, 1);
+        assert_eq!(list[0].ignored_messages, 1);
+        assert_eq!(list[0].nodes.len(), 1);
+        assert_eq!(list[0].nodes[0].name, "Alice");
+        assert_eq!(list[0].nodes[0].messages_received, 2);
+        assert_eq!(list[0].nodes[0].messages_per_minute, 2);
+        assert!(list[1].nodes.is_empty());
+
+        first.node_removed(1);
+        assert!(stats.list_for(home)[0].nodes.is_empty());
+
+        drop(second);
+        assert_eq!(stats.list_for(home).len(), 1);
+        assert_eq!(stats.list_for(away).len(), 1);
+    }
+}
@@ -78,6 +78,12 @@ pub enum Payload {
     AfgAuthoritySet(AfgAuthoritySet),
     #[serde(rename = "sysinfo.hwbench")]
     HwBench(NodeHwBench),
+    #[serde(rename = "afg.finalized_blocks_up_to")]
+    AfgFinalized(AfgFinalized),
+    #[serde(rename = "afg.received_prevote")]
+    AfgReceivedPrevote(AfgReceived),
+    #[serde(rename = "afg.received_precommit")]
+    AfgReceivedPrecommit(AfgReceived),
 }
 
 impl From<Payload> for internal::Payload {
@@ -87,8 +93,11 @@ impl From<Payload> for internal::Payload {
             Payload::SystemInterval(m) => internal::Payload::SystemInterval(m.into()),
             Payload::BlockImport(m) => internal::Payload::BlockImport(m.into()),
             Payload::NotifyFinalized(m) => internal::Payload::NotifyFinalized(m.into()),
-            Payload::AfgAuthoritySet(m) => internal::Payload::AfgAuthoritySet(m.into()),
+            Payload::AfgAuthoritySet(m) => m.into(),
             Payload::HwBench(m) => internal::Payload::HwBench(m.into()),
+            Payload::AfgFinalized(m) => internal::Payload::AfgFinalized(m.into()),
+            Payload::AfgReceivedPrevote(m) => internal::Payload::AfgReceivedPrevote(m.into()),
+            Payload::AfgReceivedPrecommit(m) => internal::Payload::AfgReceivedPrecommit(m.into()),
         }
     }
 }
@@ -156,12 +165,60 @@ impl From<Finalized> for internal::Finalized {
 #[derive(Deserialize, Debug)]
 pub struct AfgAuthoritySet {
     pub authority_id: Box<str>,
+    // Nodes send these too, but we can make do with just the above:
+    pub authorities: Option<Box<str>>,
+    pub authority_set_id: Option<Box<str>>,
+    pub hash: Option<Hash>,
+    pub number: Option<Box<str>>,
 }
 
-impl From<AfgAuthoritySet> for internal::AfgAuthoritySet {
+impl From<AfgAuthoritySet> for internal::Payload {
     fn from(msg: AfgAuthoritySet) -> Self {
-        internal::AfgAuthoritySet {
-            authority_id: msg.authority_id,
+        match (msg.authorities, msg.authority_set_id, msg.hash, msg.number) {
+            (Some(authorities), Some(authority_set_id), Some(hash), Some(number)) => {
+                internal::Payload::AfgAuthorities(internal::AfgAuthorities {
+                    authority_id: msg.authority_id,
+                    authorities,
+                    authority_set_id,
+                    hash: hash.into(),
+                    number,
+                })
+            }
+            _ => internal::Payload::AfgAuthoritySet(internal::AfgAuthoritySet {
+                authority_id: msg.authority_id,
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AfgFinalized {
+    pub hash: Hash,
+    pub number: Box<str>,
+}
+
+impl From<AfgFinalized> for internal::AfgFinalized {
+    fn from(msg: AfgFinalized) -> Self {
+        internal::AfgFinalized {
+            hash: msg.hash.into(),
+            number: msg.number,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AfgReceived {
+    pub target_hash: Hash,
+    pub target_number: Box<str>,
+    pub voter: Option<Box<str>>,
+}
+
+impl From<AfgReceived> for internal::AfgReceived {
+    fn from(msg: AfgReceived) -> Self {
+        internal::AfgReceived {
+            target_hash: msg.target_hash.into(),
+            target_number: msg.target_number,
+            voter: msg.voter,
         }
     }
 }
@@ -404,6 +461,57 @@ mod tests {
         );
     }
 
+    #[test]
+    fn afg_authority_set_with_authorities() {
+        let json = r#"{
+            "msg":"afg.authority_set",
+            "authority_id":"5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY",
+            "authorities":"[\"5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY\"]",
+            "authority_set_id":"3",
+            "hash":"0xcc41708573f2acaded9dd75e07dac2d4163d136ca35b3061c558d7a35a09dd8d",
+            "number":"209"
+        }"#;
+        let msg: internal::NodeMessage = serde_json::from_str::<NodeMessage>(json).unwrap().into();
+        assert!(
+            matches!(
+                msg.into_payload(),
+                internal::Payload::AfgAuthorities(internal::AfgAuthorities { ref authority_set_id, .. })
+                    if &**authority_set_id == "3"
+            ),
+            "message did not match the expected output",
+        );
+
+        // Without the rest of the set, it's just the node's own authority ID:
+        let json = r#"{
+            "msg":"afg.authority_set",
+            "authority_id":"5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY"
+        }"#;
+        let msg: internal::NodeMessage = serde_json::from_str::<NodeMessage>(json).unwrap().into();
+        assert!(
+            matches!(msg.into_payload(), internal::Payload::AfgAuthoritySet(..)),
+            "message did not match the expected output",
+        );
+    }
+
+    #[test]
+    fn afg_received_prevote() {
+        let json = r#"{
+            "msg":"afg.received_prevote",
+            "target_hash":"0xcc41708573f2acaded9dd75e07dac2d4163d136ca35b3061c558d7a35a09dd8d",
+            "target_number":"209",
+            "voter":"5GrwvaEF5zXb26Fz9rcQpDWS57CtERHpNehXCPcNoHGKutQY (d4359...)"
+        }"#;
+        assert!(
+            matches!(
+                serde_json::from_str::<NodeMessage>(json).unwrap(),
+                NodeMessage::V1 {
+                    payload: Payload::AfgReceivedPrevote(AfgReceived { voter: Some(_), .. }),
+                },
+            ),
+            "message did not match the expected output",
+        );
+    }
+
     #[test]
     fn split_old_style_version_works() {
         let (version, target_arch, target_os, target_env) =
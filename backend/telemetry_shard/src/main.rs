@@ -18,18 +18,21 @@
 mod aggregator;
 mod blocked_addrs;
 mod connection;
+mod ingest_stats;
 mod json_message;
 mod real_ip;
 
 use std::{
     collections::HashMap,
     net::IpAddr,
+    sync::Arc,
     time::{Duration, Instant},
 };
 
 use aggregator::{Aggregator, FromWebsocket};
 use blocked_addrs::BlockedAddrs;
 use common::byte_size::ByteSize;
+use common::compression;
 use common::http_utils;
 use common::node_message;
 use common::node_message::NodeMessageId;
@@ -37,6 +40,7 @@ use common::rolling_total::RollingTotalBuilder;
 use futures::{SinkExt, StreamExt};
 use http::Uri;
 use hyper::{Method, Response};
+use ingest_stats::{ConnectionStats, IngestStats};
 use simple_logger::SimpleLogger;
 use structopt::StructOpt;
 
@@ -102,6 +106,27 @@ struct Opts {
     /// dropped.
     #[structopt(long, default_value = "60")]
     stale_node_timeout: u64,
+    /// Compress the messages that we send to the core. The only supported value is
+    /// 'deflate'. The core must be new enough to understand this, so leave it unset
+    /// until every core that shards connect to has been updated.
+    #[structopt(long)]
+    core_compression: Option<compression::Kind>,
+    /// Only send the core what's changed in each node's periodic stats, rather than
+    /// all of them every time, sending them in full once every this many intervals. As
+    /// with '--core-compression', the core must be new enough to understand this.
+    #[structopt(long)]
+    core_stats_deltas: Option<u32>,
+    /// Pass on the GRANDPA votes, finalizations and authority sets that nodes send, so
+    /// that feeds which ask for them can see them. As with '--core-compression', the core
+    /// must be new enough to understand this.
+    #[structopt(long)]
+    core_finality: bool,
+    /// The address of a proxy in front of this shard whose forwarding headers can be
+    /// believed when working out whose connections '/ingest_stats' should show. Anyone
+    /// can send those headers, so by default it goes by the address that connects to it.
+    /// Can be given more than once.
+    #[structopt(long = "trusted-proxy")]
+    trusted_proxies: Vec<IpAddr>,
 }
 
 fn main() {
@@ -138,19 +163,40 @@ fn main() {
 /// Declare our routes and start the server.
 async fn start_server(opts: Opts) -> anyhow::Result<()> {
     let block_list = BlockedAddrs::new(Duration::from_secs(opts.node_block_seconds));
-    let aggregator = Aggregator::spawn(opts.core_url).await?;
+    let aggregator = Aggregator::spawn(
+        opts.core_url,
+        opts.core_compression,
+        opts.core_stats_deltas,
+        opts.core_finality,
+    )
+    .await?;
     let socket_addr = opts.socket;
     let max_nodes_per_connection = opts.max_nodes_per_connection;
     let bytes_per_second = opts.max_node_data_per_second;
     let stale_node_timeout = Duration::from_secs(opts.stale_node_timeout);
+    let ingest_stats = IngestStats::default();
+    let trusted_proxies = Arc::new(opts.trusted_proxies);
 
     let server = http_utils::start_server(socket_addr, move |addr, req| {
         let aggregator = aggregator.clone();
         let block_list = block_list.clone();
+        let ingest_stats = ingest_stats.clone();
+        let trusted_proxies = trusted_proxies.clone();
         async move {
             match (req.method(), req.uri().path().trim_end_matches('/')) {
                 // Check that the server is up and running:
                 (&Method::GET, "/health") => Ok(Response::new("OK".into())),
+                // Node operators can see what's arriving from their own address here:
+                (&Method::GET, "/ingest_stats") => {
+                    // Only what the caller sends from their own address, which they can't
+                    // be allowed to claim just by sending a header:
+                    let real_addr = real_ip::trusted_real_ip(addr, req.headers(), &trusted_proxies);
+                    let list = ingest_stats.list_for(real_addr);
+                    Ok(Response::builder()
+                        .header(http::header::CONTENT_TYPE, "application/json")
+                        .body(serde_json::to_vec(&list)?.into())
+                        .unwrap())
+                }
                 // Nodes send messages here:
                 (&Method::GET, "/submit") => {
                     let (real_addr, real_addr_source) = real_ip::real_ip(addr, req.headers());
@@ -168,6 +214,7 @@ async fn start_server(opts: Opts) -> anyhow::Result<()> {
                                 real_addr_source
                             );
                             let tx_to_aggregator = aggregator.subscribe_node();
+                            let stats = ingest_stats.add(real_addr);
                             let (mut tx_to_aggregator, mut ws_send) =
                                 handle_node_websocket_connection(
                                     real_addr,
@@ -178,6 +225,7 @@ async fn start_server(opts: Opts) -> anyhow::Result<()> {
                                     bytes_per_second,
                                     block_list,
                                     stale_node_timeout,
+                                    stats,
                                 )
                                 .await;
                             log::info!(
@@ -214,6 +262,7 @@ async fn handle_node_websocket_connection<S>(
     bytes_per_second: ByteSize,
     block_list: BlockedAddrs,
     stale_node_timeout: Duration,
+    stats: ConnectionStats,
 ) -> (S, http_utils::WsSender)
 where
     S: futures::Sink<FromWebsocket, Error = anyhow::Error> + Unpin + Send + 'static,
@@ -294,6 +343,7 @@ where
                 for &message_id in &stale_ids {
                     log::info!("Removing stale node with message ID {message_id} from {real_addr:?}");
                     allowed_message_ids.remove(&message_id);
+                    stats.node_removed(message_id);
                     let _ = tx_to_aggregator.send(FromWebsocket::Remove { message_id } ).await;
                 }
 
@@ -311,6 +361,8 @@ where
                     None => { break; }
                 };
 
+                stats.received(bytes.len());
+
                 // Keep track of total bytes and bail if average over last 10 secs exceeds preference.
                 rolling_total_bytes.push(bytes.len());
                 let this_bytes_per_second = rolling_total_bytes.total() / 10;
@@ -325,6 +377,7 @@ where
                     Ok(node_message) => node_message,
                     #[cfg(debug)]
                     Err(e) => {
+                        stats.payload_error();
                         let bytes: &[u8] = bytes.get(..512).unwrap_or_else(|| &bytes);
                         let msg_start = std::str::from_utf8(bytes).unwrap_or_else(|_| "INVALID UTF8");
                         log::warn!("Failed to parse node message ({msg_start}): {e}");
@@ -332,6 +385,7 @@ where
                     },
                     #[cfg(not(debug))]
                     Err(_) => {
+                        stats.payload_error();
                         continue;
                     }
                 };
@@ -348,6 +402,7 @@ where
                     // Too many nodes seen on this connection? Ignore this one.
                     if allowed_message_ids.len() >= max_nodes_per_connection {
                         log::info!("Ignoring new node with ID {message_id} from {real_addr:?} (we've hit the max of {max_nodes_per_connection} nodes per connection)");
+                        stats.ignored();
                         continue;
                     }
 
@@ -355,11 +410,13 @@ where
                     let prev_join_time = allowed_message_ids.insert(message_id, Instant::now());
                     if prev_join_time.is_some() {
                         log::info!("Ignoring duplicate new node with ID {message_id} from {real_addr:?}");
+                        stats.ignored();
                         continue;
                     }
 
                     // Tell the aggregator loop about the new node.
                     log::info!("Adding node with message ID {message_id} from {real_addr:?}");
+                    stats.node_added(message_id, &info.node.name, info.genesis_hash);
                     let _ = tx_to_aggregator.send(FromWebsocket::Add {
                         message_id,
                         ip: real_addr,
@@ -372,12 +429,14 @@ where
                 else {
                     if let Some(last_seen) = allowed_message_ids.get_mut(&message_id) {
                         *last_seen = Instant::now();
+                        stats.node_message(message_id);
                         if let Err(e) = tx_to_aggregator.send(FromWebsocket::Update { message_id, payload } ).await {
                             log::error!("Failed to send node message to aggregator: {e}");
                             continue;
                         }
                     } else {
                         log::info!("Ignoring message with ID {message_id} from {real_addr:?} (we've hit the max of {max_nodes_per_connection} nodes per connection)");
+                        stats.ignored();
                         continue;
                     }
                 }
@@ -42,6 +42,39 @@ pub fn real_ip(addr: SocketAddr, headers: &hyper::HeaderMap) -> (IpAddr, Source)
     pick_best_ip_from_options(forwarded, forwarded_for, real_ip, addr)
 }
 
+/// Like [`real_ip`], but for when the address decides what the caller gets to see. Anyone
+/// can send forwarding headers, so they're only believed on connections from one of the
+/// `trusted_proxies`, and then only as far back as the first address that isn't another
+/// trusted proxy: each proxy appends the address that connected to it, but whatever comes
+/// before that was given by the client. Otherwise, this is the socket address.
+pub fn trusted_real_ip(
+    addr: SocketAddr,
+    headers: &hyper::HeaderMap,
+    trusted_proxies: &[IpAddr],
+) -> IpAddr {
+    if !trusted_proxies.contains(&addr.ip()) {
+        return addr.ip();
+    }
+    let chain = if let Some(forwarded) = headers.get("forwarded").and_then(header_as_str) {
+        get_addrs_from_forwarded_header(forwarded)
+    } else if let Some(forwarded_for) = headers.get("x-forwarded-for").and_then(header_as_str) {
+        forwarded_for.split(',').map(|val| val.trim()).collect()
+    } else if let Some(real_ip) = headers.get("x-real-ip").and_then(header_as_str) {
+        vec![real_ip.trim()]
+    } else {
+        vec![]
+    };
+    for hop in chain.into_iter().rev() {
+        match parse_ip(hop) {
+            Some(ip) if trusted_proxies.contains(&ip) => continue,
+            Some(ip) => return ip,
+            // Anything further back can't be vouched for:
+            None => break,
+        }
+    }
+    addr.ip()
+}
+
 /// The source of the address returned
 pub enum Source {
     ForwardedHeader,
@@ -95,22 +128,22 @@ fn pick_best_ip_from_options(
                 Some((addr, Source::XRealIpHeader))
             })
         })
-        .and_then(|(ip, source)| {
-            // Try parsing assuming it may have a port first,
-            // and then assuming it doesn't.
-            let addr = ip
-                .parse::<SocketAddr>()
-                .map(|s| s.ip())
-                .or_else(|_| ip.parse::<IpAddr>())
-                .ok()?;
-            Some((addr, source))
-        })
+        .and_then(|(ip, source)| Some((parse_ip(ip)?, source)))
         // Fall back to local IP address if the above fails
         .unwrap_or((addr.ip(), Source::SocketAddr));
 
     realip
 }
 
+fn parse_ip(ip: &str) -> Option<IpAddr> {
+    // Try parsing assuming it may have a port first,
+    // and then assuming it doesn't.
+    ip.parse::<SocketAddr>()
+        .map(|s| s.ip())
+        .or_else(|_| ip.parse::<IpAddr>())
+        .ok()
+}
+
 /// Follow <https://datatracker.ietf.org/doc/html/rfc7239> to decode the Forwarded header value.
 /// Roughly, proxies can add new sets of values by appending a comma to the existing list
 /// (so we have something like "values1, values2, values3" from proxy1, proxy2 and proxy3 for
@@ -126,9 +159,20 @@ fn pick_best_ip_from_options(
 /// Forwarded: for=192.0.2.43, for=198.51.100.17
 /// ```
 fn get_first_addr_from_forwarded_header(value: &str) -> Option<&str> {
-    let first_values = value.split(',').next()?;
+    get_addr_from_forwarded_values(value.split(',').next()?)
+}
+
+/// The "for" address given by each proxy in a Forwarded header value, in order.
+fn get_addrs_from_forwarded_header(value: &str) -> Vec<&str> {
+    value
+        .split(',')
+        .filter_map(get_addr_from_forwarded_values)
+        .collect()
+}
 
-    for pair in first_values.split(';') {
+/// The "for" address in one proxy's set of ';' separated values.
+fn get_addr_from_forwarded_values(values: &str) -> Option<&str> {
+    for pair in values.split(';') {
         let mut parts = pair.trim().splitn(2, '=');
         let key = parts.next()?;
         let value = parts.next()?;
@@ -176,4 +220,27 @@ mod test {
             );
         }
     }
+
+    #[test]
+    fn only_trusted_proxies_are_believed() {
+        let socket: SocketAddr = "10.0.0.1:1234".parse().unwrap();
+        let proxy: IpAddr = "10.0.0.1".parse().unwrap();
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert(
+            "x-forwarded-for",
+            "1.2.3.4, 5.6.7.8, 10.0.0.1".parse().unwrap(),
+        );
+
+        // Anyone can send the header, so it's ignored unless it's from a trusted proxy:
+        assert_eq!(trusted_real_ip(socket, &headers, &[]), proxy);
+        // The proxy appended 5.6.7.8, the address that connected to it; the client could
+        // have made up 1.2.3.4:
+        assert_eq!(
+            trusted_real_ip(socket, &headers, &[proxy]),
+            "5.6.7.8".parse::<IpAddr>().unwrap()
+        );
+
+        headers.insert("forwarded", "for=1.2.3.4, for=\"_gazonk\"".parse().unwrap());
+        assert_eq!(trusted_real_ip(socket, &headers, &[proxy]), proxy);
+    }
 }
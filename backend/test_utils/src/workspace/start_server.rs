@@ -37,6 +37,9 @@ pub struct CoreOpts {
     pub feed_timeout: Option<u64>,
     pub worker_threads: Option<usize>,
     pub num_aggregators: Option<usize>,
+    pub record_feed: Vec<String>,
+    pub record_dir: Option<String>,
+    pub admin_token: Option<String>,
 }
 
 impl Default for CoreOpts {
@@ -45,6 +48,9 @@ impl Default for CoreOpts {
             feed_timeout: None,
             worker_threads: None,
             num_aggregators: None,
+            record_feed: Vec::new(),
+            record_dir: None,
+            admin_token: None,
         }
     }
 }
@@ -55,6 +61,9 @@ pub struct ShardOpts {
     pub max_node_data_per_second: Option<usize>,
     pub node_block_seconds: Option<u64>,
     pub worker_threads: Option<usize>,
+    pub core_compression: Option<String>,
+    pub core_stats_deltas: Option<u32>,
+    pub core_finality: bool,
 }
 
 impl Default for ShardOpts {
@@ -64,6 +73,9 @@ impl Default for ShardOpts {
             max_node_data_per_second: None,
             node_block_seconds: None,
             worker_threads: None,
+            core_compression: None,
+            core_stats_deltas: None,
+            core_finality: false,
         }
     }
 }
@@ -129,6 +141,9 @@ pub async fn start_server(
             .arg("--max-nodes-per-connection")
             .arg(val.to_string());
     }
+    if shard_opts.core_finality {
+        shard_command = shard_command.arg("--core-finality");
+    }
     if let Some(val) = shard_opts.max_node_data_per_second {
         shard_command = shard_command
             .arg("--max-node-data-per-second")
@@ -142,6 +157,14 @@ pub async fn start_server(
     if let Some(val) = shard_opts.worker_threads {
         shard_command = shard_command.arg("--worker-threads").arg(val.to_string());
     }
+    if let Some(val) = shard_opts.core_compression {
+        shard_command = shard_command.arg("--core-compression").arg(val);
+    }
+    if let Some(val) = shard_opts.core_stats_deltas {
+        shard_command = shard_command
+            .arg("--core-stats-deltas")
+            .arg(val.to_string());
+    }
 
     // Build the core command
     let mut core_command = std::env::var("TELEMETRY_CORE_BIN")
@@ -161,6 +184,15 @@ pub async fn start_server(
     if let Some(val) = core_opts.num_aggregators {
         core_command = core_command.arg("--num-aggregators").arg(val.to_string());
     }
+    for val in core_opts.record_feed {
+        core_command = core_command.arg("--record-feed").arg(val);
+    }
+    if let Some(val) = core_opts.record_dir {
+        core_command = core_command.arg("--record-dir").arg(val);
+    }
+    if let Some(val) = core_opts.admin_token {
+        core_command = core_command.arg("--admin-token").arg(val);
+    }
 
     // Start the server
     Server::start(server::StartOpts::ShardAndCore {